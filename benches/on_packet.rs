@@ -0,0 +1,163 @@
+//! What `on_packet` costs per segment on the workloads that matter for the segmentation and
+//! congestion logic: a stream of in-order data, the same stream with every other segment
+//! arriving ahead of the one before it, and a sender fielding a loss's worth of duplicate ACKs
+//! every burst. Only the feeding of the peer's segments is timed, a burst at a time as the
+//! packet loop would take them, and throughput is per segment fed.
+
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use etherparse::PacketBuilder;
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad};
+
+/// segments fed per run, whatever the workload
+const SEGMENTS: usize = 20_000;
+/// segments the peer sends before we get to read or write again
+const BURST: usize = 40;
+const MSS: usize = 1460;
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const PEER_ISS: u32 = 100;
+const QUAD: Quad = Quad {
+    src: (std::net::IpAddr::V4(PEER), 40000),
+    dst: (std::net::IpAddr::V4(LOCAL), 80),
+};
+
+fn segment(seq: u32, ack: Option<u32>, data: &[u8]) -> Vec<u8> {
+    let b = PacketBuilder::ipv4(PEER.octets(), LOCAL.octets(), 64).tcp(40000, 80, seq, u16::MAX);
+    let b = match ack {
+        Some(ack) => b.ack(ack),
+        None => b.syn(),
+    };
+    let mut p = Vec::new();
+    b.write(&mut p, data).unwrap();
+    p
+}
+
+fn end_of(packet: &[u8]) -> u32 {
+    let (_, tcph, data) = parse_segment(packet);
+    tcph.sequence_number()
+        .wrapping_add(data.len() as u32 + tcph.syn() as u32)
+}
+
+/// An established connection with room to receive a whole burst, and our next sequence
+/// number.
+fn establish() -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().recv_window(u16::MAX));
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    let iss = end_of(&r.take_sent().pop().unwrap());
+    r.feed(&segment(PEER_ISS + 1, Some(iss), &[])).unwrap();
+    (r, iss)
+}
+
+/// Feed `burst`, reading whatever it delivered and dropping what we sent in reply, and return
+/// how long the feeding took.
+fn feed(r: &mut Replay, burst: &[Vec<u8>]) -> Duration {
+    let start = Instant::now();
+    r.process_batch(burst.iter().map(|p| &p[..])).unwrap();
+    let elapsed = start.elapsed();
+    r.read(QUAD, BURST * MSS).unwrap();
+    r.take_sent();
+    elapsed
+}
+
+/// Receive full-sized segments of data, in order.
+fn in_order() -> Duration {
+    let (mut r, iss) = establish();
+    let data = vec![0u8; MSS];
+    let mut seq = PEER_ISS + 1;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..SEGMENTS / BURST {
+        let burst: Vec<_> = (0..BURST)
+            .map(|i| segment(seq + (i * MSS) as u32, Some(iss), &data))
+            .collect();
+        seq += (BURST * MSS) as u32;
+        elapsed += feed(&mut r, &burst);
+    }
+    elapsed
+}
+
+/// Receive full-sized segments with every other one arriving ahead of the one before it. We
+/// don't keep data that's out of order, so the peer sends it again once the gap is filled:
+/// three segments fed for every two delivered.
+fn out_of_order() -> Duration {
+    let (mut r, iss) = establish();
+    let data = vec![0u8; MSS];
+    let mut seq = PEER_ISS + 1;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..SEGMENTS / BURST {
+        let mut burst = Vec::with_capacity(BURST);
+        while burst.len() + 3 <= BURST {
+            let next = segment(seq + MSS as u32, Some(iss), &data);
+            burst.push(next.clone());
+            burst.push(segment(seq, Some(iss), &data));
+            burst.push(next);
+            seq += 2 * MSS as u32;
+        }
+        while burst.len() < BURST {
+            burst.push(segment(seq, Some(iss), &data));
+            seq += MSS as u32;
+        }
+        elapsed += feed(&mut r, &burst);
+    }
+    elapsed
+}
+
+/// Send full-sized segments, with the first of every flight lost: the peer answers each of
+/// the rest with a duplicate ACK, which brings on a fast retransmit, then ACKs the lot.
+fn duplicate_acks() -> Duration {
+    let (mut r, mut una) = establish();
+    let data = vec![0u8; BURST * MSS];
+    let mut fed = 0;
+    let mut elapsed = Duration::ZERO;
+    while fed < SEGMENTS {
+        r.write(QUAD, &data).unwrap();
+        // leaving out the retransmission from last time, which the peer has had ACKed
+        let flight: Vec<_> = r
+            .take_sent()
+            .into_iter()
+            .filter(|p| {
+                let (_, tcph, data) = parse_segment(p);
+                !data.is_empty() && tcph.sequence_number().wrapping_sub(una) < 1 << 31
+            })
+            .collect();
+        let Some((first, last)) = flight.first().zip(flight.last()) else {
+            continue;
+        };
+        let lost = parse_segment(first).1.sequence_number();
+        una = end_of(last);
+        let mut acks: Vec<_> = flight[1..]
+            .iter()
+            .map(|_| segment(PEER_ISS + 1, Some(lost), &[]))
+            .collect();
+        acks.push(segment(PEER_ISS + 1, Some(una), &[]));
+        acks.truncate(SEGMENTS - fed);
+        fed += acks.len();
+        let start = Instant::now();
+        r.process_batch(acks.iter().map(|p| &p[..])).unwrap();
+        elapsed += start.elapsed();
+    }
+    elapsed
+}
+
+fn bench(c: &mut Criterion) {
+    let mut g = c.benchmark_group("on_packet");
+    g.sample_size(10);
+    g.throughput(Throughput::Elements(SEGMENTS as u64));
+    g.bench_function("in order", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| in_order()).sum())
+    });
+    g.bench_function("out of order", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| out_of_order()).sum())
+    });
+    g.bench_function("duplicate ACKs", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| duplicate_acks()).sum())
+    });
+    g.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
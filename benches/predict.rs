@@ -0,0 +1,114 @@
+//! What header prediction saves per segment, for the two kinds of segment it's for: in-order
+//! data on a connection we're receiving on, and ACKs on one we're sending on. Each is timed
+//! with prediction on and off; only the feeding of the peer's segments counts. They're fed a
+//! burst at a time, as the packet loop would take them, so there's one ACK per burst.
+
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use etherparse::PacketBuilder;
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad};
+
+const SEGMENTS: usize = 20_000;
+/// segments the peer sends before we get to read or write again
+const BURST: usize = 40;
+const MSS: usize = 1460;
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const PEER_ISS: u32 = 100;
+const QUAD: Quad = Quad {
+    src: (std::net::IpAddr::V4(PEER), 40000),
+    dst: (std::net::IpAddr::V4(LOCAL), 80),
+};
+
+fn segment(seq: u32, ack: Option<u32>, data: &[u8]) -> Vec<u8> {
+    let b = PacketBuilder::ipv4(PEER.octets(), LOCAL.octets(), 64).tcp(40000, 80, seq, u16::MAX);
+    let b = match ack {
+        Some(ack) => b.ack(ack),
+        None => b.syn(),
+    };
+    let mut p = Vec::new();
+    b.write(&mut p, data).unwrap();
+    p
+}
+
+fn end_of(packet: &[u8]) -> u32 {
+    let (_, tcph, data) = parse_segment(packet);
+    tcph.sequence_number()
+        .wrapping_add(data.len() as u32 + tcph.syn() as u32)
+}
+
+/// An established connection with room to receive a whole burst, and our next sequence
+/// number.
+fn establish(header_prediction: bool) -> (Replay, u32) {
+    let config = ConnectionConfig::default()
+        .recv_window(u16::MAX)
+        .header_prediction(header_prediction);
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    let iss = end_of(&r.take_sent().pop().unwrap());
+    r.feed(&segment(PEER_ISS + 1, Some(iss), &[])).unwrap();
+    (r, iss)
+}
+
+/// Receive `SEGMENTS` full-sized segments of data, in order, reading after each burst.
+fn receive(header_prediction: bool) -> Duration {
+    let (mut r, iss) = establish(header_prediction);
+    let data = vec![0u8; MSS];
+    let mut seq = PEER_ISS + 1;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..SEGMENTS / BURST {
+        let burst: Vec<_> = (0..BURST)
+            .map(|i| segment(seq + (i * MSS) as u32, Some(iss), &data))
+            .collect();
+        seq += (BURST * MSS) as u32;
+        let start = Instant::now();
+        r.process_batch(burst.iter().map(|p| &p[..])).unwrap();
+        elapsed += start.elapsed();
+        r.read(QUAD, BURST * MSS).unwrap();
+        r.take_sent();
+    }
+    elapsed
+}
+
+/// Send `SEGMENTS` segments of data, with the peer ACKing each one on its own.
+fn send(header_prediction: bool) -> Duration {
+    let (mut r, _) = establish(header_prediction);
+    let data = vec![0u8; BURST * MSS];
+    let mut sent = 0;
+    let mut elapsed = Duration::ZERO;
+    while sent < SEGMENTS {
+        r.write(QUAD, &data).unwrap();
+        let acks: Vec<_> = r
+            .take_sent()
+            .iter()
+            .map(|p| segment(PEER_ISS + 1, Some(end_of(p)), &[]))
+            .collect();
+        sent += acks.len();
+        let start = Instant::now();
+        r.process_batch(acks.iter().map(|p| &p[..])).unwrap();
+        elapsed += start.elapsed();
+    }
+    elapsed
+}
+
+fn bench(c: &mut Criterion) {
+    let mut g = c.benchmark_group("header prediction");
+    g.sample_size(10);
+    for on in [true, false] {
+        let name = if on { "on" } else { "off" };
+        g.bench_function(format!("receive 20k segments, {name}"), |b| {
+            b.iter_custom(|iters| (0..iters).map(|_| receive(on)).sum())
+        });
+        g.bench_function(format!("20k ACKs, {name}"), |b| {
+            b.iter_custom(|iters| (0..iters).map(|_| send(on)).sum())
+        });
+    }
+    g.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
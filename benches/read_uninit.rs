@@ -0,0 +1,83 @@
+//! Bulk receive, read out a burst at a time into a fresh 64 KB buffer: one zeroed before
+//! `read` overwrites it, against one left uninitialized for `read_uninit`, as a reader
+//! handing out a new `Vec` per read would have it.
+
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use etherparse::PacketBuilder;
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad};
+
+const SEGMENTS: usize = 20_000;
+/// segments the peer sends before the application gets to read, which fit in the window
+const BURST: usize = 40;
+const MSS: usize = 1460;
+const READ_SIZE: usize = 64 * 1024;
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const PEER_ISS: u32 = 100;
+const QUAD: Quad = Quad {
+    src: (std::net::IpAddr::V4(PEER), 40000),
+    dst: (std::net::IpAddr::V4(LOCAL), 80),
+};
+
+fn segment(seq: u32, ack: Option<u32>, data: &[u8]) -> Vec<u8> {
+    let b = PacketBuilder::ipv4(PEER.octets(), LOCAL.octets(), 64).tcp(40000, 80, seq, u16::MAX);
+    let b = match ack {
+        Some(ack) => b.ack(ack),
+        None => b.syn(),
+    };
+    let mut p = Vec::new();
+    b.write(&mut p, data).unwrap();
+    p
+}
+
+/// Receive `SEGMENTS` segments, reading each burst into a new buffer, zeroed or not. Only
+/// the reading counts, not the segments coming in.
+fn receive(uninit: bool) -> Duration {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().recv_window(u16::MAX));
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    let sent = r.take_sent();
+    let (_, synack, _) = parse_segment(&sent[0]);
+    let ours = synack.sequence_number().wrapping_add(1);
+    r.feed(&segment(PEER_ISS + 1, Some(ours), &[])).unwrap();
+
+    let data = vec![0u8; MSS];
+    let mut seq = PEER_ISS + 1;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..SEGMENTS / BURST {
+        for _ in 0..BURST {
+            r.feed(&segment(seq, Some(ours), &data)).unwrap();
+            seq += MSS as u32;
+        }
+        let start = Instant::now();
+        let n = if uninit {
+            let mut buf = Vec::<u8>::with_capacity(READ_SIZE);
+            r.read_uninit(QUAD, buf.spare_capacity_mut()).unwrap()
+        } else {
+            r.read(QUAD, READ_SIZE).unwrap().len()
+        };
+        elapsed += start.elapsed();
+        assert_eq!(n, BURST * MSS);
+        r.take_sent();
+    }
+    elapsed
+}
+
+fn bench(c: &mut Criterion) {
+    let mut g = c.benchmark_group("read");
+    g.sample_size(10);
+    g.bench_function("20k segments, zeroed buffer", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| receive(false)).sum())
+    });
+    g.bench_function("20k segments, uninitialized buffer", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| receive(true)).sum())
+    });
+    g.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,108 @@
+//! How fast, and with how many allocations, a connection turns queued data into segments.
+//!
+//! The allocation count includes the one `MockNic` makes to record each segment it's sent. It's
+//! checked against what the same bench counted before segments were assembled in a reusable
+//! buffer, when each one's payload was first copied out of the send queue into a `Vec` of its
+//! own, and the bench fails if that saving has been lost.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use etherparse::PacketBuilder;
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const SEGMENTS: usize = 100_000;
+/// Allocations per segment with the old copy path, as this bench counted them then.
+const BASELINE_ALLOCATIONS: f64 = 2.08;
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+fn segment(seq: u32, ack: Option<u32>) -> Vec<u8> {
+    let b = PacketBuilder::ipv4(PEER.octets(), LOCAL.octets(), 64).tcp(40000, 80, seq, u16::MAX);
+    let b = match ack {
+        Some(ack) => b.ack(ack),
+        None => b.syn(),
+    };
+    let mut p = Vec::new();
+    b.write(&mut p, &[]).unwrap();
+    p
+}
+
+fn ack_of(packet: &[u8]) -> u32 {
+    let (_, tcph, data) = parse_segment(packet);
+    tcph.sequence_number()
+        .wrapping_add(data.len() as u32 + tcph.syn() as u32)
+}
+
+/// Send `SEGMENTS` full-sized segments on an established connection, ACKing each burst.
+fn send_segments() -> Duration {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    r.feed(&segment(100, None)).unwrap();
+    let synack = r.take_sent().pop().unwrap();
+    r.feed(&segment(101, Some(ack_of(&synack)))).unwrap();
+    let quad = Quad {
+        src: (PEER.into(), 40000),
+        dst: (LOCAL.into(), 80),
+    };
+
+    let data = vec![0u8; 64 * 1024];
+    let start = Instant::now();
+    let mut sent = 0;
+    while sent < SEGMENTS {
+        r.write(quad, &data).unwrap();
+        let burst = r.take_sent();
+        sent += burst.len();
+        r.feed(&segment(101, Some(ack_of(burst.last().unwrap()))))
+            .unwrap();
+    }
+    start.elapsed()
+}
+
+fn bench(c: &mut Criterion) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let took = send_segments();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    let per_segment = allocations as f64 / SEGMENTS as f64;
+    println!(
+        "{allocations} allocations for {SEGMENTS} segments ({per_segment:.2} per segment, down \
+         from {BASELINE_ALLOCATIONS:.2}) in {took:?}"
+    );
+    // the copy was one allocation a segment, so its coming back would take us past this
+    assert!(
+        per_segment < BASELINE_ALLOCATIONS - 0.5,
+        "{per_segment:.2} allocations per segment, where the copy path took {BASELINE_ALLOCATIONS:.2}"
+    );
+
+    let mut g = c.benchmark_group("send");
+    g.sample_size(10);
+    g.bench_function("100k segments", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| send_segments()).sum())
+    });
+    g.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+//! Bulk receive, with the data copied into the receive buffer and read out of it, against
+//! left in the packet buffers it arrived in and handed out by `recv_bytes`. Either way the
+//! peer's full-sized segments come a burst at a time, and the application takes what's
+//! arrived before the next.
+
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use etherparse::PacketBuilder;
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad};
+
+const SEGMENTS: usize = 20_000;
+/// segments the peer sends before the application gets to read, which just fit in the
+/// window even when each one takes up a whole packet buffer
+const BURST: usize = 40;
+const MSS: usize = 1460;
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const PEER_ISS: u32 = 100;
+const QUAD: Quad = Quad {
+    src: (std::net::IpAddr::V4(PEER), 40000),
+    dst: (std::net::IpAddr::V4(LOCAL), 80),
+};
+
+fn segment(seq: u32, ack: Option<u32>, data: &[u8]) -> Vec<u8> {
+    let b = PacketBuilder::ipv4(PEER.octets(), LOCAL.octets(), 64).tcp(40000, 80, seq, u16::MAX);
+    let b = match ack {
+        Some(ack) => b.ack(ack),
+        None => b.syn(),
+    };
+    let mut p = Vec::new();
+    b.write(&mut p, data).unwrap();
+    p
+}
+
+/// Receive `SEGMENTS` segments, a payload at a time with `recv_bytes` if `zero_copy`, and a
+/// burst at a time with `read` otherwise. Only the feeding and reading count, not building
+/// the segments.
+fn receive(zero_copy: bool) -> Duration {
+    let config = ConnectionConfig::default()
+        .recv_window(u16::MAX)
+        .zero_copy(zero_copy);
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    let sent = r.take_sent();
+    let (_, synack, _) = parse_segment(&sent[0]);
+    let ours = synack.sequence_number().wrapping_add(1);
+    r.feed(&segment(PEER_ISS + 1, Some(ours), &[])).unwrap();
+
+    let data = vec![0u8; MSS];
+    let mut seq = PEER_ISS + 1;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..SEGMENTS / BURST {
+        let burst: Vec<_> = (0..BURST)
+            .map(|i| segment(seq + (i * MSS) as u32, Some(ours), &data))
+            .collect();
+        seq += (BURST * MSS) as u32;
+        let start = Instant::now();
+        for p in burst {
+            r.feed_owned(p).unwrap();
+        }
+        let mut n = 0;
+        while n < BURST * MSS {
+            n += if zero_copy {
+                r.recv_bytes(QUAD).unwrap().len()
+            } else {
+                r.read(QUAD, BURST * MSS).unwrap().len()
+            };
+        }
+        elapsed += start.elapsed();
+        r.take_sent();
+    }
+    elapsed
+}
+
+fn bench(c: &mut Criterion) {
+    let mut g = c.benchmark_group("receive");
+    g.sample_size(10);
+    g.bench_function("20k segments, copied", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| receive(false)).sum())
+    });
+    g.bench_function("20k segments, zero-copy", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| receive(true)).sum())
+    });
+    g.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
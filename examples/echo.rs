@@ -0,0 +1,69 @@
+//! An echo server (RFC 862): whatever a client sends comes straight back, until it closes its
+//! end and we close ours.
+//!
+//! Opening the tun device takes root (or `CAP_NET_ADMIN`). Start the server, then give the
+//! kernel's end of the device an address and talk to it as any other host on the subnet:
+//!
+//! ```sh
+//! cargo run --example echo -- 7 tun0 &
+//! sudo ip addr add 10.0.0.1/24 dev tun0
+//! sudo ip link set up dev tun0
+//! nc 10.0.0.2 7 < bigfile
+//! ```
+//!
+//! The port defaults to 7 and the device to `tun0`. Pings to 10.0.0.2 are answered too.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr};
+use std::thread;
+
+use trust::{Interface, TcpStream, Tun};
+
+fn main() -> io::Result<()> {
+    let mut args = env::args().skip(1);
+    let port = match args.next() {
+        Some(port) => port.parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("not a port: {port}"))
+        })?,
+        None => 7,
+    };
+    let device = args.next().unwrap_or_else(|| "tun0".to_string());
+
+    // the packet loop runs for as long as `iface` is alive, so it stays here in `main` while
+    // the streams go off to threads of their own
+    let mut iface = Interface::with_nic(Tun::open(&device)?);
+    iface.add_address(Ipv4Addr::new(10, 0, 0, 2).into());
+    let mut listener = iface.bind(port)?;
+    eprintln!("echoing on port {port} of {device}");
+    loop {
+        let stream = listener.accept()?;
+        let (addr, port) = stream.quad().src;
+        let peer = SocketAddr::new(addr, port);
+        thread::spawn(move || match echo(stream) {
+            Ok(n) => eprintln!("{peer}: echoed {n} bytes"),
+            Err(e) => eprintln!("{peer}: {e}"),
+        });
+    }
+}
+
+/// Send everything `stream` receives back to it until the peer closes its end, then close
+/// ours. Returns how many bytes were echoed.
+pub fn echo(mut stream: TcpStream) -> io::Result<u64> {
+    let mut buf = [0; 4096];
+    let mut total = 0;
+    loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        // blocks while the send queue is full, which holds off reading more until the peer
+        // has caught up with what we're sending back
+        stream.write_all(&buf[..n])?;
+        total += n as u64;
+    }
+    // the FIN goes out after everything written so far. dropping the stream would send it
+    // too, but this way a stream that's already been reset says so.
+    stream.shutdown(Shutdown::Write)?;
+    Ok(total)
+}
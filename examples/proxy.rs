@@ -0,0 +1,145 @@
+//! A TCP port forwarder: connections accepted on one port of the tun device are relayed to an
+//! upstream server, both ways at once, with each side's FIN passed on to the other.
+//!
+//! Opening the tun device takes root (or `CAP_NET_ADMIN`). To put a web server behind it:
+//!
+//! ```sh
+//! cargo run --example proxy -- 8080 10.0.0.1:8000 tun0 &
+//! sudo ip addr add 10.0.0.1/24 dev tun0
+//! sudo ip link set up dev tun0
+//! python3 -m http.server --bind 10.0.0.1 8000 &
+//! curl http://10.0.0.2:8080/
+//! ```
+//!
+//! The upstream connection goes out through this stack too, from 10.0.0.2, so the upstream has
+//! to be reachable from the tun device: the kernel's end of it, or anywhere the kernel will
+//! forward to. With `--std` it goes through the kernel's own stack instead, which reaches
+//! whatever the host can. The device defaults to `tun0`.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr};
+use std::thread;
+
+use trust::{ConnectionConfig, Interface, Tun};
+
+/// The address we claim on the tun device's subnet, and connect upstream from.
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+fn main() -> io::Result<()> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let use_std = args.iter().any(|a| a == "--std");
+    args.retain(|a| a != "--std");
+    let (port, upstream, device) = match &args[..] {
+        [port, upstream] => (port, upstream, "tun0"),
+        [port, upstream, device] => (port, upstream, &device[..]),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "usage: proxy PORT UPSTREAM [DEVICE] [--std]",
+            ));
+        }
+    };
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidInput, what.to_string());
+    let port: u16 = port.parse().map_err(|_| invalid("not a port"))?;
+    let upstream: SocketAddr = upstream.parse().map_err(|_| invalid("not an address"))?;
+
+    let mut iface = Interface::with_nic(Tun::open(device)?);
+    iface.add_address(LOCAL.into());
+    // everything a proxy does is bulk data, so offer both sides as much window as there is
+    let config = ConnectionConfig::default().recv_window(u16::MAX);
+    let mut listener = iface.bind_with_config(port, 128, config.clone())?;
+    eprintln!("forwarding port {port} of {device} to {upstream}");
+    loop {
+        let client = listener.accept()?;
+        let (addr, port) = client.quad().src;
+        let peer = SocketAddr::new(addr, port);
+        // the connect blocks for the handshake, and holds up accepting the next client until
+        // it's done: it needs the interface, which stays here
+        let spawned = if use_std {
+            std::net::TcpStream::connect(upstream)
+                .map(|upstream| spawn_relay(peer, client, upstream))
+        } else {
+            iface
+                .connect_with_config(LOCAL.into(), upstream, config.clone())
+                .map(|upstream| spawn_relay(peer, client, upstream))
+        };
+        if let Err(e) = spawned {
+            // dropping the client closes it, which is all it's going to get
+            eprintln!("{peer}: failed to connect to {upstream}: {e}");
+        }
+    }
+}
+
+fn spawn_relay(peer: SocketAddr, client: impl Split, upstream: impl Split) {
+    thread::spawn(move || match relay(client, upstream) {
+        Ok((up, down)) => eprintln!("{peer}: relayed {up} bytes up and {down} down"),
+        Err(e) => eprintln!("{peer}: {e}"),
+    });
+}
+
+/// What the relay needs of a connection, whichever stack it's on: to be split into a half
+/// for each direction, each of which can go to a thread of its own.
+pub trait Split: Send + 'static {
+    type Reader: Read + Send + 'static;
+    /// Dropping it sends a FIN, while the reader goes on reading whatever the peer has left
+    /// to send.
+    type Writer: Write + Send + 'static;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)>;
+}
+
+impl Split for trust::TcpStream {
+    type Reader = trust::ReadHalf;
+    type Writer = trust::WriteHalf;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        Ok(trust::TcpStream::split(self))
+    }
+}
+
+impl Split for std::net::TcpStream {
+    type Reader = std::net::TcpStream;
+    type Writer = StdWriteHalf;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        Ok((self.try_clone()?, StdWriteHalf(self)))
+    }
+}
+
+/// A `std::net::TcpStream` to write to, which shuts down writing when it's dropped, as a
+/// `trust::WriteHalf` does.
+pub struct StdWriteHalf(std::net::TcpStream);
+
+impl Write for StdWriteHalf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Drop for StdWriteHalf {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown(Shutdown::Write);
+    }
+}
+
+/// Relay between `client` and `upstream` until both have closed their end, returning how many
+/// bytes went up and how many came back down.
+///
+/// Each direction gets a thread of its own, which copies from one side's reader to the other
+/// side's writer, and drops the writer at EOF to pass the FIN on. A write blocks for as long
+/// as the side it's going to has no room, which pushes back on the side it came from and
+/// nothing else.
+pub fn relay(client: impl Split, upstream: impl Split) -> io::Result<(u64, u64)> {
+    let (mut client_rx, mut client_tx) = client.split()?;
+    let (mut upstream_rx, mut upstream_tx) = upstream.split()?;
+    let up = thread::spawn(move || io::copy(&mut client_rx, &mut upstream_tx));
+    let down = io::copy(&mut upstream_rx, &mut client_tx);
+    drop(client_tx);
+    let up = up.join().expect("relay thread panicked");
+    Ok((up?, down?))
+}
@@ -0,0 +1,192 @@
+//! Injects attacker-controlled TCP segments straight into a freshly accepted connection, past
+//! the IP layer, checksums and demux, so that every input reaches the state machine. After each
+//! step the sequence space has to stay sane, and a second run with header prediction off has to
+//! end up in the same state having sent the same segments.
+//!
+//! Run with `cargo fuzz run inject` from the repository root.
+
+#![no_main]
+
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, State};
+
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const LOCAL_PORT: u16 = 80;
+const PEER_PORT: u16 = 40000;
+const PEER_ISS: u32 = 1000;
+
+#[derive(Arbitrary, Debug)]
+enum Step {
+    /// whatever bytes the fuzzer likes, as a TCP header and payload
+    Raw(Vec<u8>),
+    /// a well-formed segment, with sequence numbers relative to where each side currently is
+    Segment {
+        seq: i16,
+        ack: i16,
+        flags: u8,
+        window: u16,
+        options: Vec<u8>,
+        payload: Vec<u8>,
+    },
+    /// the application writing
+    Write(u16),
+    /// the application reading
+    Read(u16),
+    /// the application closing
+    Close,
+    /// time passing
+    Advance(u16),
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    recv_window: u16,
+    steps: Vec<Step>,
+}
+
+struct Peer {
+    replay: Replay,
+    /// the same again, without header prediction
+    shadow: Replay,
+    quad: Quad,
+    /// the next sequence number the peer will send
+    seq: u32,
+    /// the next sequence number the peer expects from us
+    ack: u32,
+}
+
+impl Peer {
+    /// Do the same to both stacks, then check they still agree.
+    fn each(&mut self, mut f: impl FnMut(&mut Replay)) {
+        f(&mut self.replay);
+        f(&mut self.shadow);
+        self.replay.check_invariants();
+        self.shadow.check_invariants();
+        assert_eq!(
+            self.replay.snapshot(self.quad),
+            self.shadow.snapshot(self.quad),
+            "header prediction left the connection somewhere else"
+        );
+        let sent = self.replay.take_sent();
+        assert_eq!(
+            sent,
+            self.shadow.take_sent(),
+            "header prediction made a difference"
+        );
+        // track the highest sequence number we've sent, so relative acks land near it
+        for p in sent {
+            let (_, t, data) = parse_segment(&p);
+            let end = t
+                .sequence_number()
+                .wrapping_add(data.len() as u32 + t.syn() as u32 + t.fin() as u32);
+            if end.wrapping_sub(self.ack) < 1 << 31 {
+                self.ack = end;
+            }
+        }
+    }
+
+    fn inject(&mut self, segment: &[u8]) {
+        let quad = self.quad;
+        // errors are fine; panics are not
+        self.each(|r| {
+            let _ = r.inject(quad, segment);
+        });
+    }
+
+    fn send(&mut self, seq: u32, ack: u32, flags: u8, window: u16, options: &[u8], payload: &[u8]) {
+        let mut t = etherparse::TcpHeader::new(PEER_PORT, LOCAL_PORT, seq, window);
+        t.fin = flags & 0x01 != 0;
+        t.syn = flags & 0x02 != 0;
+        t.rst = flags & 0x04 != 0;
+        t.psh = flags & 0x08 != 0;
+        t.ack = flags & 0x10 != 0;
+        t.urg = flags & 0x20 != 0;
+        t.acknowledgment_number = ack;
+        // options have to come in whole words, and at most 40 bytes of them
+        let options = &options[..options.len().min(40) / 4 * 4];
+        if t.set_options_raw(options).is_err() {
+            return;
+        }
+        let mut v = Vec::new();
+        if t.write(&mut v).is_err() {
+            return;
+        }
+        v.extend_from_slice(&payload[..payload.len().min(1400)]);
+        self.inject(&v);
+    }
+}
+
+const ACK: u8 = 0x10;
+
+fuzz_target!(|input: Input| {
+    let config = ConnectionConfig::default()
+        .recv_window(input.recv_window)
+        .initial_sequence_number(0);
+    let mut replay = Replay::new(LOCAL);
+    replay.listen(LOCAL_PORT, config.clone());
+    let mut shadow = Replay::new(LOCAL);
+    shadow.listen(LOCAL_PORT, config.header_prediction(false));
+    let quad = Quad {
+        src: (PEER.into(), PEER_PORT),
+        dst: (LOCAL.into(), LOCAL_PORT),
+    };
+    let mut peer = Peer {
+        replay,
+        shadow,
+        quad,
+        seq: PEER_ISS,
+        ack: 0,
+    };
+
+    // the SYN has to go through the listener; the connection is there to inject into after
+    let syn = etherparse::PacketBuilder::ipv4(PEER.octets(), LOCAL.octets(), 64)
+        .tcp(PEER_PORT, LOCAL_PORT, PEER_ISS, u16::MAX)
+        .syn();
+    let mut p = Vec::new();
+    syn.write(&mut p, &[]).unwrap();
+    peer.each(|r| {
+        let _ = r.feed(&p);
+    });
+    peer.seq = peer.seq.wrapping_add(1);
+    peer.send(peer.seq, peer.ack, ACK, u16::MAX, &[], &[]);
+    if peer.replay.state(quad) != Some(State::Estab) {
+        // e.g. a zero receive window that the handshake can't get through; not interesting
+        return;
+    }
+
+    for step in input.steps {
+        match step {
+            Step::Raw(bytes) => peer.inject(&bytes),
+            Step::Segment {
+                seq,
+                ack,
+                flags,
+                window,
+                options,
+                payload,
+            } => {
+                let seq = peer.seq.wrapping_add(seq as u32);
+                let ack = peer.ack.wrapping_add(ack as u32);
+                peer.send(seq, ack, flags, window, &options, &payload);
+            }
+            Step::Write(len) => peer.each(|r| {
+                let _ = r.write(quad, &vec![7; len as usize]);
+            }),
+            Step::Read(len) => peer.each(|r| {
+                let _ = r.read(quad, len as usize);
+            }),
+            Step::Close => peer.each(|r| {
+                let _ = r.close(quad);
+            }),
+            Step::Advance(ms) => peer.each(|r| {
+                let _ = r.advance(Duration::from_millis(ms as u64));
+            }),
+        }
+    }
+});
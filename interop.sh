@@ -0,0 +1,16 @@
+#!/bin/bash
+# Run the kernel interop tests in tests/interop.rs. Setting up tun devices takes root, so the
+# tests are built as you, and only the test binary is run under sudo. Arguments are passed on
+# to it, e.g. a test name to run just that one. They're built with tcp-md5, which one of them
+# needs.
+cargo test --features tcp-md5 --test interop --no-run
+ext=$?
+if [[ $ext -ne 0 ]]; then
+        exit $ext
+fi
+bin=$(cargo test --features tcp-md5 --test interop --no-run --message-format=json 2>/dev/null |
+        grep -o '"executable":"[^"]*/interop-[^"]*"' | cut -d'"' -f4)
+if [[ $EUID -ne 0 ]]; then
+        exec sudo "$bin" --ignored "$@"
+fi
+exec "$bin" --ignored "$@"
@@ -0,0 +1,19 @@
+use std::time::Instant;
+
+/// Where the stack gets the current time from.
+///
+/// Every timer deadline is computed from this rather than from `Instant::now()` directly, so
+/// tests can substitute `testing::ManualClock` and skip through timeouts instantly.
+pub trait Clock: Send + 'static {
+    fn now(&self) -> Instant;
+}
+
+/// The real, monotonic clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
@@ -0,0 +1,263 @@
+//! Congestion control: how much a connection may have in flight, whatever the peer's window,
+//! and how that changes as ACKs come back and segments go missing.
+//!
+//! The connection does the detecting and the retransmitting; an algorithm only decides on the
+//! window, and when a loss has been recovered from. `Reno` is what connections get unless
+//! their config asks for something else; `NewReno` copes better with lossy paths.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A congestion control algorithm, one per connection.
+///
+/// Windows are in bytes, and `smss` in each event is the sender's maximum segment size at the
+/// time, for algorithms that count in segments.
+pub trait CongestionControl: Send {
+    /// How much may be in flight at once.
+    fn cwnd(&self) -> u32;
+
+    /// The slow start threshold, if the algorithm has one and it's been set. Only used to
+    /// report on the connection, to the congestion sampler.
+    fn ssthresh(&self) -> Option<u32> {
+        None
+    }
+
+    /// An ACK moved SND.UNA forward.
+    fn on_ack(&mut self, ack: &AckEvent);
+
+    /// A duplicate ACK (RFC 5681 S2) arrived, other than the one that set off `on_loss`: the
+    /// peer got another segment, just not the one it's waiting for.
+    fn on_duplicate_ack(&mut self, _ack: &AckEvent) {}
+
+    /// Three duplicate ACKs in a row say the segment at SND.UNA was lost, and it's about to be
+    /// retransmitted without waiting for the timer (fast retransmit, RFC 5681 S3.2).
+    fn on_loss(&mut self, loss: &LossEvent);
+
+    /// The retransmission timer went off, and everything from SND.UNA on is about to be sent
+    /// again.
+    fn on_rto(&mut self, loss: &LossEvent);
+
+    /// Whether we're still recovering from the last `on_loss`. An ACK that leaves us in
+    /// recovery has the segment at the new SND.UNA retransmitted straight away, and duplicate
+    /// ACKs in the meantime don't count towards another fast retransmit.
+    fn in_recovery(&self) -> bool {
+        false
+    }
+}
+
+/// An ACK, as far as congestion control is concerned.
+#[derive(Clone, Copy, Debug)]
+pub struct AckEvent {
+    pub now: Instant,
+    /// SEG.ACK
+    pub ack: u32,
+    /// bytes of data newly acknowledged; zero for a duplicate ACK, and for one that only covers
+    /// our SYN or FIN
+    pub acked: u32,
+    /// sequence space still sent but not acknowledged, after this ACK
+    pub in_flight: u32,
+    pub smss: u32,
+    /// the smoothed round-trip time, once there's been a segment to time
+    pub srtt: Option<Duration>,
+}
+
+/// A segment given up for lost, by duplicate ACKs or a timeout.
+#[derive(Clone, Copy, Debug)]
+pub struct LossEvent {
+    pub now: Instant,
+    /// sequence space that was outstanding when the loss was noticed, up to `recover`
+    pub in_flight: u32,
+    /// SND.MAX at the time: once everything up to here is acknowledged, the loss has been
+    /// recovered from (RFC 6582's "recover")
+    pub recover: u32,
+    pub smss: u32,
+}
+
+/// Reno (RFC 5681): slow start and congestion avoidance, halving the window on loss, with fast
+/// retransmit and fast recovery.
+#[derive(Clone, Debug)]
+pub struct Reno {
+    cwnd: u32,
+    /// the window grows by a segment per ACK below this, and by about a segment per round trip
+    /// above it. unbounded until something is lost.
+    ssthresh: u32,
+    /// in fast recovery since the third duplicate ACK, until new data is acknowledged
+    recovering: bool,
+}
+
+impl Reno {
+    /// Start in slow start, from a window of `initial` bytes.
+    pub fn new(initial: u32) -> Self {
+        Reno {
+            cwnd: initial,
+            ssthresh: u32::MAX,
+            recovering: false,
+        }
+    }
+}
+
+impl CongestionControl for Reno {
+    fn cwnd(&self) -> u32 {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> Option<u32> {
+        (self.ssthresh != u32::MAX).then_some(self.ssthresh)
+    }
+
+    fn on_ack(&mut self, ack: &AckEvent) {
+        if self.recovering {
+            // the retransmission made it, so take back the inflation (RFC 5681 S3.2 step 6)
+            self.recovering = false;
+            self.cwnd = self.ssthresh;
+        } else {
+            self.cwnd = grow(self.cwnd, self.ssthresh, ack);
+        }
+    }
+
+    fn on_duplicate_ack(&mut self, ack: &AckEvent) {
+        if self.recovering {
+            // another segment has left the network, so another may go in (S3.2 step 4)
+            self.cwnd = self.cwnd.saturating_add(ack.smss);
+        }
+    }
+
+    fn on_loss(&mut self, loss: &LossEvent) {
+        // S3.2 steps 2 and 3: the three segments the duplicate ACKs stand for have left too
+        self.ssthresh = std::cmp::max(loss.in_flight / 2, 2 * loss.smss);
+        self.cwnd = self.ssthresh.saturating_add(3 * loss.smss);
+        self.recovering = true;
+    }
+
+    fn on_rto(&mut self, loss: &LossEvent) {
+        // RFC 5681 S3.1 eq. 4, and back to slow start from a single segment
+        self.ssthresh = std::cmp::max(loss.in_flight / 2, 2 * loss.smss);
+        self.cwnd = loss.smss;
+        self.recovering = false;
+    }
+
+    fn in_recovery(&self) -> bool {
+        self.recovering
+    }
+}
+
+/// NewReno (RFC 6582): Reno, except that fast recovery lasts until everything that was in
+/// flight at the loss is acknowledged. An ACK for only part of it means another segment from
+/// the same window was lost too, and that one is resent at once, where Reno would leave
+/// recovery and wait for the timer.
+#[derive(Clone, Debug)]
+pub struct NewReno {
+    cwnd: u32,
+    ssthresh: u32,
+    /// while in fast recovery, the ACK that ends it
+    recover: Option<u32>,
+}
+
+impl NewReno {
+    /// Start in slow start, from a window of `initial` bytes.
+    pub fn new(initial: u32) -> Self {
+        NewReno {
+            cwnd: initial,
+            ssthresh: u32::MAX,
+            recover: None,
+        }
+    }
+}
+
+impl CongestionControl for NewReno {
+    fn cwnd(&self) -> u32 {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> Option<u32> {
+        (self.ssthresh != u32::MAX).then_some(self.ssthresh)
+    }
+
+    fn on_ack(&mut self, ack: &AckEvent) {
+        let Some(recover) = self.recover else {
+            self.cwnd = grow(self.cwnd, self.ssthresh, ack);
+            return;
+        };
+        // SEG.ACK >= recover, in wrapped terms
+        if ack.ack.wrapping_sub(recover) < 1 << 31 {
+            // a full ACK: out of recovery, without a burst from whatever the window allows
+            // (RFC 6582 S3.2 step 3, option 1)
+            self.recover = None;
+            let flight = std::cmp::max(ack.in_flight, ack.smss);
+            self.cwnd = std::cmp::min(self.ssthresh, flight.saturating_add(ack.smss));
+        } else {
+            // a partial ACK: the newly acked data has left the network, and the retransmission
+            // about to go out takes its place (step 4)
+            self.cwnd = self.cwnd.saturating_sub(ack.acked);
+            if ack.acked >= ack.smss {
+                self.cwnd = self.cwnd.saturating_add(ack.smss);
+            }
+        }
+    }
+
+    fn on_duplicate_ack(&mut self, ack: &AckEvent) {
+        if self.recover.is_some() {
+            self.cwnd = self.cwnd.saturating_add(ack.smss);
+        }
+    }
+
+    fn on_loss(&mut self, loss: &LossEvent) {
+        self.ssthresh = std::cmp::max(loss.in_flight / 2, 2 * loss.smss);
+        self.cwnd = self.ssthresh.saturating_add(3 * loss.smss);
+        self.recover = Some(loss.recover);
+    }
+
+    fn on_rto(&mut self, loss: &LossEvent) {
+        self.ssthresh = std::cmp::max(loss.in_flight / 2, 2 * loss.smss);
+        self.cwnd = loss.smss;
+        self.recover = None;
+    }
+
+    fn in_recovery(&self) -> bool {
+        self.recover.is_some()
+    }
+}
+
+/// The window after a new ACK outside of recovery, as RFC 5681 S3.1 has it.
+fn grow(cwnd: u32, ssthresh: u32, ack: &AckEvent) -> u32 {
+    if cwnd < ssthresh {
+        // slow start
+        cwnd.saturating_add(std::cmp::min(ack.acked, ack.smss))
+    } else if ack.acked > 0 {
+        // congestion avoidance, by the approximation in eq. 3
+        cwnd.saturating_add(std::cmp::max(ack.smss * ack.smss / cwnd, 1))
+    } else {
+        cwnd
+    }
+}
+
+/// Makes a connection's congestion control, given its initial window in bytes.
+#[derive(Clone)]
+pub(crate) struct Factory(Arc<dyn Fn(u32) -> Box<dyn CongestionControl> + Send + Sync>);
+
+impl Factory {
+    pub(crate) fn new<C, F>(new: F) -> Self
+    where
+        C: CongestionControl + 'static,
+        F: Fn(u32) -> C + Send + Sync + 'static,
+    {
+        Factory(Arc::new(move |initial| Box::new(new(initial))))
+    }
+
+    pub(crate) fn build(&self, initial: u32) -> Box<dyn CongestionControl> {
+        (self.0)(initial)
+    }
+}
+
+impl Default for Factory {
+    fn default() -> Self {
+        Factory::new(Reno::new)
+    }
+}
+
+impl fmt::Debug for Factory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Factory(..)")
+    }
+}
@@ -0,0 +1,89 @@
+//! The errors the stack itself raises, as opposed to ones passed up from the NIC.
+
+use std::fmt;
+use std::io;
+
+/// Why an operation on an `Interface`, `TcpListener` or `TcpStream` failed.
+///
+/// Everything in the public API returns `io::Result`, as `std::net` does, so these reach the
+/// application wrapped in an `io::Error` of the matching `kind()`. [`TcpError::from_io`] gets
+/// the original back; errors from the NIC or the OS have none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TcpError {
+    /// The interface's packet loop has stopped, so neither it nor any of its connections can
+    /// do anything more.
+    InterfaceShutDown,
+    /// Another listener already has this port.
+    PortInUse(u16),
+    /// A `ConnectionConfig` no connection could work with, and why.
+    InvalidConfig(&'static str),
+    /// The connection is gone, with nothing more specific known about why: it went idle for
+    /// too long, or the interface forgot it.
+    Aborted,
+    /// The peer reset the connection.
+    Reset,
+    /// The peer stopped acknowledging what we sent, or never answered our SYN.
+    TimedOut,
+    /// The peer answered our SYN with a RST: nothing is listening on the port.
+    Refused,
+    /// An ICMP error said the peer can't be reached. `kind` is `ConnectionRefused`,
+    /// `HostUnreachable` or `NetworkUnreachable`, and `reason` is what the message said.
+    Unreachable {
+        kind: io::ErrorKind,
+        reason: &'static str,
+    },
+    /// The stream was shut down for writing, so nothing more can be written to it.
+    SendShutDown,
+    /// The interface already has as many connections as `Interface::set_max_connections`
+    /// allows.
+    ConnectionLimit,
+}
+
+impl TcpError {
+    /// The `io::ErrorKind` this is reported as.
+    pub fn kind(&self) -> io::ErrorKind {
+        match *self {
+            TcpError::InterfaceShutDown => io::ErrorKind::NotConnected,
+            TcpError::PortInUse(_) => io::ErrorKind::AddrInUse,
+            TcpError::InvalidConfig(_) => io::ErrorKind::InvalidInput,
+            TcpError::Aborted => io::ErrorKind::ConnectionAborted,
+            TcpError::Reset => io::ErrorKind::ConnectionReset,
+            TcpError::TimedOut => io::ErrorKind::TimedOut,
+            TcpError::Refused => io::ErrorKind::ConnectionRefused,
+            TcpError::Unreachable { kind, .. } => kind,
+            TcpError::SendShutDown => io::ErrorKind::BrokenPipe,
+            TcpError::ConnectionLimit => io::ErrorKind::QuotaExceeded,
+        }
+    }
+
+    /// The `TcpError` inside `e`, if the stack is where it came from.
+    pub fn from_io(e: &io::Error) -> Option<TcpError> {
+        e.get_ref()?.downcast_ref().copied()
+    }
+}
+
+impl fmt::Display for TcpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TcpError::InterfaceShutDown => f.write_str("interface shut down"),
+            TcpError::PortInUse(port) => write!(f, "port {port} already bound"),
+            TcpError::InvalidConfig(msg) => f.write_str(msg),
+            TcpError::Aborted => f.write_str("stream was terminated unexpectedly"),
+            TcpError::Reset => f.write_str("connection reset by peer"),
+            TcpError::TimedOut => f.write_str("connection timed out"),
+            TcpError::Refused => f.write_str("connection refused"),
+            TcpError::Unreachable { reason, .. } => f.write_str(reason),
+            TcpError::SendShutDown => f.write_str("connection has been shut down for writing"),
+            TcpError::ConnectionLimit => f.write_str("too many connections"),
+        }
+    }
+}
+
+impl std::error::Error for TcpError {}
+
+impl From<TcpError> for io::Error {
+    fn from(e: TcpError) -> Self {
+        io::Error::new(e.kind(), e)
+    }
+}
@@ -0,0 +1,366 @@
+//! Optional Ethernet framing, for devices that carry frames rather than IP packets, such as a
+//! tap device or a bridge port.
+//!
+//! Everything above the NIC deals in IP packets, so this sits underneath as a `Nic` of its own:
+//! frames coming in are stripped down to the IPv4 packets in them, with ARP answered along the
+//! way, and packets going out are framed for the next hop's MAC address, looked up with ARP
+//! first if it isn't known yet. IPv6 would need neighbour discovery, which there isn't, so it
+//! isn't carried.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, MonotonicClock};
+use crate::nic::Nic;
+
+/// A MAC address.
+pub type MacAddr = [u8; 6];
+
+const BROADCAST: MacAddr = [0xff; 6];
+
+const HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+
+/// an ARP packet for IPv4 over Ethernet, the only kind there is here
+const ARP_LEN: usize = 28;
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+
+/// the most frames `recv_batch` takes from the device in one go
+const MAX_BATCH: usize = 64;
+
+/// How long a neighbour's MAC address is used for before it's asked for again.
+const NEIGHBOR_LIFETIME: Duration = Duration::from_secs(60);
+
+/// How long to wait for an answer before asking again, if there's something new to send.
+const ARP_RETRY: Duration = Duration::from_secs(1);
+
+/// How long packets wait for their next hop to be resolved before they're given up on.
+const UNRESOLVED_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How many packets wait on each next hop being resolved; past it the oldest is dropped, which
+/// TCP makes up for with a retransmission once the neighbour is known.
+const MAX_PENDING: usize = 8;
+
+/// How many next hops can be waited on at once, so a flood of SYNs from addresses that never
+/// answer ARP can't fill memory with SYN-ACKs. Past it, packets to any other are dropped.
+const MAX_UNRESOLVED: usize = 64;
+
+struct Neighbor {
+    mac: MacAddr,
+    expires: Instant,
+}
+
+/// Packets waiting for a next hop's MAC address.
+struct Unresolved {
+    packets: VecDeque<Vec<u8>>,
+    /// when the first of them was queued
+    since: Instant,
+    /// when we last asked for the hop, if we've been able to
+    asked: Option<Instant>,
+}
+
+/// Ethernet and ARP on top of `nic`, which sends and receives whole frames, so that the stack
+/// can run on it as it would on a tun device, as the host with MAC address `mac` and whichever
+/// IPv4 addresses the interface is given (see `Interface::add_address`). Those are the only
+/// ones ARP is answered for, so an interface on one needs at least one.
+///
+/// By default every destination is taken to be on the link; see `gateway` for ones that
+/// aren't.
+pub struct Ethernet<N, C = MonotonicClock> {
+    nic: N,
+    clock: C,
+    mac: MacAddr,
+    addresses: Vec<Ipv4Addr>,
+    gateway: Option<(Ipv4Addr, u32)>,
+    neighbors: HashMap<Ipv4Addr, Neighbor>,
+    unresolved: HashMap<Ipv4Addr, Unresolved>,
+    neighbor_lifetime: Duration,
+    /// where frames are put together on the way out, and read into on the way in
+    tx: Vec<u8>,
+    frames: Vec<Vec<u8>>,
+}
+
+impl<N: Nic> Ethernet<N> {
+    pub fn new(nic: N, mac: MacAddr) -> Self {
+        Self::with_clock(nic, mac, MonotonicClock)
+    }
+}
+
+impl<N: Nic, C: Clock> Ethernet<N, C> {
+    /// Like `new`, but with neighbours expiring and ARP retried by `clock`.
+    pub fn with_clock(nic: N, mac: MacAddr, clock: C) -> Self {
+        Ethernet {
+            nic,
+            clock,
+            mac,
+            addresses: Vec::new(),
+            gateway: None,
+            neighbors: HashMap::new(),
+            unresolved: HashMap::new(),
+            neighbor_lifetime: NEIGHBOR_LIFETIME,
+            tx: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Send packets for hosts outside the `prefix_len`-bit subnet of the address they're from
+    /// by way of `gateway`, rather than straight to them.
+    pub fn gateway(mut self, gateway: Ipv4Addr, prefix_len: u8) -> Self {
+        let mask = u32::MAX
+            .checked_shl(32 - u32::from(prefix_len.min(32)))
+            .unwrap_or(0);
+        self.gateway = Some((gateway, mask));
+        self
+    }
+
+    /// How long a neighbour's MAC address is used for once learned, before ARP is asked for it
+    /// again: a minute by default.
+    pub fn neighbor_lifetime(mut self, lifetime: Duration) -> Self {
+        self.neighbor_lifetime = lifetime;
+        self
+    }
+
+    /// The IPv4 packet in `frame`, if it has one for us, answering it instead if it's ARP.
+    fn receive<'a>(&mut self, frame: &'a [u8]) -> io::Result<Option<&'a [u8]>> {
+        if frame.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let dst: MacAddr = frame[..6].try_into().unwrap();
+        if dst != self.mac && dst != BROADCAST {
+            return Ok(None);
+        }
+        let payload = &frame[HEADER_LEN..];
+        match u16::from_be_bytes([frame[12], frame[13]]) {
+            ETHERTYPE_IPV4 => Ok(Some(unpadded(payload))),
+            ETHERTYPE_ARP => {
+                self.on_arp(payload)?;
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Learn from an ARP packet, answering it if it asks for one of our addresses (RFC 826).
+    fn on_arp(&mut self, arp: &[u8]) -> io::Result<()> {
+        if arp.len() < ARP_LEN || arp[..6] != [0, 1, 8, 0, 6, 4] {
+            return Ok(());
+        }
+        let op = u16::from_be_bytes([arp[6], arp[7]]);
+        let sha: MacAddr = arp[8..14].try_into().unwrap();
+        let spa = ipv4(&arp[14..18]);
+        let tpa = ipv4(&arp[24..28]);
+        // only what's addressed to us is worth remembering, but a neighbour we already know
+        // of is updated whoever it's talking to
+        let for_us = self.addresses.contains(&tpa);
+        if for_us || self.neighbors.contains_key(&spa) {
+            self.learn(spa, sha)?;
+        }
+        if for_us && op == ARP_REQUEST {
+            self.send_arp(ARP_REPLY, sha, tpa, sha, spa)?;
+        }
+        Ok(())
+    }
+
+    /// Remember that `ip` is at `mac`, and send whatever was waiting on it.
+    fn learn(&mut self, ip: Ipv4Addr, mac: MacAddr) -> io::Result<()> {
+        let now = self.clock.now();
+        if !self.neighbors.contains_key(&ip) {
+            // the only time the cache grows, so the time to clear out what's gone stale
+            self.neighbors.retain(|_, n| n.expires > now);
+        }
+        let expires = now + self.neighbor_lifetime;
+        self.neighbors.insert(ip, Neighbor { mac, expires });
+        if let Some(waiting) = self.unresolved.remove(&ip) {
+            for packet in waiting.packets {
+                self.send_frame(mac, ETHERTYPE_IPV4, &packet)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The host a packet from `src` to `dst` goes to first.
+    fn next_hop(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Ipv4Addr {
+        match self.gateway {
+            Some((gateway, mask)) if u32::from(src) & mask != u32::from(dst) & mask => gateway,
+            _ => dst,
+        }
+    }
+
+    /// Hold on to `packet` until `hop` is resolved, asking for it if it's been long enough.
+    fn resolve(&mut self, src: Ipv4Addr, hop: Ipv4Addr, packet: &[u8]) -> io::Result<()> {
+        let now = self.clock.now();
+        if !self.unresolved.contains_key(&hop) && self.unresolved.len() >= MAX_UNRESOLVED {
+            self.unresolved
+                .retain(|_, u| now.duration_since(u.since) < UNRESOLVED_TIMEOUT);
+            if self.unresolved.len() >= MAX_UNRESOLVED {
+                tracing::trace!(%hop, "too many unresolved neighbours, dropping packet");
+                return Ok(());
+            }
+        }
+        let u = self.unresolved.entry(hop).or_insert_with(|| Unresolved {
+            packets: VecDeque::new(),
+            since: now,
+            asked: None,
+        });
+        if now.duration_since(u.since) >= UNRESOLVED_TIMEOUT {
+            // nobody answered for the last lot; start over with this one
+            u.packets.clear();
+            u.since = now;
+        }
+        if u.packets.len() == MAX_PENDING {
+            u.packets.pop_front();
+        }
+        u.packets.push_back(packet.to_vec());
+        if u.asked.is_some_and(|t| now.duration_since(t) < ARP_RETRY) {
+            return Ok(());
+        }
+        u.asked = Some(now);
+        self.send_arp(ARP_REQUEST, BROADCAST, src, [0; 6], hop)
+    }
+
+    fn send_arp(
+        &mut self,
+        op: u16,
+        to: MacAddr,
+        spa: Ipv4Addr,
+        tha: MacAddr,
+        tpa: Ipv4Addr,
+    ) -> io::Result<()> {
+        let mut arp = [0; ARP_LEN];
+        arp[..6].copy_from_slice(&[0, 1, 8, 0, 6, 4]);
+        arp[6..8].copy_from_slice(&op.to_be_bytes());
+        arp[8..14].copy_from_slice(&self.mac);
+        arp[14..18].copy_from_slice(&spa.octets());
+        arp[18..24].copy_from_slice(&tha);
+        arp[24..28].copy_from_slice(&tpa.octets());
+        self.send_frame(to, ETHERTYPE_ARP, &arp)
+    }
+
+    fn send_frame(&mut self, to: MacAddr, ethertype: u16, payload: &[u8]) -> io::Result<()> {
+        self.tx.clear();
+        self.tx.extend_from_slice(&to);
+        self.tx.extend_from_slice(&self.mac);
+        self.tx.extend_from_slice(&ethertype.to_be_bytes());
+        self.tx.extend_from_slice(payload);
+        self.nic.send(&self.tx)?;
+        Ok(())
+    }
+}
+
+impl<N: Nic, C: Clock> Nic for Ethernet<N, C> {
+    /// Frame `buf`, an IPv4 packet, for its next hop. If the next hop's MAC address has to be
+    /// looked up first, the packet is queued and the send reported as done, as it would be by
+    /// a device whose queue it went into.
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < 20 || buf[0] >> 4 != 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only IPv4 goes over Ethernet",
+            ));
+        }
+        let (src, dst) = (ipv4(&buf[12..16]), ipv4(&buf[16..20]));
+        let hop = self.next_hop(src, dst);
+        let now = self.clock.now();
+        match self.neighbors.get(&hop) {
+            Some(n) if n.expires > now => {
+                let mac = n.mac;
+                self.send_frame(mac, ETHERTYPE_IPV4, buf)?;
+            }
+            _ => self.resolve(src, hop, buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut frame = self.frames.pop().unwrap_or_default();
+        frame.resize(self.nic.mtu() + HEADER_LEN, 0);
+        let result = loop {
+            let len = match self.nic.recv(&mut frame) {
+                Ok(len) => len,
+                Err(e) => break Err(e),
+            };
+            match self.receive(&frame[..len]) {
+                Ok(Some(packet)) => {
+                    let len = packet.len().min(buf.len());
+                    buf[..len].copy_from_slice(&packet[..len]);
+                    break Ok(len);
+                }
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.frames.push(frame);
+        result
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        let count = bufs.len().min(MAX_BATCH);
+        let frame_len = self.nic.mtu() + HEADER_LEN;
+        let mut frames = std::mem::take(&mut self.frames);
+        frames.resize_with(count, Vec::new);
+        let mut slices: Vec<&mut [u8]> = frames
+            .iter_mut()
+            .map(|f| {
+                f.resize(frame_len, 0);
+                f.as_mut_slice()
+            })
+            .collect();
+        let mut frame_lens = [0; MAX_BATCH];
+        let received = self.nic.recv_batch(&mut slices, &mut frame_lens[..count]);
+        drop(slices);
+        let mut n = 0;
+        let result = received.and_then(|got| {
+            for (frame, &len) in frames.iter().zip(&frame_lens[..got]) {
+                if let Some(packet) = self.receive(&frame[..len])? {
+                    let len = packet.len().min(bufs[n].len());
+                    bufs[n][..len].copy_from_slice(&packet[..len]);
+                    lens[n] = len;
+                    n += 1;
+                }
+            }
+            Ok(n)
+        });
+        self.frames = frames;
+        result
+    }
+
+    fn mtu(&self) -> usize {
+        self.nic.mtu()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.nic.poll(timeout)
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        self.nic.fd()
+    }
+
+    fn add_address(&mut self, addr: IpAddr) {
+        if let IpAddr::V4(addr) = addr
+            && !self.addresses.contains(&addr)
+        {
+            self.addresses.push(addr);
+        }
+    }
+}
+
+fn ipv4(b: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(b[0], b[1], b[2], b[3])
+}
+
+/// `payload` without any padding a short frame was given to make the minimum length, going by
+/// the total length in its IPv4 header. Anything that doesn't parse is left for the IP layer
+/// to reject.
+fn unpadded(payload: &[u8]) -> &[u8] {
+    if payload.len() < 20 {
+        return payload;
+    }
+    let total = usize::from(u16::from_be_bytes([payload[2], payload[3]]));
+    &payload[..total.clamp(20, payload.len())]
+}
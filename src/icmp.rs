@@ -0,0 +1,302 @@
+//! ICMP errors about the segments we sent, and answering pings, for both ICMPv4 and ICMPv6.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::ip;
+use crate::{Quad, TcpError};
+
+const PROTO_ICMP: u8 = 1;
+const PROTO_ICMPV6: u8 = 58;
+const PROTO_TCP: u8 = 6;
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+const ECHOV6_REQUEST: u8 = 128;
+const ECHOV6_REPLY: u8 = 129;
+
+/// How many echo replies we'll send back to back, and how often we earn another after that:
+/// enough for any ping, while a flood of them can't have us spend the link on answers.
+const ECHO_BURST: u32 = 50;
+const ECHO_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How the ICMP messages that reached the stack were handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IcmpStats {
+    /// destination-unreachable, time-exceeded and packet-too-big messages quoting a TCP
+    /// segment
+    pub received: u64,
+    /// hard errors that aborted a connection still in the handshake
+    pub aborted: u64,
+    /// errors recorded against a connection, to be reported if it later times out
+    pub soft_errors: u64,
+    /// fragmentation-needed and packet-too-big messages that lowered a connection's path MTU
+    pub mtu_reductions: u64,
+    /// path MTU black holes: paths that dropped our full-sized segments without a word, until
+    /// probing with smaller ones found a size that got through
+    pub mtu_blackholes: u64,
+    /// messages for a connection we don't have, quoting a sequence number we never sent, or
+    /// reporting an MTU no smaller than what we already use, and echo requests for an address
+    /// that isn't ours
+    pub ignored: u64,
+    /// echo requests we answered
+    pub echo_replies: u64,
+    /// echo requests left unanswered because we'd already sent as many replies as we're
+    /// allowed lately
+    pub echo_rate_limited: u64,
+    /// echo requests dropped because their checksum was wrong
+    pub bad_checksums: u64,
+}
+
+/// An ICMP message about a segment we sent.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Message {
+    /// the connection the quoted segment belongs to, in our usual peer-first order
+    pub(crate) quad: Quad,
+    /// the sequence number of the quoted segment
+    pub(crate) seq: u32,
+    pub(crate) report: Report,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Report {
+    Error(Error),
+    /// the segment was too big for some link on the path, which can carry at most this much
+    /// (RFC 1191, RFC 8201)
+    PacketTooBig(usize),
+}
+
+/// An ICMP error, reduced to what a connection needs to act on it.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Error {
+    /// whether RFC 1122 S4.2.3.9 counts it as a hard error, i.e. the peer isn't there at all
+    /// rather than just not reachable right now
+    pub(crate) hard: bool,
+    pub(crate) kind: io::ErrorKind,
+    pub(crate) msg: &'static str,
+}
+
+impl Error {
+    /// What to tell the application if the connection dies of it.
+    pub(crate) fn cause(self) -> TcpError {
+        TcpError::Unreachable {
+            kind: self.kind,
+            reason: self.msg,
+        }
+    }
+}
+
+/// How long a discovered path MTU is remembered by default. RFC 1191 S6.3 suggests ten minutes
+/// before trying a larger one again.
+const PATH_MTU_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+/// Path MTUs learned from ICMP, by destination, so new connections to a host we've already
+/// had to shrink segments for start out small enough.
+pub(crate) struct PathMtuCache {
+    entries: HashMap<IpAddr, (usize, Instant)>,
+    pub(crate) lifetime: Duration,
+}
+
+impl Default for PathMtuCache {
+    fn default() -> Self {
+        PathMtuCache {
+            entries: HashMap::new(),
+            lifetime: PATH_MTU_LIFETIME,
+        }
+    }
+}
+
+impl PathMtuCache {
+    /// The path MTU to `dst`, if we've learned one that hasn't expired.
+    pub(crate) fn get(&self, dst: IpAddr, now: Instant) -> Option<usize> {
+        self.entries
+            .get(&dst)
+            .filter(|&&(_, expires)| expires > now)
+            .map(|&(mtu, _)| mtu)
+    }
+
+    pub(crate) fn insert(&mut self, dst: IpAddr, mtu: usize, now: Instant) {
+        self.entries.insert(dst, (mtu, now + self.lifetime));
+    }
+
+    /// Forget everything that has expired.
+    pub(crate) fn prune(&mut self, now: Instant) {
+        self.entries.retain(|_, &mut (_, expires)| expires > now);
+    }
+}
+
+/// Limits how fast we answer echo requests: a burst of `ECHO_BURST`, then one every
+/// `ECHO_INTERVAL`.
+#[derive(Default)]
+pub(crate) struct EchoLimit {
+    /// replies we can send right away
+    tokens: u32,
+    /// when `tokens` was last topped up, or `None` before the first request
+    refilled: Option<Instant>,
+}
+
+impl EchoLimit {
+    /// Whether we can answer another echo request at `now`, counting the reply if so.
+    pub(crate) fn allow(&mut self, now: Instant) -> bool {
+        match self.refilled {
+            None => {
+                self.tokens = ECHO_BURST;
+                self.refilled = Some(now);
+            }
+            Some(at) => {
+                let elapsed = now.saturating_duration_since(at);
+                let earned = elapsed.as_nanos() / ECHO_INTERVAL.as_nanos();
+                if self.tokens as u128 + earned >= ECHO_BURST as u128 {
+                    self.tokens = ECHO_BURST;
+                    self.refilled = Some(now);
+                } else if earned > 0 {
+                    // keep the part of an interval we haven't been paid for yet
+                    self.tokens += earned as u32;
+                    self.refilled = Some(at + ECHO_INTERVAL * earned as u32);
+                }
+            }
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+/// Whether `protocol` is ICMP for the IP version the packet came in on.
+pub(crate) fn is_icmp(protocol: u8) -> bool {
+    protocol == PROTO_ICMP || protocol == PROTO_ICMPV6
+}
+
+/// Whether `msg` (everything after the IP header) is an echo request, i.e. a ping.
+pub(crate) fn is_echo_request(protocol: u8, msg: &[u8]) -> bool {
+    matches!(
+        (protocol, msg.first()),
+        (PROTO_ICMP, Some(&ECHO_REQUEST)) | (PROTO_ICMPV6, Some(&ECHOV6_REQUEST))
+    )
+}
+
+/// Whether the checksum of `msg`, which came from `src` to `dst`, is right.
+pub(crate) fn checksum_ok(src: IpAddr, dst: IpAddr, msg: &[u8]) -> bool {
+    msg.len() >= 4 && checksum(src, dst, msg) == 0
+}
+
+/// Write the reply to `request`, an echo request from `src` to `dst`, into `out`: the same
+/// identifier, sequence number and data, going back the other way. Returns the length of the
+/// packet, IP header included.
+pub(crate) fn echo_reply(
+    src: IpAddr,
+    dst: IpAddr,
+    request: &[u8],
+    out: &mut [u8],
+) -> io::Result<usize> {
+    let (protocol, ty) = match dst {
+        IpAddr::V4(_) => (etherparse::IpTrafficClass::Icmp, ECHO_REPLY),
+        IpAddr::V6(_) => (etherparse::IpTrafficClass::IPv6Icmp, ECHOV6_REPLY),
+    };
+    let mut ip = ip::Outgoing::with_protocol(dst, src, protocol);
+    ip.set_payload_len(request.len())?;
+    let header_len = ip.header_len();
+    let len = header_len + request.len();
+    if out.len() < len {
+        return Err(io::Error::new(
+            io::ErrorKind::WriteZero,
+            format!("{len} bytes needed for the echo reply"),
+        ));
+    }
+    ip.write(&mut &mut out[..header_len])?;
+    let reply = &mut out[header_len..len];
+    reply.copy_from_slice(request);
+    reply[0] = ty;
+    reply[2..4].fill(0);
+    let sum = checksum(dst, src, reply);
+    reply[2..4].copy_from_slice(&sum.to_be_bytes());
+    Ok(len)
+}
+
+/// The internet checksum (RFC 1071) of an ICMP message from `src` to `dst`, which for ICMPv6
+/// covers a pseudo-header too (RFC 4443 S2.3). A message with the right checksum in it sums
+/// to zero.
+fn checksum(src: IpAddr, dst: IpAddr, msg: &[u8]) -> u16 {
+    let mut sum = 0;
+    if let (IpAddr::V6(src), IpAddr::V6(dst)) = (src, dst) {
+        sum = ones_complement_sum(sum, &src.octets());
+        sum = ones_complement_sum(sum, &dst.octets());
+        sum = ones_complement_sum(sum, &(msg.len() as u32).to_be_bytes());
+        sum = ones_complement_sum(sum, &[0, 0, 0, PROTO_ICMPV6]);
+    }
+    sum = ones_complement_sum(sum, msg);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Add `bytes` to `sum` as big-endian 16-bit words, padding an odd last byte with zero.
+fn ones_complement_sum(sum: u64, bytes: &[u8]) -> u64 {
+    bytes.chunks(2).fold(sum, |sum, word| {
+        sum + u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u64
+    })
+}
+
+/// Parse an ICMP message (everything after the IP header), if it's a destination-unreachable,
+/// time-exceeded or packet-too-big message about a TCP segment. Anything else, echo requests
+/// included, is `None`.
+pub(crate) fn parse(protocol: u8, msg: &[u8]) -> Option<Message> {
+    use io::ErrorKind::*;
+
+    // type, code, checksum, and four bytes that are unused by most of these messages
+    let (&[ty, code], quoted) = (msg.first_chunk::<2>()?, msg.get(8..)?);
+    let (hard, kind, text) = match (protocol, ty, code) {
+        (PROTO_ICMP, 3, 0) => (false, NetworkUnreachable, "network unreachable"),
+        (PROTO_ICMP, 3, 1) => (false, HostUnreachable, "host unreachable"),
+        (PROTO_ICMP, 3, 2) => (true, ConnectionRefused, "protocol unreachable"),
+        (PROTO_ICMP, 3, 3) => (true, ConnectionRefused, "port unreachable"),
+        // fragmentation needed, with the next hop's MTU in the second half of the unused word
+        (PROTO_ICMP, 3, 4) => {
+            let mtu = u16::from_be_bytes([msg[6], msg[7]]);
+            return quoted_segment(quoted, Report::PacketTooBig(mtu as usize));
+        }
+        (PROTO_ICMP, 3, _) => (false, HostUnreachable, "destination unreachable"),
+        (PROTO_ICMP, 11, _) => (false, HostUnreachable, "time exceeded in transit"),
+        (PROTO_ICMPV6, 1, 0) => (false, NetworkUnreachable, "no route to destination"),
+        (PROTO_ICMPV6, 1, 4) => (true, ConnectionRefused, "port unreachable"),
+        (PROTO_ICMPV6, 1, _) => (false, HostUnreachable, "destination unreachable"),
+        (PROTO_ICMPV6, 3, _) => (false, HostUnreachable, "time exceeded in transit"),
+        (PROTO_ICMPV6, 2, 0) => {
+            let mtu = u32::from_be_bytes([msg[4], msg[5], msg[6], msg[7]]);
+            return quoted_segment(quoted, Report::PacketTooBig(mtu as usize));
+        }
+        _ => return None,
+    };
+    let err = Error {
+        hard,
+        kind,
+        msg: text,
+    };
+    quoted_segment(quoted, Report::Error(err))
+}
+
+/// Work out which of our segments `quoted` is the start of.
+fn quoted_segment(quoted: &[u8], report: Report) -> Option<Message> {
+    // the quoted packet is one of ours, of which only the first 8 bytes of TCP header are
+    // guaranteed to be there: the ports and the sequence number
+    let iph = ip::Header::parse(quoted).ok()?;
+    if iph.protocol != PROTO_TCP {
+        return None;
+    }
+    let tcp = quoted.get(iph.payload..iph.payload + 8)?;
+    let sport = u16::from_be_bytes([tcp[0], tcp[1]]);
+    let dport = u16::from_be_bytes([tcp[2], tcp[3]]);
+    Some(Message {
+        quad: Quad {
+            src: (iph.dst, dport),
+            dst: (iph.src, sport),
+        },
+        seq: u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+        report,
+    })
+}
@@ -0,0 +1,947 @@
+//! The interface's side of the stack: the packet loop that owns the NIC and every connection on
+//! it, and the demux that hands each incoming packet to the connection or listener it's for.
+//! The application talks to it through `Command`s and each connection's `tcp::Shared`.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, trace, warn};
+
+use crate::clock::Clock;
+use crate::nic::{self, Nic};
+use crate::{
+    ConnectionConfig, ConnectionInfo, IcmpStats, Quad, SegmentStats, State, TcpError, icmp, ip,
+    mirror, pcap, tcp,
+};
+
+/// Most packets the packet loop will take off the NIC before processing them.
+const BATCH_SIZE: usize = 16;
+
+/// Longest the packet loop sleeps even with no timers due, as a backstop.
+const MAX_POLL: Duration = Duration::from_secs(1);
+
+/// How often the packet loop checks in when the NIC has no fd to sleep on alongside the wakeup
+/// pipe, so that the application's requests are still picked up promptly.
+const FALLBACK_POLL: Duration = Duration::from_millis(10);
+
+/// The first of the local ports for connections we open, which run to the top of the range: the
+/// IANA dynamic ports (RFC 6335 S6).
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+/// What the `Interface` and every listener and stream from it share with the packet loop.
+pub(crate) struct InterfaceShared {
+    /// set when the `Interface` is dropped, for the packet loop to stop
+    pub(crate) terminate: AtomicBool,
+    /// requests for the packet loop, which owns the connection table
+    pub(crate) commands: mpsc::Sender<Command>,
+    pub(crate) capture: pcap::CaptureSlot,
+    pub(crate) mirror: mirror::MirrorSlot,
+    pub(crate) trace: crate::trace::TraceSlot,
+    pub(crate) wakeup: nic::Wakeup,
+}
+
+pub(crate) type InterfaceHandle = Arc<InterfaceShared>;
+
+impl InterfaceShared {
+    /// Hand `cmd` to the packet loop, and make sure it notices.
+    pub(crate) fn send(&self, cmd: Command) -> io::Result<()> {
+        self.commands.send(cmd).map_err(|_| shut_down())?;
+        self.wakeup.wake();
+        Ok(())
+    }
+}
+
+/// Everything the application asks of the packet loop other than moving data, which goes
+/// through each connection's `tcp::Shared` instead.
+pub(crate) enum Command {
+    /// listen on `port` at `local`, or at every address of ours if `None`
+    Bind {
+        local: Option<IpAddr>,
+        port: u16,
+        listener: Listener,
+        reply: mpsc::Sender<io::Result<()>>,
+    },
+    Unbind(ListenAddr),
+    /// open a connection from `local` to `remote` on a port of our choosing
+    Connect {
+        local: IpAddr,
+        remote: (IpAddr, u16),
+        config: ConnectionConfig,
+        reply: mpsc::Sender<io::Result<(Quad, Arc<tcp::Shared>)>>,
+    },
+    /// pick up a connection that's past the handshake without one
+    Restore {
+        quad: Quad,
+        established: tcp::Established,
+        config: ConnectionConfig,
+        reply: mpsc::Sender<io::Result<Arc<tcp::Shared>>>,
+    },
+    Close(Quad),
+    /// send an ACK now, rather than whenever one would have gone out
+    FlushAck(Quad),
+    Observe(tcp::StateObserver),
+    Sample(tcp::CongestionSampler),
+    /// a snapshot of the connection, if it still exists, TIME-WAIT included
+    Info(Quad, mpsc::Sender<ConnectionInfo>),
+    IcmpStats(mpsc::Sender<IcmpStats>),
+    Connections(mpsc::Sender<Vec<(Quad, ConnectionInfo)>>),
+    SegmentStats(mpsc::Sender<SegmentStats>),
+    PathMtuLifetime(Duration),
+    AddAddress(IpAddr),
+    MaxConnections(Option<usize>),
+    MaxBufferMemory(Option<usize>),
+}
+
+/// The listener that takes connections to `dst`, if any: the one bound to its address, or else
+/// the one bound to all of ours.
+fn listener_for(
+    listeners: &HashMap<ListenAddr, Listener>,
+    (addr, port): (IpAddr, u16),
+) -> Option<ListenAddr> {
+    [(Some(addr), port), (None, port)]
+        .into_iter()
+        .find(|k| listeners.contains_key(k))
+}
+
+fn not_ours() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        "not one of this interface's addresses",
+    )
+}
+
+pub(crate) fn shut_down() -> io::Error {
+    TcpError::InterfaceShutDown.into()
+}
+
+pub(crate) fn terminated() -> io::Error {
+    TcpError::Aborted.into()
+}
+
+#[derive(Default)]
+pub(crate) struct ConnectionManager {
+    /// in the middle of `process_batch`, so ACKs are being held back
+    batching: bool,
+    pub(crate) connections: HashMap<Quad, tcp::Connection>,
+    /// connections in TIME-WAIT, which are taken out of `connections` once they get there
+    pub(crate) time_wait: HashMap<Quad, tcp::TimeWait>,
+    pub(crate) listeners: HashMap<ListenAddr, Listener>,
+    pub(crate) observer: Option<tcp::StateObserver>,
+    pub(crate) sampler: Option<tcp::CongestionSampler>,
+    pub(crate) icmp: IcmpStats,
+    pub(crate) segments: SegmentStats,
+    path_mtus: icmp::PathMtuCache,
+    /// where outgoing segments are assembled, reused for every one of them
+    tx: Vec<u8>,
+    /// where to start looking for a free ephemeral port, counting from the first
+    next_port: u16,
+    /// the addresses that are ours, in the order they were added. With none, every address is
+    /// taken to be, except for answering pings.
+    addresses: Vec<IpAddr>,
+    echo_limit: icmp::EchoLimit,
+    /// how many connections there may be at once, in any state, TIME-WAIT included
+    pub(crate) max_connections: Option<usize>,
+    /// what every connection's buffers are charged to
+    pub(crate) budget: Arc<tcp::MemoryBudget>,
+}
+
+/// The address and port a listener is bound to, the address being `None` for all of ours.
+pub(crate) type ListenAddr = (Option<IpAddr>, u16);
+
+/// Everything a bound port owns: its accept queue and the config new connections inherit.
+pub(crate) struct Listener {
+    /// how many connections may be half-open or waiting to be accepted at once
+    backlog: usize,
+    config: ConnectionConfig,
+    /// connections still in the middle of the handshake
+    syn_queue: HashSet<Quad>,
+    /// established connections waiting for `accept`, shared with the `TcpListener`
+    pub(crate) queue: Arc<AcceptQueue>,
+}
+
+impl Listener {
+    pub(crate) fn new(backlog: usize, config: ConnectionConfig) -> Self {
+        Listener {
+            backlog,
+            config,
+            syn_queue: HashSet::new(),
+            queue: Arc::default(),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        // nothing will ever be added again, so don't leave `accept` waiting
+        self.queue.pending.lock().unwrap().closed = true;
+        self.queue.ready.notify_all();
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct AcceptQueue {
+    pub(crate) pending: Mutex<Pending>,
+    pub(crate) ready: Condvar,
+}
+
+#[derive(Default)]
+pub(crate) struct Pending {
+    pub(crate) streams: VecDeque<(Quad, Arc<tcp::Shared>)>,
+    /// the listener is gone, or the interface shut down
+    pub(crate) closed: bool,
+}
+
+impl AcceptQueue {
+    fn push(&self, quad: Quad, shared: Arc<tcp::Shared>) {
+        self.pending
+            .lock()
+            .unwrap()
+            .streams
+            .push_back((quad, shared));
+        self.ready.notify_one();
+    }
+
+    fn len(&self) -> usize {
+        self.pending.lock().unwrap().streams.len()
+    }
+
+    /// Forget the connections for which `keep` says no.
+    fn retain(&self, keep: impl Fn(&Quad) -> bool) {
+        self.pending
+            .lock()
+            .unwrap()
+            .streams
+            .retain(|(q, _)| keep(q));
+    }
+
+    pub(crate) fn take_all(&self) -> VecDeque<(Quad, Arc<tcp::Shared>)> {
+        std::mem::take(&mut self.pending.lock().unwrap().streams)
+    }
+}
+
+impl ConnectionManager {
+    /// Parse a packet fresh off the NIC and hand it to the connection (or listener) it's for.
+    /// `buf` is the buffer it was read into, if the connection may hold on to that.
+    pub(crate) fn dispatch<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        now: Instant,
+        packet: &[u8],
+        buf: Option<&tcp::PacketBuf>,
+    ) -> io::Result<()> {
+        self.tx.resize(nic.mtu(), 0);
+        match ip::Header::parse(packet) {
+            Ok(iph) => {
+                // anything past the IP length is link-layer padding, not payload
+                let Some(packet) = packet.get(..iph.end) else {
+                    debug!(src = %iph.src, "dropping packet shorter than its IP header says");
+                    return Ok(());
+                };
+                if icmp::is_icmp(iph.protocol) {
+                    return self.on_icmp(nic, now, &iph, &packet[iph.payload..]);
+                }
+                if iph.protocol != 0x06 {
+                    // not tcp
+                    return Ok(());
+                }
+
+                match etherparse::TcpHeaderSlice::from_slice(&packet[iph.payload..]) {
+                    Ok(tcph) => {
+                        let datai = iph.payload + tcph.slice().len();
+                        if !iph.tcp_checksum_ok(&tcph, &packet[datai..]) {
+                            debug!(src = %iph.src, "dropping segment with a bad checksum");
+                            self.segments.bad_checksums += 1;
+                            return Ok(());
+                        }
+                        let q = Quad {
+                            src: (iph.src, tcph.source_port()),
+                            dst: (iph.dst, tcph.destination_port()),
+                        };
+                        let ours = Quad {
+                            src: q.dst,
+                            dst: q.src,
+                        };
+                        if q.src == q.dst
+                            || (!self.connections.contains_key(&q)
+                                && self.connections.contains_key(&ours))
+                        {
+                            // fed to the connection, it would take its own sequence numbers
+                            // for the peer's
+                            debug!(
+                                src = %SocketAddr::new(q.src.0, q.src.1),
+                                dst = %SocketAddr::new(q.dst.0, q.dst.1),
+                                "dropping a segment of our own that looped back"
+                            );
+                            self.segments.looped_back += 1;
+                            return Ok(());
+                        }
+                        if !self.is_ours(q.dst.0) {
+                            trace!(dst = %q.dst.0, "dropping segment for an address not ours");
+                            self.segments.not_ours += 1;
+                            return Ok(());
+                        }
+                        let at_limit = self.at_connection_limit();
+                        match self.connections.entry(q) {
+                            Entry::Occupied(mut c) => {
+                                let c = c.get_mut();
+                                c.set_defer_acks(self.batching);
+                                c.on_packet(nic, &mut self.tx, now, tcph, &packet[datai..], buf)?;
+                                if c.take_mtu_blackhole() {
+                                    // as good as a packet-too-big, had the path sent one
+                                    self.icmp.mtu_blackholes += 1;
+                                    self.path_mtus.insert(q.src.0, c.mtu(), now);
+                                }
+
+                                if c.state() != State::SynRcvd {
+                                    // a finished handshake is ready for accept, the peer's FIN
+                                    // perhaps having come with the ACK that finished it; one
+                                    // reset or timed out half-way leaves nothing to accept
+                                    let open = matches!(c.state(), State::Estab | State::CloseWait);
+                                    for addr in [Some(q.dst.0), None] {
+                                        if let Some(l) = self.listeners.get_mut(&(addr, q.dst.1))
+                                            && l.syn_queue.remove(&q)
+                                        {
+                                            if open {
+                                                l.queue.push(q, c.shared());
+                                            }
+                                            break;
+                                        }
+                                    }
+                                }
+                            }
+                            Entry::Vacant(e) => {
+                                if let Some(tw) = self.time_wait.get_mut(&q) {
+                                    let seg = match tw.on_packet(
+                                        nic,
+                                        &mut self.tx,
+                                        now,
+                                        q,
+                                        &tcph,
+                                        &packet[datai..],
+                                    )? {
+                                        tcp::TimeWaitOutcome::Kept => return Ok(()),
+                                        tcp::TimeWaitOutcome::Reset(seg) => {
+                                            self.time_wait.remove(&q);
+                                            left_time_wait(&self.observer, q, Some(seg));
+                                            return Ok(());
+                                        }
+                                        tcp::TimeWaitOutcome::Reopened(seg) => seg,
+                                    };
+                                    // on to the listener, as if the quad had been free
+                                    self.time_wait.remove(&q);
+                                    left_time_wait(&self.observer, q, Some(seg));
+                                }
+                                // the destination picks the listener, if there is one: one
+                                // bound to its address, or else one bound to all of ours
+                                let Some(l) = listener_for(&self.listeners, q.dst)
+                                    .and_then(|addr| self.listeners.get_mut(&addr))
+                                else {
+                                    trace!(
+                                        port = tcph.destination_port(),
+                                        "dropping segment for a port nobody is listening on"
+                                    );
+                                    return Ok(());
+                                };
+                                if at_limit && tcph.syn() {
+                                    // as with a full backlog, the peer may get in on a retry
+                                    debug!(
+                                        port = tcph.destination_port(),
+                                        "at the connection limit; dropping SYN"
+                                    );
+                                    self.segments.connection_limit += 1;
+                                    return Ok(());
+                                }
+                                if l.syn_queue.len() + l.queue.len() >= l.backlog {
+                                    // backlog is full; drop the SYN and let the peer retry
+                                    debug!(
+                                        port = tcph.destination_port(),
+                                        "backlog full; dropping SYN"
+                                    );
+                                    return Ok(());
+                                }
+                                let config = match self.path_mtus.get(q.src.0, now) {
+                                    Some(mtu) => &l.config.clone().clamp_mtu(mtu),
+                                    None => &l.config,
+                                };
+                                if let Some(c) = tcp::Connection::accept(
+                                    nic,
+                                    &mut self.tx,
+                                    now,
+                                    config,
+                                    &self.budget,
+                                    q,
+                                    tcph,
+                                    &packet[datai..],
+                                )? {
+                                    let c = e.insert(c);
+                                    c.set_observer(self.observer.clone());
+                                    c.set_sampler(self.sampler.clone());
+                                    l.syn_queue.insert(q);
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        debug!(error = ?e, "ignoring malformed tcp packet");
+                    }
+                }
+            }
+            Err(e) => {
+                trace!(error = ?e, "ignoring non-IP packet");
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer an echo request for one of our addresses, unless we've been answering too many.
+    fn on_echo_request<N: Nic>(&mut self, nic: &mut N, now: Instant, iph: &ip::Header, msg: &[u8]) {
+        if !self.addresses.contains(&iph.dst) {
+            trace!(dst = %iph.dst, "ignoring echo request for an address that isn't ours");
+            self.icmp.ignored += 1;
+            return;
+        }
+        if !icmp::checksum_ok(iph.src, iph.dst, msg) {
+            debug!(src = %iph.src, "dropping echo request with a bad checksum");
+            self.icmp.bad_checksums += 1;
+            return;
+        }
+        if !self.echo_limit.allow(now) {
+            trace!(src = %iph.src, "not answering echo request; too many lately");
+            self.icmp.echo_rate_limited += 1;
+            return;
+        }
+        // a reply that doesn't make it out is no worse than one lost on the way, so unlike a
+        // segment it's no reason to stop the packet loop
+        match icmp::echo_reply(iph.src, iph.dst, msg, &mut self.tx)
+            .and_then(|n| nic.send(&self.tx[..n]))
+        {
+            Ok(_) => self.icmp.echo_replies += 1,
+            Err(e) => debug!(error = %e, "failed to send echo reply"),
+        }
+    }
+
+    /// Answer a ping, or hand an ICMP error to the connection it's about, if there is one.
+    fn on_icmp<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        now: Instant,
+        iph: &ip::Header,
+        msg: &[u8],
+    ) -> io::Result<()> {
+        if icmp::is_echo_request(iph.protocol, msg) {
+            self.on_echo_request(nic, now, iph, msg);
+            return Ok(());
+        }
+        let Some(msg) = icmp::parse(iph.protocol, msg) else {
+            return Ok(());
+        };
+        self.icmp.received += 1;
+        let Some(c) = self.connections.get_mut(&msg.quad) else {
+            trace!(quad = ?msg.quad, "ignoring ICMP message for an unknown connection");
+            self.icmp.ignored += 1;
+            return Ok(());
+        };
+        match msg.report {
+            icmp::Report::Error(err) => match c.on_icmp(msg.seq, err) {
+                tcp::IcmpOutcome::Aborted => self.icmp.aborted += 1,
+                tcp::IcmpOutcome::Recorded => self.icmp.soft_errors += 1,
+                tcp::IcmpOutcome::Ignored => self.icmp.ignored += 1,
+            },
+            icmp::Report::PacketTooBig(mtu) => {
+                match c.on_packet_too_big(nic, &mut self.tx, now, msg.seq, mtu)? {
+                    tcp::IcmpOutcome::Ignored => self.icmp.ignored += 1,
+                    _ => {
+                        self.icmp.mtu_reductions += 1;
+                        // so later connections to the same host don't have to find out again
+                        self.path_mtus.insert(msg.quad.src.0, c.mtu(), now);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hand `segment`, a TCP header and its payload as `quad.src` sent them, straight to what
+    /// we have of the connection for `quad`, with no IP header, checksum or listener in the
+    /// way. Nothing happens if there's no connection for `quad`.
+    #[cfg(feature = "testing")]
+    pub(crate) fn inject<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        now: Instant,
+        quad: Quad,
+        segment: &[u8],
+    ) -> io::Result<()> {
+        let tcph = etherparse::TcpHeaderSlice::from_slice(segment).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("bad TCP header: {e:?}"))
+        })?;
+        let data = &segment[tcph.slice().len()..];
+        if let Some(c) = self.connections.get_mut(&quad) {
+            return c.on_packet(nic, &mut self.tx, now, tcph, data, None);
+        }
+        if let Some(tw) = self.time_wait.get_mut(&quad) {
+            match tw.on_packet(nic, &mut self.tx, now, quad, &tcph, data)? {
+                tcp::TimeWaitOutcome::Kept => {}
+                // with no listener to take a new SYN, reopening just ends it
+                tcp::TimeWaitOutcome::Reset(seg) | tcp::TimeWaitOutcome::Reopened(seg) => {
+                    self.time_wait.remove(&quad);
+                    left_time_wait(&self.observer, quad, Some(seg));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Dispatch a burst of packets, each with the buffer it's in as for `dispatch`, but send at
+    /// most one ACK per connection for what arrived in order, once they've all been processed,
+    /// rather than one for every segment. Out-of-order segments are ACKed as they come.
+    pub(crate) fn process_batch<'a, N: Nic>(
+        &mut self,
+        nic: &mut N,
+        now: Instant,
+        packets: impl IntoIterator<Item = (&'a [u8], Option<&'a tcp::PacketBuf>)>,
+    ) -> io::Result<()> {
+        // one packet's trouble, a send that failed say, is no reason to leave the rest
+        // unprocessed; the first error is the one returned
+        self.batching = true;
+        let mut res = Ok(());
+        for (p, buf) in packets {
+            res = res.and(self.dispatch(nic, now, p, buf));
+        }
+        self.batching = false;
+
+        for c in self.connections.values_mut() {
+            c.set_defer_acks(false);
+            res = res.and(c.send_pending_ack(nic, &mut self.tx));
+        }
+        res
+    }
+
+    /// Let every connection's timers fire, forget the ones that are finished, and keep only
+    /// what's needed of the ones in TIME-WAIT until it's over. A connection whose timer can't
+    /// send what it has to doesn't hold up the rest; the first such error is returned once
+    /// they've all had their turn.
+    pub(crate) fn on_tick<N: Nic>(&mut self, nic: &mut N, now: Instant) -> io::Result<()> {
+        self.tx.resize(nic.mtu(), 0);
+        let mut res = Ok(());
+        for c in self.connections.values_mut() {
+            res = res.and(c.on_tick(nic, &mut self.tx, now));
+        }
+        let time_wait = &mut self.time_wait;
+        self.connections.retain(|q, c| {
+            if c.state() == State::TimeWait {
+                time_wait.insert(*q, c.to_time_wait(now));
+                return false;
+            }
+            !c.is_done()
+        });
+        let observer = &self.observer;
+        self.time_wait.retain(|q, tw| {
+            let over = tw.expiry() <= now;
+            if over {
+                debug!(
+                    src = %SocketAddr::new(q.src.0, q.src.1),
+                    dst = %SocketAddr::new(q.dst.0, q.dst.1),
+                    "2MSL timer expired"
+                );
+                left_time_wait(observer, *q, None);
+            }
+            !over
+        });
+        self.path_mtus.prune(now);
+        for l in self.listeners.values_mut() {
+            // forget connections that timed out before anyone accepted them
+            l.syn_queue.retain(|q| self.connections.contains_key(q));
+            l.queue.retain(|q| self.connections.contains_key(q));
+        }
+        res
+    }
+
+    /// Open a connection from `local` to `remote`, on the next ephemeral port that isn't
+    /// already taken by a listener or a connection to the same peer. A connection in
+    /// TIME-WAIT only keeps its port if the new one couldn't safely take over its quad. An
+    /// unspecified `local` is our primary address for `remote`'s IP version.
+    pub(crate) fn connect<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        now: Instant,
+        local: IpAddr,
+        remote: (IpAddr, u16),
+        config: &ConnectionConfig,
+    ) -> io::Result<(Quad, Arc<tcp::Shared>)> {
+        let local = if local.is_unspecified() {
+            self.primary_address(remote.0).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    "no address of this IP version to connect from",
+                )
+            })?
+        } else if self.is_ours(local) {
+            local
+        } else {
+            return Err(not_ours());
+        };
+        let ports = u16::MAX - FIRST_EPHEMERAL_PORT + 1;
+        for _ in 0..ports {
+            let quad = Quad {
+                src: remote,
+                dst: (local, FIRST_EPHEMERAL_PORT + self.next_port),
+            };
+            self.next_port = (self.next_port + 1) % ports;
+            if listener_for(&self.listeners, quad.dst).is_none() && self.claim(now, quad, config) {
+                let shared = self.open(nic, now, quad, config)?;
+                return Ok((quad, shared));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no ephemeral ports left for this peer",
+        ))
+    }
+
+    /// Open a connection for `quad`, which must not be in use, sending our SYN. Returns the
+    /// buffers for its stream.
+    pub(crate) fn open<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        now: Instant,
+        quad: Quad,
+        config: &ConnectionConfig,
+    ) -> io::Result<Arc<tcp::Shared>> {
+        if self.at_connection_limit() {
+            return Err(TcpError::ConnectionLimit.into());
+        }
+        self.tx.resize(nic.mtu(), 0);
+        let config = match self.path_mtus.get(quad.src.0, now) {
+            Some(mtu) => &config.clone().clamp_mtu(mtu),
+            None => config,
+        };
+        let c = tcp::Connection::connect(nic, &mut self.tx, now, config, &self.budget, quad)?;
+        let c = self.connections.entry(quad).or_insert(c);
+        c.set_observer(self.observer.clone());
+        c.set_sampler(self.sampler.clone());
+        Ok(c.shared())
+    }
+
+    /// Start off with a connection for `quad` that's already where `established` says, sending
+    /// nothing. Fails if `quad` isn't to one of our addresses, there's a connection on it
+    /// already, or `established` isn't one the connection could be in. Returns the buffers for
+    /// its stream.
+    pub(crate) fn restore<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        now: Instant,
+        quad: Quad,
+        established: &tcp::Established,
+        config: &ConnectionConfig,
+    ) -> io::Result<Arc<tcp::Shared>> {
+        if !self.is_ours(quad.dst.0) {
+            return Err(not_ours());
+        }
+        if !self.claim(now, quad, config) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "already have a connection for this quad",
+            ));
+        }
+        if self.at_connection_limit() {
+            return Err(TcpError::ConnectionLimit.into());
+        }
+        self.tx.resize(nic.mtu(), 0);
+        let c = tcp::Connection::restore(nic, now, config, &self.budget, quad, established)?;
+        let c = self.connections.entry(quad).or_insert(c);
+        c.set_observer(self.observer.clone());
+        c.set_sampler(self.sampler.clone());
+        Ok(c.shared())
+    }
+
+    /// Whether a connection of ours with `config` can be opened on `quad`: either there's
+    /// nothing there, or only a connection in TIME-WAIT that it can take over, which goes.
+    pub(crate) fn claim(&mut self, now: Instant, quad: Quad, config: &ConnectionConfig) -> bool {
+        if self.connections.contains_key(&quad) {
+            return false;
+        }
+        let Some(tw) = self.time_wait.get(&quad) else {
+            return true;
+        };
+        if !tw.reusable(config, &quad, now) {
+            return false;
+        }
+        debug!(?quad, "reusing quad still in TIME-WAIT");
+        self.time_wait.remove(&quad);
+        left_time_wait(&self.observer, quad, None);
+        true
+    }
+
+    /// Start listening on `port` at `local`, or at all our addresses if that's `None`, unless
+    /// there's a listener there already or, if `listener` doesn't reuse addresses, connections
+    /// that were on it are still in TIME-WAIT. One bound to all addresses and one bound to
+    /// just one of them can share a port, the more specific one getting its address's SYNs.
+    pub(crate) fn bind(
+        &mut self,
+        local: Option<IpAddr>,
+        port: u16,
+        listener: Listener,
+    ) -> io::Result<()> {
+        if local.is_some_and(|a| !self.is_ours(a)) {
+            return Err(not_ours());
+        }
+        let lingering = || {
+            self.time_wait
+                .keys()
+                .any(|q| q.dst.1 == port && local.is_none_or(|a| a == q.dst.0))
+        };
+        if self.listeners.contains_key(&(local, port))
+            || !listener.config.reuses_addr() && lingering()
+        {
+            return Err(TcpError::PortInUse(port).into());
+        }
+        self.listeners.insert((local, port), listener);
+        Ok(())
+    }
+
+    /// Take `addr` as one of ours. The first of each IP version is the one connections are
+    /// opened from when the application doesn't say.
+    pub(crate) fn add_address(&mut self, addr: IpAddr) {
+        if !self.addresses.contains(&addr) {
+            self.addresses.push(addr);
+        }
+    }
+
+    /// Whether segments for `addr` are for us. With no addresses of our own yet, all are.
+    pub(crate) fn is_ours(&self, addr: IpAddr) -> bool {
+        self.addresses.is_empty() || self.addresses.contains(&addr)
+    }
+
+    /// The address to open a connection to `remote` from if the application doesn't say.
+    fn primary_address(&self, remote: IpAddr) -> Option<IpAddr> {
+        self.addresses
+            .iter()
+            .copied()
+            .find(|a| a.is_ipv4() == remote.is_ipv4())
+    }
+
+    fn at_connection_limit(&self) -> bool {
+        self.max_connections
+            .is_some_and(|max| self.connections.len() + self.time_wait.len() >= max)
+    }
+
+    /// Have the connection for `quad`, if there is one, send an ACK right away.
+    pub(crate) fn flush_ack<N: Nic>(&mut self, nic: &mut N, quad: Quad) -> io::Result<()> {
+        match self.connections.get_mut(&quad) {
+            Some(c) => c.flush_ack(nic, &mut self.tx),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop listening on `addr`. Connections that were never accepted die with the listener,
+    /// each reset so the peer isn't left waiting on it; the ones that were already handed out
+    /// belong to their streams now.
+    pub(crate) fn unbind<N: Nic>(&mut self, nic: &mut N, addr: ListenAddr) {
+        let Some(mut l) = self.listeners.remove(&addr) else {
+            return;
+        };
+        let pending = l.queue.take_all().into_iter().map(|(q, _)| q);
+        for quad in l.syn_queue.drain().chain(pending) {
+            if let Some(mut c) = self.connections.remove(&quad) {
+                // a RST that doesn't make it out is as good as the peer timing out
+                let _ = c.abort(nic, &mut self.tx);
+            }
+        }
+    }
+
+    fn handle<N: Nic>(&mut self, nic: &mut N, now: Instant, cmd: Command) {
+        match cmd {
+            Command::Bind {
+                local,
+                port,
+                listener,
+                reply,
+            } => {
+                let res = self.bind(local, port, listener);
+                // the caller may have given up waiting; nothing to be done about that
+                let _ = reply.send(res);
+            }
+            Command::Unbind(addr) => self.unbind(nic, addr),
+            Command::Connect {
+                local,
+                remote,
+                config,
+                reply,
+            } => {
+                let res = self.connect(nic, now, local, remote, &config);
+                // as with a bind, nothing to be done if the caller has given up
+                let _ = reply.send(res);
+            }
+            Command::Restore {
+                quad,
+                established,
+                config,
+                reply,
+            } => {
+                let res = self.restore(nic, now, quad, &established, &config);
+                let _ = reply.send(res);
+            }
+            Command::Close(quad) => {
+                if let Some(c) = self.connections.get_mut(&quad) {
+                    c.close(now);
+                }
+            }
+            Command::FlushAck(quad) => {
+                // a failed send is logged, and the ACK owed as it was
+                let _ = self.flush_ack(nic, quad);
+            }
+            Command::Observe(observer) => {
+                for c in self.connections.values_mut() {
+                    c.set_observer(Some(observer.clone()));
+                }
+                self.observer = Some(observer);
+            }
+            Command::Sample(sampler) => {
+                for c in self.connections.values_mut() {
+                    c.set_sampler(Some(sampler.clone()));
+                }
+                self.sampler = Some(sampler);
+            }
+            Command::Info(quad, reply) => {
+                let info = match self.connections.get(&quad) {
+                    Some(c) => Some(c.info()),
+                    None => self.time_wait.get(&quad).map(|tw| tw.info()),
+                };
+                // with neither, the reply is dropped unanswered
+                if let Some(info) = info {
+                    let _ = reply.send(info);
+                }
+            }
+            Command::IcmpStats(reply) => {
+                let _ = reply.send(self.icmp);
+            }
+            Command::Connections(reply) => {
+                let all = self.connections.iter().map(|(q, c)| (*q, c.info()));
+                let time_wait = self.time_wait.iter().map(|(q, tw)| (*q, tw.info()));
+                let _ = reply.send(all.chain(time_wait).collect());
+            }
+            Command::SegmentStats(reply) => {
+                let _ = reply.send(self.segments);
+            }
+            Command::PathMtuLifetime(lifetime) => self.path_mtus.lifetime = lifetime,
+            Command::AddAddress(addr) => {
+                nic.add_address(addr);
+                self.add_address(addr);
+            }
+            Command::MaxConnections(max) => self.max_connections = max,
+            Command::MaxBufferMemory(max) => self.budget.set_limit(max),
+        }
+    }
+
+    /// How long until some connection's timers need servicing, if ever.
+    fn poll_delay(&self, now: Instant) -> Option<Duration> {
+        let time_wait = self.time_wait.values();
+        self.connections
+            .values()
+            .filter_map(|c| c.poll_delay(now))
+            .chain(time_wait.map(|tw| tw.expiry().saturating_duration_since(now)))
+            .min()
+    }
+
+    pub(crate) fn state_of(&self, quad: Quad) -> Option<State> {
+        match self.connections.get(&quad) {
+            Some(c) => Some(c.state()),
+            None => self
+                .time_wait
+                .contains_key(&quad)
+                .then_some(State::TimeWait),
+        }
+    }
+}
+
+/// Tell `observer`, if there is one, that the connection for `quad` is done with TIME-WAIT,
+/// because of `segment` or because the 2MSL wait is up.
+fn left_time_wait(
+    observer: &Option<tcp::StateObserver>,
+    quad: Quad,
+    segment: Option<tcp::SegmentSummary>,
+) {
+    if let Some(observer) = observer {
+        observer(&tcp::StateChange {
+            quad,
+            from: State::TimeWait,
+            to: State::Closed,
+            segment,
+        });
+    }
+}
+
+pub(crate) fn packet_loop<N: Nic, C: Clock>(
+    nic: N,
+    clock: C,
+    ih: InterfaceHandle,
+    commands: mpsc::Receiver<Command>,
+) -> io::Result<()> {
+    let nic = mirror::Tap::new(nic, ih.mirror.clone());
+    let mut nic = crate::trace::Tap::new(pcap::Tap::new(nic, ih.capture.clone()), ih.trace.clone());
+    // only ever touched from this thread; the application gets at it through `commands`. when
+    // it goes away, so do the connections, which tells any streams still waiting on them.
+    let mut cm = ConnectionManager::default();
+    let mut bufs: [tcp::PacketBuf; BATCH_SIZE] = Default::default();
+    let mut lens = [0; BATCH_SIZE];
+    let mut ready = false;
+    loop {
+        if ih.terminate.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        for cmd in commands.try_iter() {
+            cm.handle(&mut nic, clock.now(), cmd);
+        }
+
+        if ready {
+            // take whatever else has queued up too, so it can all be ACKed in one go
+            let mtu = nic.mtu();
+            let mut slices = bufs.each_mut().map(|b| {
+                if Arc::get_mut(b).is_none_or(|b| b.len() != mtu) {
+                    // a connection is holding on to the packet that was in it
+                    *b = Arc::new(vec![0; mtu]);
+                }
+                Arc::get_mut(b).unwrap().as_mut_slice()
+            });
+            let n = nic.recv_batch(&mut slices, &mut lens)?;
+            let packets = bufs[..n]
+                .iter()
+                .zip(&lens)
+                .map(|(b, &len)| (&b[..len], Some(b)));
+            for (p, _) in packets.clone() {
+                nic.log_in(p, |q| cm.state_of(q));
+            }
+            if let Err(e) = cm.process_batch(&mut nic, clock.now(), packets) {
+                // what couldn't be sent goes again in its own time, as if it had been lost,
+                // so a NIC that's only briefly out of buffers is no reason to stop
+                warn!(error = %e, "failed to send in answer to incoming segments");
+            }
+            nic.log_out(|q| cm.state_of(q));
+        }
+
+        let now = clock.now();
+        if let Err(e) = cm.on_tick(&mut nic, now) {
+            warn!(error = %e, "failed to send from a timer");
+        }
+        nic.log_out(|q| cm.state_of(q));
+        let delay = cm.poll_delay(now).map_or(MAX_POLL, |d| d.min(MAX_POLL));
+
+        // sleep until a packet arrives, a timer is due, or the application wakes us up
+        ready = match nic.fd() {
+            Some(fd) => {
+                let [ready, woken] = nic::poll_fds([fd, ih.wakeup.fd()], delay)?;
+                if woken {
+                    ih.wakeup.drain();
+                }
+                ready
+            }
+            None => nic.poll(delay.min(FALLBACK_POLL))?,
+        };
+    }
+}
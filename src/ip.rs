@@ -0,0 +1,202 @@
+//! The IP layer, for both versions. Everything above it only deals in addresses.
+
+use std::io;
+use std::net::IpAddr;
+
+use etherparse::SerializedSize;
+
+/// What the stack needs from an incoming packet's IP header.
+pub(crate) struct Header {
+    pub(crate) src: IpAddr,
+    pub(crate) dst: IpAddr,
+    /// the protocol of the payload, past any IPv6 extension headers
+    pub(crate) protocol: u8,
+    /// where the payload starts, past the IPv4 options if there are any
+    pub(crate) payload: usize,
+    /// where the packet ends going by its header, which can be short of where the buffer does
+    /// if the link layer padded it, or past it if the packet was cut short, as the ones quoted
+    /// in ICMP errors are
+    pub(crate) end: usize,
+}
+
+impl Header {
+    /// Parse the IP header at the start of `packet`, going by the version in its first nibble.
+    pub(crate) fn parse(packet: &[u8]) -> Result<Self, etherparse::ReadError> {
+        match packet.first().map(|b| b >> 4) {
+            Some(4) => {
+                // checks the header length (IHL) is at least the minimum, and that the total
+                // length leaves room for it
+                let iph = etherparse::Ipv4HeaderSlice::from_slice(packet)?;
+                Ok(Header {
+                    src: iph.source_addr().into(),
+                    dst: iph.destination_addr().into(),
+                    protocol: iph.protocol(),
+                    payload: iph.slice().len(),
+                    end: usize::from(iph.total_len()),
+                })
+            }
+            Some(6) => {
+                let iph = etherparse::Ipv6HeaderSlice::from_slice(packet)?;
+                // a zero length is a jumbogram's, whose real length is in an extension header;
+                // there are none of those on links we can use, so take the buffer's word for it
+                let end = match usize::from(iph.payload_length()) {
+                    0 => packet.len(),
+                    len => iph.slice().len() + len,
+                };
+                let (protocol, payload) =
+                    etherparse::Ipv6Header::skip_all_header_extensions_in_slice(
+                        &packet[iph.slice().len()..end.min(packet.len())],
+                        iph.next_header(),
+                    )?;
+                Ok(Header {
+                    src: iph.source_addr().into(),
+                    dst: iph.destination_addr().into(),
+                    protocol,
+                    payload: end.min(packet.len()) - payload.len(),
+                    end,
+                })
+            }
+            Some(v) => Err(etherparse::ReadError::IpUnsupportedVersion(v)),
+            None => Err(etherparse::ReadError::UnexpectedEndOfSlice(1)),
+        }
+    }
+
+    /// Whether the checksum of `tcph` and `payload`, the TCP segment this header came on, is
+    /// right. The pseudo-header it covers is made from the addresses and the segment's own
+    /// length, so IPv4 options and IPv6 extension headers don't come into it.
+    pub(crate) fn tcp_checksum_ok(
+        &self,
+        tcph: &etherparse::TcpHeaderSlice,
+        payload: &[u8],
+    ) -> bool {
+        let sum = match (self.src, self.dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                tcph.calc_checksum_ipv4_raw(&src.octets(), &dst.octets(), payload)
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                tcph.calc_checksum_ipv6_raw(&src.octets(), &dst.octets(), payload)
+            }
+            _ => unreachable!("addresses of different versions"),
+        };
+        sum.is_ok_and(|sum| sum == tcph.checksum())
+    }
+}
+
+/// The IP header a connection puts on everything it sends.
+pub(crate) enum Outgoing {
+    V4(etherparse::Ipv4Header),
+    V6(etherparse::Ipv6Header),
+}
+
+impl Outgoing {
+    /// A header for TCP from `src` to `dst`, which are the same version since they came off
+    /// the same incoming packet.
+    pub(crate) fn new(src: IpAddr, dst: IpAddr) -> Self {
+        Outgoing::with_protocol(src, dst, etherparse::IpTrafficClass::Tcp)
+    }
+
+    /// A header for `protocol` from `src` to `dst`, for the little we send that isn't TCP.
+    pub(crate) fn with_protocol(
+        src: IpAddr,
+        dst: IpAddr,
+        protocol: etherparse::IpTrafficClass,
+    ) -> Self {
+        match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => Outgoing::V4(etherparse::Ipv4Header::new(
+                0,
+                64,
+                protocol,
+                src.octets(),
+                dst.octets(),
+            )),
+            (IpAddr::V6(src), IpAddr::V6(dst)) => Outgoing::V6(etherparse::Ipv6Header {
+                traffic_class: 0,
+                flow_label: 0,
+                payload_length: 0,
+                next_header: protocol as u8,
+                hop_limit: 64,
+                source: src.octets(),
+                destination: dst.octets(),
+            }),
+            _ => unreachable!("addresses of different versions"),
+        }
+    }
+
+    /// The smallest path MTU we'll believe an ICMP message about: the minimum every IPv6 link
+    /// must carry, and for IPv4 the 576 every host must accept, rather than the 68 that's
+    /// technically allowed, so a forged message can't shrink our segments to nothing.
+    pub(crate) fn min_mtu(&self) -> usize {
+        match self {
+            Outgoing::V4(_) => 576,
+            Outgoing::V6(_) => 1280,
+        }
+    }
+
+    pub(crate) fn header_len(&self) -> usize {
+        match self {
+            Outgoing::V4(ip) => ip.header_len(),
+            Outgoing::V6(_) => etherparse::Ipv6Header::SERIALIZED_SIZE,
+        }
+    }
+
+    /// Fails if `len` doesn't fit in the header's length field.
+    pub(crate) fn set_payload_len(&mut self, len: usize) -> io::Result<()> {
+        match self {
+            Outgoing::V4(ip) => ip.set_payload_len(len),
+            Outgoing::V6(ip) => ip.set_payload_length(len),
+        }
+        .map_err(value_error)
+    }
+
+    /// Set the IPv4 identification field. IPv6 only has one in the fragment header, which we
+    /// never send.
+    pub(crate) fn set_identification(&mut self, id: u16) {
+        if let Outgoing::V4(ip) = self {
+            ip.identification = id;
+        }
+    }
+
+    /// Set the IPv4 don't-fragment bit. IPv6 routers never fragment, so there's nothing to
+    /// set there.
+    pub(crate) fn set_dont_fragment(&mut self, df: bool) {
+        if let Outgoing::V4(ip) = self {
+            ip.dont_fragment = df;
+        }
+    }
+
+    /// The TCP checksum of `tcph` and `payload`, over this header's pseudo-header.
+    /// Fails if the segment is too long for the pseudo-header's length field.
+    pub(crate) fn tcp_checksum(
+        &self,
+        tcph: &etherparse::TcpHeader,
+        payload: &[u8],
+    ) -> io::Result<u16> {
+        match self {
+            Outgoing::V4(ip) => tcph.calc_checksum_ipv4(ip, payload),
+            Outgoing::V6(ip) => tcph.calc_checksum_ipv6(ip, payload),
+        }
+        .map_err(value_error)
+    }
+
+    /// Fails if `w` runs out of room, or the header has fields that can't be represented.
+    pub(crate) fn write(&self, w: &mut impl io::Write) -> io::Result<()> {
+        match self {
+            Outgoing::V4(ip) => ip.write(w),
+            Outgoing::V6(ip) => ip.write(w),
+        }
+        .map_err(|e| match e {
+            etherparse::WriteError::IoError(e) => e,
+            etherparse::WriteError::ValueError(e) => value_error(e),
+            etherparse::WriteError::SliceTooSmall(n) => io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("{n} bytes needed for the IP header"),
+            ),
+        })
+    }
+}
+
+/// A header field etherparse couldn't fit a value into. Only ever our own doing, but the
+/// caller is better placed than we are to decide what to do about that.
+fn value_error(e: etherparse::ValueError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{e:?}"))
+}
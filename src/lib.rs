@@ -0,0 +1,1051 @@
+//! A userspace TCP stack, serving connections that arrive on a tun device (or any other
+//! [`Nic`]) through an API shaped like `std::net`'s.
+//!
+//! An [`Interface`] owns the device and a thread that runs the protocol. Binding a port on it
+//! gives a [`TcpListener`], whose accepted connections are [`TcpStream`]s that implement
+//! `Read` and `Write`; [`Interface::connect`] opens one to a peer instead. An echo server
+//! looks like this (`examples/echo.rs` is a complete one):
+//!
+//! ```no_run
+//! use std::io::{self, Read, Write};
+//! use std::thread;
+//!
+//! fn main() -> io::Result<()> {
+//!     let mut iface = trust::Interface::new()?;
+//!     let mut listener = iface.bind(7)?;
+//!     while let Ok(mut stream) = listener.accept() {
+//!         thread::spawn(move || -> io::Result<()> {
+//!             let mut buf = [0; 1500];
+//!             loop {
+//!                 let n = stream.read(&mut buf)?;
+//!                 if n == 0 {
+//!                     return Ok(());
+//!                 }
+//!                 stream.write_all(&buf[..n])?;
+//!             }
+//!         });
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Errors are `io::Error`s as in `std::net`; the ones the stack raises itself carry a
+//! [`TcpError`]. Per-connection settings go in a [`ConnectionConfig`] given to
+//! [`Interface::bind_with_config`], and [`TcpStream::info`] reports what was negotiated.
+//! With the `testing` feature, `testing` drives the stack without a device, for tests.
+
+use std::io::{self, prelude::*};
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, mpsc};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::error;
+
+mod clock;
+mod congestion;
+mod error;
+mod ether;
+mod icmp;
+mod iface;
+mod ip;
+#[cfg(feature = "tcp-md5")]
+mod md5;
+mod mirror;
+mod nic;
+pub mod pcap;
+mod raw;
+mod split;
+mod tcp;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trace;
+
+pub use clock::{Clock, MonotonicClock};
+pub use congestion::{AckEvent, CongestionControl, LossEvent, NewReno, Reno};
+pub use error::TcpError;
+pub use ether::{Ethernet, MacAddr};
+pub use icmp::IcmpStats;
+pub use nic::{Nic, Tun};
+pub use raw::RawSocket;
+pub use split::{ReadHalf, WriteHalf};
+pub use tcp::{
+    CongestionSample, ConnectionConfig, ConnectionInfo, Established, MtuProbing, Payload,
+    SegmentSummary, State, StateChange,
+};
+
+use iface::{
+    AcceptQueue, Command, InterfaceHandle, InterfaceShared, Listener, packet_loop, shut_down,
+    terminated,
+};
+
+const DEFAULT_BACKLOG: usize = 128;
+
+/// The addresses and ports that identify a connection.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct Quad {
+    /// the peer's end
+    pub src: (IpAddr, u16),
+    /// our end
+    pub dst: (IpAddr, u16),
+}
+
+/// TCP segments the stack dropped before they got to a connection or listener, by reason.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SegmentStats {
+    /// segments of our own that came back to us, as they can with some tun setups: ones from
+    /// the local end of a connection to its peer, and ones addressed from and to the same port
+    pub looped_back: u64,
+    /// SYNs for new connections that would have taken the interface past its connection
+    /// limit
+    pub connection_limit: u64,
+    /// segments for an address that isn't one of the interface's, once it has any
+    pub not_ours: u64,
+    /// segments whose TCP checksum was wrong
+    pub bad_checksums: u64,
+}
+
+/// A network device with a TCP stack running on it. Dropping it stops the stack, after which
+/// its listeners and streams fail with `TcpError::InterfaceShutDown`.
+pub struct Interface {
+    ih: Option<InterfaceHandle>,
+    jh: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl Drop for Interface {
+    fn drop(&mut self) {
+        let ih = self.ih.as_ref().unwrap();
+        ih.terminate.store(true, Ordering::Release);
+        ih.wakeup.wake();
+
+        drop(self.ih.take());
+        let jh = self.jh.take().expect("interface dropped more than once");
+        match jh.join() {
+            Ok(Ok(())) => {}
+            // nobody's left to hand it to, and panicking in a drop helps no one
+            Ok(Err(e)) => error!(error = %e, "packet loop failed"),
+            Err(_) => error!("packet loop panicked"),
+        }
+    }
+}
+
+impl Interface {
+    /// Run the stack on the tun device `tun0`.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self::with_nic(Tun::open("tun0")?))
+    }
+
+    /// Run the stack on top of an arbitrary `Nic` instead of `tun0`, e.g. a `RawSocket` where
+    /// tun isn't available, a `Tun` with a different name, or an `Ethernet` on a tap device.
+    pub fn with_nic<N: Nic + Send + 'static>(nic: N) -> Self {
+        Self::with_clock(nic, MonotonicClock)
+    }
+
+    /// Like `with_nic`, but with all timers driven by `clock`.
+    pub fn with_clock<N: Nic + Send + 'static, C: Clock>(nic: N, clock: C) -> Self {
+        let (commands, rx) = mpsc::channel();
+        let ih: InterfaceHandle = Arc::new(InterfaceShared {
+            terminate: AtomicBool::new(false),
+            commands,
+            capture: Default::default(),
+            mirror: Default::default(),
+            trace: Arc::new(Mutex::new(trace::Tracer::from_env())),
+            wakeup: Default::default(),
+        });
+
+        let jh = {
+            let ih = ih.clone();
+            thread::spawn(move || packet_loop(nic, clock, ih, rx))
+        };
+
+        Interface {
+            ih: Some(ih),
+            jh: Some(jh),
+        }
+    }
+
+    /// Register a callback that's invoked for every connection state transition, e.g.
+    /// `SynRcvd -> Estab`. It runs on the packet processing thread, so it should be quick;
+    /// forwarding into an `mpsc::Sender` is a good fit.
+    ///
+    /// Replaces any previously registered observer.
+    pub fn on_state_change<F>(&mut self, f: F)
+    where
+        F: Fn(&StateChange) + Send + Sync + 'static,
+    {
+        let observer: tcp::StateObserver = Arc::new(f);
+        // if the packet loop has died there's nothing left to observe
+        let _ = self.ih.as_ref().unwrap().send(Command::Observe(observer));
+    }
+
+    /// Register a callback that's handed a timestamped `CongestionSample` every time a
+    /// connection processes an ACK, for studying congestion dynamics. Like the state observer
+    /// it runs on the packet processing thread, and it runs far more often, so it should do
+    /// little more than record the sample. Without one, nothing is sampled.
+    ///
+    /// Replaces any previously registered sampler.
+    pub fn on_congestion_sample<F>(&mut self, f: F)
+    where
+        F: Fn(&CongestionSample) + Send + Sync + 'static,
+    {
+        let sampler: tcp::CongestionSampler = Arc::new(f);
+        let _ = self.ih.as_ref().unwrap().send(Command::Sample(sampler));
+    }
+
+    /// Claim `addr` as one of this interface's own, so echo requests (pings) to it are
+    /// answered. Until the interface has an address, every address is taken to be its own for
+    /// TCP, and a listener accepts on whatever address the SYN was sent to. From then on,
+    /// segments for any other address are dropped (see `SegmentStats::not_ours`), and the
+    /// first address of each IP version is the one `connect` uses when not told which.
+    pub fn add_address(&mut self, addr: IpAddr) {
+        let _ = self.ih.as_ref().unwrap().send(Command::AddAddress(addr));
+    }
+
+    /// How many ICMP errors about our connections have come in, and what was done about
+    /// them, and how many pings were answered.
+    pub fn icmp_stats(&self) -> io::Result<IcmpStats> {
+        let (tx, rx) = mpsc::channel();
+        self.ih.as_ref().unwrap().send(Command::IcmpStats(tx))?;
+        rx.recv().map_err(|_| shut_down())
+    }
+
+    /// Every connection the interface has, including any closing or lingering in TIME-WAIT,
+    /// with where each one stands. This is a snapshot taken by the packet loop, so nothing is
+    /// left locked while it's looked through.
+    pub fn connections(&self) -> io::Result<impl Iterator<Item = (Quad, ConnectionInfo)>> {
+        let (tx, rx) = mpsc::channel();
+        self.ih.as_ref().unwrap().send(Command::Connections(tx))?;
+        rx.recv().map(Vec::into_iter).map_err(|_| shut_down())
+    }
+
+    /// How many incoming segments were dropped before reaching a connection, and why.
+    pub fn segment_stats(&self) -> io::Result<SegmentStats> {
+        let (tx, rx) = mpsc::channel();
+        self.ih.as_ref().unwrap().send(Command::SegmentStats(tx))?;
+        rx.recv().map_err(|_| shut_down())
+    }
+
+    /// How long a path MTU learned from ICMP is remembered for new connections to the same
+    /// host, ten minutes by default. Only affects path MTUs learned from now on.
+    pub fn set_path_mtu_lifetime(&mut self, lifetime: Duration) {
+        let _ = self
+            .ih
+            .as_ref()
+            .unwrap()
+            .send(Command::PathMtuLifetime(lifetime));
+    }
+
+    /// Allow at most `max` connections at once, in any state, or any number with `None`, which
+    /// is the default. Past it, SYNs for new connections are dropped, and counted in
+    /// `segment_stats`, until some close; `connect` fails with `TcpError::ConnectionLimit`.
+    /// Going below how many there are already closes none of them.
+    pub fn set_max_connections(&mut self, max: Option<usize>) {
+        let _ = self.ih.as_ref().unwrap().send(Command::MaxConnections(max));
+    }
+
+    /// Limit the memory connections' buffers may take up between them to `max` bytes, or take
+    /// the limit away with `None`, the default. What counts is everything sent and not yet
+    /// acknowledged, everything received and not yet read, and the receive windows advertised.
+    /// Past the limit, writes only take what there's room for, and windows advertise no more
+    /// than there's room for, though every connection keeps a few KB of each so none of them
+    /// stall.
+    pub fn set_max_buffer_memory(&mut self, max: Option<usize>) {
+        let _ = self
+            .ih
+            .as_ref()
+            .unwrap()
+            .send(Command::MaxBufferMemory(max));
+    }
+
+    /// Append every packet received from or sent to the NIC to a pcap file at `path`,
+    /// replacing any capture already in progress.
+    ///
+    /// With `max_size`, the file is rotated to `<path>.1` once it would grow past that many
+    /// bytes, so a long-running capture keeps at most two files' worth of packets.
+    pub fn capture(&mut self, path: impl AsRef<Path>, max_size: Option<u64>) -> io::Result<()> {
+        let capture = pcap::Capture::create(path.as_ref(), max_size)?;
+        let old = self
+            .ih
+            .as_mut()
+            .unwrap()
+            .capture
+            .lock()
+            .unwrap()
+            .replace(capture);
+        // flushing the old capture may take a moment; don't hold up the packet path for it
+        drop(old);
+        Ok(())
+    }
+
+    /// Stop capturing, flushing anything still buffered to disk.
+    pub fn stop_capture(&mut self) {
+        let old = self.ih.as_mut().unwrap().capture.lock().unwrap().take();
+        drop(old);
+    }
+
+    /// Send a copy of every packet received from or sent to the NIC out of `mirror` too, e.g.
+    /// a second `Tun` to run tcpdump on, replacing any mirror already set. Nothing is ever read
+    /// from it, and a packet it fails to send is just left out.
+    ///
+    /// The kernel takes whatever we send out of a tun device as arriving on it, so a mirror
+    /// device should have no addresses, and strict reverse path filtering (`rp_filter=1`) to
+    /// keep the kernel from taking the copies of what we send for packets to itself.
+    pub fn mirror(&mut self, mirror: impl Nic + Send + 'static) {
+        let old = self
+            .ih
+            .as_mut()
+            .unwrap()
+            .mirror
+            .lock()
+            .unwrap()
+            .replace(Box::new(mirror));
+        drop(old);
+    }
+
+    /// Stop mirroring, and close the mirror.
+    pub fn stop_mirror(&mut self) {
+        let old = self.ih.as_mut().unwrap().mirror.lock().unwrap().take();
+        drop(old);
+    }
+
+    /// Print a tcpdump-style line to `sink` for every segment sent or received, followed by a
+    /// hexdump of up to `hexdump` bytes of its payload. See the `trace` module for the format.
+    /// Replaces any trace already in progress.
+    ///
+    /// Setting `TRUST_TRACE` in the environment starts a trace to stderr when the interface is
+    /// created, hexdumping as many bytes as its value says.
+    pub fn trace(&mut self, sink: impl Write + Send + 'static, hexdump: usize) {
+        let tracer = trace::Tracer::new(Box::new(sink), hexdump);
+        *self.ih.as_mut().unwrap().trace.lock().unwrap() = Some(tracer);
+    }
+
+    /// Stop the trace in progress, if any, as started by `trace` or `TRUST_TRACE`.
+    pub fn stop_trace(&mut self) {
+        self.ih.as_mut().unwrap().trace.lock().unwrap().take();
+    }
+
+    /// Listen for connections to `port`, with the default config. Fails with
+    /// `TcpError::PortInUse` if there's already a listener on it, or if connections that were on
+    /// it are still in TIME-WAIT and the config doesn't allow reusing it (see
+    /// `ConnectionConfig::reuse_addr`).
+    pub fn bind(&mut self, port: u16) -> io::Result<TcpListener> {
+        self.bind_with_config(port, DEFAULT_BACKLOG, ConnectionConfig::default())
+    }
+
+    /// Like `bind`, but with an explicit accept queue length and the config that every
+    /// connection accepted on this port will use.
+    pub fn bind_with_config(
+        &mut self,
+        port: u16,
+        backlog: usize,
+        config: ConnectionConfig,
+    ) -> io::Result<TcpListener> {
+        self.listen(None, port, backlog, config)
+    }
+
+    /// Like `bind`, but only for connections to `addr`'s IP address, which has to be one of
+    /// the interface's (see `add_address`), unless it's the unspecified address, which is the
+    /// same as `bind`. A listener on a port at one address and one on it at all of them can
+    /// be there together, the first taking the connections to its address.
+    pub fn bind_addr(&mut self, addr: SocketAddr) -> io::Result<TcpListener> {
+        self.bind_addr_with_config(addr, DEFAULT_BACKLOG, ConnectionConfig::default())
+    }
+
+    /// `bind_addr` with the accept queue length and config of `bind_with_config`.
+    pub fn bind_addr_with_config(
+        &mut self,
+        addr: SocketAddr,
+        backlog: usize,
+        config: ConnectionConfig,
+    ) -> io::Result<TcpListener> {
+        let local = (!addr.ip().is_unspecified()).then(|| addr.ip());
+        self.listen(local, addr.port(), backlog, config)
+    }
+
+    fn listen(
+        &mut self,
+        local: Option<IpAddr>,
+        port: u16,
+        backlog: usize,
+        config: ConnectionConfig,
+    ) -> io::Result<TcpListener> {
+        config.validate()?;
+        let ih = self.ih.as_ref().unwrap();
+        let listener = Listener::new(backlog, config);
+        let queue = listener.queue.clone();
+        let (reply, rx) = mpsc::channel();
+        ih.send(Command::Bind {
+            local,
+            port,
+            listener,
+            reply,
+        })?;
+        rx.recv().map_err(|_| shut_down())??;
+        Ok(TcpListener {
+            local,
+            port,
+            queue,
+            h: ih.clone(),
+        })
+    }
+
+    /// Open a connection to `remote` from `local`, which has to be an address the NIC's
+    /// traffic is routed to, and one of the interface's if it has any, with the default
+    /// config. The unspecified address (`0.0.0.0` or `::`) stands for the first of the
+    /// interface's addresses of that IP version. Blocks until the handshake is done, and
+    /// fails with `TcpError::Refused` if the peer resets the connection instead, or
+    /// `TcpError::TimedOut` if it doesn't answer within the handshake timeout.
+    pub fn connect(&mut self, local: IpAddr, remote: SocketAddr) -> io::Result<TcpStream> {
+        self.connect_with_config(local, remote, ConnectionConfig::default())
+    }
+
+    /// Like `connect`, but with the given config for the connection.
+    pub fn connect_with_config(
+        &mut self,
+        local: IpAddr,
+        remote: SocketAddr,
+        config: ConnectionConfig,
+    ) -> io::Result<TcpStream> {
+        let stream = self.open(local, remote, config)?;
+        stream.wait_connected()?;
+        Ok(stream)
+    }
+
+    /// Pick up a connection from `local` to `remote` that's already past the handshake, as
+    /// `established` describes it, without one: one moved over from another stack, say.
+    /// Nothing is sent until there's something to send, so the peer needn't notice. Fails
+    /// with `AddrInUse` if there's a connection between the two already, and `InvalidInput` if
+    /// `established` isn't in a state a connection could be picked up in; see `Established`
+    /// for what else it has to get right.
+    pub fn restore(
+        &mut self,
+        local: SocketAddr,
+        remote: SocketAddr,
+        established: &Established,
+        config: ConnectionConfig,
+    ) -> io::Result<TcpStream> {
+        config.validate()?;
+        let quad = Quad {
+            src: (remote.ip(), remote.port()),
+            dst: (local.ip(), local.port()),
+        };
+        let ih = self.ih.as_ref().unwrap();
+        let (reply, rx) = mpsc::channel();
+        ih.send(Command::Restore {
+            quad,
+            established: *established,
+            config,
+            reply,
+        })?;
+        let shared = rx.recv().map_err(|_| shut_down())??;
+        Ok(TcpStream::new(quad, ih.clone(), shared))
+    }
+
+    /// Like `connect`, but with `data` sent as soon as the connection is established, as the
+    /// first thing on it. As much of it as fits in the send queue goes out with the ACK that
+    /// completes the handshake, and the rest as `write_all` would send it.
+    pub fn connect_and_send(
+        &mut self,
+        local: IpAddr,
+        remote: SocketAddr,
+        data: &[u8],
+    ) -> io::Result<TcpStream> {
+        let mut stream = self.open(local, remote, ConnectionConfig::default())?;
+        let n = stream.with_buffers(|b| b.queue_send(data))??;
+        stream.wait_connected()?;
+        stream.write_all(&data[n..])?;
+        Ok(stream)
+    }
+
+    /// Have the packet loop open a connection and send our SYN, without waiting for an answer.
+    fn open(
+        &mut self,
+        local: IpAddr,
+        remote: SocketAddr,
+        config: ConnectionConfig,
+    ) -> io::Result<TcpStream> {
+        config.validate()?;
+        if local.is_ipv4() != remote.is_ipv4() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "local and remote addresses are different IP versions",
+            ));
+        }
+        let ih = self.ih.as_ref().unwrap();
+        let (reply, rx) = mpsc::channel();
+        ih.send(Command::Connect {
+            local,
+            remote: (remote.ip(), remote.port()),
+            config,
+            reply,
+        })?;
+        let (quad, shared) = rx.recv().map_err(|_| shut_down())??;
+        Ok(TcpStream::new(quad, ih.clone(), shared))
+    }
+}
+
+/// A port being listened on. Connections to it are only accepted while this exists.
+pub struct TcpListener {
+    /// the address it's bound to, or `None` for all of them
+    local: Option<IpAddr>,
+    port: u16,
+    queue: Arc<AcceptQueue>,
+    h: InterfaceHandle,
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        // if the packet loop is gone, so is the listener
+        let _ = self.h.send(Command::Unbind((self.local, self.port)));
+    }
+}
+
+impl TcpListener {
+    /// Block until a connection has completed the three-way handshake, and return it.
+    pub fn accept(&mut self) -> io::Result<TcpStream> {
+        let mut pending = self.queue.pending.lock().unwrap();
+        loop {
+            if let Some((quad, shared)) = pending.streams.pop_front() {
+                return Ok(TcpStream::new(quad, self.h.clone(), shared));
+            }
+            if pending.closed {
+                return Err(shut_down());
+            }
+
+            pending = self.queue.ready.wait(pending).unwrap();
+        }
+    }
+}
+
+/// A connection, accepted by a `TcpListener` or opened with `Interface::connect`. Dropping it
+/// closes the connection. It can be `split` into halves for reading and writing.
+///
+/// Cloning it gives another handle on the same connection, which can go to another thread.
+/// Reads and writes from different handles take turns, and the connection only closes once
+/// the last of them is dropped, or one of them calls `close`. If more than one is blocked
+/// reading, they're all woken when data arrives, and whichever gets to it first takes it; the
+/// rest go back to waiting. Each handle has its own timeouts, and what `fill_buf` hands out
+/// is only for the handle that called it.
+pub struct TcpStream {
+    quad: Quad,
+    h: InterfaceHandle,
+    /// the connection's buffers; everything else about it lives with the packet loop
+    shared: Arc<tcp::Shared>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    nonblocking: bool,
+    /// what `fill_buf` took out of the receive buffer that hasn't been consumed yet, which
+    /// comes before anything still in there
+    unread: Payload,
+    /// which sides of the connection this stream has, and shuts down if it's the last to have
+    /// them when it's dropped: both, unless it's one of the halves of a split stream
+    reads: bool,
+    writes: bool,
+}
+
+impl Clone for TcpStream {
+    fn clone(&self) -> Self {
+        if self.reads {
+            self.shared.readers.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.writes {
+            self.shared.writers.fetch_add(1, Ordering::Relaxed);
+        }
+        TcpStream {
+            quad: self.quad,
+            h: self.h.clone(),
+            shared: self.shared.clone(),
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            nonblocking: self.nonblocking,
+            unread: Payload::default(),
+            reads: self.reads,
+            writes: self.writes,
+        }
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        if self.reads && self.shared.readers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let _ = self.shutdown(std::net::Shutdown::Read);
+        }
+        if self.writes && self.shared.writers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // if the packet loop is gone, so is the connection
+            let _ = self.h.send(Command::Close(self.quad));
+        }
+        // the packet loop forgets the connection once the close has run its course, to CLOSED
+        // or into TIME-WAIT
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(n) = self.read_unread(|mut u| u.read(buf)) {
+            return Ok(n);
+        }
+        self.read_with(buf.is_empty(), |b| b.read(buf))
+    }
+
+    /// Fills `bufs` in order from the receive buffer, as much as there is at once, blocking
+    /// only if there's nothing at all to read, like `read`.
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        if let Some(n) = self.read_unread(|mut u| u.read_vectored(bufs)) {
+            return Ok(n);
+        }
+        let empty = bufs.iter().all(|b| b.is_empty());
+        self.read_with(empty, |b| b.read_vectored(bufs))
+    }
+}
+
+/// Line-oriented reading straight from the receive buffer, with no `BufReader` copying
+/// everything once more. `fill_buf` blocks like `read`, and then hands out the next run of
+/// received data where it is, without copying it: a segment's payload, in the buffer it
+/// arrived in, if the connection doesn't copy what it receives (see
+/// `ConnectionConfig::zero_copy`), and otherwise as much of the receive buffer as lies in one
+/// piece from the front. A line can run across any number of them, which `read_line` and the
+/// like take care of.
+impl BufRead for TcpStream {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.unread.is_empty() {
+            self.unread = self.recv_bytes()?;
+        }
+        Ok(&self.unread)
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.unread.advance(n);
+    }
+}
+
+impl TcpStream {
+    /// Like `read`, but hand over the next segment's worth of received data as it is, without
+    /// copying it, if the connection was set up with `ConnectionConfig::zero_copy`. Otherwise
+    /// it's everything received so far, or what comes before the end of the receive buffer if
+    /// it runs around that, and that isn't copied either. Empty at end of file. Anything
+    /// `fill_buf` handed out that hasn't been consumed comes first, on its own.
+    pub fn recv_bytes(&mut self) -> io::Result<Payload> {
+        if !self.unread.is_empty() {
+            return Ok(std::mem::take(&mut self.unread));
+        }
+        let mut payload = Payload::default();
+        self.read_with(false, |b| {
+            payload = b.take_payload();
+            payload.len()
+        })?;
+        Ok(payload)
+    }
+
+    /// Like `read`, but into memory that needn't have been initialized, such as a `Vec`'s
+    /// spare capacity, so it doesn't have to be zeroed first just to be overwritten. Returns
+    /// the part of `buf` that was read into, which is empty at end of file.
+    pub fn read_uninit<'a>(&mut self, buf: &'a mut [MaybeUninit<u8>]) -> io::Result<&'a mut [u8]> {
+        let n = match self.read_unread(|u| {
+            let n = std::cmp::min(u.len(), buf.len());
+            buf[..n].write_copy_of_slice(&u[..n]);
+            Ok(n)
+        }) {
+            Some(n) => n,
+            None => self.read_with(buf.is_empty(), |b| b.read_uninit(buf))?,
+        };
+        // SAFETY: `read_uninit` initializes everything it reads into
+        Ok(unsafe { buf[..n].assume_init_mut() })
+    }
+
+    /// Write a message made of `bufs`, one after the other, without first putting them
+    /// together, as `Write::write_vectored` does. Returns how much of it was queued: all of
+    /// it if there's room, otherwise everything up to where the send queue filled, partway
+    /// through one of `bufs`, with the rest left for the next call. Blocks only if none of it
+    /// fits.
+    pub fn sendv(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.write_vectored(bufs)
+    }
+
+    /// `read` for an async executor: rather than block with nothing to read, returns
+    /// `Poll::Pending`, and has `cx`'s waker woken once there's data, the peer's FIN, or an
+    /// error to return. Any number of tasks can be waiting at once, and they're all woken. The
+    /// read timeout doesn't apply.
+    pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if let Some(n) = self.read_unread(|mut u| u.read(buf)) {
+            return Poll::Ready(Ok(n));
+        }
+        let mut b = self.shared.buffers.lock().unwrap();
+        match self.try_read(&mut b, buf.is_empty(), |b| b.read(buf)) {
+            Some(res) => Poll::Ready(res),
+            None => {
+                b.register_reader(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// `write` for an async executor: rather than block with no room in the send queue,
+    /// returns `Poll::Pending`, and has `cx`'s waker woken once the peer has ACKed enough of it
+    /// (see `ConnectionConfig::send_buffer`), or the connection is gone. As with `poll_read`,
+    /// all waiting tasks are woken, and the write timeout doesn't apply.
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut b = self.shared.buffers.lock().unwrap();
+        match self.try_write(&mut b, buf.is_empty(), |b| b.queue_send(buf)) {
+            Some(res) => Poll::Ready(res),
+            None => {
+                b.register_writer(cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Read with `read` from what `fill_buf` left unconsumed, if there's any, as that comes
+    /// first. `None` means it's on to the receive buffer.
+    fn read_unread(&mut self, read: impl FnOnce(&[u8]) -> io::Result<usize>) -> Option<usize> {
+        if self.unread.is_empty() {
+            return None;
+        }
+        // reading from a slice can't fail
+        let n = read(&self.unread).unwrap();
+        self.unread.advance(n);
+        Some(n)
+    }
+
+    /// Block until there's something to read, then take it out of the receive buffer with
+    /// `read`, returning how much that was. With nowhere to put anything (`empty`), returns
+    /// straight away.
+    fn read_with(
+        &self,
+        empty: bool,
+        mut read: impl FnMut(&mut tcp::Buffers) -> usize,
+    ) -> io::Result<usize> {
+        let deadline = self.read_timeout.map(|t| Instant::now() + t);
+        let mut b = self.shared.buffers.lock().unwrap();
+        loop {
+            if let Some(res) = self.try_read(&mut b, empty, &mut read) {
+                return res;
+            }
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            b = wait_until(&self.shared.readable, b, deadline, "read timed out")?;
+        }
+    }
+
+    /// `read_with` without the blocking: `None` if there's nothing to read yet.
+    fn try_read(
+        &self,
+        b: &mut tcp::Buffers,
+        empty: bool,
+        read: impl FnOnce(&mut tcp::Buffers) -> usize,
+    ) -> Option<io::Result<usize>> {
+        if b.recv_buffer_len() > 0 || empty {
+            let n = read(b);
+            if n > 0 && b.take_window_closed() {
+                // there's room again, which the peer needs to hear about
+                self.h.wakeup.wake();
+            }
+            return Some(Ok(n));
+        }
+        if b.is_recv_closed() {
+            // no more data will come
+            return Some(Ok(0));
+        }
+        if b.is_aborted() {
+            return Some(Err(b.error().unwrap_or_else(terminated)));
+        }
+        None
+    }
+
+    /// Block until there's room in the send queue, then put something in it with `queue`,
+    /// returning how much that was. With nothing to write (`empty`), returns straight away.
+    fn write_with(
+        &self,
+        empty: bool,
+        mut queue: impl FnMut(&mut tcp::Buffers) -> io::Result<usize>,
+    ) -> io::Result<usize> {
+        let deadline = self.write_timeout.map(|t| Instant::now() + t);
+        let mut b = self.shared.buffers.lock().unwrap();
+        loop {
+            if let Some(res) = self.try_write(&mut b, empty, &mut queue) {
+                return res;
+            }
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            // the send queue is full; wait for the peer to ACK some of it
+            b = wait_until(&self.shared.writable, b, deadline, "write timed out")?;
+        }
+    }
+
+    /// `write_with` without the blocking: `None` if there's no room in the send queue yet.
+    fn try_write(
+        &self,
+        b: &mut tcp::Buffers,
+        empty: bool,
+        queue: impl FnOnce(&mut tcp::Buffers) -> io::Result<usize>,
+    ) -> Option<io::Result<usize>> {
+        if b.is_aborted() {
+            return Some(Err(b.error().unwrap_or_else(terminated)));
+        }
+        let n = match queue(b) {
+            Ok(n) => n,
+            Err(e) => return Some(Err(e)),
+        };
+        if n > 0 {
+            // get it on the wire now rather than on the next timer
+            self.h.wakeup.wake();
+        }
+        (n > 0 || empty).then_some(Ok(n))
+    }
+}
+
+/// Wait on `var` like `Condvar::wait`, but give up with `TimedOut` once `deadline` (if any)
+/// has passed. Waking up early, spuriously or not, leaves the deadline where it was for the
+/// next wait.
+fn wait_until<'a, T>(
+    var: &Condvar,
+    guard: MutexGuard<'a, T>,
+    deadline: Option<Instant>,
+    msg: &'static str,
+) -> io::Result<MutexGuard<'a, T>> {
+    let Some(deadline) = deadline else {
+        return Ok(var.wait(guard).unwrap());
+    };
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, msg));
+    }
+    Ok(var.wait_timeout(guard, deadline - now).unwrap().0)
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_with(buf.is_empty(), |b| b.queue_send(buf))
+    }
+
+    /// Queues `bufs` in order, one after the other in the stream, as much of them as fits at
+    /// once, blocking only if nothing fits at all, like `write`.
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let empty = bufs.iter().all(|b| b.is_empty());
+        self.write_with(empty, |b| b.queue_send_vectored(bufs))
+    }
+
+    /// Waits until everything written has been acknowledged, sending it first if the stream
+    /// is corked. In nonblocking mode, `WouldBlock` until then.
+    fn flush(&mut self) -> io::Result<()> {
+        let deadline = self.write_timeout.map(|t| Instant::now() + t);
+        let mut b = self.shared.buffers.lock().unwrap();
+        if b.push() {
+            self.h.wakeup.wake();
+        }
+        loop {
+            if b.is_aborted() {
+                return Err(b.error().unwrap_or_else(terminated));
+            }
+            if b.is_send_queue_empty() {
+                return Ok(());
+            }
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+
+            b = wait_until(&self.shared.writable, b, deadline, "flush timed out")?;
+        }
+    }
+}
+
+impl TcpStream {
+    fn new(quad: Quad, h: InterfaceHandle, shared: Arc<tcp::Shared>) -> Self {
+        shared.readers.fetch_add(1, Ordering::Relaxed);
+        shared.writers.fetch_add(1, Ordering::Relaxed);
+        TcpStream {
+            quad,
+            h,
+            shared,
+            read_timeout: None,
+            write_timeout: None,
+            nonblocking: false,
+            unread: Payload::default(),
+            reads: true,
+            writes: true,
+        }
+    }
+
+    /// Block until the connection we're opening is established, or fails to be.
+    fn wait_connected(&self) -> io::Result<()> {
+        let mut b = self.shared.buffers.lock().unwrap();
+        loop {
+            if b.is_connected() {
+                return Ok(());
+            }
+            if b.is_aborted() {
+                return Err(b.error().unwrap_or_else(terminated));
+            }
+
+            b = self.shared.writable.wait(b).unwrap();
+        }
+    }
+
+    /// The connection's addresses and ports.
+    pub fn quad(&self) -> Quad {
+        self.quad
+    }
+
+    /// Make `read` give up with `TimedOut` if no data arrives within `timeout`, as with
+    /// `SO_RCVTIMEO`. Unlike `std::net::TcpStream` on Unix, that's not `WouldBlock`, so it can't
+    /// be taken for a nonblocking read with nothing to return. `None` (the default) blocks
+    /// indefinitely, and like `std::net::TcpStream` a zero timeout is rejected.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot set a 0 duration timeout",
+            ));
+        }
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    /// The timeout set by `set_read_timeout`, or `None` if reads block indefinitely.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Make `write` give up with `TimedOut` if the send queue stays full for `timeout`, as
+    /// with `SO_SNDTIMEO`; e.g. when the peer stops ACKing or keeps its window shut. `flush`
+    /// obeys it too. `None` (the default) blocks indefinitely, and a zero timeout is rejected.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot set a 0 duration timeout",
+            ));
+        }
+        self.write_timeout = timeout;
+        Ok(())
+    }
+
+    /// The timeout set by `set_write_timeout`, or `None` if writes block indefinitely.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// Put the stream in or out of nonblocking mode, as with `std::net::TcpStream`: rather
+    /// than wait, reads with nothing to read, writes with no room in the send queue (see
+    /// `ConnectionConfig::send_buffer` for when there's room again), and flushes with data
+    /// still unacknowledged return `WouldBlock`. Like the timeouts, it's for this stream only,
+    /// not its clones.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    /// Shut down the write side of the connection by sending a FIN, or the read side, after
+    /// which reads see end of file and whatever has arrived or arrives later is thrown away,
+    /// or both. Like `std::net::TcpStream` the connection also closes when the stream is
+    /// dropped.
+    pub fn shutdown(&self, how: std::net::Shutdown) -> io::Result<()> {
+        if how != std::net::Shutdown::Write {
+            let reopened = self.with_buffers(|b| {
+                b.shutdown_recv();
+                b.take_window_closed()
+            })?;
+            // with the receive buffer emptied there's room again, which the peer needs to hear
+            if reopened {
+                self.h.wakeup.wake();
+            }
+            // a blocked read has nothing to wait for any more
+            self.shared.wake_readers();
+        }
+        if how != std::net::Shutdown::Read {
+            // refuse further writes right away, rather than once the packet loop gets to it
+            self.with_buffers(|b| b.shutdown_send())?;
+            self.h.send(Command::Close(self.quad))?;
+        }
+        Ok(())
+    }
+
+    /// Send the peer an ACK for everything received so far right away, even if it's had one.
+    /// There's no delayed ACK to hurry, as what arrives is ACKed once the packet loop has taken
+    /// in the burst it came with, so this repeats the latest ACK with the window as it is now:
+    /// useful for nudging a peer that's waiting to hear from us, if that ACK was lost.
+    pub fn flush_ack(&self) -> io::Result<()> {
+        self.with_buffers(|_| ())?;
+        self.h.send(Command::FlushAck(self.quad))
+    }
+
+    /// Cork the stream, like `TCP_CORK`: until it's uncorked, what's written is held back
+    /// rather than sent, even when there's a full segment of it, so a response can be put
+    /// together from several writes (a header, then a body) and still go out in as few
+    /// segments as possible. `flush` sends what's been held back, as does shutting down the
+    /// write side, and so does uncorking. If it fills more than half the send buffer, as much
+    /// of it as makes full-sized segments goes out anyway. Unlike Nagle's algorithm, it
+    /// doesn't matter whether anything is in flight.
+    pub fn set_cork(&mut self, cork: bool) -> io::Result<()> {
+        self.with_buffers(|b| b.set_cork(cork))?;
+        if !cork {
+            self.h.wakeup.wake();
+        }
+        Ok(())
+    }
+
+    /// Limit how long closing the connection may take, as with `SO_LINGER`: if the peer
+    /// hasn't ACKed our FIN and sent its own within `linger` of our closing the write side
+    /// (by `shutdown`, `close`, or dropping the last stream that writes), the connection is
+    /// reset instead. Closing itself never waits for it. `Some(Duration::ZERO)` resets the
+    /// connection as soon as it's closed, rather than closing it gracefully at all, and `None`
+    /// leaves it closing for as long as the peer keeps ACKing. The default is 2 MSL (a
+    /// minute). Changing it once the connection is closing has no effect.
+    pub fn set_linger(&mut self, linger: Option<Duration>) -> io::Result<()> {
+        self.with_buffers(|b| b.set_linger(linger))
+    }
+
+    /// How long a closing connection is given to finish before it's reset, as `set_linger`
+    /// left it.
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        self.with_buffers(|b| b.linger())
+    }
+
+    /// Close the connection, for every handle on it, rather than waiting for the last one to
+    /// be dropped: `shutdown` of both sides.
+    pub fn close(&self) -> io::Result<()> {
+        self.shutdown(std::net::Shutdown::Both)
+    }
+
+    /// Sequence space sent to the peer but not yet acknowledged by it.
+    pub fn bytes_in_flight(&self) -> io::Result<u32> {
+        self.info().map(|info| info.bytes_in_flight)
+    }
+
+    /// Bytes written to the stream that the peer has not yet acknowledged, including those
+    /// still waiting to be sent. Writers can use this to avoid buffering far ahead of the
+    /// network.
+    pub fn send_buffer_len(&self) -> io::Result<usize> {
+        self.with_buffers(|b| b.send_buffer_len())
+    }
+
+    /// Bytes received from the peer that haven't been read yet, including any `fill_buf` has
+    /// handed out that haven't been consumed.
+    pub fn recv_buffer_len(&self) -> io::Result<usize> {
+        self.with_buffers(|b| b.recv_buffer_len() + self.unread.len())
+    }
+
+    /// The parameters negotiated with the peer during the handshake, and where the connection
+    /// stands now.
+    pub fn info(&self) -> io::Result<ConnectionInfo> {
+        let (tx, rx) = mpsc::channel();
+        self.h.send(Command::Info(self.quad, tx))?;
+        // the request is dropped unanswered if the connection is gone
+        rx.recv().map_err(|_| terminated())
+    }
+
+    fn with_buffers<T>(&self, f: impl FnOnce(&mut tcp::Buffers) -> T) -> io::Result<T> {
+        let mut b = self.shared.buffers.lock().unwrap();
+        if b.is_aborted() {
+            return Err(b.error().unwrap_or_else(terminated));
+        }
+        Ok(f(&mut b))
+    }
+}
@@ -1,75 +1,25 @@
-use std::collections::hash_map::Entry;
+use std::io;
 use std::net::Ipv4Addr;
-use std::{collections::HashMap, io};
-
-mod tcp;
-
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
-struct Quad {
-    src: (Ipv4Addr, u16),
-    dst: (Ipv4Addr, u16),
-}
+use std::thread;
 
 fn main() -> io::Result<()> {
-    let mut connections: HashMap<Quad, tcp::Connection> = Default::default();
-    let mut nic = tun_tap::Iface::without_packet_info("tun0", tun_tap::Mode::Tun).expect("failed to create");
-    let mut buf = [0u8; 1504];
-    loop {
-        let nbytes = nic.recv(&mut buf[..])?;
-        
-        // if s/without_packet_info/new/:
-        // 
-        // let _eth_flags = u16::from_be_bytes([buf[0], buf[1]]);
-        // let eth_proto = u16::from_be_bytes([buf[2], buf[3]]);
-
-        // if eth_proto != 0x0800 {
-        //     // not ipv4
-        //     continue;
-        // }
-        // 
-        // and also incluse on send
-
-        match etherparse::Ipv4HeaderSlice::from_slice(&buf[..nbytes]) {
-            Ok(iph) => {
-                let src = iph.source_addr();
-                let dst = iph.destination_addr();
-
-                if iph.protocol() != 0x06 {
-                    // not tcp
-                    continue;
-                }
-
-                match etherparse::TcpHeaderSlice::from_slice(&buf[iph.slice().len()..nbytes]) {
-                    Ok(tcph) => {
-                        let datai = iph.slice().len() + tcph.slice().len();
-                        match connections.entry(Quad {
-                            src: (src, tcph.source_port()),
-                            dst: (dst, tcph.destination_port()),
-                        }) {
-                            Entry::Occupied(mut c) => {
-                                c.get_mut().on_packet(&mut nic, iph, tcph, &buf[datai..nbytes])?;
-                            }
-                            Entry::Vacant(e) => {
-                                if let Some(c) = tcp::Connection::accept(
-                                    &mut nic,
-                                    iph,
-                                    tcph,
-                                    &buf[datai..nbytes],
-                                )? {
-                                    e.insert(c);
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("ignoring weird tcp packet {:?}", e)
-                    }
-                }
-            }
-            Err(e) => {
-                // eprintln!("ignoring weird packet {:?}", e)
-            }
+    let mut i = trust::Interface::new()?;
+    eprintln!("created interface");
+    // run.sh gives the kernel's end of tun0 192.168.0.1, so we're any other host on the subnet
+    i.add_address(Ipv4Addr::new(192, 168, 0, 2).into());
+    let mut l1 = i.bind(8000)?;
+    let mut l2 = i.bind(9000)?;
+    let jh1 = thread::spawn(move || {
+        while let Ok(_stream) = l1.accept() {
+            eprintln!("got connection on 8000!");
+        }
+    });
+    let jh2 = thread::spawn(move || {
+        while let Ok(_stream) = l2.accept() {
+            eprintln!("got connection on 9000!");
         }
-    }
-    // Ok(())
+    });
+    jh1.join().unwrap();
+    jh2.join().unwrap();
+    Ok(())
 }
@@ -0,0 +1,169 @@
+//! TCP MD5 signatures (RFC 2385), and the MD5 (RFC 1321) they're made with. MD5 is small and
+//! fixed enough that it isn't worth a dependency for this one legacy option.
+
+use std::fmt;
+use std::net::IpAddr;
+
+/// The TCP option carrying the signature: kind, length, and the 16-byte digest.
+pub(crate) const OPTION_KIND: u8 = 19;
+pub(crate) const OPTION_LEN: usize = 18;
+
+/// A shared secret with one peer. Kept out of `Debug` output, so logging a config doesn't
+/// give it away.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Key(pub(crate) Vec<u8>);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// The signature of a TCP segment from `src` to `dst` (RFC 2385 S2.0): the MD5 of the
+/// pseudo-header, the fixed part of the TCP `header` with a zero checksum, the `payload`, and
+/// the key. Options aren't covered, the signature included, though they count towards the
+/// segment length in the pseudo-header.
+pub(crate) fn sign(src: IpAddr, dst: IpAddr, header: &[u8], payload: &[u8], key: &Key) -> [u8; 16] {
+    let mut md5 = Md5::new();
+    let len = header.len() + payload.len();
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            md5.update(&src.octets());
+            md5.update(&dst.octets());
+            md5.update(&[0, 6]);
+            md5.update(&(len as u16).to_be_bytes());
+        }
+        // RFC 2385 predates IPv6, but everyone signs over its pseudo-header (RFC 8200 S8.1)
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            md5.update(&src.octets());
+            md5.update(&dst.octets());
+            md5.update(&(len as u32).to_be_bytes());
+            md5.update(&[0, 0, 0, 6]);
+        }
+        _ => unreachable!("addresses of different versions"),
+    }
+    let mut fixed = [0; 20];
+    fixed.copy_from_slice(&header[..20]);
+    fixed[16..18].fill(0);
+    md5.update(&fixed);
+    md5.update(payload);
+    md5.update(&key.0);
+    md5.finish()
+}
+
+/// The signature carried in a TCP header's options, if there is one.
+pub(crate) fn signature(options: &[u8]) -> Option<&[u8]> {
+    crate::tcp::find_option(options, OPTION_KIND).filter(|d| d.len() == 16)
+}
+
+/// Whether a segment from `src` to `dst` is signed with `key`. The comparison takes as long
+/// however much of the signature is right, so a forger can't find it out a byte at a time.
+pub(crate) fn verify(
+    src: IpAddr,
+    dst: IpAddr,
+    tcph: &etherparse::TcpHeaderSlice,
+    payload: &[u8],
+    key: &Key,
+) -> bool {
+    let Some(sig) = signature(tcph.options()) else {
+        return false;
+    };
+    let expected = sign(src, dst, tcph.slice(), payload, key);
+    let diff = sig.iter().zip(expected).fold(0, |d, (a, b)| d | (a ^ b));
+    diff == 0
+}
+
+/// Per-round shift amounts.
+const S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// floor(abs(sin(i + 1)) * 2^32), from RFC 1321 S3.4.
+const K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+/// An MD5 computation in progress (RFC 1321).
+struct Md5 {
+    state: [u32; 4],
+    /// the start of a block that isn't complete yet
+    buf: [u8; 64],
+    /// bytes hashed so far
+    len: u64,
+}
+
+impl Md5 {
+    fn new() -> Self {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            buf: [0; 64],
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        let mut have = (self.len % 64) as usize;
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = std::cmp::min(64 - have, data.len());
+            self.buf[have..have + n].copy_from_slice(&data[..n]);
+            data = &data[n..];
+            have += n;
+            if have == 64 {
+                let block = self.buf;
+                self.compress(&block);
+                have = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        // a one bit, zeroes up to 8 bytes short of a block, and the length in bits
+        let bits = self.len.wrapping_mul(8);
+        let pad = (119 - self.len % 64) % 64 + 1;
+        let mut tail = [0; 64];
+        tail[0] = 0x80;
+        self.update(&tail[..pad as usize]);
+        self.update(&bits.to_le_bytes());
+        debug_assert_eq!(self.len % 64, 0);
+
+        let mut digest = [0; 16];
+        for (d, s) in digest.chunks_exact_mut(4).zip(self.state) {
+            d.copy_from_slice(&s.to_le_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut m = [0u32; 16];
+        for (w, b) in m.iter_mut().zip(block.chunks_exact(4)) {
+            *w = u32::from_le_bytes(b.try_into().unwrap());
+        }
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(S[i]));
+        }
+        for (s, x) in self.state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(x);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Optional copying of every packet crossing the NIC to a second one, like a switch's SPAN
+//! port, so the traffic can be watched with tcpdump there without touching the live device.
+
+use std::io;
+use std::net::IpAddr;
+use std::os::fd::RawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::nic::Nic;
+
+pub(crate) type MirrorSlot = Arc<Mutex<Option<Box<dyn Nic + Send>>>>;
+
+/// Wraps the real NIC so that, while a mirror is set, every packet in either direction is
+/// sent out of it as well.
+pub(crate) struct Tap<N> {
+    nic: N,
+    mirror: MirrorSlot,
+}
+
+impl<N> Tap<N> {
+    pub(crate) fn new(nic: N, mirror: MirrorSlot) -> Self {
+        Tap { nic, mirror }
+    }
+
+    fn copy(&self, packet: &[u8]) {
+        if let Some(m) = &mut *self.mirror.lock().unwrap() {
+            // whatever is wrong with the mirror, it's no reason to hold up the real traffic
+            if let Err(e) = m.send(packet) {
+                tracing::trace!(error = %e, "failed to mirror packet");
+            }
+        }
+    }
+}
+
+impl<N: Nic> Nic for Tap<N> {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.copy(buf);
+        self.nic.send(buf)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.nic.recv(buf)?;
+        self.copy(&buf[..n]);
+        Ok(n)
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        let n = self.nic.recv_batch(bufs, lens)?;
+        for (buf, &len) in bufs.iter().zip(&lens[..n]) {
+            self.copy(&buf[..len]);
+        }
+        Ok(n)
+    }
+
+    fn mtu(&self) -> usize {
+        self.nic.mtu()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.nic.poll(timeout)
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        self.nic.fd()
+    }
+
+    fn add_address(&mut self, addr: IpAddr) {
+        self.nic.add_address(addr)
+    }
+}
@@ -0,0 +1,250 @@
+use std::io;
+use std::net::IpAddr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+/// Anything that can move raw IP packets in and out of the stack.
+///
+/// The stack never touches a tun device directly, so tests (see `testing::MockNic`) can stand
+/// in for the kernel without needing privileges, and where tun isn't available `RawSocket`
+/// can be used instead.
+pub trait Nic {
+    /// Transmit a single IP packet.
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize>;
+
+    /// Receive a single IP packet into `buf`, blocking until one is available.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Receive as many IP packets as are waiting, up to one into each of `bufs`, putting their
+    /// lengths in the same places in `lens` and returning how many there were. Blocks like
+    /// `recv` until the first, though a non-blocking device may turn out to have none after
+    /// all.
+    ///
+    /// The packet loop reads this way, so a burst is taken in and ACKed in one go. By default
+    /// it's a `recv` for each packet with a zero-timeout `poll` before the next; a device that
+    /// can do better should, e.g. by reading a non-blocking descriptor until it would block,
+    /// which saves the polls, or taking the lot with a single `recvmmsg`.
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < bufs.len() {
+            match self.recv(bufs[n]) {
+                Ok(len) => lens[n] = len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+            n += 1;
+            if n < bufs.len() && !self.poll(Duration::ZERO)? {
+                break;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Largest IP packet this device can carry. Asked often, so it should be cheap.
+    fn mtu(&self) -> usize;
+
+    /// Wait for up to `timeout` for a packet to become available, returning whether `recv`
+    /// would now succeed without blocking.
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool>;
+
+    /// A file descriptor that becomes readable when a packet arrives, if there is one. The
+    /// packet loop polls on it together with its own wakeup pipe, so that it can sleep until
+    /// the next timer without missing anything the application asks of it in the meantime.
+    fn fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Told of each address the interface is given (see `Interface::add_address`), for a
+    /// device that has to answer for them itself, as `Ethernet` does ARP.
+    fn add_address(&mut self, _addr: IpAddr) {}
+}
+
+/// A tun device, or a tap device, with its MTU looked up once when it's opened.
+pub struct Tun {
+    iface: tun_tap::Iface,
+    mtu: usize,
+}
+
+impl Tun {
+    /// Open the tun device called `name` (e.g. `tun0`), without packet info.
+    pub fn open(name: &str) -> io::Result<Self> {
+        Self::open_mode(name, tun_tap::Mode::Tun)
+    }
+
+    /// Open the tap device called `name` (e.g. `tap0`), which carries Ethernet frames rather
+    /// than IP packets, so it needs an `Ethernet` on top for the stack to run on.
+    pub fn open_tap(name: &str) -> io::Result<Self> {
+        Self::open_mode(name, tun_tap::Mode::Tap)
+    }
+
+    fn open_mode(name: &str, mode: tun_tap::Mode) -> io::Result<Self> {
+        let iface = tun_tap::Iface::without_packet_info(name, mode)?;
+        // the packet loop only reads once poll says there's something there, but never block
+        // on a read poll was wrong about
+        set_nonblocking(iface.as_raw_fd())?;
+        let mtu = interface_mtu(iface.name())?;
+        Ok(Tun { iface, mtu })
+    }
+}
+
+impl Nic for Tun {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.iface.send(buf)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.iface.recv(buf)
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        // tun has no recvmmsg, but the descriptor is non-blocking, so reading until it would
+        // block is as good as polling between packets without the polls
+        drain(bufs, lens, |buf| self.iface.recv(buf))
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        poll_fd(self.iface.as_raw_fd(), timeout)
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.iface.as_raw_fd())
+    }
+}
+
+/// Read packets with `recv` from a non-blocking descriptor into `bufs` until it would block
+/// or they're all full, as for `Nic::recv_batch`.
+pub(crate) fn drain(
+    bufs: &mut [&mut [u8]],
+    lens: &mut [usize],
+    mut recv: impl FnMut(&mut [u8]) -> io::Result<usize>,
+) -> io::Result<usize> {
+    for (n, buf) in bufs.iter_mut().enumerate() {
+        match recv(buf) {
+            Ok(len) => lens[n] = len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(n),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(bufs.len())
+}
+
+/// The MTU of the network interface called `ifname`.
+pub(crate) fn interface_mtu(ifname: &str) -> io::Result<usize> {
+    let mut ifr: libc::ifreq = unsafe { std::mem::zeroed() };
+    if ifname.len() >= ifr.ifr_name.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "interface name too long",
+        ));
+    }
+    for (d, s) in ifr.ifr_name.iter_mut().zip(ifname.bytes()) {
+        *d = s as libc::c_char;
+    }
+    // any socket will do to ask
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCGIFMTU as _, &mut ifr) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { ifr.ifr_ifru.ifru_mtu } as usize)
+}
+
+/// Wait for up to `timeout` for `fd` to become readable.
+pub(crate) fn poll_fd(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    Ok(poll_fds([fd], timeout)?[0])
+}
+
+/// Wait for up to `timeout` for any of `fds` to become readable, returning which are.
+pub(crate) fn poll_fds<const N: usize>(
+    fds: [RawFd; N],
+    timeout: Duration,
+) -> io::Result<[bool; N]> {
+    let mut pfd = fds.map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    // round up, so that we don't wake up just short of a timer and spin
+    let ms = timeout
+        .as_micros()
+        .div_ceil(1000)
+        .min(libc::c_int::MAX as u128);
+    let n = unsafe { libc::poll(pfd.as_mut_ptr(), N as libc::nfds_t, ms as libc::c_int) };
+    if n < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok([false; N]);
+        }
+        return Err(err);
+    }
+    Ok(pfd.map(|p| p.revents != 0))
+}
+
+/// Put `fd` in non-blocking mode.
+pub(crate) fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A self-pipe that other threads use to wake the packet loop out of `poll`, e.g. when the
+/// application has written something that should go out now rather than on the next timer.
+pub(crate) struct Wakeup {
+    rx: OwnedFd,
+    tx: OwnedFd,
+}
+
+impl Default for Wakeup {
+    fn default() -> Self {
+        // only fails if we're out of file descriptors, in which case there's little hope anyway
+        Wakeup::new().expect("failed to create wakeup pipe")
+    }
+}
+
+impl Wakeup {
+    pub(crate) fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        Ok(Wakeup { rx, tx })
+    }
+
+    pub(crate) fn wake(&self) {
+        // if the pipe is full, the loop has plenty of wakeups pending already
+        unsafe {
+            libc::write(
+                self.tx.as_raw_fd(),
+                [1u8].as_ptr() as *const libc::c_void,
+                1,
+            )
+        };
+    }
+
+    /// Swallow any pending wakeups.
+    pub(crate) fn drain(&self) {
+        let mut buf = [0u8; 64];
+        while unsafe {
+            libc::read(
+                self.rx.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        } > 0
+        {}
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.rx.as_raw_fd()
+    }
+}
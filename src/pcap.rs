@@ -0,0 +1,286 @@
+//! Optional capture of every packet crossing the NIC into a pcap file, and reading such files
+//! back for replay.
+//!
+//! Packets are written with `LINKTYPE_RAW`, since the tun device hands us bare IP packets, so
+//! Wireshark and tcpdump open the result directly.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::IpAddr;
+use std::os::fd::RawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::nic::Nic;
+
+const LINKTYPE_ETHERNET: u32 = 1;
+/// BSD's DLT_RAW, which some platforms write instead of LINKTYPE_RAW
+const LINKTYPE_RAW_BSD: u32 = 12;
+/// OpenBSD's DLT_RAW
+const LINKTYPE_RAW_OPENBSD: u32 = 14;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const SNAPLEN: u32 = 65535;
+const GLOBAL_HEADER_LEN: u64 = 24;
+const RECORD_HEADER_LEN: u64 = 16;
+
+/// How often the writer thread flushes buffered records to disk when traffic is light.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Records queued for the writer thread before we start dropping them rather than stall the
+/// packet path.
+const QUEUE_DEPTH: usize = 4096;
+
+struct Record {
+    ts: Duration,
+    data: Vec<u8>,
+}
+
+/// A running capture. Dropping it flushes everything queued so far and closes the file.
+pub(crate) struct Capture {
+    tx: Option<mpsc::SyncSender<Record>>,
+    jh: Option<thread::JoinHandle<()>>,
+}
+
+pub(crate) type CaptureSlot = Arc<Mutex<Option<Capture>>>;
+
+impl Capture {
+    /// Start capturing into `path`. When the file grows past `max_size` bytes it is moved to
+    /// `<path>.1` (replacing any earlier one) and a fresh file is started, so at most two
+    /// files' worth of capture is kept around.
+    pub(crate) fn create(path: &Path, max_size: Option<u64>) -> io::Result<Self> {
+        let out = PcapFile::create(path.to_path_buf())?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_DEPTH);
+        let jh = thread::spawn(move || {
+            if let Err(e) = write_loop(out, rx, max_size) {
+                tracing::warn!(error = %e, "pcap capture stopped");
+            }
+        });
+        Ok(Capture {
+            tx: Some(tx),
+            jh: Some(jh),
+        })
+    }
+
+    pub(crate) fn record(&self, packet: &[u8]) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if let Some(tx) = &self.tx {
+            // if the writer can't keep up (or has died), lose the record rather than block
+            let _ = tx.try_send(Record {
+                ts,
+                data: packet.to_vec(),
+            });
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        drop(self.tx.take());
+        if let Some(jh) = self.jh.take() {
+            let _ = jh.join();
+        }
+    }
+}
+
+struct PcapFile {
+    path: PathBuf,
+    out: BufWriter<File>,
+    written: u64,
+}
+
+impl PcapFile {
+    fn create(path: PathBuf) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(&path)?);
+        out.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        // thiszone and sigfigs
+        out.write_all(&0i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&SNAPLEN.to_le_bytes())?;
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        Ok(PcapFile {
+            path,
+            out,
+            written: GLOBAL_HEADER_LEN,
+        })
+    }
+
+    fn write(&mut self, r: &Record) -> io::Result<()> {
+        let incl = std::cmp::min(r.data.len(), SNAPLEN as usize);
+        self.out.write_all(&(r.ts.as_secs() as u32).to_le_bytes())?;
+        self.out.write_all(&r.ts.subsec_micros().to_le_bytes())?;
+        self.out.write_all(&(incl as u32).to_le_bytes())?;
+        self.out.write_all(&(r.data.len() as u32).to_le_bytes())?;
+        self.out.write_all(&r.data[..incl])?;
+        self.written += RECORD_HEADER_LEN + incl as u64;
+        Ok(())
+    }
+
+    fn rotate(self) -> io::Result<Self> {
+        let PcapFile { path, out, .. } = self;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        let mut old = path.clone().into_os_string();
+        old.push(".1");
+        fs::rename(&path, old)?;
+        PcapFile::create(path)
+    }
+}
+
+fn write_loop(
+    mut out: PcapFile,
+    rx: mpsc::Receiver<Record>,
+    max_size: Option<u64>,
+) -> io::Result<()> {
+    loop {
+        match rx.recv_timeout(FLUSH_INTERVAL) {
+            Ok(r) => {
+                if let Some(max) = max_size
+                    && out.written > GLOBAL_HEADER_LEN
+                    && out.written + RECORD_HEADER_LEN + r.data.len() as u64 > max
+                {
+                    out = out.rotate()?;
+                }
+                out.write(&r)?;
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => out.out.flush()?,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return out.out.flush(),
+        }
+    }
+}
+
+/// Wraps the real NIC so that, while a capture is active, every packet in either direction
+/// is recorded.
+pub(crate) struct Tap<N> {
+    nic: N,
+    capture: CaptureSlot,
+}
+
+impl<N> Tap<N> {
+    pub(crate) fn new(nic: N, capture: CaptureSlot) -> Self {
+        Tap { nic, capture }
+    }
+
+    fn record(&self, packet: &[u8]) {
+        if let Some(c) = &*self.capture.lock().unwrap() {
+            c.record(packet);
+        }
+    }
+}
+
+impl<N: Nic> Nic for Tap<N> {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.record(buf);
+        self.nic.send(buf)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.nic.recv(buf)?;
+        self.record(&buf[..n]);
+        Ok(n)
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        let n = self.nic.recv_batch(bufs, lens)?;
+        for (buf, &len) in bufs.iter().zip(&lens[..n]) {
+            self.record(&buf[..len]);
+        }
+        Ok(n)
+    }
+
+    fn mtu(&self) -> usize {
+        self.nic.mtu()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.nic.poll(timeout)
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        self.nic.fd()
+    }
+
+    fn add_address(&mut self, addr: IpAddr) {
+        self.nic.add_address(addr)
+    }
+}
+
+/// A packet read back out of a capture file.
+pub struct CapturedPacket {
+    /// when the packet was captured, relative to the unix epoch
+    pub ts: Duration,
+    /// the IP packet, with any link-layer framing already stripped
+    pub data: Vec<u8>,
+}
+
+/// Read every IP packet out of a classic pcap file, whichever link type it was captured
+/// with. Non-IP frames (e.g. ARP on an ethernet capture) are skipped.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<CapturedPacket>> {
+    fn invalid(msg: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+    }
+
+    /// IPv4 or IPv6, going by the ethertype
+    fn is_ip(ethertype: &[u8]) -> bool {
+        ethertype == [0x08, 0x00] || ethertype == [0x86, 0xdd]
+    }
+
+    let mut r = BufReader::new(File::open(path)?);
+    let mut hdr = [0u8; GLOBAL_HEADER_LEN as usize];
+    r.read_exact(&mut hdr)?;
+
+    // the magic tells us both the byte order and whether timestamps are in µs or ns
+    let (le, nanos) = match hdr[..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (true, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (false, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (true, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (false, true),
+        _ => return Err(invalid("not a pcap file")),
+    };
+    let u32_at = |b: &[u8], i: usize| {
+        let v = [b[i], b[i + 1], b[i + 2], b[i + 3]];
+        if le {
+            u32::from_le_bytes(v)
+        } else {
+            u32::from_be_bytes(v)
+        }
+    };
+    let linktype = u32_at(&hdr, 20);
+
+    let mut packets = Vec::new();
+    let mut rec = [0u8; RECORD_HEADER_LEN as usize];
+    loop {
+        match r.read_exact(&mut rec) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(packets),
+            Err(e) => return Err(e),
+        }
+        let secs = u32_at(&rec, 0) as u64;
+        let frac = u32_at(&rec, 4);
+        let ts = if nanos {
+            Duration::new(secs, frac)
+        } else {
+            Duration::new(secs, 0) + Duration::from_micros(frac as u64)
+        };
+        let mut data = vec![0u8; u32_at(&rec, 8) as usize];
+        r.read_exact(&mut data)?;
+
+        let ip = match linktype {
+            LINKTYPE_RAW | LINKTYPE_RAW_BSD | LINKTYPE_RAW_OPENBSD => 0,
+            LINKTYPE_ETHERNET if data.len() >= 14 && is_ip(&data[12..14]) => 14,
+            LINKTYPE_LINUX_SLL if data.len() >= 16 && is_ip(&data[14..16]) => 16,
+            LINKTYPE_ETHERNET | LINKTYPE_LINUX_SLL => continue,
+            _ => return Err(invalid("unsupported pcap link type")),
+        };
+        data.drain(..ip);
+        if !matches!(data.first().map(|b| b >> 4), Some(4 | 6)) {
+            continue;
+        }
+        packets.push(CapturedPacket { ts, data });
+    }
+}
@@ -0,0 +1,247 @@
+//! A `Nic` on top of raw sockets, for when there's no tun device to be had.
+//!
+//! Packets are read off a real interface with an `AF_PACKET` socket and written back out with
+//! a raw IP socket, which leaves routing and ARP to the kernel. A BPF filter attached to the
+//! receiving socket means we only ever see TCP for our own address (and ports, if given).
+//!
+//! The kernel's own stack sees the same packets we do. Since it has no socket for our
+//! connections it will answer them with RSTs, so those need dropping on the way out, e.g.
+//! `iptables -A OUTPUT -p tcp --tcp-flags RST RST -s <addr> -j DROP`.
+
+use std::ffi::CString;
+use std::io;
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+use crate::nic::{Nic, interface_mtu, poll_fd};
+
+// classic BPF opcodes (linux/filter.h)
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_LD_H_ABS: u16 = 0x28;
+const BPF_LD_B_ABS: u16 = 0x30;
+const BPF_LD_H_IND: u16 = 0x48;
+const BPF_LDX_B_MSH: u16 = 0xb1;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JSET_K: u16 = 0x45;
+const BPF_RET_K: u16 = 0x06;
+
+/// the most packets `recv_batch` takes in one `recvmmsg`
+const MAX_BATCH: usize = 64;
+
+/// BPF jumps are at most 255 instructions, and the port checks have to reach past each other
+const MAX_PORTS: usize = 250;
+
+pub struct RawSocket {
+    /// `AF_PACKET`, for receiving
+    rx: OwnedFd,
+    /// `AF_INET`/`SOCK_RAW`, for sending
+    tx: OwnedFd,
+    mtu: usize,
+}
+
+impl RawSocket {
+    /// Receive TCP segments for `addr` on the interface named `ifname` (e.g. `eth0`), keeping
+    /// only those for the given destination `ports`, or for any port if `ports` is empty.
+    ///
+    /// Needs `CAP_NET_RAW`.
+    pub fn open(ifname: &str, addr: Ipv4Addr, ports: &[u16]) -> io::Result<Self> {
+        let name = CString::new(ifname)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bad interface name"))?;
+        if ports.len() > MAX_PORTS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many ports to filter on",
+            ));
+        }
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // start out with protocol 0, which receives nothing, so that no packets sneak in
+        // before the filter is in place
+        let rx = socket(libc::AF_PACKET, libc::SOCK_DGRAM | libc::SOCK_NONBLOCK, 0)?;
+        let filter = filter(addr, ports);
+        let prog = libc::sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr() as *mut _,
+        };
+        setsockopt(&rx, libc::SOL_SOCKET, libc::SO_ATTACH_FILTER, &prog)?;
+        // our own transmissions show up on the interface too
+        setsockopt(&rx, libc::SOL_PACKET, libc::PACKET_IGNORE_OUTGOING, &1i32)?;
+
+        let mut sll: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        sll.sll_family = libc::AF_PACKET as u16;
+        sll.sll_protocol = (libc::ETH_P_IP as u16).to_be();
+        sll.sll_ifindex = ifindex as i32;
+        let r = unsafe {
+            libc::bind(
+                rx.as_raw_fd(),
+                &sll as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // IPPROTO_RAW implies IP_HDRINCL, so we get to send our own headers
+        let tx = socket(libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_RAW)?;
+
+        let mtu = interface_mtu(ifname)?;
+        Ok(RawSocket { rx, tx, mtu })
+    }
+}
+
+impl Nic for RawSocket {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let iph = etherparse::Ipv4HeaderSlice::from_slice(buf)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "not an IPv4 packet"))?;
+        let mut sin: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+        sin.sin_family = libc::AF_INET as libc::sa_family_t;
+        sin.sin_addr.s_addr = u32::from(iph.destination_addr()).to_be();
+        let n = unsafe {
+            libc::sendto(
+                self.tx.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                0,
+                &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe {
+            libc::recv(
+                self.rx.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        // the whole batch in one go; the socket's non-blocking, so it's whatever is waiting
+        let count = bufs.len().min(MAX_BATCH);
+        let mut iovs = [libc::iovec {
+            iov_base: std::ptr::null_mut(),
+            iov_len: 0,
+        }; MAX_BATCH];
+        let mut msgs: [libc::mmsghdr; MAX_BATCH] = unsafe { std::mem::zeroed() };
+        for ((iov, msg), buf) in iovs.iter_mut().zip(&mut msgs).zip(bufs.iter_mut()) {
+            iov.iov_base = buf.as_mut_ptr() as *mut libc::c_void;
+            iov.iov_len = buf.len();
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+        }
+        let n = unsafe {
+            libc::recvmmsg(
+                self.rx.as_raw_fd(),
+                msgs.as_mut_ptr(),
+                count as libc::c_uint,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Ok(0);
+            }
+            return Err(err);
+        }
+        let n = n as usize;
+        for (len, msg) in lens.iter_mut().zip(&msgs[..n]) {
+            *len = msg.msg_len as usize;
+        }
+        Ok(n)
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        poll_fd(self.rx.as_raw_fd(), timeout)
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.rx.as_raw_fd())
+    }
+}
+
+fn socket(domain: libc::c_int, ty: libc::c_int, proto: libc::c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(domain, ty | libc::SOCK_CLOEXEC, proto) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn setsockopt<T>(fd: &OwnedFd, level: libc::c_int, name: libc::c_int, v: &T) -> io::Result<()> {
+    let r = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            v as *const T as *const libc::c_void,
+            std::mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if r < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A classic BPF program accepting unfragmented (or first-fragment) TCP to `addr`, and to one
+/// of `ports` unless that's empty. With a `SOCK_DGRAM` packet socket, offsets are from the
+/// start of the IP header.
+fn filter(addr: Ipv4Addr, ports: &[u16]) -> Vec<libc::sock_filter> {
+    let len = 4 + if ports.is_empty() { 0 } else { 4 + ports.len() } + 2;
+    let (accept, drop) = (len - 2, len - 1);
+
+    let mut prog = Vec::with_capacity(len);
+    // jump targets are given as absolute indices, and made relative here
+    let mut ins = |code, k, jt: Option<usize>, jf: Option<usize>| {
+        let i = prog.len();
+        let rel = |t: Option<usize>| t.map_or(0, |t| (t - i - 1) as u8);
+        prog.push(libc::sock_filter {
+            code,
+            jt: rel(jt),
+            jf: rel(jf),
+            k,
+        });
+    };
+    ins(BPF_LD_B_ABS, 9, None, None);
+    ins(BPF_JEQ_K, libc::IPPROTO_TCP as u32, None, Some(drop));
+    ins(BPF_LD_W_ABS, 16, None, None);
+    ins(BPF_JEQ_K, u32::from(addr), None, Some(drop));
+    if !ports.is_empty() {
+        // later fragments carry no TCP header to look at
+        ins(BPF_LD_H_ABS, 6, None, None);
+        ins(BPF_JSET_K, 0x1fff, Some(drop), None);
+        // X = IP header length, then load the destination port
+        ins(BPF_LDX_B_MSH, 0, None, None);
+        ins(BPF_LD_H_IND, 2, None, None);
+        for (i, &port) in ports.iter().enumerate() {
+            let last = i == ports.len() - 1;
+            ins(BPF_JEQ_K, port as u32, Some(accept), last.then_some(drop));
+        }
+    }
+    ins(BPF_RET_K, u32::MAX, None, None);
+    ins(BPF_RET_K, 0, None, None);
+    prog
+}
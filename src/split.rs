@@ -0,0 +1,213 @@
+//! The two halves of a split `TcpStream`, for reading on one thread while writing on another.
+
+use std::io::{self, BufRead, Read, Write};
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::{ConnectionInfo, Payload, Quad, TcpStream};
+
+/// The reading half of a `TcpStream`, from `TcpStream::split`. Dropping it shuts down the
+/// read side of the connection.
+pub struct ReadHalf {
+    stream: TcpStream,
+}
+
+/// The writing half of a `TcpStream`, from `TcpStream::split`. Dropping it shuts down the
+/// write side of the connection, sending a FIN once everything written has gone out.
+pub struct WriteHalf {
+    stream: TcpStream,
+}
+
+impl TcpStream {
+    /// Split the stream into a half that reads and a half that writes, which can go to
+    /// different threads. Each shuts down its own side of the connection when it's dropped,
+    /// unless a clone of the stream still has it, and the connection lasts until both are
+    /// gone. The read timeout goes with the read half, the write timeout with the write half,
+    /// and so does anything `fill_buf` handed out that hasn't been consumed yet. Each half
+    /// starts out nonblocking if the stream was. `ReadHalf::unsplit` puts the stream back
+    /// together.
+    pub fn split(mut self) -> (ReadHalf, WriteHalf) {
+        let write = TcpStream {
+            quad: self.quad,
+            h: self.h.clone(),
+            shared: self.shared.clone(),
+            read_timeout: None,
+            write_timeout: self.write_timeout.take(),
+            nonblocking: self.nonblocking,
+            unread: Payload::default(),
+            reads: false,
+            writes: true,
+        };
+        self.writes = false;
+        (ReadHalf { stream: self }, WriteHalf { stream: write })
+    }
+}
+
+impl ReadHalf {
+    /// Put the stream back together from the halves `split` made of it.
+    ///
+    /// # Panics
+    ///
+    /// If `write` is half of some other stream.
+    pub fn unsplit(self, write: WriteHalf) -> TcpStream {
+        assert!(
+            Arc::ptr_eq(&self.stream.shared, &write.stream.shared),
+            "unsplit halves of different streams"
+        );
+        let mut stream = self.stream;
+        let mut write = write.stream;
+        // the write half gets dropped without shutting anything down
+        write.writes = false;
+        stream.writes = true;
+        stream.write_timeout = write.write_timeout;
+        stream
+    }
+
+    /// The connection's addresses and ports.
+    pub fn quad(&self) -> Quad {
+        self.stream.quad()
+    }
+
+    /// As with `TcpStream::set_read_timeout`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// As with `TcpStream::read_timeout`.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.stream.read_timeout()
+    }
+
+    /// As with `TcpStream::set_nonblocking`, for reads.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    /// As with `TcpStream::recv_bytes`.
+    pub fn recv_bytes(&mut self) -> io::Result<Payload> {
+        self.stream.recv_bytes()
+    }
+
+    /// As with `TcpStream::read_uninit`.
+    pub fn read_uninit<'a>(&mut self, buf: &'a mut [MaybeUninit<u8>]) -> io::Result<&'a mut [u8]> {
+        self.stream.read_uninit(buf)
+    }
+
+    /// As with `TcpStream::poll_read`.
+    pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.stream.poll_read(cx, buf)
+    }
+
+    /// As with `TcpStream::flush_ack`.
+    pub fn flush_ack(&self) -> io::Result<()> {
+        self.stream.flush_ack()
+    }
+
+    /// As with `TcpStream::recv_buffer_len`.
+    pub fn recv_buffer_len(&self) -> io::Result<usize> {
+        self.stream.recv_buffer_len()
+    }
+
+    /// As with `TcpStream::info`.
+    pub fn info(&self) -> io::Result<ConnectionInfo> {
+        self.stream.info()
+    }
+}
+
+impl Read for ReadHalf {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        self.stream.read_vectored(bufs)
+    }
+}
+
+impl BufRead for ReadHalf {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.stream.fill_buf()
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.stream.consume(n)
+    }
+}
+
+impl WriteHalf {
+    /// The connection's addresses and ports.
+    pub fn quad(&self) -> Quad {
+        self.stream.quad()
+    }
+
+    /// As with `TcpStream::set_write_timeout`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(timeout)
+    }
+
+    /// As with `TcpStream::write_timeout`.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.stream.write_timeout()
+    }
+
+    /// As with `TcpStream::set_nonblocking`, for writes and flushes.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    /// As with `TcpStream::sendv`.
+    pub fn sendv(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.stream.sendv(bufs)
+    }
+
+    /// As with `TcpStream::set_linger`.
+    pub fn set_linger(&mut self, linger: Option<Duration>) -> io::Result<()> {
+        self.stream.set_linger(linger)
+    }
+
+    /// As with `TcpStream::linger`.
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        self.stream.linger()
+    }
+
+    /// As with `TcpStream::set_cork`.
+    pub fn set_cork(&mut self, cork: bool) -> io::Result<()> {
+        self.stream.set_cork(cork)
+    }
+
+    /// As with `TcpStream::poll_write`.
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.stream.poll_write(cx, buf)
+    }
+
+    /// As with `TcpStream::bytes_in_flight`.
+    pub fn bytes_in_flight(&self) -> io::Result<u32> {
+        self.stream.bytes_in_flight()
+    }
+
+    /// As with `TcpStream::send_buffer_len`.
+    pub fn send_buffer_len(&self) -> io::Result<usize> {
+        self.stream.send_buffer_len()
+    }
+
+    /// As with `TcpStream::info`.
+    pub fn info(&self) -> io::Result<ConnectionInfo> {
+        self.stream.info()
+    }
+}
+
+impl Write for WriteHalf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        self.stream.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
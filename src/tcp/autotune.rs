@@ -0,0 +1,116 @@
+//! Receive buffer autotuning, after Dynamic Right-Sizing (Fisk and Feng, "Dynamic Right-Sizing
+//! in TCP", 2001) as Linux does it: rather than offer every connection the same window, which
+//! is either more memory than an idle connection needs or less window than a long, fast path
+//! needs to fill, each one's receive buffer starts small and is sized once a round trip to
+//! what the application has shown it can read in one.
+
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use super::Connection;
+use super::seq::wrapping_lt;
+
+/// How a connection's receive buffer is being sized.
+#[derive(Debug)]
+pub(super) struct RecvTuning {
+    /// the configured receive window, which the buffer is never sized below, and the most it
+    /// may grow to
+    min: usize,
+    max: usize,
+    /// the round-trip time as seen from the receiving end, once there's been a window to time
+    rtt: Option<Duration>,
+    /// the right edge of the window we've advertised that's being timed, and since when
+    probe: Option<(u32, Instant)>,
+    /// when the current round trip began, with RCV.NXT then and how much was waiting to be read
+    round: Option<(Instant, u32, usize)>,
+}
+
+impl RecvTuning {
+    pub(super) fn new(min: u16, max: u16) -> Self {
+        RecvTuning {
+            min: min as usize,
+            max: max as usize,
+            rtt: None,
+            probe: None,
+            round: None,
+        }
+    }
+
+    pub(super) fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Take in an RTT sample. Each one is only an upper bound, as the peer may have had
+    /// something other than our window holding it up, so a lower one is taken as it is and a
+    /// higher one only moves the estimate up gradually.
+    fn sample_rtt(&mut self, sample: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(rtt) if rtt <= sample => rtt + (sample - rtt) / 8,
+            _ => sample,
+        });
+    }
+}
+
+impl Connection {
+    /// Time the window, and once a round trip, resize the receive buffer: to twice what the
+    /// application read over the last one, so there's room for a round trip's worth arriving
+    /// while another's read, as long as the application is keeping up, and back down towards
+    /// the configured size if data is piling up unread. The buffer never shrinks below what
+    /// it holds and the window already advertised, so the window's right edge never moves
+    /// back. Called with each run of data received, before the window is worked out afresh.
+    pub(super) fn tune_recv_buffer(&mut self, now: Instant) {
+        let Some(t) = &mut self.recv_tuning else {
+            return;
+        };
+        // data past the window's right edge can't have been sent until the ACK that moved it
+        // on got to the peer, which isn't until this one's been answered
+        match t.probe {
+            Some((edge, at)) if wrapping_lt(edge, self.recv.nxt) => {
+                t.sample_rtt(now - at);
+                t.probe = None;
+            }
+            Some(_) => {}
+            None => t.probe = Some((self.rcv_adv, now)),
+        }
+        let Some(rtt) = t.rtt else {
+            return;
+        };
+
+        let mut b = self.shared.buffers.lock().unwrap();
+        let unread = b.recv_buffer_len();
+        let (start, start_seq, start_unread) = *t.round.get_or_insert((now, self.recv.nxt, unread));
+        if now - start < rtt {
+            return;
+        }
+        t.round = Some((now, self.recv.nxt, unread));
+        let arrived = self.recv.nxt.wrapping_sub(start_seq) as usize;
+        let read = (start_unread + arrived).saturating_sub(unread);
+
+        let size = b.recv_buffer_size;
+        let target = if unread > size / 2 {
+            // the application isn't keeping up, and a bigger buffer would only hold more of
+            // what it hasn't got to
+            std::cmp::max(2 * read, t.min)
+        } else {
+            std::cmp::max(2 * read, size)
+        };
+        let promised = self.rcv_adv.wrapping_sub(self.recv.nxt);
+        let promised = if promised < 1 << 31 {
+            promised as usize
+        } else {
+            0
+        };
+        let floor = b.recv_held() + promised;
+        b.recv_buffer_size = std::cmp::min(target, t.max).max(floor);
+        if b.recv_buffer_size != size {
+            debug!(
+                from = size,
+                to = b.recv_buffer_size,
+                ?rtt,
+                read,
+                "resized receive buffer"
+            );
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! The memory every connection's buffers take up between them, and the stack-wide limit on
+//! it. Each connection charges the budget for what it holds, sent and received, and for the
+//! window it's advertised, which is memory it's promised the peer; what's left decides how
+//! much more any of them may take on.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How much of the send queue and receive window every connection gets, budget or no
+/// budget, so none of them can be starved of room to make progress altogether.
+pub(super) const MIN_SHARE: usize = 4096;
+
+/// The buffer memory the stack's connections may take up between them.
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        MemoryBudget {
+            limit: AtomicUsize::new(usize::MAX),
+            used: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemoryBudget {
+    /// Set the limit, or take it away with `None`. Going below what's already in use takes
+    /// nothing back; connections just can't take on any more until enough is freed.
+    pub(crate) fn set_limit(&self, limit: Option<usize>) {
+        self.limit
+            .store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// What connections have charged to the budget between them.
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// How much more connections may take on between them.
+    pub(super) fn room(&self) -> usize {
+        self.limit
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.used())
+    }
+}
+
+/// What one connection has charged to the budget, which is given back when it's dropped
+/// along with the connection's buffers.
+#[derive(Debug, Default)]
+pub(super) struct Charge {
+    budget: Arc<MemoryBudget>,
+    charged: usize,
+}
+
+impl Charge {
+    pub(super) fn new(budget: Arc<MemoryBudget>) -> Self {
+        Charge { budget, charged: 0 }
+    }
+
+    pub(super) fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+
+    /// Charge `charged` in place of whatever was charged before.
+    pub(super) fn update(&mut self, charged: usize) {
+        if charged > self.charged {
+            self.budget
+                .used
+                .fetch_add(charged - self.charged, Ordering::Relaxed);
+        } else {
+            self.budget
+                .used
+                .fetch_sub(self.charged - charged, Ordering::Relaxed);
+        }
+        self.charged = charged;
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.update(0);
+    }
+}
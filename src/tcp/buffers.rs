@@ -0,0 +1,683 @@
+//! The data a connection holds on to in each direction, shared with the application's
+//! stream.
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem::MaybeUninit;
+use std::ops::{Deref, Range};
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Waker;
+use std::time::{Duration, Instant};
+
+use super::Connection;
+use super::budget::{Charge, MIN_SHARE};
+use crate::TcpError;
+
+/// A buffer the packet loop reads a packet into, which a connection receiving without copying
+/// can go on holding on to once the packet has been processed.
+pub(crate) type PacketBuf = Arc<Vec<u8>>;
+
+/// Received data, handed out by `TcpStream::recv_bytes`: the payload of a segment, left in the
+/// buffer it arrived in if the connection receives without copying (see
+/// `ConnectionConfig::zero_copy`). Cloning it doesn't copy the data either.
+#[derive(Clone, Debug, Default)]
+pub struct Payload {
+    buf: Arc<Vec<u8>>,
+    range: Range<usize>,
+}
+
+impl Payload {
+    /// `data`, which is part of `buf`, without copying it.
+    fn within(buf: &PacketBuf, data: &[u8]) -> Self {
+        let start = data.as_ptr() as usize - buf.as_ptr() as usize;
+        debug_assert!(
+            start + data.len() <= buf.len(),
+            "payload isn't in its packet buffer"
+        );
+        Payload {
+            buf: buf.clone(),
+            range: start..start + data.len(),
+        }
+    }
+
+    /// A copy of `data`, in a buffer of its own.
+    fn copy(data: impl Into<Vec<u8>>) -> Self {
+        let buf = data.into();
+        Payload {
+            range: 0..buf.len(),
+            buf: Arc::new(buf),
+        }
+    }
+
+    /// How much of the receive buffer this takes up: all of the buffer the data is in, which
+    /// for data left in its packet buffer includes the headers and the rest of the MTU.
+    fn held(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Drop the first `n` bytes, which have been read. Once there's nothing left, the buffer
+    /// is let go of, so a receive ring it's part of can be written into again.
+    pub(crate) fn advance(&mut self, n: usize) {
+        assert!(n <= self.len(), "advanced past the end of the payload");
+        self.range.start += n;
+        if self.range.is_empty() {
+            *self = Payload::default();
+        }
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[self.range.clone()]
+    }
+}
+
+impl AsRef<[u8]> for Payload {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Received data waiting to be read, for a connection that copies what it receives: a ring
+/// the size of the receive buffer, allocated once there's something to put in it. The front
+/// of it can be handed out as a `Payload` without copying; if that's still held on to when
+/// more arrives, what's left in the ring moves to a fresh buffer rather than write over it.
+#[derive(Debug, Default)]
+pub(crate) struct RecvRing {
+    buf: Arc<Vec<u8>>,
+    /// where the data starts in `buf`, and how much there is, which may run past the end of
+    /// `buf` and on from the start of it
+    head: usize,
+    len: usize,
+}
+
+impl RecvRing {
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The data in order, in the two pieces it's in either side of the end of the ring.
+    pub(super) fn as_slices(&self) -> (&[u8], &[u8]) {
+        let front = std::cmp::min(self.len, self.buf.len() - self.head);
+        (
+            &self.buf[self.head..self.head + front],
+            &self.buf[..self.len - front],
+        )
+    }
+
+    /// Drop the first `n` bytes, which have been read.
+    pub(super) fn advance(&mut self, n: usize) {
+        assert!(n <= self.len, "advanced past the end of the receive ring");
+        self.len -= n;
+        self.head = if self.len == 0 {
+            0
+        } else {
+            (self.head + n) % self.buf.len()
+        };
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.advance(self.len);
+    }
+
+    /// Add `data` at the end, in a ring of at least `capacity` bytes, the size of the receive
+    /// buffer, or more if that's too small to hold it.
+    pub(super) fn push(&mut self, data: &[u8], capacity: usize) {
+        if data.is_empty() {
+            return;
+        }
+        let capacity = std::cmp::max(capacity, self.len + data.len());
+        if self.buf.len() < self.len + data.len() || Arc::get_mut(&mut self.buf).is_none() {
+            self.move_to(capacity);
+        }
+        let size = self.buf.len();
+        let buf = Arc::get_mut(&mut self.buf).expect("receive ring is shared");
+        let tail = (self.head + self.len) % size;
+        let n = std::cmp::min(data.len(), size - tail);
+        buf[tail..tail + n].copy_from_slice(&data[..n]);
+        buf[..data.len() - n].copy_from_slice(&data[n..]);
+        self.len += data.len();
+    }
+
+    /// Move the data to the front of a buffer of its own, `capacity` bytes long.
+    fn move_to(&mut self, capacity: usize) {
+        let mut buf = vec![0; capacity];
+        let (front, back) = self.as_slices();
+        buf[..front.len()].copy_from_slice(front);
+        buf[front.len()..self.len].copy_from_slice(back);
+        self.buf = Arc::new(buf);
+        self.head = 0;
+    }
+
+    /// Take as much from the front as lies in one piece, up to the end of the ring, without
+    /// copying it.
+    pub(super) fn take_front(&mut self) -> Payload {
+        let n = self.as_slices().0.len();
+        let p = Payload {
+            buf: self.buf.clone(),
+            range: self.head..self.head + n,
+        };
+        self.advance(n);
+        p
+    }
+}
+
+/// The part of a connection that the application's stream shares with the packet loop: the
+/// data in both directions, and just enough about the connection to know when to stop waiting
+/// on it. Everything else stays with the packet loop.
+#[derive(Default)]
+pub(crate) struct Shared {
+    pub(crate) buffers: Mutex<Buffers>,
+    /// signalled when there's data to read, or there never will be again
+    pub(crate) readable: Condvar,
+    /// signalled when there's room in the send queue again, or the connection goes away
+    pub(crate) writable: Condvar,
+    /// how many of the application's `TcpStream`s (clones and halves) can read and write. each
+    /// side is shut down when the last one that has it is dropped.
+    pub(crate) readers: AtomicUsize,
+    pub(crate) writers: AtomicUsize,
+}
+
+#[derive(Default)]
+pub(crate) struct Buffers {
+    /// data the application has written that the peer hasn't acknowledged yet, starting at
+    /// SND.UNA (not counting our SYN). everything past SND.NXT hasn't been sent at all.
+    pub(super) unacked: VecDeque<u8>,
+    /// how much `unacked` may hold, and how far it has to drain once it's filled up before
+    /// there's room in it again
+    pub(super) send_buffer_size: usize,
+    pub(super) send_low_watermark: usize,
+    /// `unacked` has filled up and hasn't yet drained below the low watermark
+    pub(super) send_full: bool,
+
+    /// data the peer has sent that the application hasn't read yet, ending at RCV.NXT.
+    pub(super) incoming: RecvRing,
+    /// the same, for a connection that receives without copying: each segment's payload where
+    /// it arrived. only one of the two is ever in use.
+    pub(super) payloads: VecDeque<Payload>,
+    /// bytes of data in `payloads`, and how much of the receive buffer they take up
+    pub(super) payload_len: usize,
+    pub(super) payload_held: usize,
+    /// how much `incoming` may hold, or `payloads` take up. RCV.WND is whatever of it is free.
+    pub(super) recv_buffer_size: usize,
+    /// the window we've advertised is closed, or too small to be any use, so reading should
+    /// get the packet loop to tell the peer once there's room again
+    pub(super) window_closed: bool,
+
+    /// the peer has sent its FIN, so nothing past what's in `incoming` will arrive
+    pub(super) recv_closed: bool,
+    /// we've been shut down for writing
+    pub(super) send_closed: bool,
+    /// the application has corked the connection, so what it writes is held back until it's
+    /// flushed, uncorked, or fills half the send buffer
+    pub(super) corked: bool,
+    /// how much of `unacked` was written before the last flush while corked, which goes out
+    /// regardless
+    pub(super) pushed: usize,
+    /// how long a close may take before the connection is reset instead, if there's a limit
+    pub(super) linger: Option<Duration>,
+    /// we've been shut down for reading, so whatever arrives is thrown away
+    pub(super) recv_shut_down: bool,
+    /// the handshake is done, which `Interface::connect` waits for
+    pub(super) connected: bool,
+    /// the connection is gone altogether: aborted, timed out, or the interface shut down
+    pub(super) aborted: bool,
+    /// why, if we know better than just "aborted": it timed out, or an ICMP error did it in
+    pub(super) error: Option<TcpError>,
+
+    /// tasks waiting in `poll_read` and `poll_write`, woken (and forgotten) along with threads
+    /// waiting on the condvars
+    pub(super) read_wakers: Vec<Waker>,
+    pub(super) write_wakers: Vec<Waker>,
+
+    /// what all this, and the window last worked out, which is memory promised to the peer,
+    /// have been charged to the stack's memory budget
+    pub(super) charge: Charge,
+    pub(super) window_charged: usize,
+}
+
+impl Shared {
+    /// Wake everyone waiting for something to read, or for the end of it: threads blocked in
+    /// a read, and tasks that polled for one.
+    pub(crate) fn wake_readers(&self) {
+        let wakers = std::mem::take(&mut self.buffers.lock().unwrap().read_wakers);
+        self.readable.notify_all();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Wake everyone waiting for room in the send queue, for the handshake to finish, or for
+    /// the connection to go away.
+    pub(crate) fn wake_writers(&self) {
+        let wakers = std::mem::take(&mut self.buffers.lock().unwrap().write_wakers);
+        self.writable.notify_all();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Buffers {
+    /// Move as much received data as fits into `buf`, returning how much that was.
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> usize {
+        if !self.payloads.is_empty() {
+            return self.read_payloads(buf);
+        }
+        let n = std::cmp::min(buf.len(), self.incoming.len());
+        let (front, back) = self.incoming.as_slices();
+        let m = std::cmp::min(n, front.len());
+        buf[..m].copy_from_slice(&front[..m]);
+        buf[m..n].copy_from_slice(&back[..n - m]);
+        self.incoming.advance(n);
+        n
+    }
+
+    /// `read`, from `payloads`. A payload only stops taking up room once it's all been read.
+    fn read_payloads(&mut self, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < buf.len()
+            && let Some(p) = self.payloads.front_mut()
+        {
+            let m = std::cmp::min(buf.len() - n, p.len());
+            buf[n..n + m].copy_from_slice(&p[..m]);
+            p.range.start += m;
+            n += m;
+            if p.is_empty() {
+                self.pop_payload();
+            }
+        }
+        self.payload_len -= n;
+        n
+    }
+
+    /// Like `read`, but into memory that needn't have been initialized. What was read, the
+    /// returned number of bytes, is at the start of `buf`, and initialized.
+    pub(crate) fn read_uninit(&mut self, buf: &mut [MaybeUninit<u8>]) -> usize {
+        let mut n = 0;
+        while n < buf.len() {
+            let next = match self.payloads.front() {
+                Some(p) => &p[..],
+                None => self.incoming.as_slices().0,
+            };
+            if next.is_empty() {
+                break;
+            }
+            let m = std::cmp::min(buf.len() - n, next.len());
+            buf[n..n + m].write_copy_of_slice(&next[..m]);
+            n += m;
+            match self.payloads.front_mut() {
+                Some(p) => {
+                    p.range.start += m;
+                    self.payload_len -= m;
+                    if p.is_empty() {
+                        self.pop_payload();
+                    }
+                }
+                None => self.incoming.advance(m),
+            }
+        }
+        n
+    }
+
+    /// Take the next received payload out of the receive buffer whole, or if the connection
+    /// copies what it receives, the front of the receive ring up to where it wraps around,
+    /// which is everything received unless it runs across that. Either way it isn't copied.
+    /// Empty if there's nothing to read.
+    pub(crate) fn take_payload(&mut self) -> Payload {
+        match self.pop_payload() {
+            Some(p) => {
+                self.payload_len -= p.len();
+                p
+            }
+            None => self.incoming.take_front(),
+        }
+    }
+
+    fn pop_payload(&mut self) -> Option<Payload> {
+        let p = self.payloads.pop_front()?;
+        self.payload_held -= p.held();
+        Some(p)
+    }
+
+    /// Queue up `data`, which must fit in the receive buffer, from the packet buffer `buf` if
+    /// there is one. It's left where it is if all of `buf` fits with `reserve` bytes to spare,
+    /// and copied otherwise.
+    pub(super) fn push_payload(&mut self, buf: Option<&PacketBuf>, data: &[u8], reserve: usize) {
+        let room = self.recv_buffer_size - self.recv_held();
+        let p = match buf {
+            Some(buf) if buf.len() + reserve <= room => Payload::within(buf, data),
+            _ => Payload::copy(data),
+        };
+        self.payload_len += p.len();
+        self.payload_held += p.held();
+        self.payloads.push_back(p);
+    }
+
+    /// How much of the receive buffer is taken up by data the application hasn't read.
+    pub(super) fn recv_held(&self) -> usize {
+        self.incoming.len() + self.payload_held
+    }
+
+    /// Like `read`, but filling each of `bufs` in turn, as `Read::read_vectored` does.
+    pub(crate) fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut]) -> usize {
+        let mut n = 0;
+        for buf in bufs {
+            let m = self.read(buf);
+            n += m;
+            if m < buf.len() {
+                break;
+            }
+        }
+        n
+    }
+
+    /// Whether the receive window was closed, as of the last data to arrive, clearing it: the
+    /// application has just read, and the peer needs to hear that there's room again.
+    pub(crate) fn take_window_closed(&mut self) -> bool {
+        std::mem::take(&mut self.window_closed)
+    }
+
+    /// Have `waker` woken when there's something to read. A task that polls more than once
+    /// before then is only woken once.
+    pub(crate) fn register_reader(&mut self, waker: &Waker) {
+        register(&mut self.read_wakers, waker);
+    }
+
+    /// Have `waker` woken when there's room in the send queue.
+    pub(crate) fn register_writer(&mut self, waker: &Waker) {
+        register(&mut self.write_wakers, waker);
+    }
+
+    /// Whether the peer has sent its FIN, or we've been shut down for reading, so once the
+    /// receive buffer is drained there will never be anything more to read.
+    pub(crate) fn is_recv_closed(&self) -> bool {
+        self.recv_closed || self.recv_shut_down
+    }
+
+    /// Whether the connection has made it through the handshake, even if it's since been
+    /// closed.
+    pub(crate) fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Whether the packet loop has forgotten the connection.
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// What the connection being aborted is down to, if it's more than just "aborted".
+    pub(crate) fn error(&self) -> Option<io::Error> {
+        self.error.map(io::Error::from)
+    }
+
+    /// Queue up as much of `buf` as fits in the send queue, returning how much that was. The
+    /// data actually goes out from `on_tick`. Once the queue fills up, nothing more fits until
+    /// it's drained below the low watermark.
+    pub(crate) fn queue_send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.send_closed {
+            return Err(TcpError::SendShutDown.into());
+        }
+        if self.send_full {
+            return Ok(0);
+        }
+        let room = self.send_buffer_size - self.unacked.len();
+        // however tight the budget, there's always enough to keep something in flight
+        let budget = std::cmp::max(
+            self.charge.budget().room(),
+            MIN_SHARE.saturating_sub(self.unacked.len()),
+        );
+        let n = buf.len().min(room).min(budget);
+        self.unacked.extend(&buf[..n]);
+        self.send_full = self.unacked.len() == self.send_buffer_size;
+        self.update_charge();
+        Ok(n)
+    }
+
+    /// Charge the memory budget for what's buffered now, and the window last worked out. The
+    /// application's reads don't do this themselves, and are only caught up with the next
+    /// time the window is.
+    pub(super) fn update_charge(&mut self) {
+        let held = self.recv_held() + self.window_charged + self.unacked.len();
+        self.charge.update(held);
+    }
+
+    /// After the peer has ACKed some of the send queue, whether writers should be woken:
+    /// unless the queue filled up, as soon as there's room in it, and otherwise not until it's
+    /// drained below the low watermark.
+    pub(super) fn update_writable(&mut self) -> bool {
+        if self.unacked.len() < self.send_low_watermark {
+            self.send_full = false;
+        }
+        !self.send_full
+    }
+
+    /// Like `queue_send`, but queueing each of `bufs` in turn, as `Write::write_vectored`
+    /// does. Whatever doesn't fit is left out, from the middle of a slice on.
+    pub(crate) fn queue_send_vectored(&mut self, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        let mut n = 0;
+        for buf in bufs {
+            let m = self.queue_send(buf)?;
+            n += m;
+            if m < buf.len() {
+                break;
+            }
+        }
+        Ok(n)
+    }
+
+    /// Cork or uncork the connection; see `TcpStream::set_cork`.
+    pub(crate) fn set_cork(&mut self, cork: bool) {
+        self.corked = cork;
+        self.pushed = 0;
+    }
+
+    /// Let everything written so far go out, even while corked. Returns whether that freed up
+    /// anything the cork was holding back.
+    pub(crate) fn push(&mut self) -> bool {
+        let held = self.corked && self.pushed < self.unacked.len();
+        if held {
+            self.pushed = self.unacked.len();
+        }
+        held
+    }
+
+    /// How much of the data not yet sent, which starts `in_flight` bytes into `unacked`, may
+    /// go out now. That's all of it unless we're corked, and even then, what's been sent
+    /// before (`sent` bytes in, and now being resent), what's been pushed, and, once there's
+    /// more than half the send buffer waiting, as many full `mss`-sized segments as there are
+    /// of it. Shutting down for writing pulls the cork out, as there's nothing more to wait for,
+    /// and so does an `mss` of zero, as there's no full segment to wait for either.
+    pub(super) fn sendable(&self, in_flight: usize, sent: usize, mss: usize) -> usize {
+        let unsent = self.unacked.len().saturating_sub(in_flight);
+        if !self.corked || self.send_closed || mss == 0 {
+            return unsent;
+        }
+        let free = std::cmp::max(sent, self.pushed).saturating_sub(in_flight);
+        let free = std::cmp::min(free, unsent);
+        if unsent > self.send_buffer_size / 2 {
+            std::cmp::max(free, unsent - unsent % mss)
+        } else {
+            free
+        }
+    }
+
+    /// Limit how long a close may take; see `TcpStream::set_linger`.
+    pub(crate) fn set_linger(&mut self, linger: Option<Duration>) {
+        self.linger = linger;
+    }
+
+    pub(crate) fn linger(&self) -> Option<Duration> {
+        self.linger
+    }
+
+    /// Stop taking writes. The connection itself finds out through `Connection::close`.
+    pub(crate) fn shutdown_send(&mut self) {
+        self.send_closed = true;
+    }
+
+    /// Stop taking reads, and throw away what's been received and not read. The peer isn't
+    /// told, and whatever else it sends is thrown away as it arrives.
+    pub(crate) fn shutdown_recv(&mut self) {
+        self.recv_shut_down = true;
+        self.incoming.clear();
+        self.payloads.clear();
+        self.payload_len = 0;
+        self.payload_held = 0;
+    }
+
+    /// Whether everything the application has written has been acknowledged.
+    pub(crate) fn is_send_queue_empty(&self) -> bool {
+        self.unacked.is_empty()
+    }
+
+    /// Bytes written by the application that the peer hasn't acknowledged yet, whether or not
+    /// they've been sent.
+    pub(crate) fn send_buffer_len(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// Bytes received but not yet read by the application.
+    pub(crate) fn recv_buffer_len(&self) -> usize {
+        self.incoming.len() + self.payload_len
+    }
+}
+
+impl Connection {
+    /// Buffer whatever part of `data` (which starts at sequence number `seq`, and is in the
+    /// packet buffer `buf` if the packet loop is sharing that) comes next in sequence and fits
+    /// in the receive buffer, and advance RCV.NXT past it. Anything out of order is dropped for
+    /// the peer to retransmit.
+    pub(super) fn receive(&mut self, seq: u32, data: &[u8], buf: Option<&PacketBuf>, now: Instant) {
+        // how much of the segment we already have. a segment from the future wraps around to
+        // something huge, so it's skipped entirely.
+        let dup = self.recv.nxt.wrapping_sub(seq) as usize;
+        if dup >= data.len() {
+            return;
+        }
+        let mut b = self.shared.buffers.lock().unwrap();
+        if b.recv_shut_down {
+            // nobody's going to read it, but it's still ACKed, as far as the window goes, so the
+            // peer doesn't keep sending it
+            drop(b);
+            let n = (data.len() - dup).min(self.recv.wnd as usize);
+            self.recv.nxt = self.recv.nxt.wrapping_add(n as u32);
+            return;
+        }
+        let room = b.recv_buffer_size - b.recv_held();
+        // without copying there's more room than window, and nothing past the window is taken
+        let n = (data.len() - dup).min(room).min(self.recv.wnd as usize);
+        if n > 0 && self.zero_copy {
+            // holding on to the packet buffer takes up more room than the data, which mustn't
+            // leave too little for the rest of the window we've advertised
+            let end = self.recv.nxt.wrapping_add(n as u32);
+            let owed = self.rcv_adv.wrapping_sub(end);
+            let reserve = if owed < 1 << 31 { owed as usize } else { 0 };
+            b.push_payload(buf, &data[dup..dup + n], reserve);
+        } else {
+            let size = b.recv_buffer_size;
+            b.incoming.push(&data[dup..dup + n], size);
+        }
+        drop(b);
+        if n > 0 {
+            self.shared.wake_readers();
+        }
+        self.recv.nxt = self.recv.nxt.wrapping_add(n as u32);
+        if n > 0 {
+            self.tune_recv_buffer(now);
+        }
+        self.update_recv_window();
+        if (self.recv.wnd as usize) < self.usable_window() {
+            self.shared.buffers.lock().unwrap().window_closed = true;
+        }
+    }
+
+    /// Advertise exactly the room left in the receive buffer, and never more: anything past it
+    /// a fast sender could fill before the application reads, and we'd have to drop it.
+    ///
+    /// Without copying, a full-sized segment's data takes up a whole packet buffer, so there's
+    /// only room for that much less of it. Advertising the room as if all of it would come in
+    /// full-sized segments keeps them from having to be copied for want of it.
+    pub(super) fn update_recv_window(&mut self) {
+        self.recv.wnd = self.recv_window();
+        let mut b = self.shared.buffers.lock().unwrap();
+        b.window_charged = self.recv.wnd as usize;
+        b.update_charge();
+    }
+
+    /// The window `update_recv_window` would advertise, as of right now. With the memory
+    /// budget tight, that's only as much of the room in the receive buffer as the budget has
+    /// left (counting what this window already has of it), though never so little that the
+    /// window's right edge moves back.
+    pub(super) fn recv_window(&self) -> u16 {
+        let space = self.recv_space();
+        let b = self.shared.buffers.lock().unwrap();
+        let budget = b.charge.budget().room().saturating_add(b.window_charged);
+        drop(b);
+        let mut room = std::cmp::min(space, std::cmp::max(budget, MIN_SHARE));
+        if self.zero_copy {
+            let mss = self.mtu - self.ip.header_len() - etherparse::TCP_MINIMUM_HEADER_SIZE;
+            room = room * mss / self.packet_buf_len.max(mss);
+        }
+        let promised = self.rcv_adv.wrapping_sub(self.recv.nxt);
+        if promised < 1 << 31 {
+            room = room.max(std::cmp::min(promised as usize, space));
+        }
+        std::cmp::min(room, u16::MAX as usize) as u16
+    }
+
+    /// The smallest window worth offering the peer (RFC 1122 S4.2.3.3): room for a full-sized
+    /// segment, or half the receive buffer if that's less.
+    pub(super) fn usable_window(&self) -> usize {
+        let half = self.shared.buffers.lock().unwrap().recv_buffer_size / 2;
+        std::cmp::min(self.smss(), half)
+    }
+
+    /// Resize the receive buffer so that exactly `wnd` bytes of it are free, which makes that
+    /// RCV.WND. Only for tests, which can then pick a window without having to fill the
+    /// buffer to get it.
+    #[cfg(feature = "testing")]
+    pub(crate) fn set_recv_window(&mut self, wnd: u16) {
+        let mut b = self.shared.buffers.lock().unwrap();
+        b.recv_buffer_size = b.recv_held() + wnd as usize;
+        drop(b);
+        self.update_recv_window();
+    }
+
+    /// Room left in the receive buffer, as of right now.
+    pub(super) fn recv_space(&self) -> usize {
+        let b = self.shared.buffers.lock().unwrap();
+        b.recv_buffer_size - b.recv_held()
+    }
+
+    /// Everything the application has written and the peer hasn't acknowledged.
+    pub(super) fn unacked_len(&self) -> usize {
+        self.shared.buffers.lock().unwrap().unacked.len()
+    }
+
+    /// How much of what hasn't been sent may be, as `Buffers::sendable` has it.
+    pub(super) fn sendable(&self, b: &Buffers) -> usize {
+        let in_flight = self.bytes_in_flight() as usize;
+        let sent = self.send.max.wrapping_sub(self.send.una) as usize;
+        b.sendable(in_flight, sent, self.smss())
+    }
+}
+
+/// Fill `dst` from `q`, starting `start` bytes in.
+pub(super) fn copy_out(q: &VecDeque<u8>, start: usize, dst: &mut [u8]) {
+    let (front, back) = q.as_slices();
+    if start >= front.len() {
+        let start = start - front.len();
+        dst.copy_from_slice(&back[start..start + dst.len()]);
+        return;
+    }
+    let n = std::cmp::min(dst.len(), front.len() - start);
+    dst[..n].copy_from_slice(&front[start..start + n]);
+    let rest = dst.len() - n;
+    dst[n..].copy_from_slice(&back[..rest]);
+}
+
+fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|w| w.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
@@ -0,0 +1,1394 @@
+//! The state machine: what a connection does with each segment it's handed, and with what
+//! the application asks of it.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, trace, warn};
+
+use super::autotune::RecvTuning;
+use super::budget::{Charge, MemoryBudget};
+use super::buffers::{Buffers, PacketBuf, Shared};
+use super::segment::{Control, Negotiated};
+use super::seq::{
+    ReceiveSequenceSpace, Relative, SendSequenceSpace, is_between_wrapped, segment_acceptable,
+    wrapping_lt,
+};
+use super::timers::{MIN_RTO, MSL};
+use super::{
+    CongestionSample, CongestionSampler, ConnectionConfig, ConnectionInfo, Established,
+    IcmpOutcome, MtuProbing, SegmentSummary, State, StateChange, StateObserver,
+};
+use crate::congestion::{self, AckEvent, CongestionControl, LossEvent, Reno};
+#[cfg(feature = "tcp-md5")]
+use crate::md5;
+use crate::nic::Nic;
+use crate::{Quad, TcpError, icmp, ip};
+
+/// The initial congestion window in bytes for `segments` segments of `smss` bytes: RFC 6928's
+/// min(10*MSS, max(2*MSS, 14600)), with the ten generalized to `segments`.
+fn initial_cwnd(segments: u32, smss: usize) -> u32 {
+    let segments = segments as usize;
+    let cwnd = std::cmp::min(segments * smss, std::cmp::max(2 * smss, segments * 1460));
+    cwnd.min(u32::MAX as usize) as u32
+}
+
+/// Duplicate ACKs in a row that we take to mean a segment was lost, rather than just
+/// overtaken by the ones after it (RFC 5681 S3.2).
+const DUP_ACK_THRESHOLD: u32 = 3;
+
+pub(crate) struct Connection {
+    pub(super) quad: Quad,
+    pub(super) state: State,
+    pub(super) send: SendSequenceSpace,
+    pub(super) recv: ReceiveSequenceSpace,
+    pub(super) ip: ip::Outgoing,
+    /// the window in the last segment we sent
+    pub(super) wnd_advertised: u16,
+    /// the right edge of that window, RCV.NXT + RCV.WND as of then: the peer may send up to
+    /// here, so everything before it has to fit
+    pub(super) rcv_adv: u32,
+    /// how the receive buffer is being sized, if it's being autotuned
+    pub(super) recv_tuning: Option<RecvTuning>,
+    /// IP identification for the next packet we send. RFC 6864 only requires it to be unique
+    /// when DF is off, but it's cheap to always count, and it makes captures easier to read.
+    pub(super) ip_id: u16,
+    pub(super) negotiated: Negotiated,
+    /// largest IP packet we'll send: the NIC's MTU, unless the config asks for less
+    pub(super) mtu: usize,
+    /// decides the congestion window: how much we're willing to have in flight whatever the
+    /// peer's window, starting from the configured initial window
+    pub(super) cc: Box<dyn CongestionControl>,
+    /// what `cc` is made from: the configured congestion control, and the initial window in
+    /// segments. a connection we open only learns the MSS those are counted in from the peer's
+    /// SYN, so it makes `cc` over again then.
+    pub(super) congestion_control: congestion::Factory,
+    pub(super) initial_window: u32,
+    /// duplicate ACKs in a row, for fast retransmit
+    pub(super) dup_acks: u32,
+    /// SND.MAX as of the last fast retransmit or timeout. Duplicate ACKs from before here are
+    /// the fallout of that loss, not a new one (RFC 6582's "recover").
+    pub(super) recover: u32,
+
+    /// RTT estimation and the retransmission timeout (RFC 6298)
+    pub(super) srtt: Option<Duration>,
+    pub(super) rttvar: Duration,
+    pub(super) rto: Duration,
+    /// when the oldest unacknowledged segment is due to be retransmitted
+    pub(super) rto_deadline: Option<Instant>,
+    /// timeouts in a row without the peer acknowledging anything
+    pub(super) retransmits: u32,
+    /// the segment being timed for an RTT sample: the sequence number that ACKs it, and when it
+    /// went out. retransmitted segments are never timed (Karn's algorithm).
+    pub(super) rtt_probe: Option<(u32, Instant)>,
+
+    /// what to do if the path looks like an MTU black hole, if anything
+    pub(super) mtu_probing: Option<MtuProbing>,
+    /// timeouts of a full-sized segment in a row, with no packet-too-big to explain them
+    pub(super) blackhole_timeouts: u32,
+    /// we've stepped down the MSS to see if smaller segments get through, and DF is off until
+    /// we find out
+    pub(super) probing_mtu: bool,
+    /// probing found a black hole, and the manager hasn't heard about it yet
+    pub(super) mtu_blackhole: bool,
+    pub(super) dont_fragment: bool,
+    /// the key to sign segments to and from the peer with, if it's one we have a key for
+    #[cfg(feature = "tcp-md5")]
+    pub(super) md5_key: Option<md5::Key>,
+
+    /// the send and receive buffers, shared with whoever ends up with the stream
+    pub(super) shared: Arc<Shared>,
+
+    /// hold back ACKs until `send_pending_ack`, so a burst of segments gets just one
+    pub(super) defer_acks: bool,
+    /// we owe the peer an ACK that hasn't gone out yet
+    pub(super) ack_pending: bool,
+
+    /// the application has asked us to close; our FIN goes out on the next tick.
+    pub(super) closed: bool,
+    /// take the fast path in `predict` for segments that fit it
+    pub(super) header_prediction: bool,
+    /// keep received data in the packet buffers it arrives in, rather than copy it
+    pub(super) zero_copy: bool,
+    /// how big those buffers are: the NIC's MTU, whatever ours is
+    pub(super) packet_buf_len: usize,
+
+    /// when we give up on the handshake completing
+    pub(super) handshake_deadline: Instant,
+    /// when we get to leave TimeWait
+    pub(super) time_wait: Option<Instant>,
+    /// once we've been asked to close, when we give up waiting for the peer to close with us
+    /// and reset the connection instead
+    pub(super) linger_deadline: Option<Instant>,
+    /// when we last sent or received a segment, for the idle timeout
+    pub(super) last_activity: Instant,
+    pub(super) idle_timeout: Option<Duration>,
+    /// the last ICMP error reported about the connection that wasn't worth aborting over. it's
+    /// only passed on to the application if the connection times out (RFC 1122 S4.2.3.9).
+    pub(super) soft_error: Option<icmp::Error>,
+
+    pub(super) observer: Option<StateObserver>,
+    pub(super) sampler: Option<CongestionSampler>,
+    /// carries the quad, so everything logged about this connection can be told apart
+    pub(super) span: tracing::Span,
+}
+
+impl Connection {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn accept<'a, N: Nic>(
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+        config: &ConnectionConfig,
+        budget: &Arc<MemoryBudget>,
+        quad: Quad,
+        tcph: etherparse::TcpHeaderSlice<'a>,
+        _data: &'a [u8],
+    ) -> io::Result<Option<Self>> {
+        if !tcph.syn() {
+            // only expected SYN packet
+            trace!(
+                src = %quad.src.0,
+                port = tcph.destination_port(),
+                "dropping non-SYN segment for a listening port"
+            );
+            return Ok(None);
+        }
+
+        let span = connection_span(quad);
+        let _g = span.enter();
+        #[cfg(feature = "tcp-md5")]
+        if let Some(key) = config.md5_key_for(quad.src.0)
+            && !md5::verify(quad.src.0, quad.dst.0, &tcph, _data, key)
+        {
+            debug!("dropping SYN with a missing or bad MD5 signature");
+            return Ok(None);
+        }
+        let iss = config.iss_for(&quad, now);
+        // everything after this is logged relative to these two
+        debug!(irs = tcph.sequence_number(), iss, "accepting connection");
+        let send = SendSequenceSpace {
+            iss,
+            una: iss,
+            nxt: iss,
+            max: iss,
+            wnd: tcph.window_size(),
+            up: false,
+
+            wl1: 0,
+            wl2: 0,
+        };
+        let recv = ReceiveSequenceSpace {
+            irs: tcph.sequence_number(),
+            nxt: tcph.sequence_number().wrapping_add(1),
+            wnd: config.recv_window,
+            up: false,
+        };
+        let negotiated = Negotiated::from_syn(quad.src.0, &tcph);
+        let mut c = Connection::new(
+            nic,
+            now,
+            config,
+            budget,
+            quad,
+            span.clone(),
+            send,
+            recv,
+            negotiated,
+        );
+
+        // need to start establishing a connection
+        c.send_syn(nic, tx)?;
+        c.rtt_probe = Some((c.send.nxt, now));
+        c.rto_deadline = Some(now + c.rto);
+        Ok(Some(c))
+    }
+
+    /// Open a connection from our end of `quad` to the peer's, sending our SYN.
+    pub(crate) fn connect<N: Nic>(
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+        config: &ConnectionConfig,
+        budget: &Arc<MemoryBudget>,
+        quad: Quad,
+    ) -> io::Result<Self> {
+        let span = connection_span(quad);
+        let _g = span.enter();
+        let iss = config.iss_for(&quad, now);
+        debug!(iss, "opening connection");
+        let send = SendSequenceSpace {
+            iss,
+            una: iss,
+            nxt: iss,
+            max: iss,
+            wnd: 0,
+            up: false,
+
+            wl1: 0,
+            wl2: 0,
+        };
+        // the peer's SYN fills in the rest, and says what options are in use
+        let recv = ReceiveSequenceSpace {
+            irs: 0,
+            nxt: 0,
+            wnd: config.recv_window,
+            up: false,
+        };
+        let negotiated = Negotiated::assumed(quad.src.0);
+        let mut c = Connection::new(
+            nic,
+            now,
+            config,
+            budget,
+            quad,
+            span.clone(),
+            send,
+            recv,
+            negotiated,
+        );
+        c.state = State::SynSent;
+
+        c.send_syn(nic, tx)?;
+        c.rtt_probe = Some((c.send.nxt, now));
+        c.rto_deadline = Some(now + c.rto);
+        Ok(c)
+    }
+
+    /// Pick up a connection that's already past the handshake, as `established` describes it,
+    /// without sending anything. See `Established` for what the caller has to get right.
+    pub(crate) fn restore<N: Nic>(
+        nic: &mut N,
+        now: Instant,
+        config: &ConnectionConfig,
+        budget: &Arc<MemoryBudget>,
+        quad: Quad,
+        established: &Established,
+    ) -> io::Result<Self> {
+        let Established {
+            state,
+            snd_una,
+            snd_wnd,
+            rcv_nxt,
+            mss,
+        } = *established;
+        // the FIN we've sent, and what's become of the peer's
+        let (fin_sent, fin_in_flight, fin_received) = match state {
+            State::Estab => (false, false, false),
+            State::FinWait1 => (true, true, false),
+            State::FinWait2 => (true, false, false),
+            State::CloseWait => (false, false, true),
+            State::Closing | State::LastAck => (true, true, true),
+            State::TimeWait => (true, false, true),
+            State::SynSent | State::SynRcvd | State::Closed => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "can only restore a connection that's established or closing",
+                ));
+            }
+        };
+        let span = connection_span(quad);
+        let _g = span.enter();
+        debug!(?state, snd_una, rcv_nxt, "restoring connection");
+
+        let nxt = snd_una.wrapping_add(fin_in_flight as u32);
+        let send = SendSequenceSpace {
+            // as far as anything still to happen is concerned, the SYN was a byte ago
+            iss: snd_una.wrapping_sub(1),
+            una: snd_una,
+            nxt,
+            max: nxt,
+            wnd: snd_wnd,
+            up: false,
+
+            wl1: 0,
+            wl2: 0,
+        };
+        let recv = ReceiveSequenceSpace {
+            irs: rcv_nxt.wrapping_sub(1),
+            nxt: rcv_nxt,
+            wnd: config.recv_window,
+            up: false,
+        };
+        let negotiated = Negotiated {
+            mss,
+            window_scaling: false,
+            sack: false,
+            timestamps: false,
+        };
+        let mut c = Connection::new(
+            nic,
+            now,
+            config,
+            budget,
+            quad,
+            span.clone(),
+            send,
+            recv,
+            negotiated,
+        );
+        c.state = state;
+        if fin_sent {
+            c.close(now);
+        }
+        if fin_received {
+            c.shared.buffers.lock().unwrap().recv_closed = true;
+        }
+        if fin_in_flight {
+            c.rto_deadline = Some(now + c.rto);
+        }
+        if state == State::TimeWait {
+            c.time_wait = Some(now + 2 * MSL);
+        }
+        Ok(c)
+    }
+
+    /// A connection in SynRcvd with the given sequence spaces, not yet having sent anything.
+    #[allow(clippy::too_many_arguments)]
+    fn new<N: Nic>(
+        nic: &mut N,
+        now: Instant,
+        config: &ConnectionConfig,
+        budget: &Arc<MemoryBudget>,
+        quad: Quad,
+        span: tracing::Span,
+        send: SendSequenceSpace,
+        recv: ReceiveSequenceSpace,
+        negotiated: Negotiated,
+    ) -> Self {
+        let (iss, wnd) = (send.iss, recv.wnd);
+        let mut c = Connection {
+            quad,
+            state: State::SynRcvd,
+            wnd_advertised: wnd,
+            // nothing's been promised until the first window is worked out, below
+            rcv_adv: recv.nxt,
+            recv_tuning: config
+                .recv_window_max
+                .map(|max| RecvTuning::new(config.recv_window, max)),
+            send,
+            recv,
+            ip: ip::Outgoing::new(quad.dst.0, quad.src.0),
+            ip_id: 1,
+            negotiated,
+            mtu: config.mtu.map_or(nic.mtu(), |m| m.min(nic.mtu())),
+            cc: Box::new(Reno::new(0)),
+            congestion_control: config.congestion_control.clone(),
+            initial_window: config.initial_window,
+            dup_acks: 0,
+            recover: iss,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: MIN_RTO,
+            rto_deadline: None,
+            retransmits: 0,
+            rtt_probe: None,
+            mtu_probing: config.mtu_probing.clone(),
+            blackhole_timeouts: 0,
+            probing_mtu: false,
+            mtu_blackhole: false,
+            dont_fragment: config.dont_fragment,
+            #[cfg(feature = "tcp-md5")]
+            md5_key: config.md5_key_for(quad.src.0).cloned(),
+            shared: Arc::new(Shared {
+                buffers: Mutex::new(Buffers {
+                    recv_buffer_size: wnd as usize,
+                    send_buffer_size: config.send_buffer,
+                    send_low_watermark: config.send_low_watermark,
+                    linger: Some(2 * MSL),
+                    charge: Charge::new(budget.clone()),
+                    ..Buffers::default()
+                }),
+                ..Shared::default()
+            }),
+            defer_acks: false,
+            ack_pending: false,
+            closed: false,
+            header_prediction: config.header_prediction,
+            zero_copy: config.zero_copy,
+            packet_buf_len: nic.mtu(),
+            handshake_deadline: now + config.handshake_timeout,
+            time_wait: None,
+            linger_deadline: None,
+            last_activity: now,
+            idle_timeout: config.idle_timeout,
+            soft_error: None,
+            observer: None,
+            sampler: None,
+            span,
+        };
+
+        c.ip.set_dont_fragment(config.dont_fragment);
+        c.init_cc();
+        // the first window goes out on the SYN or SYN-ACK, so it has to be the one we'd
+        // advertise from then on
+        c.update_recv_window();
+        c.rcv_adv = c.recv.nxt.wrapping_add(c.recv.wnd as u32);
+        c
+    }
+
+    /// Make the congestion control afresh, with the initial window counted in segments of
+    /// the current SMSS.
+    fn init_cc(&mut self) {
+        let iw = initial_cwnd(self.initial_window, self.smss());
+        self.cc = self.congestion_control.build(iw);
+    }
+
+    /// Send our SYN, from ISS, telling the peer how much fits in a packet on our end: a bare
+    /// SYN in SynSent, and a SYN-ACK answering the peer's in SynRcvd.
+    pub(super) fn send_syn<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8]) -> io::Result<()> {
+        let syn = Control {
+            syn: true,
+            ..Control::default()
+        };
+        self.transmit(nic, tx, self.send.iss, 0, syn).map(|_| ())
+    }
+
+    /// Acknowledge everything received so far, or just make a note to if ACKs are deferred.
+    fn ack<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8]) -> io::Result<()> {
+        if self.defer_acks {
+            self.ack_pending = true;
+            return Ok(());
+        }
+        self.transmit(nic, tx, self.send.nxt, 0, Control::default())
+            .map(|_| ())
+    }
+
+    /// Start or stop holding back ACKs. While deferred, segments that only need an ACK in
+    /// response don't get one until `send_pending_ack`.
+    pub(crate) fn set_defer_acks(&mut self, defer: bool) {
+        self.defer_acks = defer;
+    }
+
+    /// Send the ACK we've been holding back, if there is one.
+    pub(crate) fn send_pending_ack<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+    ) -> io::Result<()> {
+        if self.ack_pending {
+            self.transmit(nic, tx, self.send.nxt, 0, Control::default())?;
+        }
+        Ok(())
+    }
+
+    /// Acknowledge everything received so far right away, whether or not an ACK is owed. None
+    /// is held back past the end of a batch, so this mostly repeats the last one, with the
+    /// window as it is now. Before the peer's SYN there's nothing to acknowledge, and once the
+    /// connection is closed nobody to tell.
+    pub(crate) fn flush_ack<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8]) -> io::Result<()> {
+        if let State::SynSent | State::Closed = self.state {
+            return Ok(());
+        }
+        self.transmit(nic, tx, self.send.nxt, 0, Control::default())
+            .map(|_| ())
+    }
+
+    /// Abort the connection: <SEQ=SND.NXT><ACK=RCV.NXT><CTL=RST,ACK> (RFC 793 S3.9, ABORT),
+    /// with a zero window.
+    pub(super) fn send_rst<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8]) -> io::Result<()> {
+        let rst = Control {
+            rst: true,
+            ..Control::default()
+        };
+        self.transmit(nic, tx, self.send.nxt, 0, rst).map(|_| ())
+    }
+
+    /// Abort the connection (RFC 9293 S3.10.5, ABORT): send a RST if the peer may still be
+    /// expecting anything of it, and go straight to CLOSED. The connection is closed even if
+    /// the RST couldn't be sent.
+    pub(crate) fn abort<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8]) -> io::Result<()> {
+        let res = match self.state {
+            State::SynRcvd
+            | State::Estab
+            | State::FinWait1
+            | State::FinWait2
+            | State::CloseWait => self.send_rst(nic, tx),
+            State::SynSent | State::Closing | State::LastAck | State::TimeWait => Ok(()),
+            State::Closed => return Ok(()),
+        };
+        self.closed = true;
+        self.set_state(State::Closed, None);
+        res
+    }
+
+    /// Answer a segment that isn't part of this connection at all: <SEQ=SEG.ACK><CTL=RST>
+    /// (RFC 9293 S3.10.7.3), with `seq` being the segment's ACK. It's from the other
+    /// connection's sequence space, so SND.NXT stays where it is, and it acknowledges nothing.
+    fn send_reset_at<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8], seq: u32) -> io::Result<()> {
+        let rst = Control {
+            rst: true,
+            no_ack: true,
+            ..Control::default()
+        };
+        self.try_transmit(nic, tx, seq, 0, rst).map(|_| ())
+    }
+
+    /// Take a segment from the peer, whose payload `data` is in the packet buffer `buf` if the
+    /// packet loop can share that.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn on_packet<'a, N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+        tcph: etherparse::TcpHeaderSlice<'a>,
+        data: &'a [u8],
+        buf: Option<&PacketBuf>,
+    ) -> io::Result<()> {
+        let span = self.span.clone();
+        let _g = span.enter();
+        // the application may have read since we last looked
+        self.update_recv_window();
+        trace!(
+            seq = %self.rcv_seq(tcph.sequence_number()),
+            ack = %self.snd_seq(tcph.acknowledgment_number()),
+            syn = tcph.syn(),
+            fin = tcph.fin(),
+            rst = tcph.rst(),
+            len = data.len(),
+            wnd = tcph.window_size(),
+            "received segment"
+        );
+        #[cfg(feature = "tcp-md5")]
+        if let Some(key) = &self.md5_key
+            && !md5::verify(self.quad.src.0, self.quad.dst.0, &tcph, data, key)
+        {
+            debug!("dropping segment with a missing or bad MD5 signature");
+            return Ok(());
+        }
+        self.last_activity = now;
+
+        if self.header_prediction && self.is_predicted(&tcph, data) {
+            return self.on_predicted(nic, tx, now, &tcph, data, buf);
+        }
+
+        if let State::SynSent = self.state {
+            return self.on_syn_sent(nic, tx, now, &tcph, data);
+        }
+
+        if let State::SynRcvd = self.state
+            && tcph.syn()
+            && tcph.sequence_number() == self.recv.irs
+        {
+            // the peer is retransmitting the SYN we already accepted, which means our SYN-ACK
+            // was lost. it's not new data, so just send the SYN-ACK again from the top.
+            debug!("peer retransmitted its SYN; resending SYN-ACK");
+            self.rtt_probe = None;
+            return self.send_syn(nic, tx);
+        }
+
+        let seqn = tcph.sequence_number();
+        let mut slen = data.len() as u32;
+        if tcph.fin() {
+            slen += 1;
+        }
+        if tcph.syn() {
+            slen += 1;
+        }
+
+        if let State::TimeWait = self.state
+            && tcph.fin()
+            && seqn.wrapping_add(slen) == self.recv.nxt
+        {
+            // the peer is retransmitting the FIN we already took, so our final ACK must have been
+            // lost. ACK it again and start the 2MSL wait over (RFC 793 S3.9).
+            debug!("peer retransmitted its FIN; re-ACKing");
+            self.ack(nic, tx)?;
+            self.time_wait = Some(now + 2 * MSL);
+            return Ok(());
+        }
+
+        // first, check that sequence numbers are valid (RFC 793 S3.3)
+        if !segment_acceptable(self.recv.nxt, self.recv.wnd as u32, seqn, slen) {
+            trace!(
+                rcv_nxt = %self.rcv_seq(self.recv.nxt),
+                rcv_wnd = self.recv.wnd,
+                "dropping segment outside the receive window"
+            );
+            if tcph.rst() {
+                return Ok(());
+            }
+            // an old duplicate, or a retransmission whose ACK was lost: tell the peer where we
+            // are, <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
+            return self.ack(nic, tx);
+        }
+        // RCV.NXT only moves past what's actually taken in below, as it's taken in
+        let rcv_nxt = self.recv.nxt;
+        let rcv_wnd = self.recv.wnd;
+
+        // second, check the RST bit
+        if tcph.rst() {
+            return self.on_reset(nic, tx, seqn, SegmentSummary::new(&tcph, data.len()));
+        }
+
+        if !tcph.ack() {
+            return Ok(());
+        }
+
+        // acceptable ack check
+        //  SND.UNA < SEQ.ACK =< SND.NXT
+        // remember wrapping!
+        //
+
+        let ackn = tcph.acknowledgment_number();
+        let seg = SegmentSummary::new(&tcph, data.len());
+        // to tell whether this ACK frees up room for more data
+        let (una, wnd) = (self.send.una, self.send.wnd);
+        if let State::SynRcvd = self.state {
+            if is_between_wrapped(self.send.una, ackn, self.send.nxt.wrapping_add(1)) {
+                // must have ACKed our SYN, since we detected at least one acked byte,
+                // and we have only sent one byte (SYN). whatever else the segment carries,
+                // data or a FIN, is taken below as if we'd been established all along, and
+                // so is any later segment that overtook the one that was meant to finish
+                // the handshake.
+                self.send.wnd = tcph.window_size();
+                self.send.wl1 = seqn;
+                self.send.wl2 = ackn;
+                self.set_state(State::Estab, Some(seg));
+            } else {
+                warn!(
+                    ack = %self.snd_seq(ackn),
+                    snd_nxt = %self.snd_seq(self.send.nxt),
+                    "handshake ACK for something we never sent"
+                );
+                // none of the rest of it can be taken until the handshake is done, and the
+                // peer is told so with <SEQ=SEG.ACK><CTL=RST> (RFC 9293 S3.10.7.4)
+                return self.send_reset_at(nic, tx, ackn);
+            }
+        }
+
+        if let State::Estab
+        | State::FinWait1
+        | State::FinWait2
+        | State::CloseWait
+        | State::Closing
+        | State::LastAck = self.state
+        {
+            if wrapping_lt(self.send.max, ackn) {
+                // it acknowledges something we haven't sent, so the rest of it can't be trusted
+                // either: ACK, and drop it (RFC 793 S3.9)
+                debug!(
+                    ack = %self.snd_seq(ackn),
+                    snd_max = %self.snd_seq(self.send.max),
+                    "ACK for unsent data"
+                );
+                return self.ack(nic, tx);
+            }
+            // a duplicate ACK (SEG.ACK =< SND.UNA) is ignored, but the rest of the segment
+            // (notably a FIN) still needs processing.
+            if is_between_wrapped(self.send.una, ackn, self.send.max.wrapping_add(1)) {
+                self.on_new_ack(nic, tx, now, ackn)?;
+            } else if ackn == self.send.una
+                && self.send.una != self.send.max
+                && data.is_empty()
+                && !tcph.syn()
+                && !tcph.fin()
+                && tcph.window_size() == wnd
+            {
+                // a duplicate ACK as RFC 5681 S2 defines it: nothing to it but the same ACK and
+                // window again, while we have something outstanding
+                self.on_duplicate_ack(nic, tx, now)?;
+            }
+
+            self.update_send_window(seqn, ackn, tcph.window_size());
+            if self.send.wnd == 0 && ackn == self.send.una {
+                // the peer is there, just not taking anything: an answer to a window probe,
+                // which mustn't count against it however long the window stays shut (RFC
+                // 1122 S4.2.2.17)
+                self.retransmits = 0;
+            }
+            self.check_invariants();
+            self.sample(now);
+
+            if !data.is_empty() {
+                if let State::Estab | State::FinWait1 | State::FinWait2 = self.state {
+                    // we only take what's next in sequence and fits in the buffer, not
+                    // necessarily the whole segment. our FIN only closed our half, so the
+                    // peer can go on sending until its own FIN.
+                    let out_of_order = wrapping_lt(self.recv.nxt, seqn);
+                    self.receive(seqn, data, buf, now);
+                    if out_of_order {
+                        // a duplicate ACK, which the peer counts towards a fast retransmit, so
+                        // it goes right away, one for each such segment, even in the middle of
+                        // a batch (RFC 5681 S4.2)
+                        self.transmit(nic, tx, self.send.nxt, 0, Control::default())?;
+                    } else {
+                        // the ACK waits until the end, in case this segment's ACK lets out
+                        // data that can carry it
+                        self.ack_pending = true;
+                    }
+                } else {
+                    // the peer has sent its FIN, so there's no more data to come (RFC 9293
+                    // S3.10.7.4, eighth step)
+                    debug!(len = data.len(), "ignoring data past the peer's FIN");
+                }
+            }
+        }
+
+        // the FIN only counts once we have everything before it, and if it fits in the window
+        // too. if it comes after data we didn't take, is out of order, or the data before it
+        // filled the window, it'll be back.
+        let fin = tcph.fin()
+            && self.recv.nxt == seqn.wrapping_add(data.len() as u32)
+            && self.recv.nxt.wrapping_sub(rcv_nxt) < rcv_wnd as u32;
+
+        if let State::FinWait1 = self.state
+            && self.send.una == self.send.max
+        {
+            // our FIN has been ACKed! (it's the last thing we sent)
+            self.set_state(State::FinWait2, Some(seg));
+        }
+
+        if let State::Closing = self.state
+            && self.send.una == self.send.max
+        {
+            // our FIN has been ACKed, and we'd already ACKed theirs
+            self.time_wait = Some(now + 2 * MSL);
+            self.set_state(State::TimeWait, Some(seg));
+        }
+
+        if let State::LastAck = self.state
+            && self.send.una == self.send.max
+        {
+            // our FIN has been ACKed, and we've already seen theirs
+            self.set_state(State::Closed, Some(seg));
+        }
+
+        if fin {
+            match self.state {
+                State::Estab => {
+                    // the peer is done sending; ACK the FIN and wait for the application to close
+                    self.recv.nxt = self.recv.nxt.wrapping_add(1);
+                    self.ack(nic, tx)?;
+                    self.set_state(State::CloseWait, Some(seg));
+                }
+                State::FinWait1 => {
+                    // the peer closed too, before it saw our FIN: ACK theirs, and wait for ours
+                    // to be ACKed
+                    self.recv.nxt = self.recv.nxt.wrapping_add(1);
+                    self.ack(nic, tx)?;
+                    self.set_state(State::Closing, Some(seg));
+                }
+                State::FinWait2 => {
+                    // we're done with the connection!
+                    self.recv.nxt = self.recv.nxt.wrapping_add(1);
+                    self.ack(nic, tx)?;
+                    self.time_wait = Some(now + 2 * MSL);
+                    self.set_state(State::TimeWait, Some(seg));
+                }
+                State::TimeWait => {
+                    self.ack(nic, tx)?;
+                    self.time_wait = Some(now + 2 * MSL);
+                }
+                // we've had the peer's FIN already, so there's no more sequence space for
+                // another to take up
+                _ => debug!("ignoring a FIN past the peer's first"),
+            }
+        }
+
+        if let State::Estab | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck =
+            self.state
+            && (self.send.una != una || self.send.wnd > wnd)
+        {
+            // ACK clocking: new data goes out as ACKs make room for it, so a flight is paced by
+            // the rate the peer takes in the last one rather than sent in one burst. the same
+            // goes for resending what's left after a timeout.
+            self.send_queued(nic, tx, now)?;
+        }
+        if self.ack_pending && !self.defer_acks {
+            // nothing went out that could carry the ACK for the data we took
+            self.transmit(nic, tx, self.send.nxt, 0, Control::default())?;
+        }
+
+        // whatever we accept has to have fit in the window we advertised
+        debug_assert!(
+            self.recv.nxt.wrapping_sub(rcv_nxt) <= rcv_wnd as u32,
+            "RCV.NXT moved from {} to {}, outside the window of {}",
+            rcv_nxt,
+            self.recv.nxt,
+            rcv_wnd
+        );
+        Ok(())
+    }
+
+    /// Take a segment that arrives while we wait for the peer to answer our SYN (RFC 9293
+    /// S3.10.7.3). A SYN-ACK establishes the connection, and a RST that ACKs our SYN refuses
+    /// it. Anything else the segment carries, data or a FIN, is left for the peer to send again.
+    fn on_syn_sent<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+        tcph: &etherparse::TcpHeaderSlice,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let seqn = tcph.sequence_number();
+        let ackn = tcph.acknowledgment_number();
+        let seg = SegmentSummary::new(tcph, data.len());
+        if tcph.ack() && !is_between_wrapped(self.send.iss, ackn, self.send.nxt.wrapping_add(1)) {
+            debug!(ack = %self.snd_seq(ackn), "dropping segment that ACKs something we never sent");
+            if !tcph.rst() {
+                // most likely the peer still has an old connection with the same ports, e.g.
+                // in TIME-WAIT. the RST clears it out of the way of our next SYN.
+                self.send_reset_at(nic, tx, ackn)?;
+            }
+            return Ok(());
+        }
+        if tcph.rst() {
+            if tcph.ack() {
+                // there's nothing listening on the port
+                debug!("connection refused");
+                self.shared.buffers.lock().unwrap().error = Some(TcpError::Refused);
+                self.closed = true;
+                self.set_state(State::Closed, Some(seg));
+            }
+            // without the ACK, there's no telling it's about our SYN at all
+            return Ok(());
+        }
+        if !tcph.syn() {
+            return Ok(());
+        }
+        if !tcph.ack() {
+            // TODO: simultaneous open (RFC 9293 S3.5), where the peer's SYN crossed ours
+            debug!("ignoring SYN that doesn't ACK ours");
+            return Ok(());
+        }
+
+        // everything after this is logged relative to the IRS too
+        debug!(irs = seqn, "peer answered our SYN");
+        self.recv.irs = seqn;
+        self.recv.nxt = seqn.wrapping_add(1);
+        self.negotiated = Negotiated::from_syn(self.quad.src.0, tcph);
+        self.init_cc();
+        self.send.wnd = tcph.window_size();
+        self.send.wl1 = seqn;
+        self.send.wl2 = ackn;
+        self.send.una = ackn;
+        self.on_ack_progress(ackn, 0, now);
+        self.set_state(State::Estab, Some(seg));
+
+        // anything written during the handshake goes out now, carrying the ACK of the peer's
+        // SYN. if there's nothing, the ACK goes on its own.
+        self.ack_pending = true;
+        self.send_queued(nic, tx, now)?;
+        if self.ack_pending {
+            self.ack(nic, tx)?;
+        }
+        Ok(())
+    }
+
+    /// Take a RST that's within the window, in any state but SYN-SENT (RFC 9293 S3.10.7.4).
+    /// Only one right at RCV.NXT is sure to be from the peer; anything else gets a challenge
+    /// ACK, which the peer answers with a RST that is, if it really meant to reset the
+    /// connection (RFC 5961 S3.2). A half-open connection from a listener just goes away, as
+    /// nobody has a handle on it yet, and one that's established fails whatever the
+    /// application does with it next. Once we've closed our side, all that's left to do is
+    /// forget it.
+    fn on_reset<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        seqn: u32,
+        seg: SegmentSummary,
+    ) -> io::Result<()> {
+        if seqn != self.recv.nxt {
+            debug!(seq = %self.rcv_seq(seqn), "RST isn't at RCV.NXT; sending a challenge ACK");
+            return self.ack(nic, tx);
+        }
+        match self.state {
+            State::SynRcvd => debug!("half-open connection reset"),
+            State::Estab | State::FinWait1 | State::FinWait2 | State::CloseWait => {
+                debug!("connection reset by peer");
+                self.shared.buffers.lock().unwrap().error = Some(TcpError::Reset);
+            }
+            State::Closing | State::LastAck | State::TimeWait => {
+                debug!("connection reset while closing");
+            }
+            State::SynSent | State::Closed => return Ok(()),
+        }
+        self.rto_deadline = None;
+        self.time_wait = None;
+        self.closed = true;
+        self.set_state(State::Closed, Some(seg));
+        Ok(())
+    }
+
+    /// Take an ACK of new data, SND.UNA < SEG.ACK =< SND.MAX: drop what it covers from the send
+    /// queue and move SND.UNA up to it, and resend the next segment if it's a partial ACK
+    /// while we recover from a loss.
+    pub(super) fn on_new_ack<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+        ackn: u32,
+    ) -> io::Result<()> {
+        let mut acked = ackn.wrapping_sub(self.send.una) as usize;
+        if self.send.una == self.send.iss {
+            // the first ACK also covers our SYN, which isn't in the queue
+            acked -= 1;
+        }
+        // and anything past the end of the queue can only be our FIN
+        let mut b = self.shared.buffers.lock().unwrap();
+        let acked = std::cmp::min(acked, b.unacked.len());
+        b.unacked.drain(..acked);
+        b.pushed = b.pushed.saturating_sub(acked);
+        b.update_charge();
+        let writable = b.update_writable();
+        drop(b);
+        if writable {
+            self.shared.wake_writers();
+        }
+        self.send.una = ackn;
+        if wrapping_lt(self.send.nxt, ackn) {
+            // we'd gone back to retransmit, and the originals turned up after all
+            self.send.nxt = ackn;
+        }
+        self.on_ack_progress(ackn, acked, now);
+        if self.cc.in_recovery() {
+            // a partial ACK: another segment from the window we're recovering was lost, and
+            // it's the one at the new SND.UNA (RFC 6582 S3.2 step 4)
+            self.retransmit_first(nic, tx)?;
+        }
+        Ok(())
+    }
+
+    /// Take the peer's window from a segment with the given SEG.SEQ and SEG.ACK, as long as
+    /// it isn't older than the last one we took the window from (RFC 793 S3.9, with
+    /// SND.UNA =< SEG.ACK =< SND.NXT per RFC 1122).
+    pub(super) fn update_send_window(&mut self, seqn: u32, ackn: u32, wnd: u16) {
+        if is_between_wrapped(
+            self.send.una.wrapping_sub(1),
+            ackn,
+            self.send.max.wrapping_add(1),
+        ) && (wrapping_lt(self.send.wl1, seqn)
+            || (self.send.wl1 == seqn && !wrapping_lt(ackn, self.send.wl2)))
+        {
+            self.send.wnd = wnd;
+            self.send.wl1 = seqn;
+            self.send.wl2 = ackn;
+        }
+    }
+
+    /// Tell the congestion sampler, if there is one, where things stand after a segment.
+    pub(super) fn sample(&self, now: Instant) {
+        if let Some(sampler) = &self.sampler {
+            sampler(&CongestionSample {
+                at: now,
+                quad: self.quad,
+                cwnd: Some(self.cc.cwnd()),
+                ssthresh: self.cc.ssthresh(),
+                srtt: self.srtt,
+                bytes_in_flight: self.bytes_in_flight(),
+                send_window: self.send.wnd,
+            });
+        }
+    }
+
+    /// Bookkeeping for an ACK that moved SND.UNA forward by `acked` bytes of data: take an RTT
+    /// sample if it covers the segment being timed, let congestion control know, and restart
+    /// the retransmission timer for whatever is still outstanding.
+    fn on_ack_progress(&mut self, ackn: u32, acked: usize, now: Instant) {
+        if let Some((end, sent)) = self.rtt_probe
+            && !wrapping_lt(ackn, end)
+        {
+            self.rtt_probe = None;
+            self.on_rtt_sample(now - sent);
+        }
+        self.cc.on_ack(&self.ack_event(now, ackn, acked as u32));
+        self.dup_acks = 0;
+        self.retransmits = 0;
+        self.blackhole_timeouts = 0;
+        if self.probing_mtu {
+            // the smaller segments got through where the bigger ones didn't, so we've found a
+            // black hole. keep the MSS we stepped down to, with DF back on for it.
+            debug!(mtu = self.mtu, "path MTU black hole confirmed");
+            self.probing_mtu = false;
+            self.mtu_blackhole = true;
+            self.ip.set_dont_fragment(self.dont_fragment);
+        }
+        // RFC 6298 S5.2, S5.3
+        self.rto_deadline = (ackn != self.send.max).then(|| now + self.rto);
+    }
+
+    /// The ACK of `ackn`, just processed, as congestion control sees it.
+    fn ack_event(&self, now: Instant, ackn: u32, acked: u32) -> AckEvent {
+        AckEvent {
+            now,
+            ack: ackn,
+            acked,
+            in_flight: self.bytes_in_flight(),
+            smss: self.smss() as u32,
+            srtt: self.srtt,
+        }
+    }
+
+    /// Count a duplicate ACK of SND.UNA. The third in a row means the segment there was lost,
+    /// so it's resent right away rather than after the timeout (RFC 5681 S3.2), and more go out
+    /// after it if congestion control lets them.
+    fn on_duplicate_ack<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+    ) -> io::Result<()> {
+        if !self.cc.in_recovery() {
+            self.dup_acks += 1;
+        }
+        // not for a window we've already recovered from, or resent after a timeout: those
+        // duplicates are just the peer catching up with the retransmissions (RFC 6582 S3.2
+        // step 1, S4)
+        if self.dup_acks == DUP_ACK_THRESHOLD && !wrapping_lt(self.send.una, self.recover) {
+            debug!(
+                una = %self.snd_seq(self.send.una),
+                "three duplicate ACKs; fast retransmit"
+            );
+            self.recover = self.send.max;
+            self.cc.on_loss(&LossEvent {
+                now,
+                in_flight: self.send.max.wrapping_sub(self.send.una),
+                recover: self.recover,
+                smss: self.smss() as u32,
+            });
+            self.retransmit_first(nic, tx)?;
+        } else {
+            let ack = self.ack_event(now, self.send.una, 0);
+            self.cc.on_duplicate_ack(&ack);
+        }
+        self.send_queued(nic, tx, now)
+    }
+
+    /// Resend the segment at SND.UNA, and only that one, leaving SND.NXT where it was. If the
+    /// peer has shrunk its window since, only as much of it as still fits.
+    fn retransmit_first<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8]) -> io::Result<()> {
+        let n = std::cmp::min(self.unacked_len(), self.smss());
+        let n = std::cmp::min(n, self.send.wnd as usize);
+        if n == 0 {
+            // all that's outstanding is our FIN, or the window is shut; the timer will see to
+            // either
+            return Ok(());
+        }
+        // Karn's algorithm: the ACK won't say which copy it's for
+        self.rtt_probe = None;
+        self.transmit(nic, tx, self.send.una, n, Control::default())
+            .map(|_| ())
+    }
+
+    /// The buffers to hand to the connection's stream once it's accepted.
+    pub(crate) fn shared(&self) -> Arc<Shared> {
+        self.shared.clone()
+    }
+
+    pub(crate) fn state(&self) -> State {
+        self.state
+    }
+
+    pub(crate) fn set_observer(&mut self, observer: Option<StateObserver>) {
+        self.observer = observer;
+    }
+
+    pub(crate) fn set_sampler(&mut self, sampler: Option<CongestionSampler>) {
+        self.sampler = sampler;
+    }
+
+    pub(super) fn set_state(&mut self, to: State, segment: Option<SegmentSummary>) {
+        let from = std::mem::replace(&mut self.state, to);
+        debug!(?from, ?to, "state change");
+        if to == State::Estab {
+            // the handshake is done, for anyone waiting on it in `connect`
+            self.shared.buffers.lock().unwrap().connected = true;
+            self.shared.wake_writers();
+        }
+        if let State::CloseWait | State::Closing | State::LastAck | State::TimeWait = to {
+            // the peer's FIN means there's nothing more coming; a blocked reader can have its EOF
+            self.shared.buffers.lock().unwrap().recv_closed = true;
+            self.shared.wake_readers();
+        }
+        if let Some(observer) = &self.observer {
+            observer(&StateChange {
+                quad: self.quad,
+                from,
+                to,
+                segment,
+            });
+        }
+    }
+
+    /// Sanity checks on the sequence spaces that must hold between any two segments.
+    pub(crate) fn check_invariants(&self) {
+        let in_flight = self.bytes_in_flight();
+        // SND.UNA =< SND.NXT, in wrapped terms
+        debug_assert!(
+            in_flight < 1 << 31,
+            "SND.UNA ({}) is past SND.NXT ({})",
+            self.send.una,
+            self.send.nxt
+        );
+        // we can't have more in flight than we have queued, plus our SYN and FIN
+        let queued = self.unacked_len();
+        debug_assert!(
+            in_flight as usize <= queued + 2,
+            "{} sequence numbers in flight but only {} bytes queued",
+            in_flight,
+            queued
+        );
+        // the right edge we advertise is one we can buffer up to. the application reading only
+        // makes more room, so this holds even if it has since the window was worked out.
+        debug_assert!(
+            self.recv.wnd as usize <= self.recv_space(),
+            "advertising a window of {} with room for only {}",
+            self.recv.wnd,
+            self.recv_space()
+        );
+    }
+
+    /// Sequence numbers sent but not yet acknowledged (SND.NXT - SND.UNA).
+    pub(crate) fn bytes_in_flight(&self) -> u32 {
+        self.send.nxt.wrapping_sub(self.send.una)
+    }
+
+    pub(crate) fn info(&self) -> ConnectionInfo {
+        let b = self.shared.buffers.lock().unwrap();
+        ConnectionInfo {
+            state: self.state,
+            mss: self.negotiated.mss,
+            window_scaling: self.negotiated.window_scaling,
+            sack: self.negotiated.sack,
+            timestamps: self.negotiated.timestamps,
+            peer_window: self.send.wnd,
+            bytes_in_flight: self.bytes_in_flight(),
+            srtt: self.srtt,
+            send_buffer_len: b.send_buffer_len(),
+            recv_buffer_len: b.recv_buffer_len(),
+            recv_buffer_size: b.recv_buffer_size,
+            recv_buffer_max: self
+                .recv_tuning
+                .as_ref()
+                .map_or(b.recv_buffer_size, |t| t.max()),
+        }
+    }
+
+    /// The state machine's variables as they stand, for `Replay::snapshot`.
+    #[cfg(feature = "testing")]
+    pub(crate) fn snapshot(&self) -> crate::testing::Snapshot {
+        crate::testing::Snapshot {
+            snd_una: self.send.una,
+            snd_nxt: self.send.nxt,
+            snd_max: self.send.max,
+            snd_wnd: self.send.wnd,
+            rcv_irs: (self.state != State::SynSent).then_some(self.recv.irs),
+            rcv_nxt: self.recv.nxt,
+            rcv_wnd: self.recv.wnd,
+            info: self.info(),
+        }
+    }
+
+    /// Ask for the connection to be shut down. The FIN itself goes out once everything written
+    /// before it has, and the linger timeout starts from `now`.
+    pub(crate) fn close(&mut self, now: Instant) {
+        self.closed = true;
+        let mut b = self.shared.buffers.lock().unwrap();
+        b.shutdown_send();
+        if self.linger_deadline.is_none() {
+            self.linger_deadline = b.linger.map(|linger| now + linger);
+        }
+    }
+
+    /// The largest segment we'll send (SMSS): whatever fits in the MTU, but no more than the
+    /// peer said it can take.
+    pub(super) fn smss(&self) -> usize {
+        std::cmp::min(
+            self.mtu - self.ip.header_len() - self.header_len(),
+            self.negotiated.mss as usize,
+        )
+    }
+
+    /// How much may be in flight: the peer's window, or the congestion window if that's
+    /// smaller.
+    pub(super) fn send_limit(&self) -> usize {
+        std::cmp::min(self.send.wnd as usize, self.cc.cwnd() as usize)
+    }
+
+    /// Send as much not-yet-sent data as the peer's window and the congestion window allow,
+    /// one segment at a time, followed by our FIN if the application has closed. After going
+    /// back to SND.UNA, "not yet sent" includes everything that needs resending.
+    pub(super) fn send_queued<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+    ) -> io::Result<()> {
+        let mss = self.smss();
+        loop {
+            // our SYN has been acked by now, so everything in flight is data, and our FIN
+            let in_flight = self.bytes_in_flight() as usize;
+            let b = self.shared.buffers.lock().unwrap();
+            if in_flight > b.unacked.len() {
+                // the FIN is out, so there's nothing left
+                return Ok(());
+            }
+            let unsent = self.sendable(&b);
+            let allowed = self.send_limit().saturating_sub(in_flight);
+            let n = std::cmp::min(std::cmp::min(unsent, allowed), mss);
+            // the FIN has to come after everything that's been written, and takes up a
+            // sequence number of the window too. it rides on the last of the data if there's
+            // room, and goes on its own once there is otherwise.
+            let fin = self.closed && n == unsent && n < allowed;
+            if n == 0 && !fin {
+                return Ok(());
+            }
+            drop(b);
+            // time one segment per round trip, as long as it isn't a retransmission
+            let timed = self.rtt_probe.is_none() && self.send.nxt == self.send.max;
+            let control = Control {
+                fin,
+                ..Control::default()
+            };
+            self.transmit(nic, tx, self.send.nxt, n, control)?;
+            if timed {
+                self.rtt_probe = Some((self.send.nxt, now));
+            }
+            if self.rto_deadline.is_none() {
+                self.rto_deadline = Some(now + self.rto);
+            }
+            if !fin {
+                continue;
+            }
+
+            let next = match self.state {
+                State::Estab => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                // resending the FIN after a timeout
+                _ => return Ok(()),
+            };
+            self.set_state(next, None);
+            return Ok(());
+        }
+    }
+
+    /// React to an ICMP error about a segment we sent on this connection. Hard errors abort a
+    /// connection still in the handshake; anything else, including hard errors on a
+    /// synchronized connection (RFC 5461 S4), is only noted in case the connection later
+    /// times out. Errors quoting a sequence number we haven't got in flight are ignored, as
+    /// they can't be about anything we sent (RFC 5927 S4.1).
+    pub(crate) fn on_icmp(&mut self, seq: u32, err: icmp::Error) -> IcmpOutcome {
+        let span = self.span.clone();
+        let _g = span.enter();
+        if !self.is_in_flight(seq) {
+            trace!(seq = %self.snd_seq(seq), "ignoring ICMP error for data not in flight");
+            return IcmpOutcome::Ignored;
+        }
+        if err.hard && !self.state.is_synchronized() {
+            // it's still in the handshake, so it can just go, as on a handshake timeout. if
+            // we're the ones opening it, `connect` fails with the error.
+            debug!(error = err.msg, "ICMP error during the handshake; aborting");
+            self.shared.buffers.lock().unwrap().error = Some(err.cause());
+            self.closed = true;
+            self.set_state(State::Closed, None);
+            return IcmpOutcome::Aborted;
+        }
+        debug!(error = err.msg, "ICMP error");
+        self.soft_error = Some(err);
+        IcmpOutcome::Recorded
+    }
+
+    /// React to a fragmentation-needed or packet-too-big message saying the segment at `seq`
+    /// didn't fit through a link with the given `mtu`: send nothing bigger from now on, and
+    /// resend everything in flight in segments that fit, rather than wait for it to be
+    /// retransmitted (RFC 1191 S6.5, RFC 8201 S5.2).
+    pub(crate) fn on_packet_too_big<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+        seq: u32,
+        mtu: usize,
+    ) -> io::Result<IcmpOutcome> {
+        let span = self.span.clone();
+        let _g = span.enter();
+        let mtu = mtu.max(self.ip.min_mtu());
+        if !self.is_in_flight(seq) || mtu >= self.mtu {
+            trace!(seq = %self.snd_seq(seq), mtu, "ignoring stale packet-too-big message");
+            return Ok(IcmpOutcome::Ignored);
+        }
+        self.mtu = mtu;
+        // the path does tell us when something is too big, so timeouts aren't a black hole
+        self.blackhole_timeouts = 0;
+        debug!(mtu = self.mtu, "path MTU reduced");
+
+        if let State::Estab | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck =
+            self.state
+        {
+            // the send queue is just bytes, so going back to SND.UNA re-segments it for free
+            self.send.nxt = self.send.una;
+            self.rtt_probe = None;
+            self.send_queued(nic, tx, now)?;
+        }
+        Ok(IcmpOutcome::Recorded)
+    }
+
+    /// The largest IP packet we'll send, which starts off as the NIC's MTU and may come down
+    /// with path MTU discovery.
+    pub(crate) fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Whether probing has found a path MTU black hole since we last asked, in which case
+    /// `mtu` is what got through.
+    pub(crate) fn take_mtu_blackhole(&mut self) -> bool {
+        std::mem::take(&mut self.mtu_blackhole)
+    }
+
+    /// Whether the segment starting at `seq` has been sent and not yet acknowledged
+    /// (SND.UNA =< SEQ < SND.MAX), as a segment quoted back at us by ICMP should be.
+    fn is_in_flight(&self, seq: u32) -> bool {
+        is_between_wrapped(self.send.una.wrapping_sub(1), seq, self.send.max)
+    }
+
+    /// `seq` from our side of the connection, for logging relative to the ISS.
+    pub(super) fn snd_seq(&self, seq: u32) -> Relative {
+        Relative::new(seq, self.send.iss)
+    }
+
+    /// `seq` from the peer's side of the connection, for logging relative to the IRS.
+    pub(super) fn rcv_seq(&self, seq: u32) -> Relative {
+        Relative::new(seq, self.recv.irs)
+    }
+
+    /// Whether the connection is fully closed and the application has let go of it, so it can
+    /// be forgotten.
+    pub(crate) fn is_done(&self) -> bool {
+        self.closed && self.state == State::Closed
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // whoever has the stream shouldn't wait on it any longer, and nothing it's waiting with
+        // should outlive the connection. one that's gone to TIME-WAIT closed cleanly, though,
+        // and the stream is left with everything received, then EOF. this may be unwinding from
+        // a panic with the buffers held, and panicking again here would abort.
+        if self.state != State::TimeWait {
+            let mut b = self
+                .shared
+                .buffers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            b.aborted = true;
+        }
+        self.shared.wake_readers();
+        self.shared.wake_writers();
+    }
+}
+
+/// The tracing span a connection's events are recorded in.
+pub(super) fn connection_span(quad: Quad) -> tracing::Span {
+    tracing::debug_span!(
+        "conn",
+        src = %SocketAddr::new(quad.src.0, quad.src.1),
+        dst = %SocketAddr::new(quad.dst.0, quad.dst.1),
+    )
+}
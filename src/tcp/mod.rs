@@ -0,0 +1,460 @@
+//! A connection's side of the protocol: the state machine in `conn`, built on the sequence
+//! arithmetic in `seq`, the segment building and option parsing in `segment`, the data in
+//! `buffers`, the timers in `timers`, the fast path for the common case in `predict`, the
+//! sizing of the receive buffer in `autotune`, the stack-wide limit on buffer memory in
+//! `budget`, and what's kept of a connection in TIME-WAIT in `time_wait`.
+//! None of it deals with a device directly; segments come in as parsed headers and go out
+//! through a `Nic`.
+
+use std::io;
+#[cfg(feature = "tcp-md5")]
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::congestion::{self, CongestionControl};
+#[cfg(feature = "tcp-md5")]
+use crate::md5;
+use crate::{Quad, TcpError};
+
+mod autotune;
+mod budget;
+mod buffers;
+mod conn;
+mod predict;
+mod segment;
+mod seq;
+mod time_wait;
+mod timers;
+
+pub(crate) use budget::MemoryBudget;
+pub use buffers::Payload;
+pub(crate) use buffers::{Buffers, PacketBuf, Shared};
+pub(crate) use conn::Connection;
+#[cfg(feature = "tcp-md5")]
+pub(crate) use segment::find_option;
+#[cfg(feature = "testing")]
+pub(crate) use seq::{in_window, is_between_wrapped, segment_acceptable, wrapping_lt};
+pub(crate) use time_wait::{TimeWait, TimeWaitOutcome};
+
+/// Where a connection is in its life, as the states of RFC 9293 S3.3.2. There is no `Listen`,
+/// which is a `TcpListener`'s business.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    // Closed,
+    // Listen,
+    /// we've sent a SYN to open the connection, and are waiting for the peer's
+    SynSent,
+    SynRcvd,
+    Estab,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    /// both sides sent a FIN before seeing the other's: the peer's has been ACKed, ours not yet
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+impl State {
+    fn is_synchronized(&self) -> bool {
+        match *self {
+            Self::SynSent | Self::SynRcvd => false,
+            Self::Estab
+            | Self::FinWait1
+            | Self::FinWait2
+            | Self::CloseWait
+            | Self::Closing
+            | Self::LastAck
+            | Self::TimeWait
+            | Self::Closed => true,
+        }
+    }
+}
+
+/// The interesting bits of the segment that caused a state transition.
+#[derive(Clone, Copy, Debug)]
+pub struct SegmentSummary {
+    pub seq: u32,
+    /// the acknowledgment number, if the ACK bit was set
+    pub ack: Option<u32>,
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub len: usize,
+}
+
+impl SegmentSummary {
+    fn new(tcph: &etherparse::TcpHeaderSlice, len: usize) -> Self {
+        SegmentSummary {
+            seq: tcph.sequence_number(),
+            ack: tcph.ack().then(|| tcph.acknowledgment_number()),
+            syn: tcph.syn(),
+            fin: tcph.fin(),
+            rst: tcph.rst(),
+            len,
+        }
+    }
+}
+
+/// Handed to the state observer whenever a connection moves between states.
+#[derive(Clone, Copy, Debug)]
+pub struct StateChange {
+    pub quad: Quad,
+    pub from: State,
+    pub to: State,
+    /// the incoming segment that triggered the transition, or `None` if it was caused locally
+    /// (e.g. the application closing the connection).
+    pub segment: Option<SegmentSummary>,
+}
+
+/// The sender's view of the path, taken every time an ACK is processed, for the congestion
+/// sampler.
+#[derive(Clone, Copy, Debug)]
+pub struct CongestionSample {
+    pub at: Instant,
+    pub quad: Quad,
+    /// the congestion window, in bytes
+    pub cwnd: Option<u32>,
+    /// the slow start threshold, once a retransmission timeout has set one
+    pub ssthresh: Option<u32>,
+    /// the smoothed round-trip time, once there's been a segment to time
+    pub srtt: Option<Duration>,
+    /// sequence space sent but not yet acknowledged, after this ACK
+    pub bytes_in_flight: u32,
+    /// the peer's receive window, after this ACK
+    pub send_window: u16,
+}
+
+/// What was agreed with the peer during the handshake, and how the connection is doing now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub state: State,
+    /// the largest segment we'll send: the peer's MSS option, or the RFC 9293 default of 536 if
+    /// it didn't send one
+    pub mss: u16,
+    pub window_scaling: bool,
+    pub sack: bool,
+    pub timestamps: bool,
+    /// the receive window the peer most recently advertised
+    pub peer_window: u16,
+    /// sequence space sent but not yet acknowledged
+    pub bytes_in_flight: u32,
+    /// the smoothed round-trip time, once there's been a segment to time
+    pub srtt: Option<Duration>,
+    /// bytes written by the application that the peer hasn't acknowledged yet
+    pub send_buffer_len: usize,
+    /// bytes received that the application hasn't read yet
+    pub recv_buffer_len: usize,
+    /// how much received data we're willing to hold, which the receive window is what's left
+    /// of once that's taken away
+    pub recv_buffer_size: usize,
+    /// the most the receive buffer may grow to, which is its size unless it's autotuned
+    pub recv_buffer_max: usize,
+}
+
+/// Where a connection that's already past the handshake stands, to pick it up from there
+/// without one, e.g. when it's being moved over from another stack, or to start a test partway
+/// through a connection's life.
+///
+/// Nothing here is checked against the peer, so it's on the caller to describe the connection
+/// as the peer sees it:
+///
+/// - `state` is one of `Estab`, `FinWait1`, `FinWait2`, `CloseWait`, `Closing`, `LastAck` or
+///   `TimeWait`.
+/// - Everything we've sent has been acknowledged up to `snd_una`, except our FIN in `FinWait1`,
+///   `Closing` and `LastAck`, which is taken to be at `snd_una` and not acknowledged yet (so
+///   SND.NXT is one past it). It's retransmitted if the peer doesn't ACK it in time.
+/// - `rcv_nxt` is the next sequence number the peer will send, past its FIN in `CloseWait`,
+///   `Closing`, `LastAck` and `TimeWait`.
+/// - `mss` is what the peer will take, as its SYN would have said. No other options are in use.
+///
+/// The connection's buffers start out empty, and its timers as if it had just been set up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Established {
+    /// where the connection is: `Estab`, or one of the states on the way to closing
+    pub state: State,
+    /// SND.UNA: the oldest sequence number the peer hasn't acknowledged
+    pub snd_una: u32,
+    /// SND.WND: the window the peer last advertised
+    pub snd_wnd: u16,
+    /// RCV.NXT: the next sequence number expected from the peer
+    pub rcv_nxt: u32,
+    /// the largest segment the peer will take, as its MSS option said
+    pub mss: u16,
+}
+
+/// What a connection did about an ICMP error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum IcmpOutcome {
+    Aborted,
+    Recorded,
+    Ignored,
+}
+
+pub(crate) type StateObserver = Arc<dyn Fn(&StateChange) + Send + Sync>;
+
+pub(crate) type CongestionSampler = Arc<dyn Fn(&CongestionSample) + Send + Sync>;
+
+/// Per-connection tunables, fixed when a listener is bound.
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+    recv_window: u16,
+    recv_window_max: Option<u16>,
+    send_buffer: usize,
+    send_low_watermark: usize,
+    handshake_timeout: Duration,
+    idle_timeout: Option<Duration>,
+    dont_fragment: bool,
+    mtu: Option<usize>,
+    initial_window: u32,
+    mtu_probing: Option<MtuProbing>,
+    iss: Option<u32>,
+    congestion_control: congestion::Factory,
+    header_prediction: bool,
+    zero_copy: bool,
+    reuse_addr: bool,
+    #[cfg(feature = "tcp-md5")]
+    md5_keys: Vec<(IpAddr, md5::Key)>,
+}
+
+/// How to find a path MTU black hole: a path that silently drops segments too big for it
+/// instead of sending back the ICMP that path MTU discovery relies on (RFC 2923 S2.1).
+///
+/// Once a full-sized segment has timed out `after_timeouts` times in a row, with no ICMP about
+/// it, it's resent with the next MSS down in `mss_steps` and DF cleared. If that's ACKed, the
+/// connection keeps the smaller MSS; if not, it moves on to the next step after as many
+/// timeouts again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MtuProbing {
+    pub after_timeouts: u32,
+    /// MSS values to try, largest first
+    pub mss_steps: Vec<u16>,
+}
+
+impl Default for MtuProbing {
+    fn default() -> Self {
+        MtuProbing {
+            after_timeouts: 2,
+            mss_steps: vec![1024, 512],
+        }
+    }
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            recv_window: 1024,
+            recv_window_max: None,
+            send_buffer: 64 * 1024,
+            send_low_watermark: 64 * 1024,
+            // the classic BSD connection-establishment timer
+            handshake_timeout: Duration::from_secs(75),
+            idle_timeout: None,
+            dont_fragment: true,
+            mtu: None,
+            initial_window: 10,
+            mtu_probing: Some(MtuProbing::default()),
+            iss: None,
+            congestion_control: congestion::Factory::default(),
+            header_prediction: true,
+            zero_copy: false,
+            reuse_addr: true,
+            #[cfg(feature = "tcp-md5")]
+            md5_keys: Vec::new(),
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// The receive window we advertise, i.e. how much unread data we're willing to buffer.
+    ///
+    /// It must not be zero: the peer could never send us anything, so binding with such a
+    /// config fails with `InvalidInput`.
+    pub fn recv_window(mut self, wnd: u16) -> Self {
+        self.recv_window = wnd;
+        self
+    }
+
+    /// Let the receive buffer, and with it the window we advertise, grow from `recv_window`
+    /// up to `max` for as long as the application reads data as fast as it arrives, so a
+    /// connection ties up only as much memory as its path needs to run at full speed. It's
+    /// sized once a round trip, to twice what the application read in the last one, and back
+    /// down if data piles up unread. As windows aren't scaled, `max` is at most 64 KiB. Off
+    /// by default, leaving the buffer the size of `recv_window`; binding with a `max` smaller
+    /// than that fails with `InvalidInput`.
+    pub fn recv_window_max(mut self, max: u16) -> Self {
+        self.recv_window_max = Some(max);
+        self
+    }
+
+    /// How much data the application may have written that the peer hasn't acknowledged yet,
+    /// sent or not, before writes block (or return `WouldBlock`), and the low watermark the
+    /// send queue then has to drain below before they can go on. Until then, writers aren't
+    /// woken as the peer ACKs, so they aren't handed room a few bytes at a time. The default
+    /// is 64 KiB, with the watermark at the same, so any room at all will do. Binding with an
+    /// empty buffer, or a watermark of zero or above the buffer's size, fails with
+    /// `InvalidInput`.
+    pub fn send_buffer(mut self, size: usize, low_watermark: usize) -> Self {
+        self.send_buffer = size;
+        self.send_low_watermark = low_watermark;
+        self
+    }
+
+    /// How long a half-open connection may take to complete the handshake before we give up
+    /// on it.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Abort the connection with a RST if nothing is sent or received on it for `timeout`.
+    ///
+    /// With no keepalive probes, this is the only way to notice a peer that has silently gone
+    /// away (e.g. behind a NAT that dropped its mapping). Off by default.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Whether to set the don't-fragment bit on outgoing packets. On by default, as routers
+    /// shouldn't be fragmenting TCP segments on our behalf.
+    pub fn dont_fragment(mut self, df: bool) -> Self {
+        self.dont_fragment = df;
+        self
+    }
+
+    /// Send no IP packet larger than `mtu` bytes, even if the NIC could carry more, and
+    /// advertise an MSS to match. Mostly useful for trying out odd MTUs like 576.
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// The congestion window a new connection starts with, in segments: ten by default, as in
+    /// RFC 6928. As there, it's capped in bytes as if the MSS were 1460, so a path with a big
+    /// MSS doesn't start with an outsized burst, but never below two segments however small
+    /// the MSS.
+    pub fn initial_window(mut self, segments: u32) -> Self {
+        self.initial_window = segments.max(1);
+        self
+    }
+
+    /// How to probe for path MTU black holes, or `None` to never second-guess a path that
+    /// doesn't send ICMP. On by default.
+    pub fn mtu_probing(mut self, probing: Option<MtuProbing>) -> Self {
+        self.mtu_probing = probing;
+        self
+    }
+
+    /// Start every connection's sequence numbers at `iss`, instead of picking one per
+    /// connection as RFC 6528 has it. Only for tests and captures that need to come out the
+    /// same every time: anyone who can guess the ISS can spoof segments on the connection.
+    pub fn initial_sequence_number(mut self, iss: u32) -> Self {
+        self.iss = Some(iss);
+        self
+    }
+
+    /// The congestion control each connection uses, made by `new` from the connection's
+    /// initial window in bytes. `Reno` by default.
+    pub fn congestion_control<C, F>(mut self, new: F) -> Self
+    where
+        C: CongestionControl + 'static,
+        F: Fn(u32) -> C + Send + Sync + 'static,
+    {
+        self.congestion_control = congestion::Factory::new(new);
+        self
+    }
+
+    /// Whether to take a shortcut for the segments an established connection mostly gets: the
+    /// next one in sequence, carrying either new data or an ACK of new data and nothing else.
+    /// On by default. Turning it off sends every segment down the same path as the unusual
+    /// ones, which should make no difference to anything but speed.
+    pub fn header_prediction(mut self, on: bool) -> Self {
+        self.header_prediction = on;
+        self
+    }
+
+    /// Keep received data in the buffers the packet loop read it into, for
+    /// `TcpStream::recv_bytes` to hand out without copying it, rather than copy it into the
+    /// receive buffer. Off by default.
+    ///
+    /// A segment's packet buffer counts against the receive window in full, headers and all,
+    /// for as long as any of its data is unread, so small segments fill the window sooner.
+    /// Data that arrives without the room for that is copied after all.
+    pub fn zero_copy(mut self, on: bool) -> Self {
+        self.zero_copy = on;
+        self
+    }
+
+    /// Whether binding a port succeeds while connections that were on it are still in
+    /// TIME-WAIT, as with `SO_REUSEADDR`, so a server can be restarted straight away. On by
+    /// default; with it off, `bind` fails with `TcpError::PortInUse` until the last of them
+    /// has waited out its 2MSL. The connections' peers are safe either way: a SYN for a quad
+    /// still in TIME-WAIT only gets through if it can't be an old duplicate.
+    pub fn reuse_addr(mut self, on: bool) -> Self {
+        self.reuse_addr = on;
+        self
+    }
+
+    /// Sign every segment to and from `peer` with `key` (RFC 2385), and drop any from it that
+    /// aren't signed, or not with this key. Connections from peers without a key are left
+    /// alone. Setting a key for the same peer again replaces it.
+    #[cfg(feature = "tcp-md5")]
+    pub fn md5_key(mut self, peer: IpAddr, key: impl Into<Vec<u8>>) -> Self {
+        self.md5_keys.retain(|&(p, _)| p != peer);
+        self.md5_keys.push((peer, md5::Key(key.into())));
+        self
+    }
+
+    /// The key segments to and from `peer` are signed with, if any.
+    #[cfg(feature = "tcp-md5")]
+    fn md5_key_for(&self, peer: IpAddr) -> Option<&md5::Key> {
+        self.md5_keys
+            .iter()
+            .find(|&&(p, _)| p == peer)
+            .map(|(_, key)| key)
+    }
+
+    pub(crate) fn reuses_addr(&self) -> bool {
+        self.reuse_addr
+    }
+
+    /// The ISS for a new connection on `quad`: the configured one if there is one, or else
+    /// RFC 6528's, which for a given quad only ever grows with time.
+    fn iss_for(&self, quad: &Quad, now: Instant) -> u32 {
+        self.iss
+            .unwrap_or_else(|| seq::initial_sequence_number(quad, now))
+    }
+
+    /// Check for settings no connection could work with.
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        if self.recv_window == 0 {
+            return Err(TcpError::InvalidConfig("receive window must not be zero").into());
+        }
+        if self
+            .recv_window_max
+            .is_some_and(|max| max < self.recv_window)
+        {
+            return Err(TcpError::InvalidConfig(
+                "receive window maximum must not be below the receive window",
+            )
+            .into());
+        }
+        if self.send_low_watermark == 0 || self.send_low_watermark > self.send_buffer {
+            return Err(TcpError::InvalidConfig(
+                "send low watermark must be above zero and at most the send buffer",
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Lower the MTU to `mtu` if it's smaller than what's configured, e.g. for a path whose MTU
+    /// we've already discovered.
+    pub(crate) fn clamp_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(self.mtu.map_or(mtu, |m| m.min(mtu)));
+        self
+    }
+}
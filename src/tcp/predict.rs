@@ -0,0 +1,79 @@
+//! Header prediction (Jacobson, "4BSD Header Prediction", 1990): almost every segment an
+//! established connection gets is either the next run of data from a peer that's sending, or
+//! an ACK for more of ours, with the window the same as last time. Those take a short path
+//! that does just what `on_packet` would end up doing for them, without asking everything it
+//! has to ask of the rest.
+
+use std::io;
+use std::time::Instant;
+
+use super::buffers::PacketBuf;
+use super::segment::Control;
+use super::seq::wrapping_lt;
+use super::{Connection, State};
+use crate::nic::Nic;
+
+impl Connection {
+    /// Whether `tcph` is a segment the fast path can take: on an established connection, the
+    /// next one in sequence, with no control bits but ACK (and maybe PSH), the window we
+    /// already have, and either in-order data that fits in the window without ACKing anything
+    /// new, or no data and an ACK of something we've sent. Out-of-order data is never
+    /// buffered, so there's no reassembly to check on. While recovering from a loss, ACKs
+    /// stay on the slow path.
+    pub(super) fn is_predicted(&self, tcph: &etherparse::TcpHeaderSlice, data: &[u8]) -> bool {
+        if self.state != State::Estab
+            || !tcph.ack()
+            || tcph.syn()
+            || tcph.fin()
+            || tcph.rst()
+            || tcph.urg()
+            || tcph.sequence_number() != self.recv.nxt
+            || tcph.window_size() != self.send.wnd
+        {
+            return false;
+        }
+        let ackn = tcph.acknowledgment_number();
+        if data.is_empty() {
+            wrapping_lt(self.send.una, ackn)
+                && !wrapping_lt(self.send.nxt, ackn)
+                && !self.cc.in_recovery()
+        } else {
+            ackn == self.send.una && data.len() <= self.recv.wnd as usize
+        }
+    }
+
+    /// Take a segment `is_predicted` has said yes to, with the same outcome as `on_packet`
+    /// would have had.
+    pub(super) fn on_predicted<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+        tcph: &etherparse::TcpHeaderSlice,
+        data: &[u8],
+        buf: Option<&PacketBuf>,
+    ) -> io::Result<()> {
+        let seqn = tcph.sequence_number();
+        let ackn = tcph.acknowledgment_number();
+        if data.is_empty() {
+            self.on_new_ack(nic, tx, now, ackn)?;
+        }
+        self.update_send_window(seqn, ackn, tcph.window_size());
+        self.check_invariants();
+        self.sample(now);
+
+        if data.is_empty() {
+            // the ACK made room in the window, so more can go out
+            self.send_queued(nic, tx, now)?;
+        } else {
+            // it all fits, as the window was checked
+            self.receive(seqn, data, buf, now);
+            debug_assert_eq!(self.recv.nxt, seqn.wrapping_add(data.len() as u32));
+            self.ack_pending = true;
+        }
+        if self.ack_pending && !self.defer_acks {
+            self.transmit(nic, tx, self.send.nxt, 0, Control::default())?;
+        }
+        Ok(())
+    }
+}
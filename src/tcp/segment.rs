@@ -0,0 +1,301 @@
+//! What goes into the segments we send, and what we make of the options on the ones we get.
+
+use std::io;
+use std::net::IpAddr;
+
+use tracing::{debug, trace};
+
+use super::buffers::copy_out;
+use super::seq::wrapping_lt;
+use super::{Connection, State};
+use crate::nic::Nic;
+#[cfg(feature = "tcp-md5")]
+use crate::{Quad, md5};
+
+/// MSS to assume for an IPv4 peer that doesn't say (RFC 9293 S3.7.1).
+const DEFAULT_MSS: u16 = 536;
+
+/// MSS to assume for an IPv6 peer that doesn't say: what fits in the minimum IPv6 MTU of 1280
+/// (RFC 9293 S3.7.1, RFC 8200 S5).
+const DEFAULT_MSS_V6: u16 = 1220;
+
+/// The smallest MSS we'll take from a peer, as Linux does. Anything less would have us send a
+/// segment's worth of headers for every handful of bytes.
+const MIN_MSS: u16 = 88;
+
+/// Options from the peer's SYN that stay in force for the life of the connection.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Negotiated {
+    pub(super) mss: u16,
+    pub(super) window_scaling: bool,
+    pub(super) sack: bool,
+    pub(super) timestamps: bool,
+}
+
+impl Negotiated {
+    /// What's in force with a peer that hasn't told us otherwise, as if its SYN had no options.
+    pub(super) fn assumed(peer: IpAddr) -> Self {
+        let mss = match peer {
+            IpAddr::V4(_) => DEFAULT_MSS,
+            IpAddr::V6(_) => DEFAULT_MSS_V6,
+        };
+        // window scaling, SACK and timestamps are only in use if both sides ask for them, and
+        // our SYN never does, whatever the peer offered
+        Negotiated {
+            mss,
+            window_scaling: false,
+            sack: false,
+            timestamps: false,
+        }
+    }
+
+    pub(super) fn from_syn(peer: IpAddr, tcph: &etherparse::TcpHeaderSlice) -> Self {
+        let mut negotiated = Negotiated::assumed(peer);
+        // a malformed option list doesn't sink the handshake; we just stop reading there
+        if let Some(&[hi, lo]) = find_option(tcph.options(), OPTION_MSS) {
+            // zero can't be meant, so it's as if the option weren't there
+            match u16::from_be_bytes([hi, lo]) {
+                0 => {}
+                mss => negotiated.mss = std::cmp::max(mss, MIN_MSS),
+            }
+        }
+        negotiated
+    }
+}
+
+/// The control bits of a segment we send, besides ACK, which is on all but our SYN and the
+/// segments that ask for `no_ack`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Control {
+    pub(super) syn: bool,
+    pub(super) fin: bool,
+    pub(super) rst: bool,
+    /// Leave ACK off, as on a RST answering a segment that isn't part of the connection,
+    /// which has nothing to acknowledge.
+    pub(super) no_ack: bool,
+}
+
+const OPTION_END: u8 = 0;
+pub(super) const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+
+/// The data of the first option of the given `kind` in a TCP header's raw `options`, if there's
+/// one before the list ends or turns out to be malformed. Unlike etherparse's iterator, this
+/// steps over kinds it doesn't know, such as an MD5 signature in front of the MSS.
+pub(crate) fn find_option(mut options: &[u8], kind: u8) -> Option<&[u8]> {
+    loop {
+        match *options {
+            [] | [OPTION_END, ..] => return None,
+            [OPTION_NOP, ref rest @ ..] => options = rest,
+            [k, len, ref rest @ ..] => {
+                let data = rest.get(..(len as usize).checked_sub(2)?)?;
+                if k == kind {
+                    return Some(data);
+                }
+                options = &rest[data.len()..];
+            }
+            [_] => return None,
+        }
+    }
+}
+
+/// Fill in the blank MD5 signature at the end of the options of `tcph`, about to go out on
+/// `quad` with `payload`.
+#[cfg(feature = "tcp-md5")]
+pub(super) fn sign(
+    quad: Quad,
+    key: &md5::Key,
+    tcph: &mut etherparse::TcpHeader,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut header = [0; 60];
+    let len = tcph.header_len() as usize;
+    tcph.write(&mut &mut header[..])?;
+    let sig = md5::sign(quad.dst.0, quad.src.0, &header[..len], payload, key);
+    let mut options = [0; 40];
+    let n = tcph.options().len();
+    options[..n].copy_from_slice(tcph.options());
+    options[n - sig.len()..n].copy_from_slice(&sig);
+    tcph.set_options_raw(&options[..n])
+        .expect("failed to set options");
+    Ok(())
+}
+
+impl Connection {
+    /// Length of the TCP header on everything but our SYN: the fixed part, and an MD5
+    /// signature if we're signing segments.
+    pub(super) fn header_len(&self) -> usize {
+        #[cfg(feature = "tcp-md5")]
+        if self.md5_key.is_some() {
+            return etherparse::TCP_MINIMUM_HEADER_SIZE + 2 + md5::OPTION_LEN;
+        }
+        etherparse::TCP_MINIMUM_HEADER_SIZE
+    }
+
+    /// The header for a segment starting at `seq`, acknowledging everything received so far
+    /// and offering the current receive window, with the `control` bits set and the options
+    /// that go with them. The SYN that opens a connection has nothing to acknowledge yet, so
+    /// it goes without an ACK, as does anything whose `control` asks to. A RST offers no
+    /// window and carries no other control bits, even if `control` asks for them. An MSS
+    /// option only goes on a SYN. An MD5 signature goes last, so `sign` knows where to find
+    /// it, and is left blank for it to fill in.
+    fn header(&self, seq: u32, control: Control) -> etherparse::TcpHeader {
+        let mut tcph = if control.rst {
+            // there'll be no connection left to offer a window for, and a SYN or FIN would
+            // have the peer take it for something other than the end (RFC 9293 S3.5.2)
+            let mut tcph = etherparse::TcpHeader::new(self.quad.dst.1, self.quad.src.1, seq, 0);
+            tcph.rst = true;
+            tcph
+        } else {
+            let mut tcph =
+                etherparse::TcpHeader::new(self.quad.dst.1, self.quad.src.1, seq, self.recv.wnd);
+            tcph.syn = control.syn;
+            tcph.fin = control.fin;
+            tcph
+        };
+        tcph.ack = self.state != State::SynSent && !control.no_ack;
+        if tcph.ack {
+            tcph.acknowledgment_number = self.recv.nxt;
+        }
+
+        let mut options = [0; 40];
+        let mut n = 0;
+        if tcph.syn {
+            // not counting any options, which the sender takes off itself (RFC 6691)
+            let mss = self.mtu - self.ip.header_len() - etherparse::TCP_MINIMUM_HEADER_SIZE;
+            let [hi, lo] = (mss.min(u16::MAX as usize) as u16).to_be_bytes();
+            options[..4].copy_from_slice(&[OPTION_MSS, 4, hi, lo]);
+            n = 4;
+        }
+        #[cfg(feature = "tcp-md5")]
+        if self.md5_key.is_some() {
+            // padded out to a multiple of four in front, as Linux does
+            let len = md5::OPTION_LEN as u8;
+            options[n..n + 4].copy_from_slice(&[OPTION_NOP, OPTION_NOP, md5::OPTION_KIND, len]);
+            n += 2 + md5::OPTION_LEN;
+        }
+        tcph.set_options_raw(&options[..n])
+            .expect("failed to set options");
+        tcph
+    }
+
+    /// Fill in the MD5 signature of `tcph`, about to go out with `payload`, if we're signing
+    /// segments.
+    #[cfg(feature = "tcp-md5")]
+    fn sign(&self, tcph: &mut etherparse::TcpHeader, payload: &[u8]) -> io::Result<()> {
+        match &self.md5_key {
+            Some(key) => sign(self.quad, key, tcph, payload),
+            None => Ok(()),
+        }
+    }
+
+    /// Send the segment that starts at sequence number `seq`: up to `len` bytes of the send
+    /// queue from there, and the `control` bits. A FIN can only go right after the last of the
+    /// queue. The segment is assembled in `tx`, which is reused from one segment to the next and
+    /// is the size of the NIC's MTU. Returns how many sequence numbers the segment covers: its
+    /// data, plus one each for a SYN and a FIN.
+    ///
+    /// This is how everything goes out, whether for the first time, again, or with nothing in
+    /// it but an ACK (from SND.NXT). Only a segment from SND.NXT moves it on; anything before it
+    /// is a retransmission, and leaves it be.
+    ///
+    /// If the segment can't be put together, or the NIC doesn't take all of it, nothing is
+    /// sent as far as the connection is concerned: SND.NXT stays put, and an ACK that was owed
+    /// still is. Whatever asked for the segment sends it again in its own time, the way it
+    /// would if the segment had been lost.
+    pub(super) fn transmit<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        seq: u32,
+        len: usize,
+        control: Control,
+    ) -> io::Result<u32> {
+        let len = match self.try_transmit(nic, tx, seq, len, control) {
+            Ok(len) => len,
+            Err(e) => {
+                debug!(error = %e, "failed to send segment");
+                return Err(e);
+            }
+        };
+        // every segment carries the latest ACK
+        self.ack_pending = false;
+        self.wnd_advertised = self.recv.wnd;
+        self.rcv_adv = self.recv.nxt.wrapping_add(self.recv.wnd as u32);
+
+        let consumed = len as u32 + control.syn as u32 + control.fin as u32;
+        let end = seq.wrapping_add(consumed);
+        if seq == self.send.nxt {
+            self.send.nxt = end;
+        }
+        if wrapping_lt(self.send.max, end) {
+            self.send.max = end;
+        }
+        self.check_invariants();
+        Ok(consumed)
+    }
+
+    /// Assemble the segment for `transmit` and hand it to the NIC, returning how much data it
+    /// carried. Leaves the connection's state alone.
+    pub(super) fn try_transmit<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        seq: u32,
+        len: usize,
+        control: Control,
+    ) -> io::Result<usize> {
+        let mut tcph = self.header(seq, control);
+        let headers = self.ip.header_len() + tcph.header_len() as usize;
+        // where `seq` is in the send queue. our SYN has been ACKed by the time there's data, so
+        // the queue starts at SND.UNA.
+        let offset = seq.wrapping_sub(self.send.una) as usize;
+        let b = self.shared.buffers.lock().unwrap();
+        let queued = b.unacked.len().saturating_sub(offset);
+        let len = std::cmp::min(std::cmp::min(len, queued), self.mtu - headers);
+        debug_assert!(
+            !control.fin || len == queued,
+            "FIN at {} isn't after the end of the queue",
+            seq.wrapping_add(len as u32)
+        );
+        let size = headers + len;
+        if len > 0 {
+            copy_out(&b.unacked, offset, &mut tx[headers..size]);
+        }
+        drop(b);
+
+        self.ip.set_payload_len(size - self.ip.header_len())?;
+        self.ip.set_identification(self.ip_id);
+        // skip zero on the way around, so the ID is never the "unset" value
+        self.ip_id = self.ip_id.checked_add(1).unwrap_or(1);
+        #[cfg(feature = "tcp-md5")]
+        self.sign(&mut tcph, &tx[headers..size])?;
+
+        // over everything that goes on the wire, so last, once the header and payload are final
+        tcph.checksum = self.ip.tcp_checksum(&tcph, &tx[headers..size])?;
+        trace!(
+            seq = %self.snd_seq(tcph.sequence_number),
+            ack = %self.rcv_seq(tcph.acknowledgment_number),
+            syn = tcph.syn,
+            fin = tcph.fin,
+            rst = tcph.rst,
+            len,
+            wnd = tcph.window_size,
+            "sending segment"
+        );
+
+        // write out the headers
+
+        let mut unwritten = &mut tx[..];
+        self.ip.write(&mut unwritten)?;
+        tcph.write(&mut unwritten)?;
+        let sent = nic.send(&tx[..size])?;
+        if sent < size {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("NIC took {sent} of {size} bytes"),
+            ));
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,163 @@
+//! Sequence numbers: the two sequence spaces a connection keeps, comparisons that hold up when
+//! the numbers wrap around, where they start, and how they read in logs.
+
+use std::fmt;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::Quad;
+
+/// Send Sequence Space (RFC 793 S3.2 F4)
+/// ```text
+///                1         2          3          4
+///           ----------|----------|----------|----------
+///                  SND.UNA    SND.NXT    SND.UNA
+///                                       +SND.WND
+///
+///      1 - old sequence numbers which have been acknowledged
+///      2 - sequence numbers of unacknowledged data
+///      3 - sequence numbers allowed for new data transmission
+///      4 - future sequence numbers which are not yet allowed
+/// ```
+pub(super) struct SendSequenceSpace {
+    /// - send unacknowledged
+    pub(super) una: u32,
+    /// - send next
+    pub(super) nxt: u32,
+    /// - the highest sequence number sent so far, which SND.NXT falls back from when we go
+    ///   back to retransmit
+    pub(super) max: u32,
+    /// - send window
+    pub(super) wnd: u16,
+    /// - send urgent pointer
+    #[allow(dead_code)]
+    pub(super) up: bool,
+    /// - segment sequence number used for last window update
+    pub(super) wl1: u32,
+    /// - segment acknowledgment number used for last window update
+    pub(super) wl2: u32,
+    /// - initial send sequence number
+    pub(super) iss: u32,
+}
+
+/// Receive Sequence Space (RFC 793 S3.2 F5)
+/// ```text
+///                1          2          3
+///            ----------|----------|----------
+///                   RCV.NXT    RCV.NXT
+///                             +RCV.WND
+///
+///     1 - old sequence numbers which have been acknowledged
+///     2 - sequence numbers allowed for new reception
+///     3 - future sequence numbers which are not yet allowed
+/// ```
+pub(super) struct ReceiveSequenceSpace {
+    /// - receive next
+    pub(super) nxt: u32,
+    /// - receive window
+    pub(super) wnd: u16,
+    /// - receive urgent pointer
+    #[allow(dead_code)]
+    pub(super) up: bool,
+    /// - initial received sequence number
+    pub(super) irs: u32,
+}
+
+pub(crate) fn wrapping_lt(lhs: u32, rhs: u32) -> bool {
+    // From RFC1323:
+    //     TCP determines if a data segment is "old" or "new" by testing
+    //     whether its sequence number is within 2**31 bytes of the left edge
+    //     of the window, and if it is not, discarding the data as "old".  To
+    //     insure that new data is never mistakenly considered old and vice-
+    //     versa, the left edge of the sender's window has to be at most
+    //     2**31 away from the right edge of the receiver's window.
+    lhs.wrapping_sub(rhs) > (1 << 31)
+}
+
+/// Whether `x` lies in the half-open window `[start, end)`, counting forward from `start`
+/// around the sequence space. A window with `start == end` is empty.
+///
+/// Both ends are measured as distances forward from `start`, so wraparound needs no special
+/// cases: `x` is in the window iff it's closer to `start` than `end` is.
+pub(crate) fn in_window(start: u32, x: u32, end: u32) -> bool {
+    x.wrapping_sub(start) < end.wrapping_sub(start)
+}
+
+/// Whether `x` lies strictly between `start` and `end` (S < X < E), going forward from `start`
+/// around the sequence space.
+pub(crate) fn is_between_wrapped(start: u32, x: u32, end: u32) -> bool {
+    x != start && in_window(start, x, end)
+}
+
+/// The segment acceptability test from RFC 793 S3.3: whether a segment of `len` sequence
+/// numbers starting at `seq` overlaps the receive window at all.
+///
+/// ```text
+///    Segment Receive  Test
+///    Length  Window
+///    ------- -------  -------------------------------------------
+///       0       0     SEG.SEQ = RCV.NXT
+///       0      >0     RCV.NXT =< SEG.SEQ < RCV.NXT+RCV.WND
+///      >0       0     not acceptable
+///      >0      >0     RCV.NXT =< SEG.SEQ < RCV.NXT+RCV.WND
+///                  or RCV.NXT =< SEG.SEQ+SEG.LEN-1 < RCV.NXT+RCV.WND
+/// ```
+pub(crate) fn segment_acceptable(rcv_nxt: u32, rcv_wnd: u32, seq: u32, len: u32) -> bool {
+    let wend = rcv_nxt.wrapping_add(rcv_wnd);
+    match (len, rcv_wnd) {
+        (0, 0) => seq == rcv_nxt,
+        (0, _) => in_window(rcv_nxt, seq, wend),
+        (_, 0) => false,
+        (_, _) => {
+            in_window(rcv_nxt, seq, wend) || in_window(rcv_nxt, seq.wrapping_add(len - 1), wend)
+        }
+    }
+}
+
+/// The ISS for a new connection on `quad` (RFC 6528 S3): a keyed hash of the quad, so it
+/// can't be predicted from the outside, plus a clock ticking every 4 microseconds, so a new
+/// incarnation of the same quad starts ahead of where an old one left off.
+pub(super) fn initial_sequence_number(quad: &Quad, now: Instant) -> u32 {
+    // the secret is made up once per process, and the clock counts from the same moment
+    static SECRET: OnceLock<(RandomState, Instant)> = OnceLock::new();
+    let (key, epoch) = SECRET.get_or_init(|| (RandomState::new(), Instant::now()));
+    let ticks = |d: Duration| (d.as_nanos() / 4000) as u32;
+    // a clock of our own (like the tests') may be behind the real one, so count back too
+    let m = match now.checked_duration_since(*epoch) {
+        Some(d) => ticks(d),
+        None => ticks(*epoch - now).wrapping_neg(),
+    };
+    m.wrapping_add(key.hash_one(quad) as u32)
+}
+
+/// `seq` the way it's worth reading in logs, as Wireshark shows it: as its offset from `start`,
+/// the ISS or IRS of its sequence space, so the SYN is 0 and the first byte of data is 1. The
+/// alternate form, `{:#}`, adds the absolute number.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) struct Relative {
+    seq: u32,
+    start: u32,
+}
+
+impl Relative {
+    pub(super) fn new(seq: u32, start: u32) -> Self {
+        Relative { seq, start }
+    }
+}
+
+impl fmt::Display for Relative {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.seq.wrapping_sub(self.start))?;
+        if f.alternate() {
+            write!(f, " ({})", self.seq)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Relative {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,202 @@
+//! What's kept of a connection once it's in TIME-WAIT: just enough to ACK the peer's FIN again
+//! if our last ACK was lost, and to tell a new SYN on the same quad from an old duplicate,
+//! until the 2MSL wait is up. A busy server has far more of these than live connections, so
+//! they go without the buffers, timers and headers a `Connection` has.
+
+use std::io;
+use std::time::Instant;
+
+use tracing::{debug, trace};
+
+#[cfg(feature = "tcp-md5")]
+use super::segment::{self, OPTION_NOP};
+use super::seq::{segment_acceptable, wrapping_lt};
+use super::timers::MSL;
+use super::{Connection, ConnectionConfig, ConnectionInfo, SegmentSummary, State, conn};
+#[cfg(feature = "tcp-md5")]
+use crate::md5;
+use crate::nic::Nic;
+use crate::{Quad, ip};
+
+/// A connection in TIME-WAIT, less everything it no longer needs. The interface keeps these
+/// apart from its connections, keyed by quad.
+#[derive(Debug)]
+pub(crate) struct TimeWait {
+    /// SND.NXT, just past our FIN
+    snd_nxt: u32,
+    /// RCV.NXT, just past the peer's FIN
+    rcv_nxt: u32,
+    /// the window we last offered, which the re-ACK offers again
+    wnd: u16,
+    mss: u16,
+    ip_id: u16,
+    dont_fragment: bool,
+    /// when the 2MSL wait is up
+    expiry: Instant,
+    #[cfg(feature = "tcp-md5")]
+    md5_key: Option<md5::Key>,
+}
+
+/// What a segment for a quad in TIME-WAIT did to it.
+pub(crate) enum TimeWaitOutcome {
+    /// nothing, or it was a retransmitted FIN, which has been ACKed again
+    Kept,
+    /// the peer reset it, so it's done with
+    Reset(SegmentSummary),
+    /// a SYN for a new connection on the same quad, which the listener should have
+    Reopened(SegmentSummary),
+}
+
+impl Connection {
+    /// What's left to keep of the connection now it's in TIME-WAIT.
+    pub(crate) fn to_time_wait(&self, now: Instant) -> TimeWait {
+        debug_assert_eq!(self.state, State::TimeWait);
+        TimeWait {
+            snd_nxt: self.send.nxt,
+            rcv_nxt: self.recv.nxt,
+            wnd: self.recv.wnd,
+            mss: self.negotiated.mss,
+            ip_id: self.ip_id,
+            dont_fragment: self.dont_fragment,
+            expiry: self.time_wait.unwrap_or(now + 2 * MSL),
+            #[cfg(feature = "tcp-md5")]
+            md5_key: self.md5_key.clone(),
+        }
+    }
+}
+
+impl TimeWait {
+    pub(crate) fn expiry(&self) -> Instant {
+        self.expiry
+    }
+
+    /// Whether a new connection of ours opened on `quad` with `config` can take the quad over
+    /// from this one now: as long as its ISS is past everything this one sent, the peer can
+    /// tell the two apart, and nothing old can be taken for part of the new one (RFC 6191 S2).
+    pub(crate) fn reusable(&self, config: &ConnectionConfig, quad: &Quad, now: Instant) -> bool {
+        !wrapping_lt(config.iss_for(quad, now), self.snd_nxt)
+    }
+
+    /// A snapshot for `Interface::connections`, with nothing in flight or buffered.
+    pub(crate) fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            state: State::TimeWait,
+            mss: self.mss,
+            window_scaling: false,
+            sack: false,
+            timestamps: false,
+            peer_window: 0,
+            bytes_in_flight: 0,
+            srtt: None,
+            send_buffer_len: 0,
+            recv_buffer_len: 0,
+            recv_buffer_size: 0,
+            recv_buffer_max: 0,
+        }
+    }
+
+    /// As `Connection::snapshot` would have it, with everything we sent ACKed.
+    #[cfg(feature = "testing")]
+    pub(crate) fn snapshot(&self) -> crate::testing::Snapshot {
+        crate::testing::Snapshot {
+            snd_una: self.snd_nxt,
+            snd_nxt: self.snd_nxt,
+            snd_max: self.snd_nxt,
+            snd_wnd: 0,
+            rcv_irs: None,
+            rcv_nxt: self.rcv_nxt,
+            rcv_wnd: self.wnd,
+            info: self.info(),
+        }
+    }
+
+    /// Take a segment from the peer on `quad`, as `Connection::on_packet` would in TIME-WAIT.
+    pub(crate) fn on_packet<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+        quad: Quad,
+        tcph: &etherparse::TcpHeaderSlice,
+        data: &[u8],
+    ) -> io::Result<TimeWaitOutcome> {
+        let span = conn::connection_span(quad);
+        let _g = span.enter();
+        #[cfg(feature = "tcp-md5")]
+        if let Some(key) = &self.md5_key
+            && !md5::verify(quad.src.0, quad.dst.0, tcph, data, key)
+        {
+            debug!("dropping segment with a missing or bad MD5 signature");
+            return Ok(TimeWaitOutcome::Kept);
+        }
+        let seqn = tcph.sequence_number();
+        let seg = SegmentSummary::new(tcph, data.len());
+
+        if tcph.syn() && !tcph.ack() && !tcph.rst() && wrapping_lt(self.rcv_nxt, seqn) {
+            // it starts past anything the old connection could have sent, so it can't be an
+            // old duplicate: a new connection can have the quad (RFC 1122 S4.2.2.13)
+            debug!(seq = seqn, "new SYN for a connection in TIME-WAIT");
+            return Ok(TimeWaitOutcome::Reopened(seg));
+        }
+
+        let slen = data.len() as u32 + tcph.fin() as u32 + tcph.syn() as u32;
+        if tcph.fin() && seqn.wrapping_add(slen) == self.rcv_nxt {
+            // our final ACK must have been lost. ACK the FIN again and start the 2MSL wait
+            // over (RFC 793 S3.9).
+            debug!("peer retransmitted its FIN; re-ACKing");
+            self.ack(nic, tx, quad)?;
+            self.expiry = now + 2 * MSL;
+            return Ok(TimeWaitOutcome::Kept);
+        }
+        if !segment_acceptable(self.rcv_nxt, self.wnd as u32, seqn, slen) {
+            trace!("dropping segment outside the receive window");
+            return Ok(TimeWaitOutcome::Kept);
+        }
+        if tcph.rst() {
+            if seqn != self.rcv_nxt {
+                debug!("RST isn't at RCV.NXT; sending a challenge ACK");
+                self.ack(nic, tx, quad)?;
+                return Ok(TimeWaitOutcome::Kept);
+            }
+            debug!("connection reset while closing");
+            return Ok(TimeWaitOutcome::Reset(seg));
+        }
+        Ok(TimeWaitOutcome::Kept)
+    }
+
+    /// Send <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>, assembled in `tx`.
+    fn ack<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8], quad: Quad) -> io::Result<()> {
+        let mut tcph = etherparse::TcpHeader::new(quad.dst.1, quad.src.1, self.snd_nxt, self.wnd);
+        tcph.ack = true;
+        tcph.acknowledgment_number = self.rcv_nxt;
+        #[cfg(feature = "tcp-md5")]
+        if let Some(key) = &self.md5_key {
+            let mut options = [0; 2 + md5::OPTION_LEN];
+            let len = md5::OPTION_LEN as u8;
+            options[..4].copy_from_slice(&[OPTION_NOP, OPTION_NOP, md5::OPTION_KIND, len]);
+            tcph.set_options_raw(&options)
+                .expect("failed to set options");
+            segment::sign(quad, key, &mut tcph, &[])?;
+        }
+
+        let mut ip = ip::Outgoing::new(quad.dst.0, quad.src.0);
+        ip.set_dont_fragment(self.dont_fragment);
+        ip.set_identification(self.ip_id);
+        self.ip_id = self.ip_id.checked_add(1).unwrap_or(1);
+        ip.set_payload_len(tcph.header_len() as usize)?;
+        tcph.checksum = ip.tcp_checksum(&tcph, &[])?;
+
+        let size = ip.header_len() + tcph.header_len() as usize;
+        let mut unwritten = &mut tx[..];
+        ip.write(&mut unwritten)?;
+        tcph.write(&mut unwritten)?;
+        let sent = nic.send(&tx[..size])?;
+        if sent < size {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("NIC took {sent} of {size} bytes"),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+//! Everything a connection does because time has passed rather than because a segment
+//! arrived: retransmission, the TimeWait, handshake and idle timeouts, and RTT estimation.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, trace};
+
+use super::segment::Control;
+use super::{Connection, State};
+use crate::congestion::LossEvent;
+use crate::nic::Nic;
+use crate::{TcpError, icmp};
+
+/// Maximum segment lifetime. We linger in TimeWait for twice this.
+pub(super) const MSL: Duration = Duration::from_secs(30);
+
+/// Bounds on the retransmission timeout (RFC 6298 S2.4, S2.5).
+pub(super) const MIN_RTO: Duration = Duration::from_secs(1);
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+/// How many times in a row a segment may time out before we give up on the peer, about
+/// fifteen minutes' worth with the timeout doubling each time as in Linux's `tcp_retries2`.
+const MAX_RETRANSMISSIONS: u32 = 15;
+
+impl Connection {
+    /// Fold a round-trip time measurement into SRTT and RTTVAR, and recompute the
+    /// retransmission timeout from them (RFC 6298 S2.2, S2.3).
+    pub(super) fn on_rtt_sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let err = srtt.abs_diff(rtt);
+                self.rttvar = self.rttvar * 3 / 4 + err / 4;
+                self.srtt = Some(srtt * 7 / 8 + rtt / 8);
+            }
+        }
+        self.rto = (self.srtt.unwrap() + 4 * self.rttvar).clamp(MIN_RTO, MAX_RTO);
+        trace!(?rtt, srtt = ?self.srtt, rto = ?self.rto, "RTT sample");
+    }
+
+    /// Whether `on_tick` should send rather than leave it to the next ACK: either nothing is in
+    /// flight, so there's no ACK coming to clock the data out, or all that's left is our FIN.
+    /// Data held back by a cork doesn't count.
+    fn should_send_on_tick(&self) -> bool {
+        let in_flight = self.bytes_in_flight() as usize;
+        let unsent = self.sendable(&self.shared.buffers.lock().unwrap());
+        (in_flight == 0 && unsent > 0) || (unsent == 0 && self.closed)
+    }
+
+    /// Whether the application has read enough for a window of `wnd` to reopen one we'd
+    /// closed. The window can also be left open just a crack, e.g. without copying, when the
+    /// last segment to fit had to be cut short. A sender avoiding silly windows (RFC 1122
+    /// S4.2.3.4) treats that as closed until its persist timer goes off, so one it'll use
+    /// counts as reopening it too.
+    fn is_window_reopened(&self, wnd: u16) -> bool {
+        let usable = self.usable_window() as u16;
+        (self.wnd_advertised == 0 && wnd > 0) || (self.wnd_advertised < usable && wnd >= usable)
+    }
+
+    /// How long until `on_tick` next has something to do, or `None` if nothing will happen
+    /// until a segment arrives or the application does something.
+    pub(crate) fn poll_delay(&self, now: Instant) -> Option<Duration> {
+        let in_flight = self.bytes_in_flight() as usize;
+        let can_send = match self.state {
+            State::Estab | State::CloseWait => {
+                // there's something to start sending, and the window has room for some of it
+                self.should_send_on_tick() && self.send_limit() > in_flight
+            }
+            _ => false,
+        };
+        let window_update = match self.state {
+            State::Estab | State::FinWait1 | State::FinWait2 => {
+                self.is_window_reopened(self.recv_window())
+            }
+            _ => false,
+        };
+        if can_send || window_update {
+            return Some(Duration::ZERO);
+        }
+
+        let deadline = match self.state {
+            State::SynSent | State::SynRcvd => Some(self.handshake_deadline),
+            State::TimeWait => self.time_wait,
+            State::Estab
+            | State::FinWait1
+            | State::FinWait2
+            | State::CloseWait
+            | State::Closing
+            | State::LastAck => {
+                let idle = self.idle_timeout.map(|t| self.last_activity + t);
+                [idle, self.linger_deadline].into_iter().flatten().min()
+            }
+            State::Closed => None,
+        };
+        let deadline = match (deadline, self.rto_deadline) {
+            (Some(d), Some(rto)) => Some(d.min(rto)),
+            (d, rto) => d.or(rto),
+        };
+        deadline.map(|d| d.saturating_duration_since(now))
+    }
+
+    pub(crate) fn on_tick<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+    ) -> io::Result<()> {
+        let span = self.span.clone();
+        let _g = span.enter();
+        self.update_recv_window();
+
+        if let State::Estab | State::CloseWait = self.state
+            && self.should_send_on_tick()
+        {
+            let nxt = self.send.nxt;
+            self.send_queued(nic, tx, now)?;
+            if self.send.nxt != nxt {
+                self.last_activity = now;
+            }
+        }
+
+        if let State::SynSent
+        | State::SynRcvd
+        | State::Estab
+        | State::CloseWait
+        | State::FinWait1
+        | State::Closing
+        | State::LastAck = self.state
+            && self.rto_deadline.is_some_and(|t| t <= now)
+        {
+            self.on_rto(nic, tx, now)?;
+        }
+
+        if let State::Estab | State::FinWait1 | State::FinWait2 = self.state
+            && self.is_window_reopened(self.recv.wnd)
+        {
+            // the peer won't send anything to find out until we tell it
+            self.transmit(nic, tx, self.send.nxt, 0, Control::default())?;
+        }
+
+        if let State::Estab
+        | State::FinWait1
+        | State::FinWait2
+        | State::CloseWait
+        | State::Closing
+        | State::LastAck = self.state
+            && self
+                .idle_timeout
+                .is_some_and(|t| self.last_activity + t <= now)
+        {
+            // the peer has gone quiet for too long; give up on it. whoever holds the stream
+            // finds out the next time they use it.
+            debug!("idle timeout expired; aborting");
+            self.send_rst(nic, tx)?;
+            self.shared.buffers.lock().unwrap().error = self.soft_error.map(icmp::Error::cause);
+            self.closed = true;
+            self.set_state(State::Closed, None);
+        }
+
+        if let State::Estab
+        | State::FinWait1
+        | State::FinWait2
+        | State::CloseWait
+        | State::Closing
+        | State::LastAck = self.state
+            && self.linger_deadline.is_some_and(|t| t <= now)
+        {
+            // we've been closing for as long as we're prepared to; the peer hasn't ACKed our
+            // FIN, or hasn't sent its own, and it's not going to get any longer. whoever is
+            // still holding the stream, a read half say, finds out that it timed out.
+            debug!("linger timeout expired; resetting");
+            self.send_rst(nic, tx)?;
+            self.shared.buffers.lock().unwrap().error = Some(TcpError::TimedOut);
+            self.set_state(State::Closed, None);
+        }
+
+        if let State::SynSent | State::SynRcvd = self.state
+            && self.handshake_deadline <= now
+        {
+            // nobody has a handle on a half-open connection from a listener, so it can go away
+            // entirely. one we're opening has `connect` waiting on it, which needs to know why.
+            debug!("handshake timed out");
+            let error = self
+                .soft_error
+                .map_or(TcpError::TimedOut, icmp::Error::cause);
+            self.shared.buffers.lock().unwrap().error = Some(error);
+            self.closed = true;
+            self.set_state(State::Closed, None);
+        }
+
+        if let State::TimeWait = self.state
+            && self.time_wait.is_some_and(|t| t <= now)
+        {
+            debug!("2MSL timer expired");
+            self.time_wait = None;
+            self.set_state(State::Closed, None);
+        }
+        Ok(())
+    }
+
+    /// The retransmission timer went off: back off, drop back to slow start, and go back to
+    /// SND.UNA to resend everything that hasn't been acknowledged (RFC 6298 S5.4-5.7, RFC 5681
+    /// S3.1), unless the peer has been unresponsive for so long that it's time to give up.
+    fn on_rto<N: Nic>(&mut self, nic: &mut N, tx: &mut [u8], now: Instant) -> io::Result<()> {
+        self.retransmits += 1;
+        if self.state.is_synchronized() && self.retransmits > MAX_RETRANSMISSIONS {
+            // a half-open connection has the handshake timeout instead
+            debug!("too many retransmissions; aborting");
+            self.send_rst(nic, tx)?;
+            let error = self
+                .soft_error
+                .map_or(TcpError::TimedOut, icmp::Error::cause);
+            self.shared.buffers.lock().unwrap().error = Some(error);
+            self.rto_deadline = None;
+            self.closed = true;
+            self.set_state(State::Closed, None);
+            return Ok(());
+        }
+        if self.state.is_synchronized() && self.send.wnd == 0 {
+            return self.on_window_probe_timeout(nic, tx, now);
+        }
+        debug!(retransmits = self.retransmits, rto = ?self.rto, "retransmission timeout");
+
+        if self.state.is_synchronized() {
+            self.check_blackhole();
+        }
+        self.cc.on_rto(&LossEvent {
+            now,
+            in_flight: self.send.max.wrapping_sub(self.send.una),
+            recover: self.send.max,
+            smss: self.smss() as u32,
+        });
+        self.dup_acks = 0;
+        self.recover = self.send.max;
+        self.rto = std::cmp::min(self.rto * 2, MAX_RTO);
+        self.rtt_probe = None;
+        // armed first, so that if the resend doesn't make it out, it's tried again next time
+        self.rto_deadline = Some(now + self.rto);
+
+        if self.state.is_synchronized() {
+            self.send.nxt = self.send.una;
+            self.send_queued(nic, tx, now)
+        } else {
+            self.send_syn(nic, tx)
+        }
+    }
+
+    /// The timer went off with the peer's window shut, whether or not it shrank over data
+    /// already in flight. Nothing's been lost, so it's no reason to slow down, and there's
+    /// nothing to resend but a probe of the first byte at SND.UNA, to find out when the window
+    /// opens again (RFC 1122 S4.2.2.17). Everything after it goes again once it has. The peer
+    /// answering keeps the connection up however long that takes.
+    fn on_window_probe_timeout<N: Nic>(
+        &mut self,
+        nic: &mut N,
+        tx: &mut [u8],
+        now: Instant,
+    ) -> io::Result<()> {
+        debug!(retransmits = self.retransmits, rto = ?self.rto, "probing zero window");
+        self.rto = std::cmp::min(self.rto * 2, MAX_RTO);
+        self.rtt_probe = None;
+        self.rto_deadline = Some(now + self.rto);
+        self.send.nxt = self.send.una;
+        let n = std::cmp::min(self.unacked_len(), 1);
+        let control = Control {
+            // all that's left is our FIN
+            fin: n == 0 && self.closed,
+            ..Control::default()
+        };
+        self.transmit(nic, tx, self.send.una, n, control)
+            .map(|_| ())
+    }
+
+    /// After a timeout, decide whether the path might be an MTU black hole (RFC 2923 S2.1),
+    /// and if so, step down to the next smaller MSS we're configured to try, with DF off.
+    fn check_blackhole(&mut self) {
+        let Some(probing) = &self.mtu_probing else {
+            return;
+        };
+        let smss = self.smss();
+        if self.unacked_len() < smss {
+            // what timed out wasn't a full-sized segment, so its size isn't the problem
+            return;
+        }
+        self.blackhole_timeouts += 1;
+        if self.blackhole_timeouts < probing.after_timeouts.max(1) {
+            return;
+        }
+        let Some(&mss) = probing.mss_steps.iter().find(|&&m| (m as usize) < smss) else {
+            return;
+        };
+        let mss = mss as usize;
+        self.blackhole_timeouts = 0;
+        self.mtu = mss + self.ip.header_len() + self.header_len();
+        self.probing_mtu = true;
+        self.ip.set_dont_fragment(false);
+        debug!(mss, "possible path MTU black hole; trying smaller segments");
+    }
+}
@@ -0,0 +1,929 @@
+//! Support code for exercising the stack without a tun device.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+use crate::iface::{ConnectionManager, Listener};
+use crate::nic::Nic;
+use crate::{
+    ConnectionConfig, ConnectionInfo, Established, IcmpStats, Payload, Quad, SegmentStats, State,
+    ip, pcap, tcp,
+};
+
+/// What one connection in TIME-WAIT takes up in the interface's table of them, quad included,
+/// though not counting the table's own overhead.
+pub const TIME_WAIT_ENTRY_SIZE: usize = size_of::<(Quad, tcp::TimeWait)>();
+
+#[derive(Default)]
+struct Wire {
+    incoming: VecDeque<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+    send_error: Option<io::ErrorKind>,
+}
+
+/// An in-memory NIC. Every packet the stack sends is recorded, and packets handed to `inject`
+/// are delivered to the stack as if they'd arrived on the wire.
+///
+/// Clones share the same wire, so a test can keep one handle while the interface owns another.
+#[derive(Clone)]
+pub struct MockNic {
+    wire: Arc<(Mutex<Wire>, Condvar)>,
+    mtu: usize,
+}
+
+impl Default for MockNic {
+    fn default() -> Self {
+        MockNic {
+            wire: Default::default(),
+            mtu: 1500,
+        }
+    }
+}
+
+impl MockNic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mtu(mtu: usize) -> Self {
+        MockNic {
+            mtu,
+            ..Self::default()
+        }
+    }
+
+    /// Queue up a packet for the stack to receive.
+    pub fn inject(&self, packet: &[u8]) {
+        let (wire, cvar) = &*self.wire;
+        wire.lock().unwrap().incoming.push_back(packet.to_vec());
+        cvar.notify_all();
+    }
+
+    /// Take all packets the stack has sent so far.
+    pub fn take_sent(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.wire.0.lock().unwrap().sent)
+    }
+
+    /// Number of packets the stack has sent that haven't been taken yet.
+    pub fn sent_len(&self) -> usize {
+        self.wire.0.lock().unwrap().sent.len()
+    }
+
+    /// Fail every send with an error of the given kind from now on, without recording the
+    /// packet, or with `None`, go back to sending normally.
+    pub fn fail_sends(&self, error: Option<io::ErrorKind>) {
+        self.wire.0.lock().unwrap().send_error = error;
+    }
+}
+
+impl Nic for MockNic {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut wire = self.wire.0.lock().unwrap();
+        if let Some(kind) = wire.send_error {
+            return Err(io::Error::new(kind, "injected send failure"));
+        }
+        wire.sent.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (wire, cvar) = &*self.wire;
+        let mut wire = wire.lock().unwrap();
+        loop {
+            if let Some(packet) = wire.incoming.pop_front() {
+                let n = std::cmp::min(buf.len(), packet.len());
+                buf[..n].copy_from_slice(&packet[..n]);
+                return Ok(n);
+            }
+            wire = cvar.wait(wire).unwrap();
+        }
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        // everything that's waiting under the one lock, as a device with recvmmsg would
+        let (wire, cvar) = &*self.wire;
+        let wire = wire.lock().unwrap();
+        let mut wire = cvar.wait_while(wire, |w| w.incoming.is_empty()).unwrap();
+        let mut n = 0;
+        while n < bufs.len()
+            && let Some(packet) = wire.incoming.pop_front()
+        {
+            let len = std::cmp::min(bufs[n].len(), packet.len());
+            bufs[n][..len].copy_from_slice(&packet[..len]);
+            lens[n] = len;
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        let (wire, cvar) = &*self.wire;
+        let wire = wire.lock().unwrap();
+        let (wire, _) = cvar
+            .wait_timeout_while(wire, timeout, |w| w.incoming.is_empty())
+            .unwrap();
+        Ok(!wire.incoming.is_empty())
+    }
+}
+
+/// Split a packet the stack sent (e.g. one from `MockNic::take_sent`) into its IPv4 header, TCP
+/// header and payload, for asserting on.
+///
+/// Panics if it isn't an IPv4 TCP segment, since anything else is as much a failure as a wrong
+/// sequence number would be.
+pub fn parse_segment(
+    packet: &[u8],
+) -> (
+    etherparse::Ipv4HeaderSlice<'_>,
+    etherparse::TcpHeaderSlice<'_>,
+    &[u8],
+) {
+    let iph = etherparse::Ipv4HeaderSlice::from_slice(packet).expect("not an IPv4 packet");
+    assert_eq!(iph.protocol(), 6, "not a TCP segment");
+    let tcp = &packet[iph.slice().len()..];
+    let tcph = etherparse::TcpHeaderSlice::from_slice(tcp).expect("malformed TCP header");
+    let payload = &tcp[tcph.slice().len()..];
+    (iph, tcph, payload)
+}
+
+/// The sequence number comparisons the state machine makes its decisions with, for checking
+/// against a model from outside the crate. Windows are half-open and measured forward from
+/// their start around the 2^32 sequence space, so one whose ends are equal is empty.
+pub mod seq {
+    /// Whether `x` lies in `[start, end)`.
+    pub fn in_window(start: u32, x: u32, end: u32) -> bool {
+        crate::tcp::in_window(start, x, end)
+    }
+
+    /// Whether `x` lies in `(start, end)`.
+    pub fn is_between_wrapped(start: u32, x: u32, end: u32) -> bool {
+        crate::tcp::is_between_wrapped(start, x, end)
+    }
+
+    /// Whether `lhs` comes before `rhs`, the two being less than 2^31 apart.
+    pub fn wrapping_lt(lhs: u32, rhs: u32) -> bool {
+        crate::tcp::wrapping_lt(lhs, rhs)
+    }
+
+    /// Whether a segment of `len` sequence numbers from `seq` is acceptable to a receive
+    /// window of `rcv_wnd` from `rcv_nxt` (RFC 793 S3.3).
+    pub fn segment_acceptable(rcv_nxt: u32, rcv_wnd: u32, seq: u32, len: u32) -> bool {
+        crate::tcp::segment_acceptable(rcv_nxt, rcv_wnd, seq, len)
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a test can keep one
+/// handle and give another to the interface.
+#[derive(Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Where a connection's state machine stands, as `Replay::snapshot` takes it. Sequence numbers
+/// are absolute, as they go on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    /// SND.UNA, the oldest sequence number not yet acknowledged
+    pub snd_una: u32,
+    /// SND.NXT, the next sequence number to send
+    pub snd_nxt: u32,
+    /// the highest sequence number sent so far, which SND.NXT is behind while retransmitting
+    pub snd_max: u32,
+    /// SND.WND, the window the peer last offered, unscaled
+    pub snd_wnd: u16,
+    /// IRS, the peer's initial sequence number, once its SYN is in and until TIME-WAIT, which
+    /// doesn't keep it
+    pub rcv_irs: Option<u32>,
+    /// RCV.NXT, the next sequence number expected from the peer
+    pub rcv_nxt: u32,
+    /// RCV.WND, the window we're offering, unscaled
+    pub rcv_wnd: u16,
+    /// everything `Interface::connections` would report, the state included
+    pub info: ConnectionInfo,
+}
+
+/// Drives the stack's packet processing directly, one packet at a time, with no packet loop
+/// thread in between. Useful for replaying a capture of a misbehaving session and asserting on
+/// what we did in response.
+pub struct Replay {
+    local: IpAddr,
+    cm: ConnectionManager,
+    /// every connection's buffers, held on to as its stream would hold them, for as long as
+    /// the stack has anything of the connection, TIME-WAIT included
+    streams: HashMap<Quad, Arc<tcp::Shared>>,
+    /// what a stream would still have to say once its connection is gone, for `error`
+    errors: HashMap<Quad, io::ErrorKind>,
+    /// connections handed to `accept`, taken off their listeners' queues as soon as they're
+    /// put there, as an application already blocked in `accept` would take them
+    accepted: VecDeque<Quad>,
+    nic: MockNic,
+    clock: ManualClock,
+}
+
+impl Replay {
+    /// `local` is the address the stack is pretending to own, until `add_address` adds more;
+    /// replayed packets addressed anywhere else (including our own responses, if they were
+    /// captured) are skipped, and it's the one `connect_to` connects from.
+    pub fn new(local: impl Into<IpAddr>) -> Self {
+        let local = local.into();
+        let mut cm = ConnectionManager::default();
+        cm.add_address(local);
+        Replay {
+            local,
+            cm,
+            streams: HashMap::new(),
+            errors: HashMap::new(),
+            accepted: VecDeque::new(),
+            nic: MockNic::new(),
+            clock: ManualClock::new(),
+        }
+    }
+
+    /// Start accepting connections on `port`, as `Interface::bind_with_config` would. Unlike
+    /// there, `config` isn't validated, so a test can set up connections that `bind` refuses,
+    /// such as ones with a zero receive window.
+    pub fn listen(&mut self, port: u16, config: ConnectionConfig) {
+        self.cm
+            .listeners
+            .insert((None, port), Listener::new(usize::MAX, config));
+    }
+
+    /// Like `listen`, but as `Interface::bind_with_config` would have it: `config` is
+    /// validated, and it fails if the port is taken.
+    pub fn bind(&mut self, port: u16, config: ConnectionConfig) -> io::Result<()> {
+        config.validate()?;
+        self.cm.bind(None, port, Listener::new(usize::MAX, config))
+    }
+
+    /// Like `bind`, but only for connections to `addr`, as `Interface::bind_addr` would have
+    /// it. See `add_address` for having more than one.
+    pub fn bind_addr(
+        &mut self,
+        addr: IpAddr,
+        port: u16,
+        config: ConnectionConfig,
+    ) -> io::Result<()> {
+        config.validate()?;
+        self.cm
+            .bind(Some(addr), port, Listener::new(usize::MAX, config))
+    }
+
+    /// Stop listening on `port`, as dropping the `TcpListener` would, followed by a timer tick.
+    pub fn unbind(&mut self, port: u16) -> io::Result<()> {
+        self.cm.unbind(&mut self.nic, (None, port));
+        self.tick()
+    }
+
+    /// Take `addr` as one of ours too, as `Interface::add_address` does, on top of the one
+    /// the `Replay` was made with.
+    pub fn add_address(&mut self, addr: impl Into<IpAddr>) {
+        self.cm.add_address(addr.into());
+    }
+
+    /// Open a connection for `quad` from its `dst` end, as `Interface::connect_with_config`
+    /// would but on a port of the test's choosing, followed by a timer tick. As with `listen`,
+    /// `config` isn't validated. Fails if there's a connection for `quad` already, unless it's
+    /// in TIME-WAIT and the new one can take the quad over.
+    pub fn connect(&mut self, quad: Quad, config: ConnectionConfig) -> io::Result<()> {
+        if !self.cm.claim(self.clock.now(), quad, &config) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "already have a connection for this quad",
+            ));
+        }
+        self.cm
+            .open(&mut self.nic, self.clock.now(), quad, &config)?;
+        self.tick()
+    }
+
+    /// Open a connection to `remote` on the next free ephemeral port, as
+    /// `Interface::connect_with_config` would, followed by a timer tick. Returns its quad.
+    pub fn connect_to(
+        &mut self,
+        remote: (IpAddr, u16),
+        config: ConnectionConfig,
+    ) -> io::Result<Quad> {
+        self.connect_from(self.local, remote, config)
+    }
+
+    /// Like `connect_to`, but from `local`, which has to be one of ours. The unspecified
+    /// address picks the first of ours of `remote`'s IP version, as with `Interface::connect`.
+    pub fn connect_from(
+        &mut self,
+        local: IpAddr,
+        remote: (IpAddr, u16),
+        config: ConnectionConfig,
+    ) -> io::Result<Quad> {
+        let now = self.clock.now();
+        let (quad, _) = self
+            .cm
+            .connect(&mut self.nic, now, local, remote, &config)?;
+        self.tick()?;
+        Ok(quad)
+    }
+
+    /// Feed a single IP packet through the dispatch path, followed by a timer tick.
+    pub fn feed(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.cm
+            .dispatch(&mut self.nic, self.clock.now(), packet, None)?;
+        self.tick()
+    }
+
+    /// Like `feed`, but handing over the buffer the packet is in, as the packet loop does, so
+    /// a connection that receives without copying (see `ConnectionConfig::zero_copy`) can
+    /// keep its data where it is.
+    pub fn feed_owned(&mut self, packet: Vec<u8>) -> io::Result<()> {
+        let buf = Arc::new(packet);
+        self.cm
+            .dispatch(&mut self.nic, self.clock.now(), &buf, Some(&buf))?;
+        self.tick()
+    }
+
+    /// Feed several IP packets as one burst, as the packet loop does when they queue up, so
+    /// each connection ACKs the in-order ones once, at the end, rather than one at a time.
+    /// Segments that arrive out of order are still ACKed as they come, as the peer needs the
+    /// duplicate ACKs. Followed by a timer tick.
+    pub fn process_batch<'a>(
+        &mut self,
+        packets: impl IntoIterator<Item = &'a [u8]>,
+    ) -> io::Result<()> {
+        let packets = packets.into_iter().map(|p| (p, None));
+        self.cm
+            .process_batch(&mut self.nic, self.clock.now(), packets)?;
+        self.tick()
+    }
+
+    /// Hand `segment` (a TCP header and payload, with no IP header) from `quad.src` straight
+    /// to the connection for `quad`, followed by a timer tick. Nothing on the way checks it
+    /// beyond parsing the header, which it fails with `InvalidData` if it can't: no checksum,
+    /// no demux, and no listener, so a handshake finished this way doesn't queue the
+    /// connection for `accept`. Does nothing but tick if there's no connection for `quad`.
+    pub fn inject(&mut self, quad: Quad, segment: &[u8]) -> io::Result<()> {
+        self.cm
+            .inject(&mut self.nic, self.clock.now(), quad, segment)?;
+        self.tick()
+    }
+
+    /// Move time forward without any packets arriving, and let timers fire.
+    pub fn advance(&mut self, by: Duration) -> io::Result<()> {
+        self.clock.advance(by);
+        self.tick()
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        for l in self.cm.listeners.values() {
+            let ready = l.queue.take_all().into_iter().map(|(q, _)| q);
+            self.accepted.extend(ready);
+        }
+        // the connection may not be there afterwards, but its stream would be
+        for (q, c) in &self.cm.connections {
+            self.streams.insert(*q, c.shared());
+        }
+        let res = self.cm.on_tick(&mut self.nic, self.clock.now());
+        let (cm, errors) = (&self.cm, &mut self.errors);
+        self.streams.retain(|q, s| {
+            let live = cm.connections.contains_key(q) || cm.time_wait.contains_key(q);
+            if !live && let Some(e) = s.buffers.lock().unwrap().error() {
+                errors.insert(*q, e.kind());
+            }
+            live
+        });
+        res
+    }
+
+    /// Feed every packet in the pcap file at `path` that's addressed to us, in order, moving
+    /// the clock forward by the gaps between their timestamps. Returns how many packets were
+    /// fed.
+    pub fn run(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let mut n = 0;
+        let mut last = None;
+        for p in pcap::read(path)? {
+            let Ok(iph) = ip::Header::parse(&p.data) else {
+                continue;
+            };
+            if !self.cm.is_ours(iph.dst) {
+                continue;
+            }
+            if let Some(last) = last {
+                self.clock.advance(p.ts.saturating_sub(last));
+            }
+            last = Some(p.ts);
+            self.feed(&p.data)?;
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Start off with a connection for `quad` that's already where `established` says, as if
+    /// the handshake and whatever came after had happened without us seeing it, as
+    /// `Interface::restore` does. Nothing is sent. Fails if there's a connection for `quad`
+    /// already, or `established` isn't one the connection could be in; see `Established` for
+    /// what else it has to get right.
+    pub fn restore(
+        &mut self,
+        quad: Quad,
+        established: &Established,
+        config: ConnectionConfig,
+    ) -> io::Result<()> {
+        config.validate()?;
+        let now = self.clock.now();
+        let shared = self
+            .cm
+            .restore(&mut self.nic, now, quad, established, &config)?;
+        self.streams.insert(quad, shared);
+        Ok(())
+    }
+
+    /// Have the application close the connection for `quad`, as dropping its `TcpStream`
+    /// would, followed by a timer tick.
+    pub fn close(&mut self, quad: Quad) -> io::Result<()> {
+        if let Some(c) = self.cm.connections.get_mut(&quad) {
+            c.close(self.clock.now());
+        }
+        self.tick()
+    }
+
+    /// Have the application ask for an ACK on the connection for `quad` right away, as
+    /// `TcpStream::flush_ack` would, followed by a timer tick.
+    pub fn flush_ack(&mut self, quad: Quad) -> io::Result<()> {
+        self.cm.flush_ack(&mut self.nic, quad)?;
+        self.tick()
+    }
+
+    /// Have the application write `data` to the connection for `quad`, followed by a timer
+    /// tick to send it. Returns how much fit in the send queue.
+    pub fn write(&mut self, quad: Quad, data: &[u8]) -> io::Result<usize> {
+        let n = match self.streams.get(&quad) {
+            Some(s) => s.buffers.lock().unwrap().queue_send(data)?,
+            None => 0,
+        };
+        self.tick()?;
+        Ok(n)
+    }
+
+    /// Like `write`, but as `TcpStream::poll_write` would: with no room in the send queue,
+    /// `Poll::Pending`, and `cx`'s waker woken once there is.
+    pub fn poll_write(
+        &mut self,
+        quad: Quad,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = match self.streams.get(&quad) {
+            Some(s) => {
+                let mut b = s.buffers.lock().unwrap();
+                let n = b.queue_send(data)?;
+                if n == 0 && !data.is_empty() {
+                    b.register_writer(cx.waker());
+                    return Poll::Pending;
+                }
+                n
+            }
+            None => 0,
+        };
+        self.tick()?;
+        Poll::Ready(Ok(n))
+    }
+
+    /// Have the application limit how long closing the connection for `quad` may take, as
+    /// `TcpStream::set_linger` would.
+    pub fn set_linger(&mut self, quad: Quad, linger: Option<Duration>) {
+        if let Some(s) = self.streams.get(&quad) {
+            s.buffers.lock().unwrap().set_linger(linger);
+        }
+    }
+
+    /// Have the application cork or uncork the connection for `quad`, as
+    /// `TcpStream::set_cork` would, followed by a timer tick.
+    pub fn set_cork(&mut self, quad: Quad, cork: bool) -> io::Result<()> {
+        if let Some(s) = self.streams.get(&quad) {
+            s.buffers.lock().unwrap().set_cork(cork);
+        }
+        self.tick()
+    }
+
+    /// Have the application flush the connection for `quad`, which sends whatever a cork is
+    /// holding back, followed by a timer tick. Unlike `TcpStream::flush`, it doesn't wait for
+    /// the peer to acknowledge it.
+    pub fn push(&mut self, quad: Quad) -> io::Result<()> {
+        if let Some(s) = self.streams.get(&quad) {
+            s.buffers.lock().unwrap().push();
+        }
+        self.tick()
+    }
+
+    /// Like `write`, but with the data in pieces, as `TcpStream::sendv` would have it.
+    pub fn write_vectored(&mut self, quad: Quad, bufs: &[io::IoSlice]) -> io::Result<usize> {
+        let n = match self.streams.get(&quad) {
+            Some(s) => s.buffers.lock().unwrap().queue_send_vectored(bufs)?,
+            None => 0,
+        };
+        self.tick()?;
+        Ok(n)
+    }
+
+    /// Have the application read up to `len` bytes from the connection for `quad`, as
+    /// `TcpStream::read` would, followed by a timer tick, which sends a window update if that
+    /// reopened a window we'd closed. Returns what was read.
+    pub fn read(&mut self, quad: Quad, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        let n = match self.streams.get(&quad) {
+            Some(s) => s.buffers.lock().unwrap().read(&mut buf),
+            None => 0,
+        };
+        buf.truncate(n);
+        self.tick()?;
+        Ok(buf)
+    }
+
+    /// Like `read`, but filling `bufs` in order, as `TcpStream::read_vectored` would. Returns
+    /// how much was read.
+    pub fn read_vectored(&mut self, quad: Quad, bufs: &mut [io::IoSliceMut]) -> io::Result<usize> {
+        let n = match self.streams.get(&quad) {
+            Some(s) => s.buffers.lock().unwrap().read_vectored(bufs),
+            None => 0,
+        };
+        self.tick()?;
+        Ok(n)
+    }
+
+    /// Like `read`, but into `buf`, as `TcpStream::read_uninit` would. Returns how much was
+    /// read.
+    pub fn read_uninit(&mut self, quad: Quad, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+        let n = match self.streams.get(&quad) {
+            Some(s) => s.buffers.lock().unwrap().read_uninit(buf),
+            None => 0,
+        };
+        self.tick()?;
+        Ok(n)
+    }
+
+    /// Have the application take the next of what's been received on the connection for
+    /// `quad`, as `TcpStream::recv_bytes` would, followed by a timer tick. Empty if there's
+    /// nothing to read.
+    pub fn recv_bytes(&mut self, quad: Quad) -> io::Result<Payload> {
+        let payload = match self.streams.get(&quad) {
+            Some(s) => s.buffers.lock().unwrap().take_payload(),
+            None => Payload::default(),
+        };
+        self.tick()?;
+        Ok(payload)
+    }
+
+    /// Resize the receive buffer of the connection for `quad` so that exactly `wnd` bytes of
+    /// it are free, which makes that the window we advertise, followed by a timer tick. Lets a
+    /// test close or reopen the window without filling or draining the buffer to get there.
+    /// Going below what we last advertised shrinks the window, which a real application can't
+    /// make us do.
+    pub fn set_recv_window(&mut self, quad: Quad, wnd: u16) -> io::Result<()> {
+        if let Some(c) = self.cm.connections.get_mut(&quad) {
+            c.set_recv_window(wnd);
+        }
+        self.tick()
+    }
+
+    /// How the ICMP errors fed so far were handled.
+    pub fn icmp_stats(&self) -> IcmpStats {
+        self.cm.icmp
+    }
+
+    /// Which of the segments fed so far were dropped before reaching a connection.
+    pub fn segment_stats(&self) -> SegmentStats {
+        self.cm.segments
+    }
+
+    /// Limit how many connections there may be at once, as
+    /// `Interface::set_max_connections` would.
+    pub fn set_max_connections(&mut self, max: Option<usize>) {
+        self.cm.max_connections = max;
+    }
+
+    /// Limit the memory connections' buffers may take up between them, as
+    /// `Interface::set_max_buffer_memory` would.
+    pub fn set_max_buffer_memory(&mut self, max: Option<usize>) {
+        self.cm.budget.set_limit(max);
+    }
+
+    /// How much buffer memory connections have charged to the budget between them.
+    pub fn buffer_memory(&self) -> usize {
+        self.cm.budget.used()
+    }
+
+    /// Check the sequence-space invariants of every connection, panicking (in debug builds) if
+    /// any of them don't hold.
+    pub fn check_invariants(&self) {
+        for c in self.cm.connections.values() {
+            c.check_invariants();
+        }
+    }
+
+    /// The next connection a listener handed to `accept`, in the order their handshakes
+    /// finished, whatever has become of them since.
+    pub fn accept(&mut self) -> Option<Quad> {
+        self.accepted.pop_front()
+    }
+
+    /// The state of the connection for `quad`, if we have one.
+    pub fn state(&self, quad: Quad) -> Option<State> {
+        self.cm.state_of(quad)
+    }
+
+    /// What the application would be told on its next read or write to the connection for
+    /// `quad` once the connection is gone, if it's more than that it was aborted. Remembered
+    /// after the stack has forgotten the connection, as its stream would.
+    pub fn error(&self, quad: Quad) -> Option<io::ErrorKind> {
+        match self.streams.get(&quad) {
+            Some(s) => s.buffers.lock().unwrap().error().map(|e| e.kind()),
+            None => self.errors.get(&quad).copied(),
+        }
+    }
+
+    /// A snapshot of the connection for `quad`, as `Interface::connections` would take it.
+    pub fn info(&self, quad: Quad) -> Option<ConnectionInfo> {
+        match self.cm.connections.get(&quad) {
+            Some(c) => Some(c.info()),
+            None => self.cm.time_wait.get(&quad).map(|tw| tw.info()),
+        }
+    }
+
+    /// The state machine's variables for the connection for `quad`, for comparing two runs
+    /// or asserting on what a segment did.
+    pub fn snapshot(&self, quad: Quad) -> Option<Snapshot> {
+        match self.cm.connections.get(&quad) {
+            Some(c) => Some(c.snapshot()),
+            None => self.cm.time_wait.get(&quad).map(|tw| tw.snapshot()),
+        }
+    }
+
+    /// Every connection the stack currently knows about, TIME-WAIT included.
+    pub fn quads(&self) -> Vec<Quad> {
+        let time_wait = self.cm.time_wait.keys();
+        self.cm
+            .connections
+            .keys()
+            .chain(time_wait)
+            .copied()
+            .collect()
+    }
+
+    /// Everything we've sent in response so far; see `MockNic::take_sent`.
+    pub fn take_sent(&self) -> Vec<Vec<u8>> {
+        self.nic.take_sent()
+    }
+
+    /// Have every send fail from now on, or not; see `MockNic::fail_sends`.
+    pub fn fail_sends(&self, error: Option<io::ErrorKind>) {
+        self.nic.fail_sends(error)
+    }
+}
+
+/// splitmix64. Plenty random for fault injection, and reproducible from a seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// true with probability `p`
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    /// uniform in `0..=n`
+    fn up_to(&mut self, n: usize) -> usize {
+        (self.next_u64() % (n as u64 + 1)) as usize
+    }
+}
+
+/// How one direction of a `ChaosLink` mistreats packets.
+#[derive(Clone, Debug, Default)]
+pub struct ChaosConfig {
+    /// probability that a packet is lost
+    pub drop: f64,
+    /// probability that a packet is delivered twice
+    pub duplicate: f64,
+    /// a packet may overtake up to this many packets sent before it
+    pub reorder: usize,
+    /// how long every packet spends on the wire
+    pub latency: Duration,
+    /// packets bigger than this are silently dropped, like on a path MTU black hole
+    pub max_size: Option<usize>,
+}
+
+/// What a `ChaosLink` direction did to the packets sent over it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChaosStats {
+    pub sent: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub delivered: u64,
+}
+
+#[derive(Default)]
+struct Direction {
+    config: ChaosConfig,
+    queue: VecDeque<(Instant, Vec<u8>)>,
+    stats: ChaosStats,
+}
+
+struct Link {
+    rng: Rng,
+    /// `dirs[i]` carries packets sent by end `i`
+    dirs: [Direction; 2],
+}
+
+/// A lossy, reordering wire between two NICs, for running two stacks against each other.
+/// Both ends share one seeded RNG, so a run is reproducible as long as the two stacks send in
+/// the same order.
+pub struct ChaosLink;
+
+impl ChaosLink {
+    /// Create both ends of a link. `a_to_b` applies to packets sent by the first end.
+    pub fn pair<C: Clock + Clone>(
+        a_to_b: ChaosConfig,
+        b_to_a: ChaosConfig,
+        seed: u64,
+        clock: C,
+    ) -> (ChaosEnd<C>, ChaosEnd<C>) {
+        let link = Arc::new((
+            Mutex::new(Link {
+                rng: Rng(seed),
+                dirs: [
+                    Direction {
+                        config: a_to_b,
+                        ..Default::default()
+                    },
+                    Direction {
+                        config: b_to_a,
+                        ..Default::default()
+                    },
+                ],
+            }),
+            Condvar::new(),
+        ));
+        (
+            ChaosEnd {
+                link: link.clone(),
+                side: 0,
+                clock: clock.clone(),
+                mtu: 1500,
+            },
+            ChaosEnd {
+                link,
+                side: 1,
+                clock,
+                mtu: 1500,
+            },
+        )
+    }
+}
+
+/// One end of a `ChaosLink`.
+///
+/// Clones are the same end of the same link, so a test can keep one to read `stats` from while
+/// the interface owns another.
+#[derive(Clone)]
+pub struct ChaosEnd<C> {
+    link: Arc<(Mutex<Link>, Condvar)>,
+    side: usize,
+    clock: C,
+    mtu: usize,
+}
+
+impl<C: Clock> ChaosEnd<C> {
+    /// What happened to the packets this end has sent.
+    pub fn stats(&self) -> ChaosStats {
+        self.link.0.lock().unwrap().dirs[self.side].stats
+    }
+
+    fn ready(&self, link: &Link) -> Option<Instant> {
+        link.dirs[1 - self.side].queue.front().map(|&(at, _)| at)
+    }
+}
+
+impl<C: Clock> Nic for ChaosEnd<C> {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (link, cvar) = &*self.link;
+        let mut link = link.lock().unwrap();
+        let link = &mut *link;
+        let dir = &mut link.dirs[self.side];
+        dir.stats.sent += 1;
+        if dir.config.max_size.is_some_and(|max| buf.len() > max) {
+            dir.stats.dropped += 1;
+            return Ok(buf.len());
+        }
+        if link.rng.chance(dir.config.drop) {
+            dir.stats.dropped += 1;
+            return Ok(buf.len());
+        }
+        let copies = if link.rng.chance(dir.config.duplicate) {
+            dir.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        let at = self.clock.now() + dir.config.latency;
+        for _ in 0..copies {
+            let overtake = link
+                .rng
+                .up_to(std::cmp::min(dir.config.reorder, dir.queue.len()));
+            if overtake > 0 {
+                dir.stats.reordered += 1;
+            }
+            let i = dir.queue.len() - overtake;
+            dir.queue.insert(i, (at, buf.to_vec()));
+        }
+        cvar.notify_all();
+        Ok(buf.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (link, cvar) = &*self.link;
+        let mut link = link.lock().unwrap();
+        loop {
+            if let Some(at) = self.ready(&link) {
+                let now = self.clock.now();
+                if at <= now {
+                    let dir = &mut link.dirs[1 - self.side];
+                    let (_, packet) = dir.queue.pop_front().unwrap();
+                    dir.stats.delivered += 1;
+                    let n = std::cmp::min(buf.len(), packet.len());
+                    buf[..n].copy_from_slice(&packet[..n]);
+                    return Ok(n);
+                }
+                link = cvar.wait_timeout(link, at - now).unwrap().0;
+            } else {
+                link = cvar.wait(link).unwrap();
+            }
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        let (link, cvar) = &*self.link;
+        let mut link = link.lock().unwrap();
+        // the timeout is in real time even if the link's clock is a manual one, or we'd never
+        // give up waiting
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = self.clock.now();
+            let wait = match self.ready(&link) {
+                Some(at) if at <= now => return Ok(true),
+                Some(at) => at - now,
+                None => timeout,
+            };
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(false);
+            }
+            link = cvar
+                .wait_timeout(link, std::cmp::min(wait, left))
+                .unwrap()
+                .0;
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! A tcpdump-like view of the segments going through the stack, one line per segment, for
+//! interactive debugging.
+//!
+//! ```text
+//! in  10.0.0.1:40000 > 10.0.0.2:80 [S] seq 100:0 win 64000 (none)
+//! out 10.0.0.2:80 > 10.0.0.1:40000 [S.] seq 0:0 ack 101 win 1024 (SynRcvd)
+//! ```
+//!
+//! The state at the end of each line is the state of the connection that handled the segment:
+//! for incoming segments the state it was in when the segment arrived, and for outgoing ones
+//! the state it was in once it was done sending.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::RawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::ip;
+use crate::nic::Nic;
+use crate::{Quad, State};
+
+/// Which way a segment was going.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// Format a single TCP packet as a trace line, followed by a hexdump of up to `hexdump`
+/// bytes of its payload. `state` is the state of the connection that handled it, if there was
+/// one.
+///
+/// Returns `None` if the packet isn't TCP over IPv4 or IPv6.
+pub fn format_segment(
+    dir: Direction,
+    packet: &[u8],
+    state: Option<State>,
+    hexdump: usize,
+) -> Option<String> {
+    let iph = ip::Header::parse(packet).ok()?;
+    if iph.protocol != 0x06 {
+        return None;
+    }
+    let tcph = etherparse::TcpHeaderSlice::from_slice(&packet[iph.payload..]).ok()?;
+    let data = &packet[iph.payload + tcph.slice().len()..];
+
+    let mut flags = String::new();
+    for (set, c) in [
+        (tcph.syn(), 'S'),
+        (tcph.fin(), 'F'),
+        (tcph.rst(), 'R'),
+        (tcph.psh(), 'P'),
+        (tcph.urg(), 'U'),
+        (tcph.ack(), '.'),
+    ] {
+        if set {
+            flags.push(c);
+        }
+    }
+
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        "{:<3} {} > {} [{}] seq {}:{}",
+        match dir {
+            Direction::In => "in",
+            Direction::Out => "out",
+        },
+        SocketAddr::new(iph.src, tcph.source_port()),
+        SocketAddr::new(iph.dst, tcph.destination_port()),
+        flags,
+        tcph.sequence_number(),
+        data.len(),
+    );
+    if tcph.ack() {
+        let _ = write!(line, " ack {}", tcph.acknowledgment_number());
+    }
+    let _ = write!(line, " win {}", tcph.window_size());
+    match state {
+        Some(state) => {
+            let _ = write!(line, " ({:?})", state);
+        }
+        None => line.push_str(" (none)"),
+    }
+
+    for (i, chunk) in data[..data.len().min(hexdump)].chunks(16).enumerate() {
+        let _ = write!(line, "\n    {:04x} ", i * 16);
+        for b in chunk {
+            let _ = write!(line, " {:02x}", b);
+        }
+        for _ in chunk.len()..16 {
+            line.push_str("   ");
+        }
+        line.push_str("  ");
+        line.extend(chunk.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+    }
+    Some(line)
+}
+
+/// Where an active trace goes.
+pub(crate) struct Tracer {
+    sink: Box<dyn Write + Send>,
+    hexdump: usize,
+}
+
+pub(crate) type TraceSlot = Arc<Mutex<Option<Tracer>>>;
+
+impl Tracer {
+    pub(crate) fn new(sink: Box<dyn Write + Send>, hexdump: usize) -> Self {
+        Tracer { sink, hexdump }
+    }
+
+    /// Trace to stderr if `TRUST_TRACE` is set in the environment. Its value, if it's a number,
+    /// is how many payload bytes to hexdump.
+    pub(crate) fn from_env() -> Option<Self> {
+        let v = std::env::var("TRUST_TRACE").ok()?;
+        Some(Tracer::new(Box::new(io::stderr()), v.parse().unwrap_or(0)))
+    }
+
+    fn log(&mut self, dir: Direction, packet: &[u8], state: Option<State>) {
+        if let Some(line) = format_segment(dir, packet, state, self.hexdump) {
+            // a broken sink shouldn't take the stack down with it
+            let _ = writeln!(self.sink, "{}", line);
+        }
+    }
+}
+
+/// Wraps the NIC to hold on to what's sent while a trace is active, so it can be logged along
+/// with the state of the connection that sent it once processing is done.
+pub(crate) struct Tap<N> {
+    nic: N,
+    tracer: TraceSlot,
+    sent: Vec<Vec<u8>>,
+}
+
+impl<N> Tap<N> {
+    pub(crate) fn new(nic: N, tracer: TraceSlot) -> Self {
+        Tap {
+            nic,
+            tracer,
+            sent: Vec::new(),
+        }
+    }
+
+    /// Log an incoming packet, before it's processed.
+    pub(crate) fn log_in(&mut self, packet: &[u8], state_of: impl Fn(Quad) -> Option<State>) {
+        if let Some(t) = &mut *self.tracer.lock().unwrap() {
+            t.log(
+                Direction::In,
+                packet,
+                quad_of(packet, false).and_then(state_of),
+            );
+        }
+    }
+
+    /// Log everything sent since the last call.
+    pub(crate) fn log_out(&mut self, state_of: impl Fn(Quad) -> Option<State>) {
+        if self.sent.is_empty() {
+            return;
+        }
+        if let Some(t) = &mut *self.tracer.lock().unwrap() {
+            for p in &self.sent {
+                t.log(Direction::Out, p, quad_of(p, true).and_then(&state_of));
+            }
+        }
+        self.sent.clear();
+    }
+}
+
+/// The connection a packet belongs to. Our own packets have the addresses the other way
+/// around.
+fn quad_of(packet: &[u8], outgoing: bool) -> Option<Quad> {
+    let iph = ip::Header::parse(packet).ok()?;
+    let tcph = etherparse::TcpHeaderSlice::from_slice(&packet[iph.payload..]).ok()?;
+    let src = (iph.src, tcph.source_port());
+    let dst = (iph.dst, tcph.destination_port());
+    Some(if outgoing {
+        Quad { src: dst, dst: src }
+    } else {
+        Quad { src, dst }
+    })
+}
+
+impl<N: Nic> Nic for Tap<N> {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.tracer.lock().unwrap().is_some() {
+            self.sent.push(buf.to_vec());
+        }
+        self.nic.send(buf)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.nic.recv(buf)
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        self.nic.recv_batch(bufs, lens)
+    }
+
+    fn mtu(&self) -> usize {
+        self.nic.mtu()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.nic.poll(timeout)
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        self.nic.fd()
+    }
+
+    fn add_address(&mut self, addr: IpAddr) {
+        self.nic.add_address(addr)
+    }
+}
@@ -0,0 +1,49 @@
+//! ACK clocking: past the initial window, new data goes out only as ACKs come in, each one
+//! releasing what it ACKed plus what slow start adds to the window. Driven through `Replay`,
+//! so no device needed.
+
+use std::time::Duration;
+
+use common::{PEER_ISS, QUAD, establish, segment};
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+/// The peer offers no MSS, so segments are 536 bytes.
+const MSS: u32 = 536;
+
+/// Which segments, counting from the first after `iss`, have been sent since last time.
+fn sent(r: &Replay, iss: u32) -> Vec<u32> {
+    r.take_sent()
+        .iter()
+        .map(|p| parse_segment(p).1.sequence_number().wrapping_sub(iss) / MSS)
+        .collect()
+}
+
+#[test]
+fn each_ack_releases_what_it_acked_and_one_more() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+
+    // far more than the initial window of ten segments, which goes in one burst
+    r.write(QUAD, &[7; 40 * MSS as usize]).unwrap();
+    assert_eq!(sent(&r, iss), (0..10).collect::<Vec<_>>());
+    // and nothing more until something's ACKed, however long that takes
+    r.advance(Duration::from_millis(500)).unwrap();
+    assert_eq!(sent(&r, iss), []);
+
+    // each ACK sends straight away what it made room for: the segments it ACKed, and one
+    // more as slow start opens the window by an MSS, picking up where the last left off
+    let mut next = 10;
+    for (acked, to) in [(1, 1), (2, 3), (1, 4), (4, 8), (2, 10)] {
+        r.feed(&segment(PEER_ISS + 1, Some(iss + to * MSS), &[]))
+            .unwrap();
+        let released: Vec<_> = (next..next + acked + 1).collect();
+        assert_eq!(sent(&r, iss), released, "after ACKing {to} segments");
+        next += acked + 1;
+    }
+    // a duplicate ACK moves nothing along
+    r.feed(&segment(PEER_ISS + 1, Some(iss + 10 * MSS), &[]))
+        .unwrap();
+    assert_eq!(sent(&r, iss), []);
+}
@@ -0,0 +1,127 @@
+//! Receive buffer autotuning: over a fast path, with an application keeping up, the window
+//! grows to the configured cap, and with one that isn't it stays where it started. Driven
+//! through `Replay`, so no device needed, with the peer sending whatever our window lets it
+//! once a simulated round trip.
+
+use std::time::Duration;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, segment};
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+const MSS: usize = 1460;
+const WINDOW: u16 = 4096;
+const RTT: Duration = Duration::from_millis(10);
+
+fn wrapping_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// The peer's view of a connection: where it's got to, and how far our window lets it go.
+struct Peer {
+    r: Replay,
+    iss: u32,
+    nxt: u32,
+    edge: u32,
+}
+
+impl Peer {
+    fn establish(config: ConnectionConfig) -> Self {
+        let mut r = Replay::new(LOCAL);
+        r.listen(80, config);
+        let (iss, synack) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+        let edge = PEER_ISS + 1 + parse_segment(&synack).1.window_size() as u32;
+        Peer {
+            r,
+            iss,
+            nxt: PEER_ISS + 1,
+            edge,
+        }
+    }
+
+    /// Move the right edge of the window along to wherever what we've sent since last time
+    /// puts it.
+    fn take_window(&mut self) {
+        for p in self.r.take_sent() {
+            let (_, tcph, _) = parse_segment(&p);
+            let edge = tcph
+                .acknowledgment_number()
+                .wrapping_add(tcph.window_size() as u32);
+            if wrapping_lt(self.edge, edge) {
+                self.edge = edge;
+            }
+        }
+    }
+
+    /// One round trip: the peer sends all the window lets it, and the application reads up
+    /// to `read` of it as each segment comes in, or all of it. The window that opens up on
+    /// the way isn't the peer's to use until our ACKs have got back to it, next time.
+    fn round_trip(&mut self, read: Option<usize>) {
+        let mut read = read.unwrap_or(u16::MAX as usize);
+        let end = self.edge;
+        while wrapping_lt(self.nxt, end) {
+            let len = std::cmp::min(MSS, end.wrapping_sub(self.nxt) as usize);
+            self.r
+                .feed(&segment(self.nxt, Some(self.iss), &vec![0; len]))
+                .unwrap();
+            self.nxt = self.nxt.wrapping_add(len as u32);
+            read -= self.r.read(QUAD, read).unwrap().len();
+        }
+        self.r.advance(RTT).unwrap();
+        self.take_window();
+    }
+
+    fn window(&self) -> u32 {
+        self.edge.wrapping_sub(self.nxt)
+    }
+}
+
+#[test]
+fn window_grows_to_the_cap_on_a_fast_path() {
+    let mut peer = Peer::establish(
+        ConnectionConfig::default()
+            .recv_window(WINDOW)
+            .recv_window_max(u16::MAX),
+    );
+    let info = peer.r.info(QUAD).unwrap();
+    assert_eq!(info.recv_buffer_size, WINDOW as usize);
+    assert_eq!(info.recv_buffer_max, u16::MAX as usize);
+    assert_eq!(peer.window(), WINDOW as u32);
+
+    for _ in 0..20 {
+        peer.round_trip(None);
+    }
+    let info = peer.r.info(QUAD).unwrap();
+    assert_eq!(info.recv_buffer_size, u16::MAX as usize);
+    assert!(peer.window() > 60_000, "window {}", peer.window());
+    peer.r.check_invariants();
+}
+
+#[test]
+fn window_stays_put_for_a_slow_reader() {
+    let mut peer = Peer::establish(
+        ConnectionConfig::default()
+            .recv_window(WINDOW)
+            .recv_window_max(u16::MAX),
+    );
+    for _ in 0..20 {
+        peer.round_trip(Some(1000));
+    }
+    let info = peer.r.info(QUAD).unwrap();
+    assert_eq!(info.recv_buffer_size, WINDOW as usize);
+    assert!(peer.window() <= WINDOW as u32);
+}
+
+#[test]
+fn window_stays_put_without_autotuning() {
+    let mut peer = Peer::establish(ConnectionConfig::default().recv_window(WINDOW));
+    for _ in 0..20 {
+        peer.round_trip(None);
+    }
+    let info = peer.r.info(QUAD).unwrap();
+    assert_eq!(info.recv_buffer_size, WINDOW as usize);
+    assert_eq!(info.recv_buffer_max, WINDOW as usize);
+    assert!(peer.window() <= WINDOW as u32);
+}
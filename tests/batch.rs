@@ -0,0 +1,60 @@
+//! `Replay::process_batch`: a burst of segments taken in together, as the packet loop does
+//! when they queue up. What arrives in order is ACKed once, at the end, while each segment
+//! past a gap still gets a duplicate ACK of its own, which fast retransmit needs.
+
+use common::{PEER_ISS, QUAD, establish, segment};
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+/// The ACK numbers on what we've sent since last time, all of which carries no data.
+fn acks(r: &Replay) -> Vec<u32> {
+    r.take_sent()
+        .iter()
+        .map(|p| {
+            let (_, tcph, data) = parse_segment(p);
+            assert!(tcph.ack() && data.is_empty());
+            tcph.acknowledgment_number()
+        })
+        .collect()
+}
+
+#[test]
+fn in_order_segments_get_one_ack() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    let burst: Vec<_> = (0..5)
+        .map(|i| segment(PEER_ISS + 1 + 100 * i, Some(iss), &[b'x'; 100]))
+        .collect();
+    r.process_batch(burst.iter().map(|p| &p[..])).unwrap();
+    assert_eq!(acks(&r), [PEER_ISS + 501]);
+    assert_eq!(r.read(QUAD, 1000).unwrap().len(), 500);
+}
+
+#[test]
+fn one_at_a_time_each_gets_an_ack() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    for i in 0..3 {
+        r.feed(&segment(PEER_ISS + 1 + 100 * i, Some(iss), &[b'x'; 100]))
+            .unwrap();
+    }
+    assert_eq!(acks(&r), [PEER_ISS + 101, PEER_ISS + 201, PEER_ISS + 301]);
+}
+
+#[test]
+fn out_of_order_segments_each_get_a_duplicate_ack() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    // the second of five is lost, so the three after it come out of order
+    let burst: Vec<_> = [0, 2, 3, 4]
+        .into_iter()
+        .map(|i| segment(PEER_ISS + 1 + 100 * i, Some(iss), &[b'x'; 100]))
+        .collect();
+    r.process_batch(burst.iter().map(|p| &p[..])).unwrap();
+    // the first of them also carries the ACK of the one that came in order
+    assert_eq!(acks(&r), [PEER_ISS + 101; 3]);
+
+    // and once the lost one comes again, one ACK for it in its own batch
+    let resent = segment(PEER_ISS + 101, Some(iss), &[b'x'; 100]);
+    r.process_batch([&resent[..]]).unwrap();
+    assert_eq!(acks(&r), [PEER_ISS + 201]);
+}
@@ -0,0 +1,89 @@
+//! Segments that carry data and acknowledge ours at once: both halves are taken in the one
+//! pass, and the ACK for the data rides on whatever new data the ACK lets out, rather than
+//! going alone just ahead of it. Driven through `Replay`.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, assert_one_ack, handshake, segment};
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+/// An established connection whose peer offered an MSS of 1460, with a full initial window of
+/// data out and more waiting behind it. Returns our next sequence number after the handshake.
+fn sending() -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    assert_eq!(r.write(QUAD, &[7; 20 * 1460]).unwrap(), 20 * 1460);
+    assert_eq!(r.take_sent().len(), 10);
+    (r, iss)
+}
+
+#[test]
+fn data_and_ack_are_both_taken() {
+    let (mut r, iss) = sending();
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 10 * 1460);
+
+    r.feed(&segment(
+        PEER_ISS + 1,
+        Some(iss.wrapping_add(1460)),
+        &[1; 100],
+    ))
+    .unwrap();
+    // the ACK's slow start lets out two more segments, and both ACK the data
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 2, "sent {} segments", sent.len());
+    for (i, p) in sent.iter().enumerate() {
+        let (_, tcph, data) = parse_segment(p);
+        assert_eq!(data.len(), 1460);
+        assert_eq!(
+            tcph.sequence_number(),
+            iss.wrapping_add((10 + i as u32) * 1460)
+        );
+        assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 101);
+    }
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 11 * 1460);
+    assert_eq!(r.read(QUAD, 1000).unwrap(), [1; 100]);
+    r.check_invariants();
+}
+
+#[test]
+fn data_whose_ack_frees_no_room_is_acked_alone() {
+    let (mut r, iss) = sending();
+    r.feed(&segment(
+        PEER_ISS + 1,
+        Some(iss.wrapping_add(1460)),
+        &[1; 100],
+    ))
+    .unwrap();
+    r.take_sent();
+
+    // nothing new acknowledged, so nothing for the ACK to ride on
+    r.feed(&segment(
+        PEER_ISS + 101,
+        Some(iss.wrapping_add(1460)),
+        &[2; 50],
+    ))
+    .unwrap();
+    assert_one_ack(&r, PEER_ISS + 151);
+    assert_eq!(
+        r.read(QUAD, 1000).unwrap(),
+        [[1; 100].as_slice(), &[2; 50]].concat()
+    );
+}
+
+#[test]
+fn data_acking_everything_gets_one_ack() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    r.write(QUAD, b"ping").unwrap();
+    r.take_sent();
+
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(4)), b"pong"))
+        .unwrap();
+    assert_one_ack(&r, PEER_ISS + 5);
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 0);
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"pong");
+    r.check_invariants();
+}
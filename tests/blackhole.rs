@@ -0,0 +1,177 @@
+//! Path MTU black holes: full-sized segments that time out again and again, with no ICMP to
+//! say why, are tried smaller with DF off, and the smaller MSS is kept once that gets through.
+//! Mostly through `Replay`, and then end to end over a `ChaosLink` that drops whatever's too
+//! big for it.
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, segment};
+use trust::testing::{ChaosConfig, ChaosLink, ManualClock, Replay, parse_segment};
+use trust::{ConnectionConfig, Interface, MtuProbing};
+
+mod common;
+
+/// An established connection with `config`, whose peer offered an MSS of 1460, and our next
+/// sequence number.
+fn establish(config: ConnectionConfig) -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    (r, iss)
+}
+
+/// Let time pass a second at a time until the retransmission timer sends something, and return
+/// the payload size and DF bit of each segment it sent.
+fn timeout(r: &mut Replay) -> Vec<(usize, bool)> {
+    for _ in 0..120 {
+        r.advance(Duration::from_secs(1)).unwrap();
+        let sent = r.take_sent();
+        if !sent.is_empty() {
+            return sent
+                .iter()
+                .map(|p| {
+                    let (iph, _, data) = parse_segment(p);
+                    (data.len(), iph.dont_fragment())
+                })
+                .collect();
+        }
+    }
+    panic!("nothing retransmitted in two minutes");
+}
+
+#[test]
+fn steps_down_and_keeps_what_gets_through() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    assert_eq!(r.write(QUAD, &[7; 1460]).unwrap(), 1460);
+    let sent = r.take_sent();
+    assert_eq!(parse_segment(&sent[0]).2.len(), 1460);
+
+    // the first timeout is just a loss as far as we know
+    assert_eq!(timeout(&mut r), [(1460, true)]);
+    // the second, and it goes smaller, letting routers fragment it
+    assert_eq!(timeout(&mut r), [(1024, false)]);
+    assert_eq!(r.icmp_stats().mtu_blackholes, 0);
+
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(1024)), &[]))
+        .unwrap();
+    assert_eq!(r.icmp_stats().mtu_blackholes, 1);
+    // the rest goes at the new size, with DF back on
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1);
+    let (iph, _, data) = parse_segment(&sent[0]);
+    assert_eq!(data.len(), 1460 - 1024);
+    assert!(iph.dont_fragment());
+
+    assert_eq!(r.write(QUAD, &[7; 2048]).unwrap(), 2048);
+    let sizes: Vec<_> = r
+        .take_sent()
+        .iter()
+        .map(|p| parse_segment(p).2.len())
+        .collect();
+    assert!(sizes.iter().all(|&n| n <= 1024), "{sizes:?}");
+    r.check_invariants();
+}
+
+#[test]
+fn a_probe_that_is_lost_too_steps_down_again() {
+    let (mut r, _) = establish(ConnectionConfig::default());
+    r.write(QUAD, &[7; 1460]).unwrap();
+    r.take_sent();
+    assert_eq!(timeout(&mut r), [(1460, true)]);
+    assert_eq!(timeout(&mut r), [(1024, false)]);
+    assert_eq!(timeout(&mut r), [(1024, false)]);
+    assert_eq!(timeout(&mut r), [(512, false)]);
+    // and there's nowhere further down to go
+    assert_eq!(timeout(&mut r), [(512, false)]);
+    assert_eq!(timeout(&mut r), [(512, false)]);
+}
+
+#[test]
+fn thresholds_are_configurable() {
+    let probing = MtuProbing {
+        after_timeouts: 1,
+        mss_steps: vec![800],
+    };
+    let (mut r, _) = establish(ConnectionConfig::default().mtu_probing(Some(probing)));
+    r.write(QUAD, &[7; 1460]).unwrap();
+    r.take_sent();
+    assert_eq!(timeout(&mut r), [(800, false)]);
+}
+
+#[test]
+fn probing_can_be_turned_off() {
+    let (mut r, _) = establish(ConnectionConfig::default().mtu_probing(None));
+    r.write(QUAD, &[7; 1460]).unwrap();
+    r.take_sent();
+    for _ in 0..4 {
+        assert_eq!(timeout(&mut r), [(1460, true)]);
+    }
+}
+
+#[test]
+fn small_segments_timing_out_are_not_a_black_hole() {
+    let (mut r, _) = establish(ConnectionConfig::default());
+    r.write(QUAD, &[7; 100]).unwrap();
+    r.take_sent();
+    for _ in 0..4 {
+        assert_eq!(timeout(&mut r), [(100, true)]);
+    }
+}
+
+#[test]
+fn transfer_through_a_black_hole() {
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    let hole = ChaosConfig {
+        max_size: Some(1000),
+        ..Default::default()
+    };
+    // time runs twenty times faster than it really does, so the timeouts before each step
+    // down don't take their full seconds
+    let clock = ManualClock::new();
+    let running = Arc::new(AtomicBool::new(true));
+    let ticker = {
+        let (clock, running) = (clock.clone(), running.clone());
+        thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+                clock.advance(Duration::from_millis(20));
+            }
+        })
+    };
+
+    let (client_end, server_end) = ChaosLink::pair(hole.clone(), hole, 122, clock.clone());
+    let link = client_end.clone();
+    let mut client = Interface::with_clock(client_end, clock.clone());
+    client.add_address(CLIENT.into());
+    let mut server = Interface::with_clock(server_end, clock);
+    server.add_address(SERVER.into());
+
+    let config = ConnectionConfig::default().recv_window(u16::MAX);
+    let mut l = server.bind_with_config(80, 1, config).unwrap();
+    let reader = thread::spawn(move || {
+        let mut s = l.accept().unwrap();
+        let mut got = Vec::new();
+        s.read_to_end(&mut got).unwrap();
+        got
+    });
+    let data: Vec<u8> = (0..32 * 1024).map(|i| (i % 251) as u8).collect();
+    let mut s = client
+        .connect(CLIENT.into(), SocketAddr::from((SERVER, 80)))
+        .unwrap();
+    s.write_all(&data).unwrap();
+    drop(s);
+
+    let got = reader.join().unwrap();
+    running.store(false, Ordering::Relaxed);
+    ticker.join().unwrap();
+    assert!(got == data, "data corrupted in transit");
+    // 1024 doesn't fit either, so it took the second step down
+    assert_eq!(client.icmp_stats().unwrap().mtu_blackholes, 1);
+    assert!(link.stats().dropped > 0);
+}
@@ -0,0 +1,79 @@
+//! `BufRead` straight from the receive buffer: `fill_buf` hands out the front of it where it
+//! is, as far as it goes in one piece, and `read_line` runs on across segments and around the
+//! end of the receive ring. Over a `MockNic`, as `BufRead` is on `TcpStream`.
+
+use std::io::{BufRead, Read};
+
+use common::{PEER_ISS, accept, segment, wait_sent};
+use trust::testing::{MockNic, parse_segment};
+use trust::{ConnectionConfig, Interface, TcpStream};
+
+mod common;
+
+/// A stream accepted with a receive buffer of `size` bytes, and the peer's next sequence
+/// number along with ours.
+fn stream(nic: &MockNic, iface: &mut Interface, size: u16) -> (TcpStream, u32) {
+    let config = ConnectionConfig::default().recv_window(size);
+    let mut l = iface.bind_with_config(80, 16, config).unwrap();
+    accept(nic, &mut l)
+}
+
+/// Have the peer send `data` at `seq`, and wait for our ACK of it, so it's in the receive
+/// buffer.
+fn deliver(nic: &MockNic, seq: u32, iss: u32, data: &[u8]) {
+    nic.inject(&segment(seq, Some(iss), data));
+    let end = seq + data.len() as u32;
+    while !wait_sent(nic, 1)
+        .iter()
+        .any(|p| parse_segment(p).1.acknowledgment_number() == end)
+    {}
+}
+
+#[test]
+fn read_line_across_a_segment_boundary() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let (mut s, iss) = stream(&nic, &mut iface, 1000);
+
+    deliver(&nic, PEER_ISS + 1, iss, b"hello wo");
+    // only the first segment is there so far, with no end of line in it
+    assert_eq!(s.fill_buf().unwrap(), b"hello wo");
+    deliver(&nic, PEER_ISS + 9, iss, b"rld\nnext line\n");
+
+    let mut line = String::new();
+    s.read_line(&mut line).unwrap();
+    assert_eq!(line, "hello world\n");
+    line.clear();
+    s.read_line(&mut line).unwrap();
+    assert_eq!(line, "next line\n");
+}
+
+#[test]
+fn read_line_across_the_ring_wrap() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let (mut s, iss) = stream(&nic, &mut iface, 100);
+
+    // 70 bytes of a line read, and 10 of the next left at 70..80 of the 100-byte ring
+    let first = [[b'a'; 69].as_slice(), b"\n"].concat();
+    deliver(
+        &nic,
+        PEER_ISS + 1,
+        iss,
+        &[&first[..], b"0123456789"].concat(),
+    );
+    let mut buf = [0; 70];
+    s.read_exact(&mut buf).unwrap();
+    assert_eq!(buf[..], first[..]);
+
+    // the next 50 fill it up to the end, and carry on from the start
+    let rest = [[b'b'; 49].as_slice(), b"\n"].concat();
+    deliver(&nic, PEER_ISS + 81, iss, &rest);
+    // the first piece stops at the end of the ring, without the end of the line
+    assert_eq!(s.fill_buf().unwrap().len(), 30);
+
+    let mut line = String::new();
+    s.read_line(&mut line).unwrap();
+    assert_eq!(line.as_bytes(), [&b"0123456789"[..], &rest].concat());
+    assert_eq!(s.recv_buffer_len().unwrap(), 0);
+}
@@ -0,0 +1,121 @@
+//! The pcap files `Interface::capture` writes, parsed back by hand.
+
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use etherparse::{IpTrafficClass, Ipv4Header, TcpHeader};
+use trust::Interface;
+use trust::testing::MockNic;
+
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+const LINKTYPE_RAW: u32 = 101;
+
+/// A SYN from the peer's port `port` to our port 80.
+fn syn(port: u16) -> Vec<u8> {
+    let mut tcph = TcpHeader::new(port, 80, 1000, 1024);
+    tcph.syn = true;
+    let mut iph = Ipv4Header::new(0, 64, IpTrafficClass::Tcp, PEER.octets(), LOCAL.octets());
+    iph.set_payload_len(tcph.header_len() as usize).unwrap();
+    tcph.checksum = tcph.calc_checksum_ipv4(&iph, &[]).unwrap();
+    let mut p = Vec::new();
+    iph.write(&mut p).unwrap();
+    tcph.write(&mut p).unwrap();
+    p
+}
+
+/// A path of its own for each test to capture into.
+fn capture_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("trust-{}-{name}.pcap", std::process::id()))
+}
+
+fn wait_sent(nic: &MockNic, n: usize) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while nic.sent_len() < n {
+        assert!(Instant::now() < deadline, "sent {} of {n}", nic.sent_len());
+        thread::sleep(Duration::from_millis(1));
+    }
+    nic.take_sent()
+}
+
+fn u32_at(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+/// Check the global header of a pcap file, and split the rest into its records' timestamps
+/// and packets.
+fn parse(file: &[u8]) -> Vec<(Duration, Vec<u8>)> {
+    assert_eq!(u32_at(file, 0), 0xa1b2c3d4, "not a microsecond pcap file");
+    assert_eq!(&file[4..8], &[2, 0, 4, 0], "not pcap 2.4");
+    assert_eq!(u32_at(file, 16), 65535);
+    assert_eq!(u32_at(file, 20), LINKTYPE_RAW);
+    let mut records = Vec::new();
+    let mut at = 24;
+    while at < file.len() {
+        let ts = Duration::new(u32_at(file, at) as u64, u32_at(file, at + 4) * 1000);
+        let incl = u32_at(file, at + 8) as usize;
+        assert_eq!(u32_at(file, at + 12) as usize, incl, "packet was cut short");
+        records.push((ts, file[at + 16..at + 16 + incl].to_vec()));
+        at += 16 + incl;
+    }
+    assert_eq!(at, file.len(), "trailing partial record");
+    records
+}
+
+#[test]
+fn captures_both_directions_in_order() {
+    let path = capture_path("both");
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let _l = iface.bind(80).unwrap();
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    iface.capture(&path, None).unwrap();
+
+    let syn = syn(40000);
+    nic.inject(&syn);
+    let synack = wait_sent(&nic, 1).remove(0);
+    iface.stop_capture();
+
+    let records = parse(&fs::read(&path).unwrap());
+    fs::remove_file(&path).unwrap();
+    let packets: Vec<_> = records.iter().map(|(_, p)| p.clone()).collect();
+    assert_eq!(packets, [syn, synack]);
+    let end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    for (ts, _) in &records {
+        // to the microsecond, which is all the file keeps
+        assert!(*ts + Duration::from_micros(1) >= start && *ts <= end);
+    }
+    assert!(records[0].0 <= records[1].0);
+}
+
+#[test]
+fn rotates_once_past_max_size() {
+    let path = capture_path("rotate");
+    let mut rotated = path.clone().into_os_string();
+    rotated.push(".1");
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let _l = iface.bind(80).unwrap();
+    // room for the header, a SYN and its SYN-ACK, options and all, but not another SYN
+    iface.capture(&path, Some(24 + (16 + 40) + 100)).unwrap();
+
+    for port in 40000..40003 {
+        nic.inject(&syn(port));
+        wait_sent(&nic, 1);
+    }
+    iface.stop_capture();
+
+    let old = parse(&fs::read(&rotated).unwrap());
+    let new = parse(&fs::read(&path).unwrap());
+    fs::remove_file(&rotated).unwrap();
+    fs::remove_file(&path).unwrap();
+    // six packets in all: the first two rotated out and then replaced, and the last SYN with
+    // its SYN-ACK in the file still being written
+    assert_eq!((old.len(), new.len()), (2, 2));
+    assert_eq!(old[0].1, syn(40001));
+    assert_eq!(new[0].1, syn(40002));
+}
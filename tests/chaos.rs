@@ -0,0 +1,147 @@
+//! Two of our stacks talking to each other over a `ChaosLink` that loses packets.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use trust::testing::seq::wrapping_lt;
+use trust::testing::{ChaosConfig, ChaosEnd, ChaosLink, ManualClock, parse_segment};
+use trust::{ConnectionConfig, Interface, NewReno, Nic};
+
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+/// `len` bytes that don't repeat with any period a lost or misplaced segment would hide in.
+fn pattern(len: usize) -> Vec<u8> {
+    let mut x: u32 = 0x9e37_79b9;
+    (0..len)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        })
+        .collect()
+}
+
+/// The client's end of the link, counting the data segments it sends again: any that start
+/// before the furthest it has sent so far. There's only the one connection over it.
+struct Retransmits {
+    end: ChaosEnd<ManualClock>,
+    sent_to: Option<u32>,
+    count: Arc<AtomicU64>,
+}
+
+impl Nic for Retransmits {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (_, tcph, data) = parse_segment(buf);
+        if !data.is_empty() {
+            let seq = tcph.sequence_number();
+            match self.sent_to {
+                Some(to) if wrapping_lt(seq, to) => {
+                    self.count.fetch_add(1, Ordering::Relaxed);
+                }
+                _ => self.sent_to = Some(seq.wrapping_add(data.len() as u32)),
+            }
+        }
+        self.end.send(buf)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.end.recv(buf)
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        self.end.recv_batch(bufs, lens)
+    }
+
+    fn mtu(&self) -> usize {
+        self.end.mtu()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.end.poll(timeout)
+    }
+}
+
+#[test]
+fn transfer_through_two_percent_loss() {
+    const LEN: usize = 5 * 1024 * 1024;
+    let lossy = ChaosConfig {
+        drop: 0.02,
+        ..Default::default()
+    };
+    // time runs twenty times faster than it really does, so every loss that's left to the
+    // retransmission timer doesn't hold things up for a whole second. the packet loop still
+    // checks in every 10ms of real time, and nothing on the link is ever that slow.
+    let clock = ManualClock::new();
+    let running = Arc::new(AtomicBool::new(true));
+    let ticker = {
+        let (clock, running) = (clock.clone(), running.clone());
+        thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+                clock.advance(Duration::from_millis(20));
+            }
+        })
+    };
+
+    let (client_end, server_end) = ChaosLink::pair(lossy.clone(), lossy, 109, clock.clone());
+    let client_stats = client_end.clone();
+    let retransmits = Arc::new(AtomicU64::new(0));
+    let client_nic = Retransmits {
+        end: client_end,
+        sent_to: None,
+        count: retransmits.clone(),
+    };
+    let mut client = Interface::with_clock(client_nic, clock.clone());
+    client.add_address(CLIENT.into());
+    let mut server = Interface::with_clock(server_end, clock);
+    server.add_address(SERVER.into());
+
+    // a window big enough to keep a few dozen segments in flight, so there's something for a
+    // lost one to be fast-retransmitted from
+    let config = ConnectionConfig::default().recv_window(u16::MAX);
+    let mut l = server.bind_with_config(80, 1, config).unwrap();
+    let reader = thread::spawn(move || {
+        let mut s = l.accept().unwrap();
+        let mut got = Vec::new();
+        s.read_to_end(&mut got).unwrap();
+        got
+    });
+    // NewReno, so that a loss is recovered from without waiting for the timer: the server
+    // keeps nothing that arrives out of order, so everything in flight behind a lost segment
+    // has to go again, and Reno would give up on fast recovery at the first partial ACK
+    let lossy_config = ConnectionConfig::default().congestion_control(NewReno::new);
+    let data = pattern(LEN);
+    let mut s = client
+        .connect_with_config(CLIENT.into(), SocketAddr::from((SERVER, 80)), lossy_config)
+        .unwrap();
+    let mss = s.info().unwrap().mss as u64;
+    s.write_all(&data).unwrap();
+    drop(s);
+
+    let got = reader.join().unwrap();
+    running.store(false, Ordering::Relaxed);
+    ticker.join().unwrap();
+    assert_eq!(got.len(), data.len());
+    assert!(got == data, "data corrupted in transit");
+
+    // every data segment the link lost had to be sent again, and at worst so did the rest of
+    // the window behind it, the server keeping none of that
+    let stats = client_stats.stats();
+    let resent = retransmits.load(Ordering::Relaxed);
+    let window = u16::MAX as u64 / mss + 1;
+    assert!(stats.dropped > 0);
+    assert!(
+        resent >= stats.dropped / 2,
+        "{stats:?}: resent only {resent}"
+    );
+    assert!(
+        resent <= stats.dropped * window,
+        "{stats:?}: resent {resent}"
+    );
+}
@@ -0,0 +1,217 @@
+//! A characterization check for refactors that mean to change nothing on the wire: a scripted
+//! set of sessions driven through `Replay`, covering the handshake, transfer both ways, loss
+//! recovery, the close sequences, a zero window, timeouts and resets, with every segment the
+//! stack sends dumped a line at a time and compared against `captures/characterization.txt`.
+//!
+//! A change that's meant to alter what goes out should say so by regenerating the dump, with
+//! `TRUST_BLESS=1 cargo test --test characterization`, and committing it alongside.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, fin, handshake, rst, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, NewReno};
+
+mod common;
+
+const ISS: u32 = 5000;
+const MSS: u16 = 1000;
+
+/// The sessions' segments, headed by which session and step sent them.
+#[derive(Default)]
+struct Dump(String);
+
+impl Dump {
+    /// Note everything `r` has sent since last time, as of `step`, or that it sent nothing.
+    fn take(&mut self, r: &Replay, step: &str) {
+        let sent = r.take_sent();
+        if sent.is_empty() {
+            writeln!(self.0, "{step}: nothing").unwrap();
+        }
+        for p in sent {
+            let (_, tcph, data) = parse_segment(&p);
+            let flags: String = [
+                (tcph.syn(), 'S'),
+                (tcph.fin(), 'F'),
+                (tcph.rst(), 'R'),
+                (tcph.psh(), 'P'),
+                (tcph.ack(), '.'),
+            ]
+            .iter()
+            .filter_map(|&(set, c)| set.then_some(c))
+            .collect();
+            let options: String = tcph.options().iter().map(|b| format!("{b:02x}")).collect();
+            writeln!(
+                self.0,
+                "{step}: [{flags}] seq {} ack {} win {} len {} opts [{options}]",
+                tcph.sequence_number(),
+                tcph.acknowledgment_number(),
+                tcph.window_size(),
+                data.len(),
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// A listener with a fixed ISN, and a connection to it from a peer offering `MSS`.
+fn established(config: ConnectionConfig, dump: &mut Dump, name: &str) -> Replay {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config.initial_sequence_number(ISS));
+    r.feed(&Segment::syn_at(PEER_ISS).mss(MSS).build(&[]))
+        .unwrap();
+    dump.take(&r, &format!("{name} syn"));
+    r.feed(&segment(PEER_ISS + 1, Some(ISS + 1), &[])).unwrap();
+    dump.take(&r, &format!("{name} ack"));
+    r
+}
+
+fn transfer_and_active_close(dump: &mut Dump) {
+    let name = "active close";
+    let mut r = established(ConnectionConfig::default(), dump, name);
+    r.write(QUAD, &[1; 2500]).unwrap();
+    dump.take(&r, &format!("{name} write"));
+    r.feed(&segment(PEER_ISS + 1, Some(ISS + 2501), b"reply"))
+        .unwrap();
+    dump.take(&r, &format!("{name} reply"));
+    r.read(QUAD, 100).unwrap();
+    dump.take(&r, &format!("{name} read"));
+    r.close(QUAD).unwrap();
+    dump.take(&r, &format!("{name} close"));
+    r.feed(&fin(PEER_ISS + 6, ISS + 2502, &[])).unwrap();
+    dump.take(&r, &format!("{name} peer fin"));
+    r.feed(&fin(PEER_ISS + 6, ISS + 2502, &[])).unwrap();
+    dump.take(&r, &format!("{name} fin again"));
+    r.advance(Duration::from_secs(130)).unwrap();
+    dump.take(&r, &format!("{name} time-wait over"));
+}
+
+fn passive_close(dump: &mut Dump) {
+    let name = "passive close";
+    let mut r = established(ConnectionConfig::default(), dump, name);
+    r.feed(&fin(PEER_ISS + 1, ISS + 1, b"bye")).unwrap();
+    dump.take(&r, &format!("{name} peer fin"));
+    r.read(QUAD, 100).unwrap();
+    r.close(QUAD).unwrap();
+    dump.take(&r, &format!("{name} close"));
+    r.advance(Duration::from_millis(1100)).unwrap();
+    dump.take(&r, &format!("{name} fin resent"));
+    r.feed(&segment(PEER_ISS + 5, Some(ISS + 2), &[])).unwrap();
+    dump.take(&r, &format!("{name} fin acked"));
+}
+
+fn loss_recovery(dump: &mut Dump) {
+    let name = "loss";
+    let config = ConnectionConfig::default().congestion_control(NewReno::new);
+    let mut r = established(config, dump, name);
+    r.write(QUAD, &[2; 10 * MSS as usize]).unwrap();
+    dump.take(&r, &format!("{name} write"));
+    let at = |k: u32| ISS + 1 + k * MSS as u32;
+    for i in 0..4 {
+        r.feed(&segment(PEER_ISS + 1, Some(at(2)), &[])).unwrap();
+        dump.take(&r, &format!("{name} dup {i}"));
+    }
+    r.feed(&segment(PEER_ISS + 1, Some(at(5)), &[])).unwrap();
+    dump.take(&r, &format!("{name} partial"));
+    r.feed(&segment(PEER_ISS + 1, Some(at(6)), &[])).unwrap();
+    dump.take(&r, &format!("{name} more"));
+    r.advance(Duration::from_millis(1100)).unwrap();
+    dump.take(&r, &format!("{name} timeout"));
+    r.feed(&segment(PEER_ISS + 1, Some(at(10)), &[])).unwrap();
+    dump.take(&r, &format!("{name} all acked"));
+
+    // and the other way round: a hole in what the peer sends, then filled
+    r.feed(&segment(PEER_ISS + 101, Some(at(10)), &[3; 100]))
+        .unwrap();
+    dump.take(&r, &format!("{name} out of order"));
+    r.feed(&segment(PEER_ISS + 1, Some(at(10)), &[3; 100]))
+        .unwrap();
+    dump.take(&r, &format!("{name} hole filled"));
+}
+
+fn zero_window(dump: &mut Dump) {
+    let name = "zero window";
+    let mut r = established(ConnectionConfig::default().recv_window(1000), dump, name);
+    r.feed(&segment(PEER_ISS + 1, Some(ISS + 1), &[4; 1000]))
+        .unwrap();
+    dump.take(&r, &format!("{name} filled"));
+    r.read(QUAD, 600).unwrap();
+    dump.take(&r, &format!("{name} read"));
+
+    // the peer closes its own window on us, and what's written waits for it to reopen
+    r.feed(
+        &Segment::new(PEER_ISS + 1001)
+            .ack(ISS + 1)
+            .window(0)
+            .build(&[]),
+    )
+    .unwrap();
+    r.write(QUAD, b"probe me").unwrap();
+    dump.take(&r, &format!("{name} write"));
+    r.advance(Duration::from_millis(1100)).unwrap();
+    dump.take(&r, &format!("{name} waiting"));
+    r.feed(&segment(PEER_ISS + 1001, Some(ISS + 1), &[]))
+        .unwrap();
+    dump.take(&r, &format!("{name} reopened"));
+}
+
+fn timeouts_and_resets(dump: &mut Dump) {
+    // our SYN-ACK goes unanswered, and is sent again
+    let name = "syn-ack lost";
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().initial_sequence_number(ISS));
+    r.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+    dump.take(&r, &format!("{name} syn"));
+    r.advance(Duration::from_millis(1100)).unwrap();
+    dump.take(&r, &format!("{name} timeout"));
+    r.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+    dump.take(&r, &format!("{name} syn again"));
+
+    // a segment for a port nobody's listening on, and a RST for a connection we have
+    let name = "reset";
+    let (mut r, iss) = {
+        let mut r = Replay::new(LOCAL);
+        r.listen(80, ConnectionConfig::default().initial_sequence_number(ISS));
+        let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+        (r, iss)
+    };
+    r.feed(
+        &Segment::new(7)
+            .ack(9)
+            .on(trust::Quad {
+                src: QUAD.src,
+                dst: (QUAD.dst.0, 81),
+            })
+            .build(b"x"),
+    )
+    .unwrap();
+    dump.take(&r, &format!("{name} no connection"));
+    r.write(QUAD, b"hello").unwrap();
+    dump.take(&r, &format!("{name} write"));
+    r.feed(&rst(PEER_ISS + 1, iss)).unwrap();
+    dump.take(&r, &format!("{name} rst"));
+}
+
+#[test]
+fn what_goes_out_is_unchanged() {
+    let mut dump = Dump::default();
+    transfer_and_active_close(&mut dump);
+    passive_close(&mut dump);
+    loss_recovery(&mut dump);
+    zero_window(&mut dump);
+    timeouts_and_resets(&mut dump);
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/captures/characterization.txt");
+    if std::env::var_os("TRUST_BLESS").is_some() {
+        fs::write(&path, &dump.0).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap();
+    for (i, (got, want)) in dump.0.lines().zip(expected.lines()).enumerate() {
+        assert_eq!(got, want, "line {} of {}", i + 1, path.display());
+    }
+    assert_eq!(dump.0.lines().count(), expected.lines().count());
+}
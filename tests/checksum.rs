@@ -0,0 +1,113 @@
+//! The checksums on what we send cover the payload, whatever size it was cut to: every segment
+//! is checked both with etherparse and with a ones' complement sum of our own, over the
+//! pseudo-header and the whole segment. Driven through `Replay`, and over a `MockNic` with a
+//! small MTU for the cut at the MTU.
+
+use std::io::Write;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, segment, wait_sent};
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Interface};
+
+mod common;
+
+/// The ones' complement sum of `data` as 16-bit words, the last padded with a zero.
+fn sum(data: &[u8], mut acc: u32) -> u32 {
+    for c in data.chunks(2) {
+        acc += u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32;
+    }
+    while acc > 0xffff {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+    acc
+}
+
+/// Check the TCP checksum of `packet`, one of ours, both ways. Returns its payload length.
+fn check(packet: &[u8]) -> usize {
+    let (iph, tcph, data) = parse_segment(packet);
+    let expected = tcph
+        .calc_checksum_ipv4_raw(iph.source(), iph.destination(), data)
+        .unwrap();
+    assert_eq!(tcph.checksum(), expected, "etherparse disagrees");
+
+    let segment = &packet[iph.slice().len()..];
+    let mut pseudo = Vec::new();
+    pseudo.extend_from_slice(iph.source());
+    pseudo.extend_from_slice(iph.destination());
+    pseudo.extend_from_slice(&[0, 6]);
+    pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+    assert_eq!(sum(segment, sum(&pseudo, 0)), 0xffff, "doesn't sum to zero");
+    data.len()
+}
+
+/// An established connection whose peer offered `mss`, if anything, with its SYN-ACK checked.
+/// Returns our next sequence number too.
+fn establish(mss: Option<u16>) -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let mut syn = Segment::syn_at(PEER_ISS);
+    if let Some(mss) = mss {
+        syn = syn.mss(mss);
+    }
+    let (iss, synack) = handshake(&mut r, syn);
+    assert_eq!(check(&synack), 0);
+    (r, iss)
+}
+
+/// `len` bytes that don't sum to the same as any shorter run of them.
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + 1) as u8).collect()
+}
+
+#[test]
+fn data_segments_are_checksummed_over_their_payload() {
+    let (mut r, iss) = establish(Some(1460));
+    let mut una = iss;
+    // an odd length too, which pads the sum
+    for len in [1, 100, 333] {
+        r.write(QUAD, &data(len)).unwrap();
+        let sent = r.take_sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(check(&sent[0]), len);
+        // ACKed, so Nagle doesn't hold the next one back
+        una = una.wrapping_add(len as u32);
+        r.feed(&segment(PEER_ISS + 1, Some(una), &[])).unwrap();
+    }
+}
+
+#[test]
+fn full_sized_segments_at_the_mtu() {
+    let (mut r, _) = establish(Some(1460));
+    // the last byte doesn't fit, and goes on its own
+    r.write(QUAD, &data(1461)).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent[0].len(), 1500);
+    let lens: Vec<_> = sent.iter().map(|p| check(p)).collect();
+    assert_eq!(lens, [1460, 1]);
+}
+
+#[test]
+fn default_mss_segments() {
+    let (mut r, _) = establish(None);
+    r.write(QUAD, &data(1000)).unwrap();
+    let lens: Vec<_> = r.take_sent().iter().map(|p| check(p)).collect();
+    assert_eq!(lens, [536, 464]);
+}
+
+#[test]
+fn cut_at_a_small_mtu() {
+    let nic = MockNic::with_mtu(600);
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    nic.inject(&Segment::syn_at(PEER_ISS).mss(1460).build(&[]));
+    let synack = &wait_sent(&nic, 1)[0];
+    check(synack);
+    let iss = parse_segment(synack).1.sequence_number().wrapping_add(1);
+    nic.inject(&Segment::syn_at(PEER_ISS).next(iss).build(&[]));
+    let mut s = l.accept().unwrap();
+    s.write_all(&data(1000)).unwrap();
+    let sent = wait_sent(&nic, 2);
+    assert_eq!(sent[0].len(), 600);
+    let lens: Vec<_> = sent.iter().map(|p| check(p)).collect();
+    assert_eq!(lens, [560, 440]);
+}
@@ -0,0 +1,65 @@
+//! Timers on the packet loop driven by a `ManualClock`, so a test skips through them rather
+//! than waiting them out.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{PEER_ISS, QUAD, fin, segment};
+use trust::testing::{ManualClock, MockNic, parse_segment};
+use trust::{Interface, State};
+
+mod common;
+
+/// Wait for the packet loop to have sent `n` packets, and take them.
+fn wait_sent(nic: &MockNic, n: usize) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while nic.sent_len() < n {
+        assert!(Instant::now() < deadline, "sent {} of {n}", nic.sent_len());
+        thread::sleep(Duration::from_millis(1));
+    }
+    nic.take_sent()
+}
+
+/// Wait for the connection for `QUAD` to be in `want`, or gone if that's `None`. Gives up
+/// after a second of wall time.
+fn wait_state(iface: &Interface, want: Option<State>) {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        let state = iface
+            .connections()
+            .unwrap()
+            .find(|(q, _)| *q == QUAD)
+            .map(|(_, info)| info.state);
+        if state == want {
+            return;
+        }
+        assert!(Instant::now() < deadline, "still {state:?}, not {want:?}");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn time_wait_expires_without_waiting_out_2msl() {
+    let nic = MockNic::new();
+    let clock = ManualClock::new();
+    let mut iface = Interface::with_clock(nic.clone(), clock.clone());
+    let mut l = iface.bind(80).unwrap();
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    let iss = parse_segment(&wait_sent(&nic, 1)[0]).1.sequence_number();
+    nic.inject(&segment(PEER_ISS + 1, Some(iss.wrapping_add(1)), &[]));
+
+    // we close first, the peer ACKs our FIN and sends its own
+    drop(l.accept().unwrap());
+    let our_fin = parse_segment(&wait_sent(&nic, 1)[0]).1.sequence_number();
+    nic.inject(&fin(PEER_ISS + 1, our_fin.wrapping_add(1), &[]));
+    wait_state(&iface, Some(State::TimeWait));
+
+    let start = Instant::now();
+    clock.advance(Duration::from_secs(59));
+    thread::sleep(Duration::from_millis(50));
+    wait_state(&iface, Some(State::TimeWait));
+    clock.advance(Duration::from_secs(1));
+    wait_state(&iface, None);
+    // a minute of TIME-WAIT, in the time it takes the packet loop to notice
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
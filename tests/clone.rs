@@ -0,0 +1,103 @@
+//! Clones of a `TcpStream`, over a `MockNic`: handles on the one connection, which lasts
+//! until the last of them is dropped or any of them closes it.
+
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::thread;
+use std::time::Duration;
+
+use common::{PEER_ISS, accept, fin, segment, sent_until_fin};
+use trust::Interface;
+use trust::testing::{MockNic, parse_segment};
+
+mod common;
+
+#[test]
+fn one_clone_reads_while_another_writes() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (mut s, _) = accept(&nic, &mut l);
+
+    let mut writer = s.clone();
+    let sent = thread::spawn(move || {
+        for i in 0..1000u32 {
+            writer.write_all(&i.to_be_bytes())?;
+        }
+        writer.shutdown(Shutdown::Write)
+    });
+    let reader = thread::spawn(move || {
+        let mut got = Vec::new();
+        s.read_to_end(&mut got).map(|_| got)
+    });
+
+    let (got, our_fin) = sent_until_fin(&nic);
+    sent.join().unwrap().unwrap();
+    let expected: Vec<u8> = (0..1000u32).flat_map(u32::to_be_bytes).collect();
+    assert!(got == expected);
+
+    nic.inject(&fin(PEER_ISS + 1, our_fin.wrapping_add(1), b"and back"));
+    assert_eq!(reader.join().unwrap().unwrap(), b"and back");
+}
+
+#[test]
+fn dropping_a_clone_leaves_the_connection_open() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (s, iss) = accept(&nic, &mut l);
+
+    let mut other = s.clone();
+    drop(s);
+    other.info().unwrap();
+    nic.inject(&segment(PEER_ISS + 1, Some(iss), b"still here"));
+    let mut buf = [0; 10];
+    other.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"still here");
+    // and no FIN went out for the one that's gone
+    thread::sleep(Duration::from_millis(50));
+    assert!(nic.take_sent().iter().all(|p| !parse_segment(p).1.fin()));
+}
+
+#[test]
+fn blocked_readers_take_turns() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (s, iss) = accept(&nic, &mut l);
+
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let mut r = s.clone();
+            thread::spawn(move || {
+                let mut b = [0; 1];
+                r.read_exact(&mut b).map(|_| b[0])
+            })
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(50));
+    nic.inject(&segment(PEER_ISS + 1, Some(iss), b"a"));
+    thread::sleep(Duration::from_millis(50));
+    nic.inject(&segment(PEER_ISS + 2, Some(iss), b"b"));
+    let mut got: Vec<u8> = readers
+        .into_iter()
+        .map(|r| r.join().unwrap().unwrap())
+        .collect();
+    got.sort();
+    assert_eq!(got, b"ab");
+}
+
+#[test]
+fn close_on_any_clone_closes_it_for_all() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (s, _) = accept(&nic, &mut l);
+
+    let mut keep = s.clone();
+    s.close().unwrap();
+    let (rest, _) = sent_until_fin(&nic);
+    assert!(rest.is_empty());
+    drop(s);
+    assert_eq!(keep.read(&mut [0; 1]).unwrap(), 0);
+}
@@ -0,0 +1,63 @@
+//! Our FIN on an active close with data still queued behind a small window: it waits for the
+//! data, and goes on the segment carrying the last of it. Driven through `Replay`, so no device
+//! needed.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// `(seq, len, fin)` of each of what was sent.
+fn sent(r: &Replay) -> Vec<(u32, usize, bool)> {
+    r.take_sent()
+        .iter()
+        .map(|p| {
+            let (_, tcph, data) = parse_segment(p);
+            (tcph.sequence_number(), data.len(), tcph.fin())
+        })
+        .collect()
+}
+
+#[test]
+fn fin_waits_for_the_window_and_rides_the_last_segment() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let peer = Segment::syn_at(PEER_ISS).window(1000);
+    let (iss, _) = handshake(&mut r, peer.clone());
+
+    // two windows' worth, and the close straight after: only the first window goes, with no
+    // FIN ahead of what's still queued
+    r.write(QUAD, &[7; 2000]).unwrap();
+    r.close(QUAD).unwrap();
+    assert_eq!(sent(&r), [(iss, 536, false), (iss + 536, 464, false)]);
+
+    // the window moves on, with room for the rest and the FIN, which goes with the last of it
+    let ack = Segment::new(PEER_ISS + 1).window(1001);
+    r.feed(&ack.clone().ack(iss + 1000).build(&[])).unwrap();
+    assert_eq!(
+        sent(&r),
+        [(iss + 1000, 536, false), (iss + 1536, 464, true)]
+    );
+
+    // and once all of it and the FIN are ACKed, that's our side closed
+    r.feed(&ack.ack(iss + 2001).build(&[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::FinWait2));
+}
+
+#[test]
+fn fin_waits_for_a_window_with_room_for_it() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).window(100));
+
+    // exactly a window's worth: the FIN doesn't fit behind it
+    r.write(QUAD, &[7; 100]).unwrap();
+    r.close(QUAD).unwrap();
+    assert_eq!(sent(&r), [(iss, 100, false)]);
+
+    // with the data ACKed and room again, it goes on its own
+    let ack = Segment::new(PEER_ISS + 1).ack(iss + 100).window(100);
+    r.feed(&ack.build(&[])).unwrap();
+    assert_eq!(sent(&r), [(iss + 100, 0, true)]);
+}
@@ -0,0 +1,291 @@
+//! What the `Replay`-driven tests share: the two ends' addresses, segments from the peer built
+//! to order, and a handshake to get a connection going. And for the tests that run a real
+//! `Interface` over a `MockNic`, the same handshake done through its packet loop.
+//!
+//! Each test file takes what it needs of this, so not all of it is used by any one of them.
+#![allow(dead_code)]
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use etherparse::{IpTrafficClass, Ipv4Header, Ipv6Header, TcpHeader, TcpOptionElement};
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, State, TcpListener, TcpStream};
+
+pub const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+pub const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+pub const PEER_ISS: u32 = 100;
+
+/// The peer's port 40000 to our port 80, which `Segment`s are sent over unless told otherwise.
+pub const QUAD: Quad = Quad {
+    src: (IpAddr::V4(PEER), 40000),
+    dst: (IpAddr::V4(LOCAL), 80),
+};
+
+/// A segment from the peer, built up a field at a time: `Segment::new(seq).ack(n).fin()` and so
+/// on, then turned into a packet with `build`.
+#[derive(Clone)]
+pub struct Segment {
+    quad: Quad,
+    seq: u32,
+    ack: Option<u32>,
+    syn: bool,
+    fin: bool,
+    rst: bool,
+    psh: bool,
+    window: u16,
+    mss: Option<u16>,
+    ip_options: Vec<u8>,
+}
+
+impl Segment {
+    /// A segment over `QUAD` starting at `seq`, with no flags set and the largest window there
+    /// is without scaling.
+    pub fn new(seq: u32) -> Self {
+        Segment {
+            quad: QUAD,
+            seq,
+            ack: None,
+            syn: false,
+            fin: false,
+            rst: false,
+            psh: false,
+            window: u16::MAX,
+            mss: None,
+            ip_options: Vec::new(),
+        }
+    }
+
+    /// A SYN starting at `seq`.
+    pub fn syn_at(seq: u32) -> Self {
+        Self::new(seq).syn()
+    }
+
+    pub fn syn(mut self) -> Self {
+        self.syn = true;
+        self
+    }
+
+    pub fn ack(mut self, ack: u32) -> Self {
+        self.ack = Some(ack);
+        self
+    }
+
+    pub fn fin(mut self) -> Self {
+        self.fin = true;
+        self
+    }
+
+    pub fn rst(mut self) -> Self {
+        self.rst = true;
+        self
+    }
+
+    pub fn psh(mut self) -> Self {
+        self.psh = true;
+        self
+    }
+
+    pub fn window(mut self, window: u16) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Offer `mss` in an MSS option.
+    pub fn mss(mut self, mss: u16) -> Self {
+        self.mss = Some(mss);
+        self
+    }
+
+    /// Send it over `quad` instead, which is from the peer's point of view as ours always are:
+    /// `src` is the peer.
+    pub fn on(mut self, quad: Quad) -> Self {
+        self.quad = quad;
+        self
+    }
+
+    /// Put `options` in the IP header; they need padding out to a multiple of four.
+    pub fn ip_options(mut self, options: &[u8]) -> Self {
+        self.ip_options = options.to_vec();
+        self
+    }
+
+    /// The segment after this one in the handshake: the ACK of a SYN-ACK ending at `ack`.
+    pub fn next(&self, ack: u32) -> Self {
+        Segment {
+            seq: self.seq.wrapping_add(1),
+            ack: Some(ack),
+            syn: false,
+            mss: None,
+            ..self.clone()
+        }
+    }
+
+    fn tcp_header(&self) -> TcpHeader {
+        let mut tcph = TcpHeader::new(self.quad.src.1, self.quad.dst.1, self.seq, self.window);
+        if let Some(ack) = self.ack {
+            tcph.ack = true;
+            tcph.acknowledgment_number = ack;
+        }
+        tcph.syn = self.syn;
+        tcph.fin = self.fin;
+        tcph.rst = self.rst;
+        tcph.psh = self.psh;
+        if let Some(mss) = self.mss {
+            tcph.set_options(&[TcpOptionElement::MaximumSegmentSize(mss)])
+                .unwrap();
+        }
+        tcph
+    }
+
+    /// The whole packet, IP header and all, carrying `data`: IPv4 or IPv6, whichever the quad
+    /// is. IP options only go on IPv4.
+    pub fn build(&self, data: &[u8]) -> Vec<u8> {
+        let mut tcph = self.tcp_header();
+        let mut p = Vec::new();
+        match (self.quad.src.0, self.quad.dst.0) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let mut iph =
+                    Ipv4Header::new(0, 64, IpTrafficClass::Tcp, src.octets(), dst.octets());
+                iph.set_options(&self.ip_options).unwrap();
+                iph.set_payload_len(tcph.header_len() as usize + data.len())
+                    .unwrap();
+                tcph.checksum = tcph.calc_checksum_ipv4(&iph, data).unwrap();
+                iph.write(&mut p).unwrap();
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                assert!(self.ip_options.is_empty(), "no IP options on IPv6");
+                let iph = Ipv6Header {
+                    traffic_class: 0,
+                    flow_label: 0,
+                    payload_length: (tcph.header_len() as usize + data.len()) as u16,
+                    next_header: IpTrafficClass::Tcp as u8,
+                    hop_limit: 64,
+                    source: src.octets(),
+                    destination: dst.octets(),
+                };
+                tcph.checksum = tcph.calc_checksum_ipv6(&iph, data).unwrap();
+                iph.write(&mut p).unwrap();
+            }
+            _ => panic!("the two ends of a quad in different families"),
+        }
+        tcph.write(&mut p).unwrap();
+        p.extend_from_slice(data);
+        p
+    }
+
+    /// Just the TCP header and `data`, unchecksummed, as `Replay::inject` takes them.
+    pub fn tcp(&self, data: &[u8]) -> Vec<u8> {
+        let mut p = Vec::new();
+        self.tcp_header().write(&mut p).unwrap();
+        p.extend_from_slice(data);
+        p
+    }
+}
+
+/// A segment from the peer over `QUAD`: an ACK of `ack` carrying `data`, or a SYN without one.
+pub fn segment(seq: u32, ack: Option<u32>, data: &[u8]) -> Vec<u8> {
+    match ack {
+        Some(ack) => Segment::new(seq).ack(ack).build(data),
+        None => Segment::syn_at(seq).build(data),
+    }
+}
+
+/// A FIN from the peer over `QUAD`, ACKing `ack` and carrying `data` before it.
+pub fn fin(seq: u32, ack: u32, data: &[u8]) -> Vec<u8> {
+    Segment::new(seq).ack(ack).fin().build(data)
+}
+
+/// A RST from the peer over `QUAD`, with an ACK of `ack` as most stacks send.
+pub fn rst(seq: u32, ack: u32) -> Vec<u8> {
+    Segment::new(seq).ack(ack).rst().build(&[])
+}
+
+/// Take the connection `syn` asks for through the handshake, with whatever's listening for it,
+/// ACKing the SYN-ACK with the same sort of segment. Returns our next sequence number and the
+/// SYN-ACK.
+pub fn handshake(r: &mut Replay, syn: Segment) -> (u32, Vec<u8>) {
+    r.feed(&syn.build(&[])).unwrap();
+    let synack = r.take_sent().pop().expect("no SYN-ACK");
+    let (iph, tcph, _) = parse_segment(&synack);
+    assert!(tcph.syn() && tcph.ack(), "SYN answered with something else");
+    assert_eq!(tcph.acknowledgment_number(), syn.seq.wrapping_add(1));
+    assert_eq!(
+        IpAddr::V4(iph.source_addr()),
+        syn.quad.dst.0,
+        "answered from the wrong address"
+    );
+    let iss = tcph.sequence_number().wrapping_add(1);
+    r.feed(&syn.next(iss).build(&[])).unwrap();
+    assert_eq!(r.state(syn.quad), Some(State::Estab));
+    (iss, synack)
+}
+
+/// A listener on port 80 taking connections with `config`, and one established to it over
+/// `QUAD`. Returns our next sequence number along with it.
+pub fn establish(config: ConnectionConfig) -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+    (r, iss)
+}
+
+/// Exactly one segment was sent, with no data, and it was a bare ACK of `ack`.
+pub fn assert_one_ack(r: &Replay, ack: u32) {
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let (_, tcph, data) = parse_segment(&sent[0]);
+    assert!(tcph.ack() && !tcph.syn() && !tcph.fin() && !tcph.rst());
+    assert!(data.is_empty());
+    assert_eq!(tcph.acknowledgment_number(), ack);
+}
+
+/// Wait for the packet loop to have sent `n` packets over `nic`, and take them.
+pub fn wait_sent(nic: &MockNic, n: usize) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while nic.sent_len() < n {
+        assert!(Instant::now() < deadline, "sent {} of {n}", nic.sent_len());
+        thread::sleep(Duration::from_millis(1));
+    }
+    nic.take_sent()
+}
+
+/// Take a connection over `QUAD` through the handshake with `l`, whose interface runs over
+/// `nic`, and accept it. Returns the stream and our next sequence number.
+pub fn accept(nic: &MockNic, l: &mut TcpListener) -> (TcpStream, u32) {
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    let iss = parse_segment(&wait_sent(nic, 1)[0])
+        .1
+        .sequence_number()
+        .wrapping_add(1);
+    nic.inject(&segment(PEER_ISS + 1, Some(iss), &[]));
+    (l.accept().unwrap(), iss)
+}
+
+/// Everything the packet loop sends over `nic` from here up to our FIN, and the FIN's sequence
+/// number. Data is ACKed as it comes, as the peer would, since what's written while some is
+/// in flight waits for an ACK; the FIN isn't.
+pub fn sent_until_fin(nic: &MockNic) -> (Vec<u8>, u32) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut got = Vec::new();
+    loop {
+        for p in nic.take_sent() {
+            let (_, tcph, data) = parse_segment(&p);
+            got.extend_from_slice(data);
+            let end = tcph.sequence_number().wrapping_add(data.len() as u32);
+            if tcph.fin() {
+                return (got, end);
+            }
+            if !data.is_empty() {
+                nic.inject(&segment(tcph.acknowledgment_number(), Some(end), &[]));
+            }
+        }
+        assert!(
+            Instant::now() < deadline,
+            "no FIN, after {} bytes",
+            got.len()
+        );
+        thread::sleep(Duration::from_millis(1));
+    }
+}
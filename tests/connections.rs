@@ -0,0 +1,51 @@
+//! Listing connections, as `Interface::connections` does: every one the stack knows about,
+//! each with its own state and buffers. Driven through `Replay`, so no device needed.
+
+use std::net::IpAddr;
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, Segment, handshake};
+use trust::testing::Replay;
+use trust::{ConnectionConfig, Quad, State};
+
+mod common;
+
+const OTHER: Quad = Quad {
+    src: (IpAddr::V4(PEER), 40001),
+    dst: (IpAddr::V4(LOCAL), 80),
+};
+
+#[test]
+fn lists_every_connection() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    let (other_iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).on(OTHER));
+    // something left unread on one of them
+    r.feed(&Segment::new(PEER_ISS + 1).ack(iss).build(b"more"))
+        .unwrap();
+
+    let mut quads = r.quads();
+    quads.sort_by_key(|q| q.src.1);
+    assert_eq!(quads, [QUAD, OTHER]);
+    for (quad, mss, unread) in [(QUAD, 1460, 4), (OTHER, 536, 0)] {
+        let info = r.info(quad).unwrap();
+        assert_eq!(info.state, State::Estab);
+        assert_eq!(info.mss, mss);
+        assert_eq!(info.bytes_in_flight, 0);
+        assert_eq!(info.recv_buffer_len, unread, "{quad:?}");
+    }
+
+    // one that's gone on to TIME-WAIT is still there, cut down to what it has left
+    r.close(OTHER).unwrap();
+    let their_fin = Segment::new(PEER_ISS + 1).ack(other_iss + 1).fin();
+    r.feed(&their_fin.on(OTHER).build(&[])).unwrap();
+    assert_eq!(r.quads().len(), 2);
+    assert_eq!(r.info(OTHER).unwrap().state, State::TimeWait);
+    assert_eq!(r.info(QUAD).unwrap().state, State::Estab);
+    // and a quad nobody's using has nothing to show
+    let unused = Quad {
+        src: (IpAddr::V4(PEER), 40002),
+        ..QUAD
+    };
+    assert!(r.info(unused).is_none());
+}
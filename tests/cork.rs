@@ -0,0 +1,101 @@
+//! Corking: nothing goes out while the stream is corked, until it's uncorked, flushed, shut
+//! down, or too much piles up, and then it goes in full-sized segments. Driven through
+//! `Replay`, so no device needed.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// the MSS the peer gets by default, as its SYN doesn't say
+const MSS: usize = 536;
+
+/// An established connection, corked, and our next sequence number.
+fn establish() -> (Replay, u32) {
+    let (mut r, iss) = common::establish(ConnectionConfig::default());
+    r.set_cork(QUAD, true).unwrap();
+    (r, iss)
+}
+
+/// The lengths of the data segments we've sent.
+fn sent_lengths(r: &Replay) -> Vec<usize> {
+    r.take_sent()
+        .iter()
+        .map(|p| parse_segment(p).2.len())
+        .filter(|&n| n > 0)
+        .collect()
+}
+
+#[test]
+fn nothing_is_sent_while_corked() {
+    let (mut r, iss) = establish();
+    r.write(QUAD, b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+    r.write(QUAD, &[0; 3 * MSS]).unwrap();
+    // neither the timers nor an ACK from the peer bring anything out
+    r.advance(std::time::Duration::from_secs(1)).unwrap();
+    r.feed(&segment(PEER_ISS + 1, Some(iss), &[])).unwrap();
+    assert!(r.take_sent().is_empty());
+
+    r.set_cork(QUAD, false).unwrap();
+    assert_eq!(sent_lengths(&r), [MSS, MSS, MSS, 19]);
+}
+
+#[test]
+fn flushing_sends_what_was_held_back() {
+    let (mut r, _) = establish();
+    r.write(QUAD, &[0; 2 * MSS + 100]).unwrap();
+    assert!(r.take_sent().is_empty());
+    r.push(QUAD).unwrap();
+    assert_eq!(sent_lengths(&r), [MSS, MSS, 100]);
+    // still corked for what comes after
+    r.write(QUAD, &[0; MSS]).unwrap();
+    assert!(r.take_sent().is_empty());
+}
+
+#[test]
+fn closing_sends_what_was_held_back() {
+    let (mut r, _) = establish();
+    r.write(QUAD, &[0; 100]).unwrap();
+    assert!(r.take_sent().is_empty());
+    r.close(QUAD).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1);
+    let (_, tcph, data) = parse_segment(&sent[0]);
+    assert!(tcph.fin());
+    assert_eq!(data.len(), 100);
+}
+
+#[test]
+fn full_segments_go_once_too_much_piles_up() {
+    let (mut r, iss) = establish();
+    let mut written = 0;
+    while written <= 32 * 1024 {
+        assert!(
+            r.take_sent().is_empty(),
+            "sent with {written} bytes written"
+        );
+        written += r.write(QUAD, &[0; 1000]).unwrap();
+    }
+    // only whole segments, leaving the rest held back
+    let sent = sent_lengths(&r);
+    assert!(!sent.is_empty());
+    assert!(sent.iter().all(|&n| n == MSS), "sent {sent:?}");
+    // and once the peer has it all, no more until there's too much again
+    let acked = iss.wrapping_add(sent.iter().sum::<usize>() as u32);
+    r.feed(&segment(PEER_ISS + 1, Some(acked), &[])).unwrap();
+    assert!(r.take_sent().is_empty());
+}
+
+#[test]
+fn a_zero_mss_from_the_peer_does_not_panic() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    handshake(&mut r, Segment::syn_at(PEER_ISS).mss(0));
+    r.set_cork(QUAD, true).unwrap();
+    // more than half the send buffer, which is when the cork lets whole segments go
+    r.write(QUAD, &[0; 60000]).unwrap();
+    r.advance(std::time::Duration::from_secs(1)).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    r.check_invariants();
+}
@@ -0,0 +1,229 @@
+//! Running on a device that carries Ethernet frames, as a tap device does: ARP is answered for
+//! the interface's addresses, packets wait on their next hop being resolved before going out
+//! framed for it, neighbours are forgotten after a while, and the framing (and any padding)
+//! comes off what's received. Over a `MockNic` standing in for the wire, so no device needed.
+
+use std::net::Ipv4Addr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{LOCAL, PEER};
+use etherparse::PacketBuilder;
+use trust::testing::{ManualClock, MockNic};
+use trust::{Ethernet, Interface, MacAddr, Nic};
+
+mod common;
+
+const OUR_MAC: MacAddr = [0x02, 0, 0, 0, 0, 2];
+const PEER_MAC: MacAddr = [0x02, 0, 0, 0, 0, 1];
+const BROADCAST: MacAddr = [0xff; 6];
+
+fn frame(dst: MacAddr, src: MacAddr, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut f = [&dst[..], &src, &ethertype.to_be_bytes(), payload].concat();
+    // as short frames are on the wire
+    f.resize(f.len().max(60), 0);
+    f
+}
+
+fn arp(op: u16, sha: MacAddr, spa: Ipv4Addr, tha: MacAddr, tpa: Ipv4Addr) -> Vec<u8> {
+    let mut a = vec![0, 1, 8, 0, 6, 4];
+    a.extend_from_slice(&op.to_be_bytes());
+    a.extend_from_slice(&sha);
+    a.extend_from_slice(&spa.octets());
+    a.extend_from_slice(&tha);
+    a.extend_from_slice(&tpa.octets());
+    a
+}
+
+/// A TCP segment from `src` to `dst`, as an IP packet without framing.
+fn packet(src: Ipv4Addr, dst: Ipv4Addr, seq: u32, ack: Option<u32>) -> Vec<u8> {
+    let b = PacketBuilder::ipv4(src.octets(), dst.octets(), 64).tcp(40000, 80, seq, u16::MAX);
+    let b = match ack {
+        Some(ack) => b.ack(ack),
+        None => b.syn(),
+    };
+    let mut p = Vec::new();
+    b.write(&mut p, &[]).unwrap();
+    p
+}
+
+/// An ARP request from the peer for `tpa`, or its reply to one of ours.
+fn from_peer(op: u16, tpa: Ipv4Addr) -> Vec<u8> {
+    let (dst, tha) = if op == 1 {
+        (BROADCAST, [0; 6])
+    } else {
+        (OUR_MAC, OUR_MAC)
+    };
+    frame(dst, PEER_MAC, 0x0806, &arp(op, PEER_MAC, PEER, tha, tpa))
+}
+
+fn ethernet(wire: &MockNic, clock: &ManualClock) -> Ethernet<MockNic, ManualClock> {
+    let mut eth = Ethernet::with_clock(wire.clone(), OUR_MAC, clock.clone());
+    eth.add_address(LOCAL.into());
+    eth
+}
+
+/// Whatever IP packets `eth` has for the stack, with the ARP it handled along the way.
+fn recv_all(eth: &mut impl Nic) -> Vec<Vec<u8>> {
+    let mut storage = [[0; 1500]; 4];
+    let mut bufs = storage.each_mut().map(|b| b.as_mut_slice());
+    let mut lens = [0; 4];
+    let n = eth.recv_batch(&mut bufs, &mut lens).unwrap();
+    (0..n).map(|i| bufs[i][..lens[i]].to_vec()).collect()
+}
+
+#[test]
+fn answers_arp_for_its_own_addresses_only() {
+    let (wire, clock) = (MockNic::new(), ManualClock::new());
+    let mut eth = ethernet(&wire, &clock);
+
+    wire.inject(&from_peer(1, LOCAL));
+    assert!(recv_all(&mut eth).is_empty());
+    let sent = wire.take_sent();
+    let reply = frame(
+        PEER_MAC,
+        OUR_MAC,
+        0x0806,
+        &arp(2, OUR_MAC, LOCAL, PEER_MAC, PEER),
+    );
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0][..42], reply[..42]);
+
+    wire.inject(&from_peer(1, Ipv4Addr::new(10, 0, 0, 3)));
+    assert!(recv_all(&mut eth).is_empty());
+    assert!(wire.take_sent().is_empty(), "answered for someone else");
+
+    // and having been asked, knows where the peer is without asking back
+    eth.send(&packet(LOCAL, PEER, 1, Some(1))).unwrap();
+    let sent = wire.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0][..12], [PEER_MAC, OUR_MAC].concat());
+}
+
+#[test]
+fn packets_wait_for_the_next_hop_to_be_resolved() {
+    let (wire, clock) = (MockNic::new(), ManualClock::new());
+    let mut eth = ethernet(&wire, &clock);
+
+    let (first, second) = (
+        packet(LOCAL, PEER, 1, Some(1)),
+        packet(LOCAL, PEER, 2, Some(1)),
+    );
+    assert_eq!(eth.send(&first).unwrap(), first.len());
+    assert_eq!(eth.send(&second).unwrap(), second.len());
+    // asked just the once, broadcast, however much is waiting
+    let sent = wire.take_sent();
+    let request = frame(
+        BROADCAST,
+        OUR_MAC,
+        0x0806,
+        &arp(1, OUR_MAC, LOCAL, [0; 6], PEER),
+    );
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0][..42], request[..42]);
+
+    // no answer for a while, so it asks again when there's more to send
+    clock.advance(Duration::from_secs(1));
+    eth.send(&second).unwrap();
+    assert_eq!(wire.take_sent().len(), 1);
+
+    wire.inject(&from_peer(2, LOCAL));
+    assert!(recv_all(&mut eth).is_empty());
+    let sent = wire.take_sent();
+    let framed = |p: &[u8]| [&PEER_MAC[..], &OUR_MAC, &[8, 0], p].concat();
+    assert_eq!(sent, [framed(&first), framed(&second), framed(&second)]);
+}
+
+#[test]
+fn neighbors_are_asked_for_again_once_expired() {
+    let (wire, clock) = (MockNic::new(), ManualClock::new());
+    let mut eth = ethernet(&wire, &clock).neighbor_lifetime(Duration::from_secs(10));
+    wire.inject(&from_peer(1, LOCAL));
+    recv_all(&mut eth);
+    wire.take_sent();
+
+    clock.advance(Duration::from_secs(9));
+    eth.send(&packet(LOCAL, PEER, 1, Some(1))).unwrap();
+    assert_eq!(wire.take_sent()[0][..6], PEER_MAC);
+    clock.advance(Duration::from_secs(1));
+    eth.send(&packet(LOCAL, PEER, 1, Some(1))).unwrap();
+    let sent = wire.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0][..6], BROADCAST, "didn't ask again");
+}
+
+#[test]
+fn off_link_destinations_go_by_way_of_the_gateway() {
+    let (wire, clock) = (MockNic::new(), ManualClock::new());
+    let mut eth = ethernet(&wire, &clock).gateway(PEER, 24);
+    let far = Ipv4Addr::new(192, 0, 2, 1);
+    eth.send(&packet(LOCAL, far, 1, Some(1))).unwrap();
+    let sent = wire.take_sent();
+    let request = frame(
+        BROADCAST,
+        OUR_MAC,
+        0x0806,
+        &arp(1, OUR_MAC, LOCAL, [0; 6], PEER),
+    );
+    assert_eq!(sent[0][..42], request[..42], "asked for the wrong hop");
+
+    wire.inject(&from_peer(2, LOCAL));
+    recv_all(&mut eth);
+    let sent = wire.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0][..6], PEER_MAC);
+    assert_eq!(sent[0][14 + 16..14 + 20], far.octets());
+}
+
+#[test]
+fn framing_and_padding_come_off_what_is_received() {
+    let (wire, clock) = (MockNic::new(), ManualClock::new());
+    let mut eth = ethernet(&wire, &clock);
+    let syn = packet(PEER, LOCAL, 100, None);
+    assert!(syn.len() + 14 < 60, "wouldn't be padded");
+
+    wire.inject(&frame(OUR_MAC, PEER_MAC, 0x0800, &syn));
+    // someone else's, and IPv6, which there's no neighbour discovery for
+    wire.inject(&frame(PEER_MAC, OUR_MAC, 0x0800, &syn));
+    wire.inject(&frame(OUR_MAC, PEER_MAC, 0x86dd, &[0x60; 40]));
+    assert_eq!(recv_all(&mut eth), [syn]);
+    assert!(eth.send(&[0x60; 40]).is_err());
+}
+
+#[test]
+fn handshake_over_ethernet() {
+    let wire = MockNic::new();
+    let mut iface = Interface::with_nic(Ethernet::new(wire.clone(), OUR_MAC));
+    iface.add_address(LOCAL.into());
+    let mut l = iface.bind(80).unwrap();
+
+    // the SYN-ACK has to wait for the peer to be resolved
+    wire.inject(&frame(
+        OUR_MAC,
+        PEER_MAC,
+        0x0800,
+        &packet(PEER, LOCAL, 100, None),
+    ));
+    let sent = wait_for_sent(&wire);
+    assert_eq!(sent[0][..6], BROADCAST);
+    assert_eq!(sent[0][12..14], [8, 6]);
+    wire.inject(&from_peer(2, LOCAL));
+    let sent = wait_for_sent(&wire);
+    assert_eq!(sent[0][..14], [&PEER_MAC[..], &OUR_MAC, &[8, 0]].concat());
+    let synack = etherparse::TcpHeaderSlice::from_slice(&sent[0][14 + 20..]).unwrap();
+    assert!(synack.syn() && synack.ack());
+
+    let ack = packet(PEER, LOCAL, 101, Some(synack.sequence_number() + 1));
+    wire.inject(&frame(OUR_MAC, PEER_MAC, 0x0800, &ack));
+    let stream = l.accept().unwrap();
+    assert_eq!(stream.quad().src.0, PEER);
+}
+
+fn wait_for_sent(wire: &MockNic) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while wire.sent_len() == 0 {
+        assert!(Instant::now() < deadline, "nothing was sent");
+        thread::sleep(Duration::from_millis(10));
+    }
+    wire.take_sent()
+}
@@ -0,0 +1,89 @@
+//! A segment that carries data, acknowledges ours, and has FIN set, all at once: the ACK is
+//! taken first, then the data, then the FIN, each moving things along for the next. Driven
+//! through `Replay`, so no device needed.
+
+use common::{PEER_ISS, QUAD, assert_one_ack, fin};
+use trust::testing::Replay;
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// An established connection with a receive window of `window`, and our next sequence
+/// number.
+fn establish(window: u16) -> (Replay, u32) {
+    common::establish(ConnectionConfig::default().recv_window(window))
+}
+
+#[test]
+fn data_ack_and_fin_while_established() {
+    let (mut r, iss) = establish(1024);
+    r.write(QUAD, b"request").unwrap();
+    assert_eq!(r.take_sent().len(), 1);
+
+    r.feed(&fin(PEER_ISS + 1, iss + 7, b"response")).unwrap();
+    // one ACK, for the data and the FIN after it
+    assert_one_ack(&r, PEER_ISS + 1 + 8 + 1);
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+    let info = r.info(QUAD).unwrap();
+    assert_eq!(info.bytes_in_flight, 0);
+    assert_eq!(info.send_buffer_len, 0);
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"response");
+    r.check_invariants();
+}
+
+#[test]
+fn data_ack_and_fin_that_acks_our_fin() {
+    let (mut r, iss) = establish(1024);
+    r.write(QUAD, b"request").unwrap();
+    r.close(QUAD).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::FinWait1));
+    r.take_sent();
+
+    // our data and FIN both ACKed, so the peer's FIN takes us straight to TIME-WAIT
+    r.feed(&fin(PEER_ISS + 1, iss + 8, b"response")).unwrap();
+    assert_one_ack(&r, PEER_ISS + 1 + 8 + 1);
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"response");
+}
+
+#[test]
+fn data_ack_and_fin_that_leaves_our_fin_unacked() {
+    let (mut r, iss) = establish(1024);
+    r.write(QUAD, b"request").unwrap();
+    r.close(QUAD).unwrap();
+    r.take_sent();
+
+    // only our data is ACKed, not our FIN: the peer closed before it saw ours
+    r.feed(&fin(PEER_ISS + 1, iss + 7, b"response")).unwrap();
+    assert_one_ack(&r, PEER_ISS + 1 + 8 + 1);
+    assert_eq!(r.state(QUAD), Some(State::Closing));
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 1);
+}
+
+#[test]
+fn fin_past_the_window_waits() {
+    // the data fills the window, which leaves no room for the FIN after it
+    let (mut r, iss) = establish(8);
+    r.feed(&fin(PEER_ISS + 1, iss, b"response")).unwrap();
+    assert_one_ack(&r, PEER_ISS + 1 + 8);
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    // once there's room, the FIN, sent again, is taken
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"response");
+    r.take_sent();
+    r.feed(&fin(PEER_ISS + 1 + 8, iss, &[])).unwrap();
+    assert_one_ack(&r, PEER_ISS + 1 + 8 + 1);
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+}
+
+#[test]
+fn another_fin_after_the_peers_fin_is_ignored() {
+    let (mut r, iss) = establish(1024);
+    r.feed(&fin(PEER_ISS + 1, iss, b"response")).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+    r.take_sent();
+    // a FIN where there can't be one, past the first, with data and an ACK
+    r.feed(&fin(PEER_ISS + 1 + 9, iss, b"more")).unwrap();
+    r.feed(&fin(PEER_ISS + 1 + 9, iss, &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"response");
+}
@@ -0,0 +1,97 @@
+//! Data from the peer after we've sent our FIN: our FIN closes only our half, so FIN-WAIT-1
+//! and FIN-WAIT-2 still take data in and ACK it, and reads see end of file only at the peer's
+//! own FIN. Over a `MockNic` for reading to EOF through a stream, and through `Replay` for the
+//! states on the way.
+
+use std::io::Read;
+use std::net::Shutdown;
+
+use common::{
+    LOCAL, PEER_ISS, QUAD, Segment, accept, assert_one_ack, fin, handshake, segment, wait_sent,
+};
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Interface, State};
+
+mod common;
+
+/// A connection that's sent its FIN, and the FIN's sequence number.
+fn fin_sent() -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+    r.close(QUAD).unwrap();
+    let sent = r.take_sent();
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.fin());
+    assert_eq!(r.state(QUAD), Some(State::FinWait1));
+    (r, iss)
+}
+
+#[test]
+fn data_is_read_before_the_peers_fin() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (mut s, iss) = accept(&nic, &mut l);
+    s.shutdown(Shutdown::Write).unwrap();
+    let sent = wait_sent(&nic, 1);
+    assert!(parse_segment(&sent[0]).1.fin());
+
+    // the peer ACKs our FIN, and has more to say before its own
+    let after = iss.wrapping_add(1);
+    nic.inject(&segment(PEER_ISS + 1, Some(after), b"still "));
+    nic.inject(&segment(PEER_ISS + 7, Some(after), b"talking"));
+    nic.inject(&fin(PEER_ISS + 14, after, &[]));
+    let mut got = Vec::new();
+    s.read_to_end(&mut got).unwrap();
+    assert_eq!(got, b"still talking");
+    assert_eq!(s.info().unwrap().state, State::TimeWait);
+}
+
+#[test]
+fn fin_wait_1_and_2_ack_data() {
+    let (mut r, iss) = fin_sent();
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"one")).unwrap();
+    assert_one_ack(&r, PEER_ISS + 4);
+    assert_eq!(r.state(QUAD), Some(State::FinWait1));
+
+    r.feed(&segment(PEER_ISS + 4, Some(iss.wrapping_add(1)), b"two"))
+        .unwrap();
+    assert_one_ack(&r, PEER_ISS + 7);
+    assert_eq!(r.state(QUAD), Some(State::FinWait2));
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"onetwo");
+
+    r.feed(&fin(PEER_ISS + 7, iss.wrapping_add(1), &[]))
+        .unwrap();
+    assert_one_ack(&r, PEER_ISS + 8);
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+}
+
+#[test]
+fn data_and_fin_before_ours_is_acked() {
+    let (mut r, iss) = fin_sent();
+    // a simultaneous close: the peer's FIN crosses ours, with data ahead of it
+    r.feed(&fin(PEER_ISS + 1, iss, b"bye")).unwrap();
+    assert_one_ack(&r, PEER_ISS + 5);
+    assert_eq!(r.state(QUAD), Some(State::Closing));
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"bye");
+
+    r.feed(&segment(PEER_ISS + 5, Some(iss.wrapping_add(1)), &[]))
+        .unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+}
+
+#[test]
+fn data_past_the_peers_fin_is_ignored() {
+    let (mut r, iss) = fin_sent();
+    let after = iss.wrapping_add(1);
+    r.feed(&fin(PEER_ISS + 1, after, b"end")).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    r.take_sent();
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"end");
+
+    r.feed(&segment(PEER_ISS + 5, Some(after), b"more"))
+        .unwrap();
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+}
@@ -0,0 +1,117 @@
+//! Every segment we send has its header built afresh, so the flags and options of one never
+//! carry over to the next: after a SYN-ACK or a RST, what follows has only the flags it's
+//! meant to. Driven through `Replay`.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, Segment, handshake, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, State};
+
+mod common;
+
+/// The flags set on `packet`, as tcpdump would show them, and how long its options are.
+fn flags(packet: &[u8]) -> (String, usize) {
+    let tcph = parse_segment(packet).1;
+    let mut s = String::new();
+    for (set, c) in [
+        (tcph.syn(), 'S'),
+        (tcph.fin(), 'F'),
+        (tcph.rst(), 'R'),
+        (tcph.psh(), 'P'),
+        (tcph.ack(), '.'),
+    ] {
+        if set {
+            s.push(c);
+        }
+    }
+    (s, tcph.options().len())
+}
+
+/// The flags on each of the segments sent since last time.
+fn sent(r: &Replay) -> Vec<(String, usize)> {
+    r.take_sent().iter().map(|p| flags(p)).collect()
+}
+
+fn listening(config: ConnectionConfig) -> Replay {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    r
+}
+
+/// What follows the handshake: data, and then our FIN, each with only ACK besides.
+fn after_the_handshake(r: &mut Replay, quad: Quad) {
+    r.write(quad, b"hello").unwrap();
+    assert_eq!(sent(r), [(".".into(), 0)]);
+    r.close(quad).unwrap();
+    assert_eq!(sent(r), [("F.".into(), 0)]);
+}
+
+#[test]
+fn after_a_syn_ack() {
+    let mut r = listening(ConnectionConfig::default());
+    let syn = Segment::syn_at(PEER_ISS).mss(1460);
+    r.feed(&syn.build(&[])).unwrap();
+    // SYN and ACK, and an MSS option only here
+    assert_eq!(sent(&r), [("S.".into(), 4)]);
+    r.advance(Duration::from_secs(1)).unwrap();
+    let resent = r.take_sent();
+    assert_eq!(
+        resent.iter().map(|p| flags(p)).collect::<Vec<_>>(),
+        [("S.".into(), 4)]
+    );
+
+    let iss = parse_segment(&resent[0])
+        .1
+        .sequence_number()
+        .wrapping_add(1);
+    r.feed(&syn.next(iss).build(&[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    after_the_handshake(&mut r, QUAD);
+}
+
+#[test]
+fn after_a_rst_in_the_handshake() {
+    let mut r = listening(ConnectionConfig::default());
+    let syn = Segment::syn_at(PEER_ISS);
+    r.feed(&syn.build(&[])).unwrap();
+    let synack = r.take_sent().pop().unwrap();
+    let iss = parse_segment(&synack).1.sequence_number().wrapping_add(1);
+
+    // an ACK of something we never sent is answered with a RST at that ACK, which
+    // acknowledges nothing itself and has no other flags
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(100)), &[]))
+        .unwrap();
+    let rst = r.take_sent();
+    assert_eq!(
+        rst.iter().map(|p| flags(p)).collect::<Vec<_>>(),
+        [("R".into(), 0)]
+    );
+    let tcph = parse_segment(&rst[0]).1;
+    assert_eq!(tcph.sequence_number(), iss.wrapping_add(100));
+    assert_eq!(tcph.acknowledgment_number(), 0);
+    assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+
+    r.feed(&syn.next(iss).build(&[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    after_the_handshake(&mut r, QUAD);
+}
+
+#[test]
+fn after_an_idle_timeout_rst() {
+    let mut r = listening(ConnectionConfig::default().idle_timeout(Duration::from_secs(10)));
+    handshake(&mut r, Segment::syn_at(PEER_ISS));
+    r.advance(Duration::from_secs(11)).unwrap();
+    // an abort is at SND.NXT, and acknowledges what's been received
+    assert_eq!(sent(&r), [("R.".into(), 0)]);
+    assert_eq!(r.state(QUAD), None);
+
+    // and a connection that comes after it gets the usual flags
+    let other = Quad {
+        src: (IpAddr::V4(PEER), 40001),
+        ..QUAD
+    };
+    handshake(&mut r, Segment::syn_at(PEER_ISS).on(other));
+    after_the_handshake(&mut r, other);
+}
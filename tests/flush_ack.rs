@@ -0,0 +1,42 @@
+//! `flush_ack`: there's no delayed ACK for it to hurry, as data is ACKed as soon as it's in,
+//! so what it does is send the peer our latest ACK again, with the window as it stands now.
+//! Driven through `Replay`, so no device needed.
+
+use std::time::Duration;
+
+use common::{PEER_ISS, QUAD, assert_one_ack, establish, segment};
+use trust::ConnectionConfig;
+use trust::testing::parse_segment;
+
+mod common;
+
+#[test]
+fn data_is_acked_without_it() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"request"))
+        .unwrap();
+    assert_one_ack(&r, PEER_ISS + 8);
+    // and with nothing held back, time passing brings out no more
+    r.advance(Duration::from_secs(1)).unwrap();
+    assert!(r.take_sent().is_empty());
+}
+
+#[test]
+fn repeats_the_last_ack_with_the_window_as_it_is_now() {
+    let (mut r, iss) = establish(ConnectionConfig::default().recv_window(1000));
+    r.feed(&segment(PEER_ISS + 1, Some(iss), &[b'x'; 600]))
+        .unwrap();
+    let sent = r.take_sent();
+    assert_eq!(parse_segment(&sent[0]).1.window_size(), 400);
+    assert_eq!(r.read(QUAD, 300).unwrap().len(), 300);
+    r.take_sent();
+
+    r.flush_ack(QUAD).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1);
+    let (_, tcph, data) = parse_segment(&sent[0]);
+    assert!(tcph.ack() && data.is_empty());
+    assert_eq!(tcph.sequence_number(), iss);
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 601);
+    assert_eq!(tcph.window_size(), 700);
+}
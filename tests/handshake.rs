@@ -0,0 +1,288 @@
+//! The passive open: what a SYN gets back from a listener, and the connection it leaves behind.
+//! Run through `MockNic`, and straight through the dispatch path with `Replay`, so it needs
+//! neither a tun device nor root.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, Segment, fin, handshake, rst, segment};
+use etherparse::TcpOptionElement;
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Interface, State};
+
+mod common;
+
+/// one ISN from the middle of the sequence space, and one at its very end, which wraps
+/// RCV.NXT round to zero
+const ISNS: [u32; 2] = [1000, u32::MAX];
+
+/// Wait for the packet loop to have sent `n` packets, and take them.
+fn wait_sent(nic: &MockNic, n: usize) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while nic.sent_len() < n {
+        assert!(Instant::now() < deadline, "sent {} of {n}", nic.sent_len());
+        thread::sleep(Duration::from_millis(1));
+    }
+    nic.take_sent()
+}
+
+/// Check that `packet` is a SYN-ACK from us for the peer's SYN, and return our ISS.
+fn assert_syn_ack(packet: &[u8]) -> u32 {
+    let (iph, tcph, _) = parse_segment(packet);
+    assert_eq!(iph.source_addr(), LOCAL);
+    assert_eq!(iph.destination_addr(), PEER);
+    assert_eq!((tcph.source_port(), tcph.destination_port()), (80, 40000));
+    assert!(tcph.syn() && tcph.ack() && !tcph.rst() && !tcph.fin());
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 1);
+    tcph.sequence_number()
+}
+
+#[test]
+fn syn_is_answered_with_a_syn_ack() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let _l = iface.bind(80).unwrap();
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    let sent = wait_sent(&nic, 1);
+    assert_eq!(sent.len(), 1);
+    assert_syn_ack(&sent[0]);
+}
+
+#[test]
+fn repeated_syn_gets_the_same_syn_ack() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let _l = iface.bind(80).unwrap();
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    let first = assert_syn_ack(&wait_sent(&nic, 1)[0]);
+    // our SYN-ACK got lost, so the peer tries again
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    let second = assert_syn_ack(&wait_sent(&nic, 1)[0]);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn accept_returns_the_connection_once_acked() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    let iss = assert_syn_ack(&wait_sent(&nic, 1)[0]);
+    nic.inject(&segment(PEER_ISS + 1, Some(iss.wrapping_add(1)), &[]));
+    let stream = l.accept().unwrap();
+    assert_eq!(stream.quad(), QUAD);
+}
+
+#[test]
+fn syn_to_another_port_is_not_answered() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let _l = iface.bind(81).unwrap();
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(nic.sent_len(), 0);
+}
+
+fn listening() -> Replay {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    r
+}
+
+/// The MSS option on `packet`, if it has one.
+fn mss(packet: &[u8]) -> Option<u16> {
+    parse_segment(packet)
+        .1
+        .options_iterator()
+        .find_map(|o| match o {
+            Ok(TcpOptionElement::MaximumSegmentSize(mss)) => Some(mss),
+            _ => None,
+        })
+}
+
+#[test]
+fn syn_ack_acknowledges_the_syn() {
+    for isn in ISNS {
+        let mut r = listening();
+        r.feed(&segment(isn, None, &[])).unwrap();
+        assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+        let snapshot = r.snapshot(QUAD).unwrap();
+        assert_eq!(snapshot.rcv_irs, Some(isn));
+        assert_eq!(snapshot.rcv_nxt, isn.wrapping_add(1));
+
+        let sent = r.take_sent();
+        assert_eq!(sent.len(), 1, "sent {} segments for a SYN", sent.len());
+        let (iph, tcph, data) = parse_segment(&sent[0]);
+        assert!(tcph.syn() && tcph.ack() && !tcph.fin() && !tcph.rst());
+        assert!(data.is_empty());
+        // RCV.NXT is IRS+1, wrapped
+        assert_eq!(tcph.acknowledgment_number(), isn.wrapping_add(1));
+        assert_eq!((iph.source_addr(), iph.destination_addr()), (LOCAL, PEER));
+        assert_eq!((tcph.source_port(), tcph.destination_port()), (80, 40000));
+        // a 1500-byte MTU, less the IP and TCP headers
+        assert_eq!(mss(&sent[0]), Some(1460));
+        assert_eq!(snapshot.snd_nxt, tcph.sequence_number().wrapping_add(1));
+    }
+}
+
+#[test]
+fn retransmitted_syn_ack_is_identical() {
+    for isn in ISNS {
+        let mut r = listening();
+        r.feed(&segment(isn, None, &[])).unwrap();
+        let first = r.take_sent().pop().unwrap();
+        // the same IRS, so it's the SYN we have already, not a new one
+        r.feed(&segment(isn, None, &[])).unwrap();
+        let again = r.take_sent().pop().expect("SYN-ACK not sent again");
+        // the same segment, options and all, whatever the IP ID
+        assert_eq!(
+            parse_segment(&again).1.slice(),
+            parse_segment(&first).1.slice()
+        );
+        assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+        assert_eq!(r.snapshot(QUAD).unwrap().rcv_irs, Some(isn));
+    }
+}
+
+#[test]
+fn repeated_syn_leaves_the_handshake_where_it_was() {
+    for isn in ISNS {
+        let mut r = listening();
+        r.feed(&segment(isn, None, &[])).unwrap();
+        assert_eq!(r.take_sent().len(), 1);
+        let before = r.snapshot(QUAD).unwrap();
+        r.feed(&segment(isn, None, &[])).unwrap();
+        let sent = r.take_sent();
+        assert_eq!(sent.len(), 1, "sent {} segments for the repeat", sent.len());
+        let tcph = parse_segment(&sent[0]).1;
+        assert!(tcph.syn() && tcph.ack());
+        assert_eq!(tcph.acknowledgment_number(), isn.wrapping_add(1));
+        // it's taken for neither data nor a new SYN, so nothing has moved
+        let after = r.snapshot(QUAD).unwrap();
+        assert_eq!(
+            (after.rcv_nxt, after.snd_nxt),
+            (before.rcv_nxt, before.snd_nxt)
+        );
+
+        // and the peer's ACK of the second SYN-ACK finishes the handshake as usual
+        let iss = tcph.sequence_number().wrapping_add(1);
+        r.feed(&segment(isn.wrapping_add(1), Some(iss), b"hi"))
+            .unwrap();
+        assert_eq!(r.state(QUAD), Some(State::Estab));
+        assert_eq!(r.read(QUAD, 10).unwrap(), b"hi");
+    }
+}
+
+#[test]
+fn ack_of_the_syn_ack_establishes() {
+    for isn in ISNS {
+        let mut r = listening();
+        r.feed(&segment(isn, None, &[])).unwrap();
+        let synack = r.take_sent().pop().unwrap();
+        let iss = parse_segment(&synack).1.sequence_number();
+        r.feed(&segment(
+            isn.wrapping_add(1),
+            Some(iss.wrapping_add(1)),
+            &[],
+        ))
+        .unwrap();
+        assert_eq!(r.state(QUAD), Some(State::Estab));
+        assert!(r.take_sent().is_empty());
+        assert_eq!(r.accept(), Some(QUAD));
+        assert_eq!(r.accept(), None);
+    }
+}
+
+#[test]
+fn half_open_connection_is_not_accepted_until_established() {
+    let mut r = listening();
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    let iss = parse_segment(&r.take_sent()[0]).1.sequence_number();
+    assert_eq!(r.accept(), None);
+
+    // the peer finishes the handshake with its FIN along, so it's on to CLOSE-WAIT before
+    // anyone accepts it; it has been established all the same
+    let finish = fin(PEER_ISS + 1, iss.wrapping_add(1), &[]);
+    r.feed(&finish).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+    assert_eq!(r.accept(), Some(QUAD));
+}
+
+#[test]
+fn half_open_connection_reset_is_never_accepted() {
+    let mut r = listening();
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    let iss = parse_segment(&r.take_sent()[0]).1.sequence_number();
+    r.feed(&rst(PEER_ISS + 1, iss.wrapping_add(1))).unwrap();
+    assert_eq!(r.accept(), None);
+
+    // and the listener still takes the peer's next try
+    handshake(&mut r, Segment::syn_at(PEER_ISS + 1000));
+    assert_eq!(r.accept(), Some(QUAD));
+    assert_eq!(r.accept(), None);
+}
+
+#[test]
+fn handshake_finished_out_of_order() {
+    let mut r = listening();
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    let iss = parse_segment(&r.take_sent()[0]).1.sequence_number();
+
+    // the peer's second data segment overtakes its first, both ACKing our SYN: the second
+    // finishes the handshake and draws a duplicate ACK for the gap in front of it
+    let ack = Some(iss.wrapping_add(1));
+    r.feed(&segment(PEER_ISS + 6, ack, b"world")).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.ack() && !tcph.rst());
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 1);
+    assert_eq!(r.accept(), Some(QUAD));
+
+    // out-of-order data isn't kept, so the peer fills the gap and then sends what overtook it
+    // again
+    r.feed(&segment(PEER_ISS + 1, ack, b"hello")).unwrap();
+    r.feed(&segment(PEER_ISS + 6, ack, b"world")).unwrap();
+    r.flush_ack(QUAD).unwrap();
+    let acked = r
+        .take_sent()
+        .iter()
+        .any(|p| parse_segment(p).1.acknowledgment_number() == PEER_ISS + 11);
+    assert!(acked, "data never ACKed");
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"helloworld");
+    r.check_invariants();
+}
+
+#[test]
+fn handshake_ack_of_something_never_sent_is_reset() {
+    let mut r = listening();
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    let iss = parse_segment(&r.take_sent()[0]).1.sequence_number();
+
+    let bogus = iss.wrapping_add(1000);
+    r.feed(&fin(PEER_ISS + 1, bogus, b"hello")).unwrap();
+    // <SEQ=SEG.ACK><CTL=RST>, and none of the segment taken
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.rst());
+    assert_eq!(tcph.sequence_number(), bogus);
+    assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+    assert_eq!(r.accept(), None);
+
+    // the handshake still finishes once the right ACK comes
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(1)), &[]))
+        .unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    assert_eq!(r.accept(), Some(QUAD));
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+}
+
+#[test]
+fn segment_without_syn_opens_nothing() {
+    let mut r = listening();
+    r.feed(&segment(PEER_ISS, Some(1), &[])).unwrap();
+    assert_eq!(r.state(QUAD), None);
+    assert!(r.take_sent().is_empty());
+}
@@ -0,0 +1,114 @@
+//! ICMP errors about our segments: a port unreachable while we're connecting refuses the
+//! connection, while a soft error on an established one is only noted, and the connection
+//! carries on. Over a `MockNic` for `connect`, and through `Replay` for the rest.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::Duration;
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, establish, segment, wait_sent};
+use etherparse::{IpTrafficClass, Ipv4Header};
+use trust::testing::{MockNic, parse_segment};
+use trust::{ConnectionConfig, Interface, State};
+
+mod common;
+
+const ROUTER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 254);
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A destination-unreachable message from `from` with the given `code`, saying `packet` (one
+/// of ours) couldn't be delivered.
+fn unreachable(from: Ipv4Addr, code: u8, packet: &[u8]) -> Vec<u8> {
+    let mut icmp = vec![3, code, 0, 0, 0, 0, 0, 0];
+    // the IP header and the first 8 bytes of the TCP header
+    icmp.extend_from_slice(&packet[..28]);
+    let sum = checksum(&icmp);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+    let mut iph = Ipv4Header::new(
+        icmp.len() as u16,
+        64,
+        IpTrafficClass::Icmp,
+        from.octets(),
+        LOCAL.octets(),
+    );
+    iph.header_checksum = iph.calc_header_checksum().unwrap();
+    let mut p = Vec::new();
+    iph.write(&mut p).unwrap();
+    p.extend_from_slice(&icmp);
+    p
+}
+
+#[test]
+fn port_unreachable_refuses_connect() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let connecting = thread::spawn(move || {
+        let res = iface.connect(IpAddr::V4(LOCAL), SocketAddr::new(IpAddr::V4(PEER), 80));
+        (iface, res.map(|_| ()))
+    });
+
+    let syn = &wait_sent(&nic, 1)[0];
+    assert!(parse_segment(syn).1.syn());
+    // from the peer itself: nothing's listening there
+    nic.inject(&unreachable(PEER, 3, syn));
+    let (iface, res) = connecting.join().unwrap();
+    assert_eq!(res.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+    let stats = iface.icmp_stats().unwrap();
+    assert_eq!((stats.received, stats.aborted), (1, 1));
+    // and the SYN isn't tried again
+    thread::sleep(Duration::from_millis(1100));
+    assert!(nic.take_sent().is_empty());
+}
+
+#[test]
+fn soft_error_leaves_an_established_connection_alone() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.write(QUAD, b"hello").unwrap();
+    let data = r.take_sent().pop().expect("no data");
+
+    // a host unreachable from a router on the way, which may well be passing
+    r.feed(&unreachable(ROUTER, 1, &data)).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    assert_eq!(r.icmp_stats().soft_errors, 1);
+    assert_eq!(r.icmp_stats().aborted, 0);
+    assert_eq!(r.error(QUAD), None);
+
+    // the data gets there after all, and the connection goes on as before
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(5)), b"world"))
+        .unwrap();
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 0);
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"world");
+    r.write(QUAD, b"again").unwrap();
+    let sent = r.take_sent();
+    let (_, tcph, data) = parse_segment(sent.last().unwrap());
+    assert_eq!(tcph.sequence_number(), iss.wrapping_add(5));
+    assert_eq!(data, b"again");
+    assert_eq!(r.error(QUAD), None);
+}
+
+#[test]
+fn even_a_hard_error_leaves_an_established_connection_alone() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.write(QUAD, b"hello").unwrap();
+    let data = r.take_sent().pop().expect("no data");
+
+    // a port unreachable only aborts a connection still in the handshake (RFC 5461 S4)
+    r.feed(&unreachable(PEER, 3, &data)).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    assert_eq!(r.icmp_stats().aborted, 0);
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(5)), &[]))
+        .unwrap();
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 0);
+    assert_eq!(r.error(QUAD), None);
+}
@@ -0,0 +1,65 @@
+//! The initial congestion window: how much goes out straight after the handshake, before
+//! anything's been ACKed. Ten segments by default, or as configured, and capped in bytes as if
+//! the MSS were 1460, but never below two segments.
+
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, wait_sent};
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Interface};
+
+mod common;
+
+/// The payload sizes of what goes out for a big write straight after the handshake, with the
+/// peer offering an MSS of 1460.
+fn first_flight(config: ConnectionConfig) -> Vec<usize> {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    r.write(QUAD, &[7; 30 * 1460]).unwrap();
+    r.take_sent()
+        .iter()
+        .map(|p| parse_segment(p).2.len())
+        .collect()
+}
+
+#[test]
+fn ten_segments_by_default() {
+    assert_eq!(first_flight(ConnectionConfig::default()), [1460; 10]);
+}
+
+#[test]
+fn as_many_as_configured() {
+    let config = ConnectionConfig::default().initial_window(4);
+    assert_eq!(first_flight(config), [1460; 4]);
+    let config = ConnectionConfig::default().initial_window(20);
+    assert_eq!(first_flight(config), [1460; 20]);
+}
+
+#[test]
+fn big_segments_are_capped_at_two() {
+    // jumbo frames, where even three segments would be more than ten of 1460
+    let nic = MockNic::with_mtu(9000);
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let syn = Segment::syn_at(PEER_ISS).mss(8960);
+    nic.inject(&syn.build(&[]));
+    let iss = parse_segment(&wait_sent(&nic, 1)[0])
+        .1
+        .sequence_number()
+        .wrapping_add(1);
+    nic.inject(&syn.next(iss).build(&[]));
+
+    let mut s = l.accept().unwrap();
+    s.write_all(&[7; 3 * 8960]).unwrap();
+    let sent = wait_sent(&nic, 2);
+    thread::sleep(Duration::from_millis(50));
+    let lens: Vec<_> = sent
+        .iter()
+        .chain(&nic.take_sent())
+        .map(|p| parse_segment(p).2.len())
+        .collect();
+    assert_eq!(lens, [8960, 8960]);
+}
@@ -0,0 +1,62 @@
+//! Raw TCP segments handed straight to a connection with `Replay::inject`, and the state they
+//! leave it in as `Replay::snapshot` sees it.
+
+use std::io;
+use std::net::IpAddr;
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, Segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, State};
+
+mod common;
+
+/// A TCP header and payload from the peer, with no IP header and no checksum.
+fn segment(seq: u32, ack: u32, data: &[u8]) -> Vec<u8> {
+    Segment::new(seq).ack(ack).tcp(data)
+}
+
+/// A connection accepted on port 80, returning our next sequence number.
+fn accepted(r: &mut Replay) -> u32 {
+    r.listen(80, ConnectionConfig::default());
+    r.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+    let synack = r.take_sent().pop().expect("no SYN-ACK");
+    parse_segment(&synack).1.sequence_number().wrapping_add(1)
+}
+
+#[test]
+fn injected_segments_drive_the_connection() {
+    let mut r = Replay::new(LOCAL);
+    let iss = accepted(&mut r);
+    r.inject(QUAD, &segment(PEER_ISS + 1, iss, &[])).unwrap();
+    let before = r.snapshot(QUAD).unwrap();
+    assert_eq!(before.info.state, State::Estab);
+    assert_eq!(before.rcv_nxt, PEER_ISS + 1);
+    assert_eq!((before.snd_una, before.snd_nxt), (iss, iss));
+
+    r.inject(QUAD, &segment(PEER_ISS + 1, iss, b"hello"))
+        .unwrap();
+    let after = r.snapshot(QUAD).unwrap();
+    assert_eq!(after.rcv_nxt, PEER_ISS + 6);
+    assert_eq!(after.info.recv_buffer_len, 5);
+    assert_eq!(r.read(QUAD, 16).unwrap(), b"hello");
+    r.check_invariants();
+}
+
+#[test]
+fn bad_header_is_invalid_data() {
+    let mut r = Replay::new(LOCAL);
+    let iss = accepted(&mut r);
+    let before = r.snapshot(QUAD);
+    let e = r.inject(QUAD, &[0; 12]).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(r.snapshot(QUAD), before);
+
+    // nothing there for an unknown quad, but it isn't an error either
+    let other = Quad {
+        src: (IpAddr::V4(PEER), 40001),
+        ..QUAD
+    };
+    r.inject(other, &segment(PEER_ISS + 1, iss, &[])).unwrap();
+    assert_eq!(r.snapshot(other), None);
+    assert!(r.take_sent().is_empty());
+}
@@ -0,0 +1,1441 @@
+//! Interop with the kernel's own TCP stack, over a real tun device (or a veth pair, for the
+//! raw-socket backend): our stack on one end, and on the other, the kernel and ordinary tools
+//! talking to it through sockets.
+//!
+//! Setting up the device and its address takes root, so these are ignored by default. Run them
+//! with `./interop.sh`, or as root with `cargo test --features tcp-md5 --test interop --
+//! --ignored`; without tcp-md5, the one test that needs it is left out. Each test gets a tun
+//! device and a /24 of its own, so they can run in parallel.
+
+use std::collections::HashMap;
+use std::future::{Future, poll_fn};
+use std::io::{self, BufRead, IoSlice, IoSliceMut, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use trust::{ConnectionConfig, Ethernet, Interface, Nic, RawSocket, State, TcpError, Tun};
+
+/// The echo server from `examples/echo.rs`, whose `echo` serves the kernel here just as it
+/// would serve `nc`.
+#[allow(dead_code)]
+#[path = "../examples/echo.rs"]
+mod echo_example;
+
+/// The port forwarder from `examples/proxy.rs`, whose `relay` sits between curl and a server
+/// on the kernel's side here.
+#[allow(dead_code)]
+#[path = "../examples/proxy.rs"]
+mod proxy_example;
+
+/// How long the kernel side waits on us before the test is failed rather than left hanging.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A tun device with our stack on it, and the kernel's end configured to route to it. The
+/// kernel is 10.97.N.1, and we answer as 10.97.N.2. The device goes away when the interface
+/// closes it; any iptables rules added for the test are deleted on drop.
+struct Net {
+    name: String,
+    iface: Interface,
+    ours: Ipv4Addr,
+    rules: Vec<String>,
+}
+
+impl Net {
+    /// Bring up `trust-iopN`, or `None` if we aren't root and the test should be skipped.
+    fn up(n: u8) -> Option<Net> {
+        Self::with_device(n, |name| {
+            Tun::open(name).expect("failed to open tun device")
+        })
+    }
+
+    /// Like `up`, but with `trust-iopN` a tap device, so that the kernel is a neighbour on an
+    /// Ethernet link rather than the far end of a point-to-point one, and we answer its ARP.
+    fn up_tap(n: u8) -> Option<Net> {
+        Self::with_device(n, |name| {
+            let tap = Tun::open_tap(name).expect("failed to open tap device");
+            Ethernet::new(tap, [0x02, 0, 0, 0, 0x97, n])
+        })
+    }
+
+    fn with_device<N: Nic + Send + 'static>(n: u8, open: impl FnOnce(&str) -> N) -> Option<Net> {
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("skipping: needs root to set up a tun device");
+            return None;
+        }
+        let name = format!("trust-iop{n}");
+        let mut iface = Interface::with_nic(open(&name));
+        let ours = Ipv4Addr::new(10, 97, n, 2);
+        iface.add_address(ours.into());
+        run("ip", &format!("addr add 10.97.{n}.1/24 dev {name}"));
+        run("ip", &format!("link set up dev {name}"));
+        Some(Net {
+            name,
+            iface,
+            ours,
+            rules: Vec::new(),
+        })
+    }
+
+    /// Have iptables drop `percent`% of packets in both directions, or return false if there's
+    /// no iptables to do it with.
+    fn lossy(&mut self, percent: f64) -> bool {
+        if !installed("iptables") {
+            return false;
+        }
+        let p = (percent / 100.0).to_string();
+        for (chain, dir) in [("INPUT", "-i"), ("OUTPUT", "-o")] {
+            let rule = format!(
+                "{chain} {dir} {} -m statistic --mode random --probability {p} -j DROP",
+                self.name
+            );
+            run("iptables", &format!("-A {rule}"));
+            self.rules.push(rule);
+        }
+        true
+    }
+
+    fn connect(&self, port: u16) -> TcpStream {
+        let addr = SocketAddr::from((self.ours, port));
+        let s = TcpStream::connect_timeout(&addr, TIMEOUT).expect("kernel failed to connect");
+        s.set_read_timeout(Some(TIMEOUT)).unwrap();
+        s.set_write_timeout(Some(TIMEOUT)).unwrap();
+        s
+    }
+
+    /// Record every state transition of every connection, as `(from, to)`.
+    fn transitions(&mut self) -> Arc<Mutex<Vec<(State, State)>>> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let s = seen.clone();
+        self.iface
+            .on_state_change(move |c| s.lock().unwrap().push((c.from, c.to)));
+        seen
+    }
+}
+
+impl Drop for Net {
+    fn drop(&mut self) {
+        for rule in &self.rules {
+            // not `run`: panicking in a drop would hide whatever failed the test
+            let _ = Command::new("iptables")
+                .arg("-D")
+                .args(rule.split_whitespace())
+                .status();
+        }
+    }
+}
+
+/// A packet socket on a device, which gets a copy of every packet the kernel receives there, as
+/// tcpdump would.
+struct Sniffer(OwnedFd);
+
+impl Sniffer {
+    fn open(dev: &str) -> Sniffer {
+        let proto = (libc::ETH_P_ALL as u16).to_be();
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_DGRAM, proto as i32) };
+        assert!(fd >= 0, "packet socket: {}", io::Error::last_os_error());
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let name = std::ffi::CString::new(dev).unwrap();
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = proto;
+        addr.sll_ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) } as i32;
+        let addr_len = size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        let addr = (&raw const addr).cast();
+        let r = unsafe { libc::bind(fd.as_raw_fd(), addr, addr_len) };
+        assert_eq!(r, 0, "binding to {dev}: {}", io::Error::last_os_error());
+        let timeout = libc::timeval {
+            tv_sec: 1,
+            tv_usec: 0,
+        };
+        let r = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                (&raw const timeout).cast(),
+                size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(r, 0, "SO_RCVTIMEO: {}", io::Error::last_os_error());
+        Sniffer(fd)
+    }
+
+    /// The IPv4 TCP segments seen until a second goes by without any packets.
+    fn segments(&self) -> Vec<Vec<u8>> {
+        let mut segments = Vec::new();
+        let mut buf = [0; 65536];
+        loop {
+            let n =
+                unsafe { libc::recv(self.0.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+            if n < 0 {
+                return segments;
+            }
+            let packet = &buf[..n as usize];
+            if etherparse::Ipv4HeaderSlice::from_slice(packet).is_ok_and(|ip| ip.protocol() == 6) {
+                segments.push(packet.to_vec());
+            }
+        }
+    }
+}
+
+/// Run `cmd` with the whitespace-separated `args`, and fail the test if it fails.
+fn run(cmd: &str, args: &str) {
+    let status = Command::new(cmd)
+        .args(args.split_whitespace())
+        .status()
+        .unwrap_or_else(|e| panic!("failed to run {cmd}: {e}"));
+    assert!(status.success(), "{cmd} {args} failed: {status}");
+}
+
+fn installed(cmd: &str) -> bool {
+    let found = Command::new("sh")
+        .args(["-c", &format!("command -v {cmd}")])
+        .output()
+        .is_ok_and(|o| o.status.success());
+    if !found {
+        eprintln!("skipping: {cmd} isn't installed");
+    }
+    found
+}
+
+/// `len` bytes of something less regular than all zeroes, so misplaced data shows up.
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+}
+
+/// Wait for the packet loop to see a transition that happens after the test has its data,
+/// e.g. the final ACK of a close.
+fn wait_for(seen: &Mutex<Vec<(State, State)>>, t: (State, State)) {
+    for _ in 0..100 {
+        if seen.lock().unwrap().contains(&t) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let seen = seen.lock().unwrap();
+    panic!("never saw {:?} -> {:?}, only {seen:?}", t.0, t.1);
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn kernel_connects_and_exchanges_data() {
+    let Some(mut net) = Net::up(1) else { return };
+    let seen = net.transitions();
+    let mut l = net.iface.bind(7000).unwrap();
+    let server = thread::spawn(move || -> io::Result<_> {
+        let mut s = l.accept()?;
+        let mut got = Vec::new();
+        s.read_to_end(&mut got)?;
+        let info = s.info()?;
+        got.reverse();
+        s.write_all(&got)?;
+        s.flush()?;
+        Ok(info)
+    });
+
+    let mut k = net.connect(7000);
+    k.write_all(b"hello from the kernel").unwrap();
+    // our side reads to EOF before answering, so it answers from CloseWait
+    k.shutdown(Shutdown::Write).unwrap();
+    let mut reply = Vec::new();
+    k.read_to_end(&mut reply).unwrap();
+    assert_eq!(reply, b"lenrek eht morf olleh");
+
+    let info = server.join().unwrap().unwrap();
+    // the kernel's MSS for a 1500-byte MTU, and none of the options we don't do
+    assert_eq!(info.mss, 1460);
+    assert!(!info.window_scaling && !info.sack && !info.timestamps);
+    wait_for(&seen, (State::LastAck, State::Closed));
+    let seen = seen.lock().unwrap().clone();
+    assert_eq!(
+        seen,
+        [
+            (State::SynRcvd, State::Estab),
+            (State::Estab, State::CloseWait),
+            (State::CloseWait, State::LastAck),
+            (State::LastAck, State::Closed),
+        ]
+    );
+    let stats = net.iface.icmp_stats().unwrap();
+    assert_eq!(stats.received, 0, "{stats:?}");
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn curl_fetches_from_our_listener() {
+    if !installed("curl") {
+        return;
+    }
+    let Some(mut net) = Net::up(2) else { return };
+    let seen = net.transitions();
+    let body = "hello from trust\n".repeat(200);
+    let mut l = net.iface.bind(8080).unwrap();
+    let server = {
+        let body = body.clone();
+        thread::spawn(move || -> io::Result<Vec<u8>> {
+            let mut s = l.accept()?;
+            let mut req = Vec::new();
+            let mut buf = [0; 1024];
+            while !req.ends_with(b"\r\n\r\n") {
+                let n = s.read(&mut buf)?;
+                assert!(n > 0, "EOF in the middle of the request");
+                req.extend_from_slice(&buf[..n]);
+            }
+            // no Content-Length, so curl reads until our FIN and only closes after that
+            write!(s, "HTTP/1.0 200 OK\r\nConnection: close\r\n\r\n{body}")?;
+            s.flush()?;
+            Ok(req)
+        })
+    };
+
+    let out = Command::new("curl")
+        .args(["-sS", "--max-time", "60"])
+        .arg(format!("http://{}:8080/hello", net.ours))
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "curl failed: {out:?}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), body);
+    let req = server.join().unwrap().unwrap();
+    assert!(req.starts_with(b"GET /hello HTTP/1.1\r\n"), "{req:?}");
+
+    // we closed first, once the response was out, and curl after it had read to EOF
+    wait_for(&seen, (State::FinWait2, State::TimeWait));
+}
+
+#[test]
+#[ignore = "needs root, a tun device and iptables; see interop.sh"]
+fn large_transfer_with_loss() {
+    let Some(mut net) = Net::up(3) else { return };
+    if !net.lossy(1.0) {
+        return;
+    }
+    let up = pattern(2 << 20, 0x5a);
+    let down = pattern(512 << 10, 0xa5);
+    let config = ConnectionConfig::default().recv_window(u16::MAX);
+    let mut l = net.iface.bind_with_config(9000, 1, config).unwrap();
+    let server = {
+        let down = down.clone();
+        thread::spawn(move || -> io::Result<_> {
+            let mut s = l.accept()?;
+            let mut got = Vec::new();
+            s.read_to_end(&mut got)?;
+            s.write_all(&down)?;
+            s.flush()?;
+            // everything has been acknowledged, so there's nothing left to account for
+            Ok((got, s.bytes_in_flight()?, s.send_buffer_len()?))
+        })
+    };
+
+    let mut k = net.connect(9000);
+    let writer = {
+        let mut k = k.try_clone().unwrap();
+        let up = up.clone();
+        thread::spawn(move || {
+            k.write_all(&up).unwrap();
+            k.shutdown(Shutdown::Write).unwrap();
+        })
+    };
+    let mut got = Vec::new();
+    k.read_to_end(&mut got).unwrap();
+    writer.join().unwrap();
+
+    let (ours, in_flight, buffered) = server.join().unwrap().unwrap();
+    // comparing the lengths first, so a short transfer doesn't print megabytes of both
+    assert_eq!(ours.len(), up.len());
+    assert!(ours == up, "our end got garbled data");
+    assert_eq!(got.len(), down.len());
+    assert!(got == down, "the kernel got garbled data");
+    assert_eq!((in_flight, buffered), (0, 0));
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn we_connect_to_a_kernel_listener() {
+    let Some(mut net) = Net::up(4) else { return };
+    let seen = net.transitions();
+    let kernel = Ipv4Addr::new(10, 97, 4, 1);
+    let l = TcpListener::bind((kernel, 7100)).unwrap();
+    let server = thread::spawn(move || -> io::Result<_> {
+        let (mut k, _) = l.accept()?;
+        k.set_read_timeout(Some(TIMEOUT))?;
+        let mut got = Vec::new();
+        k.read_to_end(&mut got)?;
+        k.write_all(b"hello from the kernel")?;
+        Ok(got)
+    });
+
+    let ours = net.ours.into();
+    let request = pattern(10_000, 0x3c);
+    let mut s = net
+        .iface
+        .connect_and_send(ours, SocketAddr::from((kernel, 7100)), &request)
+        .unwrap();
+    s.set_read_timeout(Some(TIMEOUT)).unwrap();
+    s.shutdown(Shutdown::Write).unwrap();
+    let mut reply = Vec::new();
+    s.read_to_end(&mut reply).unwrap();
+    assert_eq!(reply, b"hello from the kernel");
+    let got = server.join().unwrap().unwrap();
+    assert_eq!(got.len(), request.len());
+    assert!(got == request, "the kernel got garbled data");
+    assert_eq!(s.info().unwrap().mss, 1460);
+    wait_for(&seen, (State::FinWait2, State::TimeWait));
+    assert_eq!(seen.lock().unwrap()[0], (State::SynSent, State::Estab));
+
+    // nothing listens on the next port along, so the kernel resets our SYN
+    let err = net
+        .iface
+        .connect(ours, SocketAddr::from((kernel, 7101)))
+        .err()
+        .expect("connected to a port nobody listens on");
+    assert_eq!(TcpError::from_io(&err), Some(TcpError::Refused), "{err}");
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn kernel_pings_us() {
+    if !installed("ping") {
+        return;
+    }
+    let Some(net) = Net::up(5) else { return };
+    let out = Command::new("ping")
+        .args(["-c", "3", "-i", "0.2", "-W", "5", "-s", "100"])
+        .arg(net.ours.to_string())
+        .output()
+        .unwrap();
+    // ping checks each reply's checksum, identifier, sequence number and data for us
+    assert!(out.status.success(), "ping failed: {out:?}");
+    let stats = net.iface.icmp_stats().unwrap();
+    assert_eq!(stats.echo_replies, 3, "{stats:?}");
+    assert_eq!(stats.bad_checksums, 0, "{stats:?}");
+
+    // nothing answers for the rest of the subnet, even though the kernel routes it to us
+    let out = Command::new("ping")
+        .args(["-c", "1", "-W", "1", "10.97.5.3"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success(), "got a reply from nobody: {out:?}");
+    let stats = net.iface.icmp_stats().unwrap();
+    assert_eq!((stats.echo_replies, stats.ignored), (3, 1), "{stats:?}");
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn echo_example_serves_the_kernel() {
+    let Some(mut net) = Net::up(6) else { return };
+    let seen = net.transitions();
+    let mut l = net.iface.bind(7).unwrap();
+    let server = thread::spawn(move || echo_example::echo(l.accept()?));
+
+    let data = pattern(1 << 20, 0x3c);
+    let mut k = net.connect(7);
+    let writer = {
+        let mut k = k.try_clone().unwrap();
+        let data = data.clone();
+        thread::spawn(move || {
+            k.write_all(&data).unwrap();
+            k.shutdown(Shutdown::Write).unwrap();
+        })
+    };
+    let mut got = Vec::new();
+    k.read_to_end(&mut got).unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(server.join().unwrap().unwrap(), data.len() as u64);
+    assert_eq!(got.len(), data.len());
+    assert!(got == data, "the kernel got garbled data");
+    // the kernel closed first, and we only closed once everything had been echoed
+    wait_for(&seen, (State::LastAck, State::Closed));
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn idle_timeout_resets_the_kernel() {
+    let Some(mut net) = Net::up(7) else { return };
+    let pcap = std::env::temp_dir().join("trust-iop7.pcap");
+    net.iface.capture(&pcap, None).unwrap();
+    let config = ConnectionConfig::default().idle_timeout(Duration::from_millis(500));
+    let mut l = net.iface.bind_with_config(7700, 1, config).unwrap();
+    let server = thread::spawn(move || l.accept());
+
+    let mut k = net.connect(7700);
+    let _s = server.join().unwrap().unwrap();
+    let mut buf = [0; 16];
+    let err = k.read(&mut buf).expect_err("read past our RST");
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset, "{err}");
+
+    net.iface.stop_capture();
+    let packets = trust::pcap::read(&pcap).unwrap();
+    let _ = std::fs::remove_file(&pcap);
+    let rst = packets
+        .iter()
+        // the kernel has IPv6 housekeeping of its own to send as soon as the device is up
+        .filter(|p| {
+            etherparse::Ipv4HeaderSlice::from_slice(&p.data).is_ok_and(|ip| ip.protocol() == 6)
+        })
+        .map(|p| trust::testing::parse_segment(&p.data))
+        .find(|(_, tcph, _)| tcph.rst())
+        .expect("no RST in the capture");
+    let (iph, tcph, payload) = rst;
+    assert_eq!(iph.source_addr(), net.ours);
+    // a RST offers no window and carries no other control bits, but still ACKs
+    assert_eq!(tcph.window_size(), 0);
+    assert!(tcph.ack() && !tcph.syn() && !tcph.fin() && !tcph.psh());
+    assert!(payload.is_empty());
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn curl_through_the_proxy_example() {
+    if !installed("curl") {
+        return;
+    }
+    let Some(mut net) = Net::up(8) else { return };
+    let kernel = Ipv4Addr::new(10, 97, 8, 1);
+    let body = "proxied by trust\n".repeat(10_000);
+    let upstream = TcpListener::bind((kernel, 8800)).unwrap();
+    let server = {
+        let body = body.clone();
+        thread::spawn(move || -> io::Result<Vec<u8>> {
+            let (mut s, _) = upstream.accept()?;
+            let mut req = Vec::new();
+            let mut buf = [0; 1024];
+            while !req.ends_with(b"\r\n\r\n") {
+                let n = s.read(&mut buf)?;
+                assert!(n > 0, "EOF in the middle of the request");
+                req.extend_from_slice(&buf[..n]);
+            }
+            write!(s, "HTTP/1.0 200 OK\r\nConnection: close\r\n\r\n{body}")?;
+            Ok(req)
+        })
+    };
+
+    let config = ConnectionConfig::default().recv_window(u16::MAX);
+    let mut l = net.iface.bind_with_config(8080, 1, config.clone()).unwrap();
+    let curl = Command::new("curl")
+        .args(["-sS", "--max-time", "60"])
+        .arg(format!("http://{}:8080/proxied", net.ours))
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    // drained as it comes, or curl stops reading from the proxy once the pipe fills up
+    let curl = thread::spawn(move || curl.wait_with_output().unwrap());
+    let client = l.accept().unwrap();
+    let upstream = net
+        .iface
+        .connect_with_config(net.ours.into(), (kernel, 8800).into(), config)
+        .unwrap();
+    let (up, down) = proxy_example::relay(client, upstream).unwrap();
+
+    let out = curl.join().unwrap();
+    assert!(out.status.success(), "curl failed: {out:?}");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), body);
+    let req = server.join().unwrap().unwrap();
+    assert!(req.starts_with(b"GET /proxied HTTP/1.1\r\n"), "{req:?}");
+    // the request went up untouched, and the response (headers and all) came back down
+    assert_eq!(up, req.len() as u64);
+    assert!(down > body.len() as u64);
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn mirror_sees_both_directions() {
+    let Some(mut net) = Net::up(9) else { return };
+    let name = "trust-iop9m";
+    net.iface
+        .mirror(Tun::open(name).expect("failed to open mirror device"));
+    // no addresses, and strict reverse path filtering, so the kernel drops the copies of what
+    // we send rather than taking them for packets to itself
+    std::fs::write(format!("/proc/sys/net/ipv4/conf/{name}/rp_filter"), "1").unwrap();
+    run("ip", &format!("link set up dev {name}"));
+    let sniffer = Sniffer::open(name);
+
+    let mut l = net.iface.bind(7900).unwrap();
+    let server = thread::spawn(move || -> io::Result<()> {
+        let mut s = l.accept()?;
+        let mut ping = [0; 4];
+        s.read_exact(&mut ping)?;
+        s.write_all(b"pong")?;
+        s.flush()
+    });
+    let mut k = net.connect(7900);
+    k.write_all(b"ping").unwrap();
+    let mut pong = [0; 4];
+    k.read_exact(&mut pong).unwrap();
+    assert_eq!(&pong, b"pong");
+    server.join().unwrap().unwrap();
+
+    let seen: Vec<_> = sniffer
+        .segments()
+        .iter()
+        .map(|p| {
+            let (iph, tcph, payload) = trust::testing::parse_segment(p);
+            let ours = iph.source_addr() == net.ours;
+            (ours, tcph.syn(), tcph.ack(), payload.to_vec())
+        })
+        .collect();
+    // the handshake, and the data, in both directions
+    assert!(seen.contains(&(false, true, false, vec![])), "{seen:?}");
+    assert!(seen.contains(&(true, true, true, vec![])), "{seen:?}");
+    let ping = (false, false, true, b"ping".to_vec());
+    let pong = (true, false, true, b"pong".to_vec());
+    assert!(seen.contains(&ping), "{seen:?}");
+    assert!(seen.contains(&pong), "{seen:?}");
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn read_vectored_splits_header_and_body() {
+    let Some(mut net) = Net::up(10) else { return };
+    let mut l = net.iface.bind(8000).unwrap();
+    let server = thread::spawn(move || -> io::Result<_> {
+        let mut s = l.accept()?;
+        let (mut header, mut body) = ([0; 8], [0; 64]);
+        let n = s.read_vectored(&mut [IoSliceMut::new(&mut header), IoSliceMut::new(&mut body)])?;
+        Ok((n, header, body))
+    });
+
+    let mut k = net.connect(8000);
+    k.write_all(b"headers!and the body").unwrap();
+    let (n, header, body) = server.join().unwrap().unwrap();
+    assert_eq!(n, 20);
+    assert_eq!(&header, b"headers!");
+    assert_eq!(&body[..12], b"and the body");
+    assert!(body[12..].iter().all(|&b| b == 0));
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn recv_bytes_without_copying() {
+    let Some(mut net) = Net::up(11) else { return };
+    let config = ConnectionConfig::default()
+        .recv_window(u16::MAX)
+        .zero_copy(true);
+    let mut l = net.iface.bind_with_config(8100, 1, config).unwrap();
+    let server = thread::spawn(move || -> io::Result<_> {
+        let mut s = l.accept()?;
+        let mut got = Vec::new();
+        loop {
+            let p = s.recv_bytes()?;
+            if p.is_empty() {
+                return Ok(got);
+            }
+            got.extend_from_slice(&p);
+        }
+    });
+
+    let sent: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let mut k = net.connect(8100);
+    k.write_all(&sent).unwrap();
+    drop(k);
+    let got = server.join().unwrap().unwrap();
+    assert_eq!(got.len(), sent.len());
+    assert!(got == sent);
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn write_vectored_sends_one_stream() {
+    let Some(mut net) = Net::up(12) else { return };
+    let mut l = net.iface.bind(8200).unwrap();
+    let body = pattern(5000, 7);
+    let sent = body.clone();
+    let server = thread::spawn(move || -> io::Result<_> {
+        let mut s = l.accept()?;
+        let n = s.write_vectored(&[IoSlice::new(b"headers!"), IoSlice::new(&body)])?;
+        s.shutdown(Shutdown::Write)?;
+        Ok(n)
+    });
+
+    let mut k = net.connect(8200);
+    let mut got = Vec::new();
+    k.read_to_end(&mut got).unwrap();
+    assert_eq!(server.join().unwrap().unwrap(), 8 + sent.len());
+    assert_eq!(&got[..8], b"headers!");
+    assert!(got[8..] == sent);
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn fin_in_fin_wait2_gets_the_final_ack() {
+    let Some(mut net) = Net::up(13) else { return };
+    let sniffer = Sniffer::open(&net.name);
+    let seen = net.transitions();
+    let mut l = net.iface.bind(8300).unwrap();
+    let server = thread::spawn(move || -> io::Result<()> {
+        let mut s = l.accept()?;
+        // close first, then wait for the kernel to
+        s.shutdown(Shutdown::Write)?;
+        let mut rest = Vec::new();
+        s.read_to_end(&mut rest)?;
+        assert!(rest.is_empty());
+        Ok(())
+    });
+
+    let mut k = net.connect(8300);
+    let mut got = Vec::new();
+    k.read_to_end(&mut got).unwrap();
+    assert!(got.is_empty());
+    wait_for(&seen, (State::FinWait1, State::FinWait2));
+    drop(k);
+    server.join().unwrap().unwrap();
+    wait_for(&seen, (State::FinWait2, State::TimeWait));
+
+    let seen: Vec<_> = sniffer
+        .segments()
+        .iter()
+        .map(|p| {
+            let (iph, tcph, _) = trust::testing::parse_segment(p);
+            let ours = iph.source_addr() == net.ours;
+            let seq = tcph.sequence_number();
+            (ours, tcph.fin(), seq, tcph.acknowledgment_number())
+        })
+        .collect();
+    let fins: Vec<_> = seen.iter().filter(|s| s.1).collect();
+    let our_fin = fins.iter().find(|s| s.0).expect("we sent no FIN").2;
+    let their_fin = fins.iter().find(|s| !s.0).expect("kernel sent no FIN").2;
+    // our FIN takes up a sequence number and so does theirs, and the last thing we send ACKs
+    // theirs from just past ours, with nothing else after it
+    let last = *seen.iter().rev().find(|s| s.0).unwrap();
+    assert_eq!(last, (true, false, our_fin + 1, their_fin + 1), "{seen:?}");
+    // and theirs ACKed ours
+    assert!(fins.contains(&&(false, true, their_fin, our_fin + 1)));
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn read_uninit_until_eof() {
+    let Some(mut net) = Net::up(14) else { return };
+    let mut l = net.iface.bind(8400).unwrap();
+    let server = thread::spawn(move || -> io::Result<_> {
+        let mut s = l.accept()?;
+        let mut got = Vec::with_capacity(200_000);
+        loop {
+            let n = s.read_uninit(got.spare_capacity_mut())?.len();
+            if n == 0 {
+                return Ok(got);
+            }
+            // SAFETY: `read_uninit` just initialized the next `n` bytes
+            unsafe { got.set_len(got.len() + n) };
+        }
+    });
+
+    let sent = pattern(100_000, 3);
+    let mut k = net.connect(8400);
+    k.write_all(&sent).unwrap();
+    drop(k);
+    let got = server.join().unwrap().unwrap();
+    assert!(got == sent);
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn connections_lists_every_flow() {
+    let Some(mut net) = Net::up(15) else { return };
+    let mut l = net.iface.bind(8500).unwrap();
+    let server = thread::spawn(move || -> io::Result<_> {
+        let (mut a, mut b) = (l.accept()?, l.accept()?);
+        let mut buf = [0; 5];
+        a.read_exact(&mut buf)?;
+        b.read_exact(&mut buf)?;
+        Ok((a, b))
+    });
+
+    let mut k1 = net.connect(8500);
+    let mut k2 = net.connect(8500);
+    k1.write_all(b"first").unwrap();
+    k2.write_all(b"other").unwrap();
+    let (a, b) = server.join().unwrap().unwrap();
+    // something left unread on one of them
+    k1.write_all(b"more").unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let all: HashMap<_, _> = net.iface.connections().unwrap().collect();
+    assert_eq!(all.len(), 2, "{all:?}");
+    for (s, unread) in [(&a, 4), (&b, 0)] {
+        let info = all[&s.quad()];
+        assert_eq!(info.state, State::Estab);
+        assert_eq!(info.mss, 1460);
+        assert_eq!(info.bytes_in_flight, 0);
+        assert_eq!(info.recv_buffer_len, unread, "{:?}", s.quad());
+    }
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn read_and_write_timeouts() {
+    let Some(mut net) = Net::up(16) else { return };
+    let mut l = net.iface.bind(8600).unwrap();
+    let server = thread::spawn(move || l.accept());
+    let mut k = net.connect(8600);
+    let mut s = server.join().unwrap().unwrap();
+    let timeout = Duration::from_millis(200);
+
+    // nothing comes: the read gives up after the timeout, and not much longer
+    s.set_read_timeout(Some(timeout)).unwrap();
+    let mut buf = [0; 16];
+    let start = Instant::now();
+    let err = s.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    let waited = start.elapsed();
+    assert!(waited >= timeout && waited < 10 * timeout, "{waited:?}");
+    // and the stream is none the worse for it
+    k.write_all(b"late").unwrap();
+    assert_eq!(s.read(&mut buf).unwrap(), 4);
+
+    // the kernel doesn't read, so once its window and our send queue fill, writes give up
+    s.set_write_timeout(Some(timeout)).unwrap();
+    let chunk = [0; 16 * 1024];
+    let start = Instant::now();
+    loop {
+        match s.write(&chunk) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+            r => r.unwrap(),
+        };
+        assert!(start.elapsed() < TIMEOUT, "writes never timed out");
+    }
+
+    // the connection failing (here, for being idle) while a read waits with no timeout ends
+    // the read as soon as it fails, with its own error
+    let config = ConnectionConfig::default().idle_timeout(5 * timeout);
+    let mut l = net.iface.bind_with_config(8601, 1, config).unwrap();
+    let _k = net.connect(8601);
+    let mut s = l.accept().unwrap();
+    let start = Instant::now();
+    let err = s.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    assert!(start.elapsed() >= 4 * timeout, "{:?}", start.elapsed());
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn read_line_across_segments() {
+    let Some(mut net) = Net::up(17) else { return };
+    let line = |i: usize| format!("{i} {}\r\n", "x".repeat(i % 97));
+    let lines: Vec<_> = (0..3000).map(line).collect();
+    for (port, zero_copy) in [(8700, false), (8701, true)] {
+        let config = ConnectionConfig::default().zero_copy(zero_copy);
+        let mut l = net.iface.bind_with_config(port, 1, config).unwrap();
+        let server = thread::spawn(move || -> io::Result<_> {
+            let mut s = l.accept()?;
+            let mut first = String::new();
+            s.read_line(&mut first)?;
+            // what's left of a run fill_buf took comes before the rest, whatever reads it
+            let mut byte = [0];
+            s.read_exact(&mut byte)?;
+            let mut rest = Vec::new();
+            for line in s.lines() {
+                rest.push(line?);
+            }
+            Ok((first, byte, rest))
+        });
+
+        let mut k = net.connect(port);
+        k.set_nodelay(true).unwrap();
+        // the first line in two segments, with the start of what's next after it
+        k.write_all(b"HELO exa").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        k.write_all(b"mple.org\r\n@").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        // then enough for the receive buffer to go round many times, and lines to end up
+        // across its wrap point
+        k.write_all(lines.concat().as_bytes()).unwrap();
+        drop(k);
+
+        let (first, byte, rest) = server.join().unwrap().unwrap();
+        assert_eq!(first, "HELO example.org\r\n");
+        assert_eq!(&byte, b"@");
+        assert_eq!(rest.len(), lines.len());
+        for (got, sent) in rest.iter().zip(&lines) {
+            assert_eq!(format!("{got}\r\n"), *sent);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn kernel_reset_fails_a_blocked_read() {
+    let Some(mut net) = Net::up(18) else { return };
+    let seen = net.transitions();
+    let mut l = net.iface.bind(8800).unwrap();
+    let server = thread::spawn(move || -> io::Result<_> {
+        let mut s = l.accept()?;
+        let mut buf = [0; 16];
+        let n = s.read(&mut buf)?;
+        // blocks until the reset
+        let err = s.read(&mut buf).unwrap_err();
+        Ok((n, err.kind(), TcpError::from_io(&err)))
+    });
+
+    let mut k = net.connect(8800);
+    k.write_all(b"hi").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    // closing with a zero linger time resets the connection rather than sending a FIN
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+    let r = unsafe {
+        libc::setsockopt(
+            k.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            (&raw const linger).cast(),
+            size_of::<libc::linger>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(r, 0, "SO_LINGER: {}", io::Error::last_os_error());
+    drop(k);
+
+    let (n, kind, err) = server.join().unwrap().unwrap();
+    assert_eq!(n, 2);
+    assert_eq!(kind, io::ErrorKind::ConnectionReset);
+    assert_eq!(err, Some(TcpError::Reset));
+    wait_for(&seen, (State::Estab, State::Closed));
+    assert!(net.iface.connections().unwrap().next().is_none());
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn split_halves_close_their_own_side() {
+    let Some(mut net) = Net::up(19) else { return };
+    let mut l = net.iface.bind(8900).unwrap();
+
+    // the write half's FIN goes out while the read half is still reading
+    let mut k = net.connect(8900);
+    let (mut rx, mut tx) = l.accept().unwrap().split();
+    let writer = thread::spawn(move || tx.write_all(b"from us"));
+    let reader = thread::spawn(move || {
+        let mut got = Vec::new();
+        rx.read_to_end(&mut got).map(|_| got)
+    });
+    writer.join().unwrap().unwrap();
+    let mut got = Vec::new();
+    k.read_to_end(&mut got).unwrap();
+    assert_eq!(got, b"from us");
+    k.write_all(b"from the kernel").unwrap();
+    k.shutdown(Shutdown::Write).unwrap();
+    assert_eq!(reader.join().unwrap().unwrap(), b"from the kernel");
+
+    // with the read half gone, what arrives is ACKed and thrown away, so the kernel can send
+    // far more than our window, and the write half carries on
+    let mut k = net.connect(8900);
+    let (rx, mut tx) = l.accept().unwrap().split();
+    drop(rx);
+    k.write_all(&vec![7; 500_000]).unwrap();
+    tx.write_all(b"bye").unwrap();
+    drop(tx);
+    let mut got = Vec::new();
+    k.read_to_end(&mut got).unwrap();
+    assert_eq!(got, b"bye");
+
+    // put back together, it's a stream like any other
+    let mut k = net.connect(8900);
+    let (rx, tx) = l.accept().unwrap().split();
+    let mut s = rx.unsplit(tx);
+    k.write_all(b"ping").unwrap();
+    let mut buf = [0; 4];
+    s.read_exact(&mut buf).unwrap();
+    s.write_all(&buf).unwrap();
+    drop(s);
+    let mut got = Vec::new();
+    k.read_to_end(&mut got).unwrap();
+    assert_eq!(got, b"ping");
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn clones_read_and_write_concurrently() {
+    let Some(mut net) = Net::up(20) else { return };
+    let mut l = net.iface.bind(9000).unwrap();
+
+    // one clone reads while another writes, and dropping one leaves the connection open
+    let mut k = net.connect(9000);
+    let mut s = l.accept().unwrap();
+    let mut writer = s.clone();
+    let sent = thread::spawn(move || {
+        for i in 0..1000u32 {
+            writer.write_all(&i.to_be_bytes())?;
+        }
+        io::Result::Ok(())
+    });
+    let echo = thread::spawn(move || {
+        let mut k2 = k.try_clone().unwrap();
+        let drain = thread::spawn(move || {
+            let mut got = vec![0; 4000];
+            k2.read_exact(&mut got).map(|_| got)
+        });
+        k.write_all(&[9; 100_000]).unwrap();
+        (k, drain.join().unwrap())
+    });
+    let mut got = vec![0; 100_000];
+    s.read_exact(&mut got).unwrap();
+    assert!(got.iter().all(|&b| b == 9));
+    sent.join().unwrap().unwrap();
+    let (mut k, drained) = echo.join().unwrap();
+    let expected: Vec<u8> = (0..1000u32).flat_map(u32::to_be_bytes).collect();
+    assert_eq!(drained.unwrap(), expected);
+
+    let mut other = s.clone();
+    drop(s);
+    other.info().unwrap();
+    k.write_all(b"still here").unwrap();
+    let mut buf = [0; 10];
+    other.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"still here");
+
+    // with two clones blocked reading, data goes to one and the next to the other
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let mut r = other.clone();
+            thread::spawn(move || {
+                let mut b = [0; 1];
+                r.read_exact(&mut b).map(|_| b[0])
+            })
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(100));
+    k.write_all(b"a").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    k.write_all(b"b").unwrap();
+    let mut got: Vec<u8> = readers
+        .into_iter()
+        .map(|r| r.join().unwrap().unwrap())
+        .collect();
+    got.sort();
+    assert_eq!(got, b"ab");
+
+    // close on any clone closes the connection for all of them
+    let mut keep = other.clone();
+    other.close().unwrap();
+    let mut rest = Vec::new();
+    k.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    drop(other);
+    assert_eq!(keep.read(&mut [0; 1]).unwrap(), 0);
+}
+
+/// Just enough of an executor to drive futures that wait on our streams: the thread parks
+/// until the future's waker unparks it, then polls again.
+struct Unpark(thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = std::pin::pin!(fut);
+    loop {
+        if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+            return out;
+        }
+        thread::park();
+    }
+}
+
+async fn echo(mut s: trust::TcpStream) -> io::Result<usize> {
+    let mut buf = vec![0; 4096];
+    let mut total = 0;
+    loop {
+        let n = poll_fn(|cx| s.poll_read(cx, &mut buf)).await?;
+        if n == 0 {
+            return Ok(total);
+        }
+        let mut written = 0;
+        while written < n {
+            written += poll_fn(|cx| s.poll_write(cx, &buf[written..n])).await?;
+        }
+        total += n;
+    }
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn poll_read_and_write_drive_an_echo() {
+    let Some(mut net) = Net::up(21) else { return };
+    let mut l = net.iface.bind(9100).unwrap();
+
+    // far more than fits in the buffers, so both sides have to wait on the other
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let mut k = net.connect(9100);
+    let s = l.accept().unwrap();
+    let server = thread::spawn(move || block_on(echo(s)));
+    let mut kw = k.try_clone().unwrap();
+    let sent = data.clone();
+    let writer = thread::spawn(move || {
+        kw.write_all(&sent).unwrap();
+        kw.shutdown(Shutdown::Write).unwrap();
+    });
+    let mut got = Vec::new();
+    k.read_to_end(&mut got).unwrap();
+    writer.join().unwrap();
+    assert_eq!(server.join().unwrap().unwrap(), data.len());
+    assert!(got == data, "echoed {} bytes, not what was sent", got.len());
+
+    // two tasks waiting on the same connection are both woken
+    let mut k = net.connect(9100);
+    let s = l.accept().unwrap();
+    let waiters: Vec<_> = (0..2)
+        .map(|_| {
+            let mut s = s.clone();
+            thread::spawn(move || {
+                let mut b = [0; 1];
+                block_on(poll_fn(|cx| s.poll_read(cx, &mut b))).map(|_| b[0])
+            })
+        })
+        .collect();
+    thread::sleep(Duration::from_millis(100));
+    k.write_all(b"xy").unwrap();
+    let mut got: Vec<u8> = waiters
+        .into_iter()
+        .map(|w| w.join().unwrap().unwrap())
+        .collect();
+    got.sort();
+    assert_eq!(got, b"xy");
+
+    // a waiting task is woken by the peer's FIN, and its waker let go of
+    let unpark = Arc::new(Unpark(thread::current()));
+    let waker = Waker::from(unpark.clone());
+    let mut s = s;
+    let mut buf = [0; 16];
+    let mut poll = || s.poll_read(&mut Context::from_waker(&waker), &mut buf);
+    assert!(poll().is_pending());
+    assert_eq!(Arc::strong_count(&unpark), 3);
+    k.shutdown(Shutdown::Write).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Arc::strong_count(&unpark) > 2 {
+        assert!(Instant::now() < deadline, "waker was never woken");
+        thread::park_timeout(Duration::from_millis(10));
+    }
+    assert!(matches!(poll(), Poll::Ready(Ok(0))));
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn nonblocking_writes_fill_the_send_buffer() {
+    let Some(mut net) = Net::up(22) else { return };
+    let config = ConnectionConfig::default().send_buffer(16 * 1024, 4 * 1024);
+    let mut l = net.iface.bind_with_config(9000, 1, config).unwrap();
+    let mut k = net.connect(9000);
+    let mut s = l.accept().unwrap();
+    s.set_nonblocking(true).unwrap();
+
+    // nothing to read yet
+    let err = s.read(&mut [0; 16]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    // writes go into the send queue until it's full, without waiting for the kernel
+    let mut written = 0;
+    let err = loop {
+        match s.write(&[7; 4096]) {
+            Ok(n) => written += n,
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    assert!(written >= 16 * 1024, "only {written} bytes written");
+    assert!(s.send_buffer_len().unwrap() <= 16 * 1024);
+
+    // once the kernel has taken it all, there's room again
+    let mut got = vec![0; written];
+    k.read_exact(&mut got).unwrap();
+    assert!(got.iter().all(|&b| b == 7));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match s.write(b"more") {
+            Ok(n) => {
+                assert_eq!(n, 4);
+                break;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                assert!(Instant::now() < deadline, "never writable again");
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => panic!("{e}"),
+        }
+    }
+    let mut more = [0; 4];
+    k.read_exact(&mut more).unwrap();
+    assert_eq!(&more, b"more");
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn kernel_reaches_us_over_a_tap_device() {
+    let Some(mut net) = Net::up_tap(23) else {
+        return;
+    };
+    // the kernel has to resolve us with ARP before either gets through
+    if installed("ping") {
+        let out = Command::new("ping")
+            .args(["-c", "3", "-i", "0.2", "-W", "5"])
+            .arg(net.ours.to_string())
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "ping failed: {out:?}");
+        assert_eq!(net.iface.icmp_stats().unwrap().echo_replies, 3);
+    }
+
+    let mut l = net.iface.bind(7000).unwrap();
+    let mut k = net.connect(7000);
+    let mut s = l.accept().unwrap();
+    k.write_all(b"over ethernet").unwrap();
+    let mut got = [0; 13];
+    s.read_exact(&mut got).unwrap();
+    assert_eq!(&got, b"over ethernet");
+    s.write_all(b"and back").unwrap();
+    let mut got = [0; 8];
+    k.read_exact(&mut got).unwrap();
+    assert_eq!(&got, b"and back");
+}
+
+/// A veth pair, deleted again on drop. The kernel has the first end, with 10.97.N.1 on it, and
+/// the second end has no address at all, so packets sent down the link for 10.97.N.2 reach
+/// nothing but our raw socket on it.
+struct Veth {
+    kernel: String,
+    ours: String,
+}
+
+impl Veth {
+    fn up(n: u8) -> Veth {
+        let veth = Veth {
+            kernel: format!("trust-iop{n}"),
+            ours: format!("trust-raw{n}"),
+        };
+        run(
+            "ip",
+            &format!("link add {} type veth peer name {}", veth.kernel, veth.ours),
+        );
+        run(
+            "ip",
+            &format!("addr add 10.97.{n}.1/24 dev {}", veth.kernel),
+        );
+        run("ip", &format!("link set up dev {}", veth.kernel));
+        run("ip", &format!("link set up dev {}", veth.ours));
+        veth.no_tx_checksums();
+        // we don't answer ARP, so tell the kernel where we are
+        let mac = std::fs::read_to_string(format!("/sys/class/net/{}/address", veth.ours)).unwrap();
+        run(
+            "ip",
+            &format!(
+                "neigh replace 10.97.{n}.2 lladdr {} dev {}",
+                mac.trim(),
+                veth.kernel
+            ),
+        );
+        veth
+    }
+
+    /// Have the kernel finish its TCP checksums before they go down the link. Otherwise it
+    /// leaves them for the other end to do, since that's on the same machine, and our packet
+    /// socket sees them half done.
+    fn no_tx_checksums(&self) {
+        const ETHTOOL_STXCSUM: u32 = 0x17;
+        #[repr(C)]
+        struct EthtoolValue {
+            cmd: u32,
+            data: u32,
+        }
+        let mut value = EthtoolValue {
+            cmd: ETHTOOL_STXCSUM,
+            data: 0,
+        };
+        let mut ifr: libc::ifreq = unsafe { std::mem::zeroed() };
+        for (d, s) in ifr.ifr_name.iter_mut().zip(self.kernel.bytes()) {
+            *d = s as libc::c_char;
+        }
+        ifr.ifr_ifru.ifru_data = (&raw mut value).cast();
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+        assert!(fd >= 0, "socket: {}", io::Error::last_os_error());
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let r = unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCETHTOOL, &mut ifr) };
+        assert_eq!(r, 0, "SIOCETHTOOL: {}", io::Error::last_os_error());
+    }
+}
+
+impl Drop for Veth {
+    fn drop(&mut self) {
+        // deleting one end takes the other with it
+        let _ = Command::new("ip")
+            .args(["link", "del", &self.kernel])
+            .status();
+    }
+}
+
+#[test]
+#[ignore = "needs root and a veth pair; see interop.sh"]
+fn kernel_reaches_us_over_a_raw_socket() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipping: needs root to set up a veth pair");
+        return;
+    }
+    let veth = Veth::up(24);
+    let ours = Ipv4Addr::new(10, 97, 24, 2);
+    let nic = RawSocket::open(&veth.ours, ours, &[7100]).unwrap();
+    let mut iface = Interface::with_nic(nic);
+    iface.add_address(ours.into());
+
+    let mut l = iface.bind(7100).unwrap();
+    let mut k = TcpStream::connect_timeout(&SocketAddr::from((ours, 7100)), TIMEOUT)
+        .expect("kernel failed to connect");
+    k.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut s = l.accept().unwrap();
+    k.write_all(b"over a raw socket").unwrap();
+    let mut got = [0; 17];
+    s.read_exact(&mut got).unwrap();
+    assert_eq!(&got, b"over a raw socket");
+    s.write_all(b"and back").unwrap();
+    let mut got = [0; 8];
+    k.read_exact(&mut got).unwrap();
+    assert_eq!(&got, b"and back");
+}
+
+#[test]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn kernel_reaches_us_over_ipv6() {
+    let Some(mut net) = Net::up(25) else { return };
+    // no duplicate address detection, which would hold the address back for a second or so
+    run(
+        "ip",
+        &format!("-6 addr add fd97:25::1/64 dev {} nodad", net.name),
+    );
+    let ours: Ipv6Addr = "fd97:25::2".parse().unwrap();
+    net.iface.add_address(ours.into());
+
+    let mut l = net.iface.bind(7200).unwrap();
+    let mut k = TcpStream::connect_timeout(&SocketAddr::from((ours, 7200)), TIMEOUT)
+        .expect("kernel failed to connect");
+    k.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut s = l.accept().unwrap();
+    assert_eq!(s.info().unwrap().mss, 1440);
+    k.write_all(b"over ipv6").unwrap();
+    let mut got = [0; 9];
+    s.read_exact(&mut got).unwrap();
+    assert_eq!(&got, b"over ipv6");
+    s.write_all(b"and back").unwrap();
+    let mut got = [0; 8];
+    k.read_exact(&mut got).unwrap();
+    assert_eq!(&got, b"and back");
+}
+
+/// The kernel's `struct tcp_md5sig`, for setting a key with `TCP_MD5SIG`.
+#[cfg(feature = "tcp-md5")]
+#[repr(C)]
+struct TcpMd5Sig {
+    addr: libc::sockaddr_storage,
+    flags: u8,
+    prefixlen: u8,
+    keylen: u16,
+    ifindex: libc::c_int,
+    key: [u8; libc::TCP_MD5SIG_MAXKEYLEN],
+}
+
+/// Connect a kernel socket to `to`, signing with `key`, and giving up after `timeout`.
+#[cfg(feature = "tcp-md5")]
+fn connect_signed(
+    to: std::net::SocketAddrV4,
+    key: &[u8],
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
+    assert!(fd >= 0, "socket: {}", io::Error::last_os_error());
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let sin = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: to.port().to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(*to.ip()).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    let mut sig: TcpMd5Sig = unsafe { std::mem::zeroed() };
+    unsafe { std::ptr::write((&raw mut sig.addr).cast(), sin) };
+    sig.keylen = key.len() as u16;
+    sig.key[..key.len()].copy_from_slice(key);
+    let r = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_MD5SIG,
+            (&raw const sig).cast(),
+            size_of::<TcpMd5Sig>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(r, 0, "TCP_MD5SIG: {}", io::Error::last_os_error());
+    // a blocking connect gives up after the send timeout
+    let tv = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: 0,
+    };
+    let r = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_SNDTIMEO,
+            (&raw const tv).cast(),
+            size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(r, 0, "SO_SNDTIMEO: {}", io::Error::last_os_error());
+
+    let r = unsafe {
+        libc::connect(
+            fd.as_raw_fd(),
+            (&raw const sin).cast(),
+            size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if r != 0 {
+        let err = io::Error::last_os_error();
+        // what a connect that ran out of time fails with
+        if err.raw_os_error() == Some(libc::EINPROGRESS) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        return Err(err);
+    }
+    Ok(TcpStream::from(fd))
+}
+
+#[test]
+#[cfg(feature = "tcp-md5")]
+#[ignore = "needs root and a tun device; see interop.sh"]
+fn kernel_signs_with_md5() {
+    let Some(mut net) = Net::up(26) else { return };
+    let kernel = Ipv4Addr::new(10, 97, 26, 1);
+    let config = ConnectionConfig::default().md5_key(kernel.into(), "bgp");
+    let mut l = net.iface.bind_with_config(7300, 1, config).unwrap();
+    let to = std::net::SocketAddrV4::new(net.ours, 7300);
+
+    // the kernel checks our signatures as we check its
+    let mut k = connect_signed(to, b"bgp", TIMEOUT).expect("kernel failed to connect");
+    k.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut s = l.accept().unwrap();
+    let data = pattern(100_000, 26);
+    let writer = {
+        let (mut k, data) = (k.try_clone().unwrap(), data.clone());
+        thread::spawn(move || k.write_all(&data))
+    };
+    let mut got = vec![0; data.len()];
+    s.read_exact(&mut got).unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(got == data, "data corrupted in transit");
+    s.write_all(b"and back").unwrap();
+    let mut got = [0; 8];
+    k.read_exact(&mut got).unwrap();
+    assert_eq!(&got, b"and back");
+
+    // and with the wrong key, its SYNs are dropped
+    let err = connect_signed(to, b"not bgp", Duration::from_secs(3)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
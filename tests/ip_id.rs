@@ -0,0 +1,86 @@
+//! The IPv4 identification and DF bit on what a connection sends, run through `Replay`.
+
+use std::time::Duration;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, fin, handshake};
+use etherparse::Ipv4HeaderSlice;
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// A connection established over `QUAD` with `config`, and the SYN-ACK that went out for it.
+fn establish(config: ConnectionConfig) -> (Replay, Vec<u8>) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    let (_, synack) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+    (r, synack)
+}
+
+/// The identification and DF bit of each of `packets`.
+fn ids(packets: &[Vec<u8>]) -> Vec<(u16, bool)> {
+    packets
+        .iter()
+        .map(|p| {
+            let iph = Ipv4HeaderSlice::from_slice(p).unwrap();
+            (iph.identification(), iph.dont_fragment())
+        })
+        .collect()
+}
+
+#[test]
+fn consecutive_segments_carry_increasing_ids() {
+    let (mut r, synack) = establish(ConnectionConfig::default());
+    // four full-sized segments' worth, all of which the window lets out at once; the peer
+    // offered no MSS, so that's 536 bytes each
+    r.write(QUAD, &[0; 4 * 536]).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 4);
+
+    let (first, _) = ids(&[synack])[0];
+    assert_ne!(first, 0);
+    let ids: Vec<_> = ids(&sent).into_iter().map(|(id, _)| id).collect();
+    // each one the next after the one before, the SYN-ACK included
+    let expected: Vec<_> = (1..=4).map(|i| first + i).collect();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn df_is_set_unless_turned_off() {
+    let (mut r, synack) = establish(ConnectionConfig::default());
+    r.write(QUAD, &[0; 100]).unwrap();
+    assert!(ids(&[synack]).iter().all(|&(_, df)| df));
+    assert!(ids(&r.take_sent()).iter().all(|&(_, df)| df));
+
+    let (mut r, synack) = establish(ConnectionConfig::default().dont_fragment(false));
+    r.write(QUAD, &[0; 100]).unwrap();
+    let sent = r.take_sent();
+    assert!(!sent.is_empty());
+    assert!(ids(&[synack]).iter().all(|&(_, df)| !df));
+    assert!(ids(&sent).iter().all(|&(_, df)| !df));
+    // the IDs still count up without DF, where they matter for reassembly
+    assert_ne!(ids(&sent)[0].0, 0);
+}
+
+#[test]
+fn ids_count_on_through_retransmits_and_time_wait() {
+    let (mut r, synack) = establish(ConnectionConfig::default());
+    let iss = parse_segment(&synack).1.sequence_number().wrapping_add(1);
+    let (first, _) = ids(&[synack])[0];
+
+    // the same data again after the retransmission timeout, as a new packet with a new ID
+    r.write(QUAD, b"hello").unwrap();
+    r.advance(Duration::from_secs(1)).unwrap();
+    let sent = ids(&r.take_sent());
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].0, first + 1);
+    assert_eq!(sent[1].0, first + 2);
+
+    // and on from there in TIME-WAIT, with everything but the counter gone
+    r.close(QUAD).unwrap();
+    r.feed(&fin(PEER_ISS + 1, iss + 6, &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    r.feed(&fin(PEER_ISS + 1, iss + 6, &[])).unwrap();
+    let sent: Vec<_> = ids(&r.take_sent()).into_iter().map(|(id, _)| id).collect();
+    assert_eq!(sent, [first + 3, first + 4, first + 5]);
+}
@@ -0,0 +1,85 @@
+//! Incoming IPv4 headers that aren't the plain 20 bytes: segments behind options are found where
+//! the header length says and checksummed over the right pseudo-header, padding past the IP
+//! length isn't taken for data, and segments with a bad checksum or cut short are dropped.
+//! Driven through `Replay`, so no device needed.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// record route, with room for two addresses, and the end-of-list padding after it
+const RECORD_ROUTE: [u8; 12] = [7, 11, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// A segment from the peer with `options` in its IP header.
+fn segment(options: &[u8], seq: u32, ack: Option<u32>, data: &[u8]) -> Vec<u8> {
+    let segment = Segment::new(seq).ip_options(options);
+    match ack {
+        Some(ack) => segment.ack(ack).build(data),
+        None => segment.syn().build(data),
+    }
+}
+
+/// A listener on port 80 and a connection to it, set up through IP headers with options.
+/// Returns our next sequence number.
+fn established(r: &mut Replay) -> u32 {
+    r.listen(80, ConnectionConfig::default());
+    let syn = Segment::syn_at(PEER_ISS).ip_options(&RECORD_ROUTE);
+    let (iss, synack) = handshake(r, syn);
+    // nothing of the peer's options comes back
+    assert_eq!(parse_segment(&synack).0.ihl(), 5);
+    iss
+}
+
+#[test]
+fn segments_behind_ip_options_are_taken_in() {
+    let mut r = Replay::new(LOCAL);
+    let iss = established(&mut r);
+    r.feed(&segment(&RECORD_ROUTE, PEER_ISS + 1, Some(iss), b"hello"))
+        .unwrap();
+    // as if the link layer had padded it, which mustn't come in as data
+    let mut padded = segment(&[1, 1, 1, 0], PEER_ISS + 6, Some(iss), b", world");
+    padded.extend_from_slice(&[0; 6]);
+    r.feed(&padded).unwrap();
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"hello, world");
+    assert_eq!(r.segment_stats().bad_checksums, 0);
+    r.check_invariants();
+}
+
+#[test]
+fn bad_checksums_are_dropped() {
+    let mut r = Replay::new(LOCAL);
+    let iss = established(&mut r);
+    r.take_sent();
+
+    let mut p = segment(&RECORD_ROUTE, PEER_ISS + 1, Some(iss), b"hello");
+    let last = p.len() - 1;
+    p[last] ^= 1;
+    r.feed(&p).unwrap();
+    // nor is the right checksum one whose pseudo-header took the options for TCP, as it
+    // would going by a 20-byte IP header
+    let mut p = segment(&RECORD_ROUTE, PEER_ISS + 1, Some(iss), b"hello");
+    let right = u16::from_be_bytes([p[32 + 16], p[32 + 17]]);
+    let sum = u32::from(!right) + RECORD_ROUTE.len() as u32;
+    let wrong = !((sum & 0xffff) + (sum >> 16)) as u16;
+    p[32 + 16..32 + 18].copy_from_slice(&wrong.to_be_bytes());
+    r.feed(&p).unwrap();
+
+    assert_eq!(r.segment_stats().bad_checksums, 2);
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+    assert!(
+        r.take_sent().is_empty(),
+        "answered a segment it shouldn't have seen"
+    );
+}
+
+#[test]
+fn packets_shorter_than_their_ip_length_are_dropped() {
+    let mut r = Replay::new(LOCAL);
+    let iss = established(&mut r);
+    let p = segment(&RECORD_ROUTE, PEER_ISS + 1, Some(iss), b"hello");
+    r.feed(&p[..p.len() - 2]).unwrap();
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+}
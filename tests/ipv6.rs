@@ -0,0 +1,104 @@
+//! Connections over IPv6: the handshake, with the MSS an IPv6 header leaves room for, data
+//! each way checksummed over the IPv6 pseudo-header, and a listener taking connections from
+//! both families on the one port. Driven through `Replay`, so no device needed.
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake};
+use etherparse::{Ipv6HeaderSlice, TcpHeaderSlice, TcpOptionElement};
+use trust::testing::Replay;
+use trust::{ConnectionConfig, Quad, State};
+
+mod common;
+
+const LOCAL6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+const PEER6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+const QUAD6: Quad = Quad {
+    src: (IpAddr::V6(PEER6), 40000),
+    dst: (IpAddr::V6(LOCAL6), 80),
+};
+
+/// Split an IPv6 packet we sent into its headers and payload, checking the TCP checksum on
+/// the way.
+fn parse6(packet: &[u8]) -> (Ipv6HeaderSlice<'_>, TcpHeaderSlice<'_>, &[u8]) {
+    let iph = Ipv6HeaderSlice::from_slice(packet).expect("not an IPv6 packet");
+    assert_eq!(iph.next_header(), 6, "not a TCP segment");
+    assert_eq!(iph.payload_length() as usize, packet.len() - 40);
+    let tcph = TcpHeaderSlice::from_slice(&packet[40..]).expect("malformed TCP header");
+    let data = &packet[40 + tcph.slice().len()..];
+    assert_eq!(
+        tcph.calc_checksum_ipv6(&iph, data).unwrap(),
+        tcph.checksum()
+    );
+    (iph, tcph, data)
+}
+
+/// A listener on port 80 of `LOCAL6`, and a connection established to it over `QUAD6` by a
+/// peer offering `mss`. Returns our next sequence number, and the MSS we offered back.
+fn establish6(mss: u16) -> (Replay, u32, Option<u16>) {
+    let mut r = Replay::new(LOCAL6);
+    r.listen(80, ConnectionConfig::default());
+    let syn = Segment::syn_at(PEER_ISS).mss(mss).on(QUAD6);
+    r.feed(&syn.build(&[])).unwrap();
+    let synack = r.take_sent().pop().expect("no SYN-ACK");
+    let (iph, tcph, _) = parse6(&synack);
+    assert_eq!(iph.source_addr(), LOCAL6);
+    assert_eq!(iph.destination_addr(), PEER6);
+    assert!(tcph.syn() && tcph.ack());
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 1);
+    let offered = tcph.options_iterator().find_map(|o| match o {
+        Ok(TcpOptionElement::MaximumSegmentSize(mss)) => Some(mss),
+        _ => None,
+    });
+    let iss = tcph.sequence_number().wrapping_add(1);
+    r.feed(&syn.next(iss).build(&[])).unwrap();
+    assert_eq!(r.state(QUAD6), Some(State::Estab));
+    (r, iss, offered)
+}
+
+#[test]
+fn handshake_offers_the_ipv6_mss() {
+    // 1500 less 40 bytes of IPv6 header and 20 of TCP
+    let (r, _, offered) = establish6(1440);
+    assert_eq!(offered, Some(1440));
+    assert_eq!(r.info(QUAD6).unwrap().mss, 1440);
+}
+
+#[test]
+fn data_both_ways() {
+    let (mut r, iss, _) = establish6(1440);
+    let from_peer = Segment::new(PEER_ISS + 1).ack(iss).on(QUAD6);
+    r.feed(&from_peer.build(b"over v6")).unwrap();
+    assert_eq!(r.read(QUAD6, 100).unwrap(), b"over v6");
+    let ack = r.take_sent().pop().expect("data never ACKed");
+    assert_eq!(parse6(&ack).1.acknowledgment_number(), PEER_ISS + 8);
+
+    // a full segment and then some, cut at the MSS
+    r.write(QUAD6, &[7; 2000]).unwrap();
+    let sent = r.take_sent();
+    let lens: Vec<_> = sent.iter().map(|p| parse6(p).2.len()).collect();
+    assert_eq!(lens, [1440, 560]);
+    let (iph, tcph, _) = parse6(&sent[0]);
+    assert_eq!(iph.payload_length(), 20 + 1440);
+    assert_eq!(tcph.sequence_number(), iss);
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 8);
+}
+
+#[test]
+fn one_listener_for_both_families() {
+    let mut r = Replay::new(LOCAL);
+    r.add_address(LOCAL6);
+    r.listen(80, ConnectionConfig::default());
+
+    handshake(&mut r, Segment::syn_at(PEER_ISS));
+    let syn6 = Segment::syn_at(PEER_ISS).on(QUAD6);
+    r.feed(&syn6.build(&[])).unwrap();
+    let synack = r.take_sent().pop().expect("no SYN-ACK");
+    let iss = parse6(&synack).1.sequence_number().wrapping_add(1);
+    r.feed(&syn6.next(iss).build(&[])).unwrap();
+
+    let mut quads = r.quads();
+    quads.sort_by_key(|q| q.src.0.is_ipv6());
+    assert_eq!(quads, [QUAD, QUAD6]);
+    assert!(r.accept().is_some() && r.accept().is_some());
+}
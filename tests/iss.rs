@@ -0,0 +1,101 @@
+//! Initial sequence numbers as RFC 6528 has them: unrelated from one quad to the next, going up
+//! with the clock for any one quad, for connections we accept and ones we open alike, unless
+//! the config pins one. Driven through `Replay`.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, Segment, rst};
+use trust::testing::seq::wrapping_lt;
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad};
+
+mod common;
+
+fn quad(port: u16) -> Quad {
+    Quad {
+        src: (IpAddr::V4(PEER), port),
+        ..QUAD
+    }
+}
+
+fn listening(config: ConnectionConfig) -> Replay {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    r
+}
+
+/// Send a SYN over `quad` and return the ISS our SYN-ACK starts at.
+fn syn(r: &mut Replay, quad: Quad) -> u32 {
+    r.feed(&Segment::syn_at(PEER_ISS).on(quad).build(&[]))
+        .unwrap();
+    let synack = r.take_sent().pop().expect("no SYN-ACK");
+    parse_segment(&synack).1.sequence_number()
+}
+
+#[test]
+fn quads_get_unrelated_iss() {
+    let mut r = listening(ConnectionConfig::default());
+    let isss: Vec<_> = (40000..40010).map(|port| syn(&mut r, quad(port))).collect();
+    for (i, a) in isss.iter().enumerate() {
+        for b in &isss[i + 1..] {
+            assert_ne!(a, b, "{isss:?}");
+        }
+    }
+}
+
+#[test]
+fn one_quad_goes_up_with_the_clock() {
+    let mut r = listening(ConnectionConfig::default());
+    let mut last = syn(&mut r, QUAD);
+    for step in [
+        Duration::from_micros(4),
+        Duration::from_millis(1),
+        Duration::from_secs(1),
+        Duration::from_secs(60),
+    ] {
+        // done with the last one, so the quad is free again
+        r.feed(&rst(PEER_ISS + 1, last.wrapping_add(1))).unwrap();
+        assert!(r.quads().is_empty());
+        r.advance(step).unwrap();
+
+        let iss = syn(&mut r, QUAD);
+        assert!(wrapping_lt(last, iss), "{last} then {iss}, {step:?} on");
+        // the clock ticks every 4us, so it's gone up by about that much
+        let ticks = (step.as_micros() / 4) as u32;
+        let by = iss.wrapping_sub(last);
+        assert!(by >= ticks / 2 && by <= ticks * 2, "up by {by} in {step:?}");
+        last = iss;
+    }
+}
+
+#[test]
+fn connections_we_open_get_unrelated_iss() {
+    let mut r = Replay::new(LOCAL);
+    let remote = (IpAddr::V4(PEER), 80);
+    let mut isss = Vec::new();
+    for _ in 0..10 {
+        r.connect_to(remote, ConnectionConfig::default()).unwrap();
+        let syn = r.take_sent().pop().expect("no SYN");
+        isss.push(parse_segment(&syn).1.sequence_number());
+    }
+    for (i, a) in isss.iter().enumerate() {
+        for b in &isss[i + 1..] {
+            assert_ne!(a, b, "{isss:?}");
+        }
+    }
+}
+
+#[test]
+fn config_pins_the_iss() {
+    for iss in [0, 42, u32::MAX - 1] {
+        let config = ConnectionConfig::default().initial_sequence_number(iss);
+        let mut r = listening(config.clone());
+        assert_eq!(syn(&mut r, QUAD), iss);
+        assert_eq!(syn(&mut r, quad(40001)), iss);
+
+        let quad = r.connect_to((IpAddr::V4(PEER), 80), config).unwrap();
+        let syn = r.take_sent().pop().expect("no SYN");
+        assert_eq!(parse_segment(&syn).1.sequence_number(), iss, "{quad:?}");
+    }
+}
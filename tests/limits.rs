@@ -0,0 +1,133 @@
+//! Stack-wide limits: past the connection limit new SYNs are dropped and counted while the
+//! connections already there carry on, and with buffer memory tight the windows advertised
+//! shrink to fit the budget, though never below a connection's minimum share. Driven through
+//! `Replay`, so no device needed.
+
+use std::io;
+use std::net::IpAddr;
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, Segment, handshake};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, TcpError};
+
+mod common;
+
+const WINDOW: u16 = 16384;
+
+fn quad(port: u16) -> Quad {
+    Quad {
+        src: (IpAddr::V4(PEER), port),
+        ..QUAD
+    }
+}
+
+/// A listener on port 80 with `WINDOW` to offer each connection.
+fn listening() -> Replay {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().recv_window(WINDOW));
+    r
+}
+
+/// Complete a handshake from `port`, returning our next sequence number and the window our
+/// SYN-ACK offered.
+fn establish(r: &mut Replay, port: u16) -> (u32, u16) {
+    let (iss, synack) = handshake(r, Segment::syn_at(PEER_ISS).on(quad(port)));
+    (iss, parse_segment(&synack).1.window_size())
+}
+
+/// A segment from the peer's `port` after its SYN, ACKing `ack`.
+fn from(port: u16, ack: u32) -> Segment {
+    Segment::new(PEER_ISS + 1).ack(ack).on(quad(port))
+}
+
+/// The window the last segment we sent advertised.
+fn last_window(r: &mut Replay) -> u16 {
+    let p = r.take_sent().pop().expect("nothing sent");
+    parse_segment(&p).1.window_size()
+}
+
+#[test]
+fn syn_past_the_connection_limit_is_dropped() {
+    let mut r = listening();
+    r.set_max_connections(Some(2));
+    let (iss, _) = establish(&mut r, 40000);
+    let (other, _) = establish(&mut r, 40001);
+
+    r.feed(&Segment::syn_at(PEER_ISS).on(quad(40002)).build(&[]))
+        .unwrap();
+    assert!(r.take_sent().is_empty(), "answered a SYN past the limit");
+    assert_eq!(r.state(quad(40002)), None);
+    assert_eq!(r.segment_stats().connection_limit, 1);
+
+    // nor can the application open one of its own
+    let e = r
+        .connect(quad(40003), ConnectionConfig::default())
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::QuotaExceeded);
+    assert_eq!(TcpError::from_io(&e), Some(TcpError::ConnectionLimit));
+
+    // the connections already there carry on as before
+    r.feed(&from(40000, iss).build(b"hello")).unwrap();
+    assert_eq!(r.read(quad(40000), 16).unwrap(), b"hello");
+    assert_eq!(r.write(quad(40000), b"world").unwrap(), 5);
+    let sent = r.take_sent();
+    assert!(sent.iter().any(|p| parse_segment(p).2 == b"world"));
+
+    // once one goes, there's room for the peer's retry
+    r.feed(&from(40001, other).rst().build(&[])).unwrap();
+    assert_eq!(r.state(quad(40001)), None);
+    establish(&mut r, 40002);
+    assert_eq!(r.segment_stats().connection_limit, 1);
+}
+
+#[test]
+fn window_shrinks_to_fit_the_memory_budget() {
+    let mut r = listening();
+    r.set_max_buffer_memory(Some(24_000));
+    let (_, first) = establish(&mut r, 40000);
+    assert_eq!(first, WINDOW);
+    // what the first connection has promised leaves the second only the rest
+    let (_, second) = establish(&mut r, 40001);
+    assert_eq!(second as usize, 24_000 - WINDOW as usize);
+    // and the third only its minimum share
+    let (_, third) = establish(&mut r, 40002);
+    assert_eq!(third, 4096);
+    assert_eq!(r.buffer_memory(), 24_000 + 4096);
+    r.check_invariants();
+}
+
+#[test]
+fn window_reopens_as_the_budget_frees_up() {
+    let mut r = listening();
+    r.set_max_buffer_memory(Some(24_000));
+    establish(&mut r, 40000);
+    let (iss, second) = establish(&mut r, 40001);
+    assert!(second < WINDOW);
+
+    // the peer resetting the first connection gives back what it had
+    r.feed(&from(40000, 0).rst().build(&[])).unwrap();
+    assert_eq!(r.state(quad(40000)), None);
+    assert_eq!(r.buffer_memory(), second as usize);
+
+    // so the ACK of the second's next segment can offer all of its buffer
+    r.feed(&from(40001, iss).build(&[7; 100])).unwrap();
+    assert_eq!(last_window(&mut r), WINDOW - 100);
+    r.check_invariants();
+}
+
+#[test]
+fn writes_take_only_what_the_budget_has_room_for() {
+    let mut r = listening();
+    r.set_max_buffer_memory(Some(24_000));
+    establish(&mut r, 40000);
+    let (iss, _) = establish(&mut r, 40001);
+
+    // the windows have the budget between them, so a write gets only the minimum share
+    assert_eq!(r.write(quad(40001), &[1; 32 * 1024]).unwrap(), 4096);
+    assert_eq!(r.write(quad(40001), &[1; 1024]).unwrap(), 0);
+
+    // which the peer's ACK frees up again
+    r.feed(&from(40001, iss + 4096).build(&[])).unwrap();
+    assert_eq!(r.write(quad(40001), &[1; 32 * 1024]).unwrap(), 4096);
+    r.check_invariants();
+}
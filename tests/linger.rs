@@ -0,0 +1,88 @@
+//! The linger timeout: a close the peer doesn't go along with ends in a RST once it's taken
+//! too long. Driven through `Replay`, so no device needed.
+
+use std::io;
+use std::time::Duration;
+
+use common::{PEER_ISS, QUAD, establish, fin, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// Let `secs` seconds go by a second at a time, and return how long it was until we sent a
+/// RST, if we did, checking that nothing but our FIN went out before it.
+fn rst_after(r: &mut Replay, secs: u64) -> Option<u64> {
+    for t in 1..=secs {
+        r.advance(Duration::from_secs(1)).unwrap();
+        for p in r.take_sent() {
+            let (_, tcph, _) = parse_segment(&p);
+            if tcph.rst() {
+                return Some(t);
+            }
+            assert!(tcph.fin(), "sent something other than our FIN");
+        }
+    }
+    None
+}
+
+#[test]
+fn unacked_fin_is_reset_once_the_linger_expires() {
+    let (mut r, _) = establish(ConnectionConfig::default());
+    r.set_linger(QUAD, Some(Duration::from_secs(10)));
+    r.close(QUAD).unwrap();
+    assert!(parse_segment(&r.take_sent()[0]).1.fin());
+    assert_eq!(rst_after(&mut r, 30), Some(10));
+    assert_eq!(r.state(QUAD), None);
+    assert_eq!(r.error(QUAD), Some(io::ErrorKind::TimedOut));
+}
+
+#[test]
+fn default_linger_is_two_msl() {
+    let (mut r, _) = establish(ConnectionConfig::default());
+    r.close(QUAD).unwrap();
+    assert_eq!(rst_after(&mut r, 90), Some(60));
+}
+
+#[test]
+fn peer_that_never_sends_its_fin_is_reset_too() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.set_linger(QUAD, Some(Duration::from_secs(10)));
+    r.close(QUAD).unwrap();
+    r.take_sent();
+    r.feed(&segment(PEER_ISS + 1, Some(iss + 1), &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::FinWait2));
+    assert_eq!(rst_after(&mut r, 30), Some(10));
+    assert_eq!(r.error(QUAD), Some(io::ErrorKind::TimedOut));
+}
+
+#[test]
+fn close_that_completes_in_time_is_not_reset() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.set_linger(QUAD, Some(Duration::from_secs(10)));
+    r.close(QUAD).unwrap();
+    r.take_sent();
+    r.feed(&fin(PEER_ISS + 1, iss + 1, &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    r.take_sent();
+    assert_eq!(rst_after(&mut r, 30), None);
+    assert_eq!(r.error(QUAD), None);
+}
+
+#[test]
+fn zero_linger_resets_right_away() {
+    let (mut r, _) = establish(ConnectionConfig::default());
+    r.set_linger(QUAD, Some(Duration::ZERO));
+    r.close(QUAD).unwrap();
+    assert!(r.take_sent().iter().any(|p| parse_segment(p).1.rst()));
+    assert_eq!(r.state(QUAD), None);
+}
+
+#[test]
+fn no_linger_keeps_closing() {
+    let (mut r, _) = establish(ConnectionConfig::default());
+    r.set_linger(QUAD, None);
+    r.close(QUAD).unwrap();
+    assert_eq!(rst_after(&mut r, 90), None);
+    assert_eq!(r.state(QUAD), Some(State::FinWait1));
+}
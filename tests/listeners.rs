@@ -0,0 +1,112 @@
+//! Listeners going away while connections to them are still coming in, run through `Replay`
+//! and `MockNic`.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, Segment, handshake};
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Interface, Quad, State};
+
+mod common;
+
+/// Wait for the packet loop to have sent `n` packets, and take them.
+fn wait_sent(nic: &MockNic, n: usize) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while nic.sent_len() < n {
+        assert!(Instant::now() < deadline, "sent {} of {n}", nic.sent_len());
+        thread::sleep(Duration::from_millis(1));
+    }
+    nic.take_sent()
+}
+
+#[test]
+fn unbinding_resets_half_open_connections() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    r.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+    let iss = parse_segment(&r.take_sent()[0]).1.sequence_number();
+
+    r.unbind(80).unwrap();
+    assert_eq!(r.state(QUAD), None);
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.rst() && !tcph.syn());
+    assert_eq!(tcph.sequence_number(), iss.wrapping_add(1));
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 1);
+}
+
+#[test]
+fn unbinding_leaves_other_listeners_alone() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    r.listen(81, ConnectionConfig::default());
+    let other = Quad {
+        dst: (QUAD.dst.0, 81),
+        ..QUAD
+    };
+    handshake(&mut r, Segment::syn_at(PEER_ISS).on(other));
+    r.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+    r.take_sent();
+
+    r.unbind(80).unwrap();
+    assert_eq!(r.state(QUAD), None);
+    assert_eq!(r.quads(), [other]);
+    // the RST was for the half-open connection only
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(parse_segment(&sent[0]).1.destination_port(), 40000);
+    assert_eq!(parse_segment(&sent[0]).1.source_port(), 80);
+}
+
+#[test]
+fn dropping_a_listener_resets_what_it_never_accepted() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+
+    // two connections through their handshakes, only the first of them accepted
+    let mut isss = Vec::new();
+    let mut first = None;
+    for port in [40000, 40001] {
+        let quad = Quad {
+            src: (PEER.into(), port),
+            ..QUAD
+        };
+        let syn = Segment::syn_at(PEER_ISS).on(quad);
+        nic.inject(&syn.build(&[]));
+        let iss = parse_segment(&wait_sent(&nic, 1)[0]).1.sequence_number();
+        nic.inject(&syn.next(iss.wrapping_add(1)).build(&[]));
+        isss.push(iss);
+        if port == 40000 {
+            first = Some(l.accept().unwrap());
+        }
+    }
+    // the second is established, waiting to be accepted, before the listener goes
+    let second = Quad {
+        src: (PEER.into(), 40001),
+        ..QUAD
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !iface
+        .connections()
+        .unwrap()
+        .any(|(q, info)| q == second && info.state == State::Estab)
+    {
+        assert!(Instant::now() < deadline, "second handshake never finished");
+        thread::sleep(Duration::from_millis(1));
+    }
+    drop(l);
+
+    // the second one is reset; the first belongs to its stream, which has nothing to say
+    let sent = wait_sent(&nic, 1);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(nic.sent_len(), 0);
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.rst());
+    assert_eq!(tcph.destination_port(), 40001);
+    assert_eq!(tcph.sequence_number(), isss[1].wrapping_add(1));
+    assert_eq!(first.unwrap().quad(), QUAD);
+}
@@ -0,0 +1,150 @@
+//! An interface with more than one address of its own: listeners on the same port at each
+//! address keep their connections apart, a wildcard listener takes what no specific one does,
+//! segments for addresses that aren't ours are dropped, and connections we open go out from the
+//! address asked for or the primary one. Driven through `Replay`, so no device needed.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use common::{PEER, PEER_ISS, QUAD, Segment, handshake};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, TcpError};
+
+mod common;
+
+const FIRST: Ipv4Addr = common::LOCAL;
+const SECOND: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 3);
+const STRANGER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 4);
+
+fn quad(local: Ipv4Addr) -> Quad {
+    Quad {
+        dst: (IpAddr::V4(local), 80),
+        ..QUAD
+    }
+}
+
+/// From the peer to `local`: an ACK of `ack` carrying `data`, or a SYN without one.
+fn segment(local: Ipv4Addr, seq: u32, ack: Option<u32>, data: &[u8]) -> Vec<u8> {
+    let segment = Segment::new(seq).on(quad(local));
+    match ack {
+        Some(ack) => segment.ack(ack).build(data),
+        None => segment.syn().build(data),
+    }
+}
+
+/// Complete a handshake from the peer to `local`, returning our next sequence number and the
+/// window our SYN-ACK offered.
+fn establish(r: &mut Replay, local: Ipv4Addr) -> (u32, u16) {
+    let (iss, synack) = handshake(r, Segment::syn_at(PEER_ISS).on(quad(local)));
+    (iss, parse_segment(&synack).1.window_size())
+}
+
+/// Both addresses ours, with a listener on port 80 at each, told apart by their windows.
+fn two_listeners() -> Replay {
+    let mut r = Replay::new(FIRST);
+    r.add_address(SECOND);
+    let config = ConnectionConfig::default();
+    r.bind_addr(FIRST.into(), 80, config.clone().recv_window(1000))
+        .unwrap();
+    r.bind_addr(SECOND.into(), 80, config.recv_window(2000))
+        .unwrap();
+    r
+}
+
+#[test]
+fn same_port_on_two_addresses_keeps_connections_apart() {
+    let mut r = two_listeners();
+    let (first, wnd) = establish(&mut r, FIRST);
+    assert_eq!(wnd, 1000);
+    let (second, wnd) = establish(&mut r, SECOND);
+    assert_eq!(wnd, 2000);
+
+    // same peer address and port both times, so only our address tells them apart
+    r.feed(&segment(FIRST, PEER_ISS + 1, Some(first), b"to first"))
+        .unwrap();
+    r.feed(&segment(SECOND, PEER_ISS + 1, Some(second), b"to second"))
+        .unwrap();
+    assert_eq!(r.read(quad(FIRST), 100).unwrap(), b"to first");
+    assert_eq!(r.read(quad(SECOND), 100).unwrap(), b"to second");
+
+    r.write(quad(SECOND), b"from second").unwrap();
+    let sent = r.take_sent();
+    let (iph, tcph, payload) = parse_segment(sent.last().expect("nothing sent"));
+    assert_eq!(iph.source_addr(), SECOND);
+    assert_eq!(tcph.sequence_number(), second);
+    assert_eq!(payload, b"from second");
+    r.check_invariants();
+}
+
+#[test]
+fn segments_for_other_addresses_are_dropped() {
+    let mut r = two_listeners();
+    r.feed(&segment(STRANGER, PEER_ISS, None, &[])).unwrap();
+    assert!(
+        r.take_sent().is_empty(),
+        "answered for someone else's address"
+    );
+    assert_eq!(r.state(quad(STRANGER)), None);
+    assert_eq!(r.segment_stats().not_ours, 1);
+
+    // nor can anything be bound or connected there
+    let e = r
+        .bind_addr(STRANGER.into(), 80, ConnectionConfig::default())
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AddrNotAvailable);
+    let e = r
+        .connect_from(
+            STRANGER.into(),
+            (PEER.into(), 80),
+            ConnectionConfig::default(),
+        )
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AddrNotAvailable);
+}
+
+#[test]
+fn wildcard_listener_takes_what_a_specific_one_does_not() {
+    let mut r = Replay::new(FIRST);
+    r.add_address(SECOND);
+    let config = ConnectionConfig::default();
+    r.bind(80, config.clone().recv_window(1000)).unwrap();
+    r.bind_addr(SECOND.into(), 80, config.clone().recv_window(2000))
+        .unwrap();
+    assert_eq!(establish(&mut r, FIRST).1, 1000);
+    assert_eq!(establish(&mut r, SECOND).1, 2000);
+
+    // the port is taken at each, wildcard and specific alike
+    let e = r.bind(80, config.clone()).unwrap_err();
+    assert_eq!(TcpError::from_io(&e), Some(TcpError::PortInUse(80)));
+    let e = r.bind_addr(SECOND.into(), 80, config).unwrap_err();
+    assert_eq!(TcpError::from_io(&e), Some(TcpError::PortInUse(80)));
+}
+
+#[test]
+fn connections_go_out_from_the_address_asked_for() {
+    let mut r = two_listeners();
+    let unspecified = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+    let config = ConnectionConfig::default();
+
+    let q = r
+        .connect_from(unspecified, (PEER.into(), 80), config.clone())
+        .unwrap();
+    assert_eq!(q.dst.0, FIRST, "didn't use the primary address");
+    let q = r
+        .connect_from(SECOND.into(), (PEER.into(), 80), config.clone())
+        .unwrap();
+    assert_eq!(q.dst.0, SECOND);
+    let sent = r.take_sent();
+    assert_eq!(parse_segment(&sent[0]).0.source_addr(), FIRST);
+    assert_eq!(parse_segment(&sent[1]).0.source_addr(), SECOND);
+
+    // there's no IPv6 address to stand in for `::`
+    let e = r
+        .connect_from(
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ("fe80::1".parse().unwrap(), 80),
+            config,
+        )
+        .unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AddrNotAvailable);
+}
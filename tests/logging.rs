@@ -0,0 +1,105 @@
+//! What the stack logs through `tracing`, caught by a subscriber that keeps every event along
+//! with the fields of the span it was in. Driven through `Replay`, so it all happens on the
+//! test's own thread, where the subscriber is installed.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use common::{LOCAL, PEER_ISS, Segment, handshake};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use trust::ConnectionConfig;
+use trust::testing::Replay;
+
+mod common;
+
+type Fields = HashMap<&'static str, String>;
+
+/// The fields of an event or span, each as it would be printed.
+#[derive(Default)]
+struct Fieldset(Fields);
+
+impl Visit for Fieldset {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+/// Every event, with its fields and those of the span it was in, if any.
+#[derive(Default)]
+struct Capture {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, Fields>>,
+    entered: Mutex<Vec<u64>>,
+    events: Mutex<Vec<(Fields, Option<Fields>)>>,
+}
+
+impl Subscriber for &'static Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut fields = Fieldset::default();
+        span.record(&mut fields);
+        fields.0.insert("name", span.metadata().name().to_owned());
+        self.spans.lock().unwrap().insert(id, fields.0);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fieldset::default();
+        event.record(&mut fields);
+        let span = self.entered.lock().unwrap().last().copied();
+        let span = span.map(|id| self.spans.lock().unwrap()[&id].clone());
+        self.events.lock().unwrap().push((fields.0, span));
+    }
+
+    fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, _: &Id) {
+        self.entered.lock().unwrap().pop();
+    }
+}
+
+#[test]
+fn handshake_logs_its_transitions_in_the_connection_span() {
+    let capture: &'static Capture = Box::leak(Box::default());
+    tracing::subscriber::with_default(capture, || {
+        let mut r = Replay::new(LOCAL);
+        r.listen(80, ConnectionConfig::default());
+        handshake(&mut r, Segment::syn_at(PEER_ISS));
+    });
+
+    let events = capture.events.lock().unwrap();
+    let transitions: Vec<_> = events
+        .iter()
+        .filter(|(fields, _)| fields["message"] == "state change")
+        .collect();
+    let states: Vec<_> = transitions
+        .iter()
+        .map(|(fields, _)| (&*fields["from"], &*fields["to"]))
+        .collect();
+    assert_eq!(states, [("SynRcvd", "Estab")], "{events:?}");
+    let accepted = events
+        .iter()
+        .any(|(fields, _)| fields["message"] == "accepting connection" && fields["irs"] == "100");
+    assert!(accepted, "{events:?}");
+    // each inside the span for the connection it's about
+    for (_, span) in &transitions {
+        let span = span.as_ref().expect("transition logged outside any span");
+        assert_eq!(span["name"], "conn");
+        assert_eq!(span["src"], "10.0.0.1:40000");
+        assert_eq!(span["dst"], "10.0.0.2:80");
+    }
+}
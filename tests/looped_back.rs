@@ -0,0 +1,66 @@
+//! Our own segments handed back to us, as some tun setups do: each is dropped and counted,
+//! leaving the connection as it was, rather than taken as the peer's. Driven through
+//! `Replay`.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, State};
+
+mod common;
+
+/// A connection established over `QUAD`, and the SYN-ACK that established it.
+fn establish() -> (Replay, u32, Vec<u8>) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let (iss, synack) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+    (r, iss, synack)
+}
+
+#[test]
+fn own_segments_change_nothing() {
+    let (mut r, iss, synack) = establish();
+    r.write(QUAD, b"hello").unwrap();
+    let data = r.take_sent().pop().expect("no data");
+    let before = r.snapshot(QUAD).unwrap();
+
+    for p in [&synack, &data] {
+        r.feed(p).unwrap();
+        assert!(r.take_sent().is_empty());
+        assert_eq!(r.snapshot(QUAD).unwrap(), before);
+    }
+    assert_eq!(r.segment_stats().looped_back, 2);
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+
+    // and the connection carries on as if they'd never come back
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(5)), b"world"))
+        .unwrap();
+    let sent = r.take_sent();
+    let tcph = parse_segment(&sent[0]).1;
+    assert_eq!(tcph.sequence_number(), iss.wrapping_add(5));
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 6);
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"world");
+    r.check_invariants();
+}
+
+#[test]
+fn syn_from_ourselves_makes_no_connection() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let ourselves = Quad {
+        src: QUAD.dst,
+        dst: QUAD.dst,
+    };
+    r.feed(&Segment::syn_at(PEER_ISS).on(ourselves).build(&[]))
+        .unwrap();
+    assert!(r.take_sent().is_empty());
+    assert!(r.quads().is_empty());
+    assert_eq!(r.segment_stats().looped_back, 1);
+}
+
+#[test]
+fn peer_segments_are_not_counted() {
+    let (mut r, iss, _) = establish();
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"hi")).unwrap();
+    assert_eq!(r.segment_stats().looped_back, 0);
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+}
@@ -0,0 +1,170 @@
+//! RFC 2385 signatures, between two of our stacks driven through `Replay`s with the packets
+//! carried across by hand: with the same key at both ends everything is signed and gets
+//! through, and with a different key, no key, or a segment changed on the way, nothing does.
+#![cfg(feature = "tcp-md5")]
+
+use std::time::Duration;
+
+use common::{LOCAL, PEER, PEER_ISS, Segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, State};
+
+mod common;
+
+/// The kind of the MD5 signature option, and its length.
+const MD5SIG: [u8; 2] = [19, 18];
+
+/// A client at `PEER` connected to a server listening on `LOCAL`'s port 80, each signing with
+/// its key if it has one. Returns both ends, and the client's quad.
+fn pair(client_key: Option<&str>, server_key: Option<&str>) -> (Replay, Replay, Quad) {
+    let mut server = Replay::new(LOCAL);
+    let mut config = ConnectionConfig::default();
+    if let Some(key) = server_key {
+        config = config.md5_key(PEER.into(), key);
+    }
+    server.listen(80, config);
+
+    let mut client = Replay::new(PEER);
+    let mut config = ConnectionConfig::default();
+    if let Some(key) = client_key {
+        config = config.md5_key(LOCAL.into(), key);
+    }
+    let quad = client.connect_to((LOCAL.into(), 80), config).unwrap();
+    shuttle(&mut client, &mut server);
+    (client, server, quad)
+}
+
+/// Carry packets back and forth until neither end has anything more to send, checking that
+/// each of them is signed if `signed`. Returns how many were carried.
+fn shuttle_checked(a: &mut Replay, b: &mut Replay, signed: Option<bool>) -> usize {
+    let mut carried = 0;
+    loop {
+        let (to_b, to_a) = (a.take_sent(), b.take_sent());
+        if to_b.is_empty() && to_a.is_empty() {
+            return carried;
+        }
+        for p in &to_b {
+            if let Some(signed) = signed {
+                assert_eq!(is_signed(p), signed);
+            }
+            b.feed(p).unwrap();
+        }
+        for p in &to_a {
+            if let Some(signed) = signed {
+                assert_eq!(is_signed(p), signed);
+            }
+            a.feed(p).unwrap();
+        }
+        carried += to_a.len() + to_b.len();
+    }
+}
+
+/// `shuttle_checked`, without looking at what it carries.
+fn shuttle(a: &mut Replay, b: &mut Replay) -> usize {
+    shuttle_checked(a, b, None)
+}
+
+/// Whether `packet`'s TCP header carries a signature option.
+fn is_signed(packet: &[u8]) -> bool {
+    let options = parse_segment(packet).1.options().to_vec();
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            0 => return false,
+            1 => i += 1,
+            _ => {
+                if options[i..].starts_with(&MD5SIG) {
+                    return true;
+                }
+                i += options[i + 1].max(2) as usize;
+            }
+        }
+    }
+    false
+}
+
+/// The client's quad as the server sees it.
+fn reversed(quad: Quad) -> Quad {
+    Quad {
+        src: quad.dst,
+        dst: quad.src,
+    }
+}
+
+#[test]
+fn matching_keys_talk() {
+    let mut server = Replay::new(LOCAL);
+    server.listen(80, ConnectionConfig::default().md5_key(PEER.into(), "bgp"));
+    let mut client = Replay::new(PEER);
+    let config = ConnectionConfig::default().md5_key(LOCAL.into(), "bgp");
+    let quad = client.connect_to((LOCAL.into(), 80), config).unwrap();
+    assert!(shuttle_checked(&mut client, &mut server, Some(true)) >= 2);
+    assert_eq!(client.state(quad), Some(State::Estab));
+    let theirs = reversed(quad);
+    assert_eq!(server.accept(), Some(theirs));
+
+    client.write(quad, b"OPEN").unwrap();
+    server.write(theirs, b"KEEPALIVE").unwrap();
+    shuttle_checked(&mut client, &mut server, Some(true));
+    assert_eq!(server.read(theirs, 100).unwrap(), b"OPEN");
+    assert_eq!(client.read(quad, 100).unwrap(), b"KEEPALIVE");
+}
+
+#[test]
+fn mismatched_keys_never_connect() {
+    let (client, mut server, quad) = pair(Some("bgp"), Some("not bgp"));
+    assert_eq!(client.state(quad), Some(State::SynSent));
+    assert_eq!(server.accept(), None);
+    assert!(server.quads().is_empty());
+}
+
+#[test]
+fn unsigned_syn_is_dropped_by_a_keyed_listener() {
+    let (client, mut server, quad) = pair(None, Some("bgp"));
+    assert_eq!(client.state(quad), Some(State::SynSent));
+    assert_eq!(server.accept(), None);
+    assert!(server.quads().is_empty());
+
+    // the same from a hand-built SYN
+    server.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+    assert!(server.take_sent().is_empty());
+    assert!(server.quads().is_empty());
+}
+
+#[test]
+fn unsigned_rst_is_ignored() {
+    let (mut client, mut server, quad) = pair(Some("bgp"), Some("bgp"));
+    let theirs = reversed(quad);
+    assert_eq!(server.state(theirs), Some(State::Estab));
+
+    // the classic attack on a BGP session: a RST at exactly the right sequence number,
+    // unsigned since the attacker doesn't have the key
+    client.write(quad, b"x").unwrap();
+    let sent = client.take_sent();
+    let seq = parse_segment(&sent[0]).1.sequence_number();
+    let ack = parse_segment(&sent[0]).1.acknowledgment_number();
+    let spoofed = Segment::new(seq).ack(ack).rst().on(theirs).build(&[]);
+    server.feed(&spoofed).unwrap();
+    assert_eq!(server.state(theirs), Some(State::Estab));
+}
+
+#[test]
+fn tampered_segment_is_dropped() {
+    let (mut client, mut server, quad) = pair(Some("bgp"), Some("bgp"));
+    let theirs = reversed(quad);
+    client.write(quad, b"ROUTE 10.0.0.0/8").unwrap();
+    let mut p = client.take_sent().pop().unwrap();
+    // change the payload without breaking the checksum: one 16-bit word up by 256, the next
+    // down by as much
+    let n = p.len();
+    p[n - 4] += 1;
+    p[n - 2] -= 1;
+    server.feed(&p).unwrap();
+    assert_eq!(server.segment_stats().bad_checksums, 0);
+    assert!(server.read(theirs, 100).unwrap().is_empty());
+
+    // and once it's resent as it was, it's taken
+    client.advance(Duration::from_secs(1)).unwrap();
+    shuttle(&mut client, &mut server);
+    assert_eq!(server.read(theirs, 100).unwrap(), b"ROUTE 10.0.0.0/8");
+}
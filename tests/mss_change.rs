@@ -0,0 +1,172 @@
+//! Path MTU discovery: re-segmenting what's in flight when an ICMP message brings the MSS down
+//! mid-connection, sending smaller from then on, and starting new connections to the same host
+//! small too, for as long as that's remembered. Run through `Replay`.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, Segment, handshake};
+use etherparse::{IpTrafficClass, Ipv4Header, TcpOptionElement};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad};
+
+mod common;
+
+const ROUTER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 254);
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A fragmentation-needed message from a router on the way, saying `packet` (one of ours)
+/// didn't fit through a link with an MTU of `mtu`.
+fn frag_needed(packet: &[u8], mtu: u16) -> Vec<u8> {
+    let mut icmp = vec![3, 4, 0, 0, 0, 0];
+    icmp.extend_from_slice(&mtu.to_be_bytes());
+    // the IP header and the first 8 bytes of the TCP header
+    icmp.extend_from_slice(&packet[..28]);
+    let sum = checksum(&icmp);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+    let mut iph = Ipv4Header::new(
+        icmp.len() as u16,
+        64,
+        IpTrafficClass::Icmp,
+        ROUTER.octets(),
+        LOCAL.octets(),
+    );
+    iph.header_checksum = iph.calc_header_checksum().unwrap();
+    let mut p = Vec::new();
+    iph.write(&mut p).unwrap();
+    p.extend_from_slice(&icmp);
+    p
+}
+
+/// An established connection whose peer offered an MSS of 1460, and our next sequence number.
+fn establish() -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    (r, iss)
+}
+
+/// The sequence number and payload size of each of `packets`, checking that none of them is
+/// bigger than `mtu`.
+fn segments(packets: &[Vec<u8>], mtu: usize) -> Vec<(u32, usize)> {
+    packets
+        .iter()
+        .map(|p| {
+            assert!(
+                p.len() <= mtu,
+                "{}-byte packet over a {mtu}-byte MTU",
+                p.len()
+            );
+            let (_, tcph, data) = parse_segment(p);
+            (tcph.sequence_number(), data.len())
+        })
+        .collect()
+}
+
+#[test]
+fn retransmissions_use_the_lowered_mss() {
+    let (mut r, iss) = establish();
+    r.write(QUAD, &[7; 4000]).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(
+        segments(&sent, 1500),
+        [
+            (iss, 1460),
+            (iss.wrapping_add(1460), 1460),
+            (iss.wrapping_add(2920), 1080)
+        ]
+    );
+
+    // the first of them didn't fit through a 1000-byte link, so everything in flight is resent
+    // from SND.UNA in 960-byte segments right away
+    r.feed(&frag_needed(&sent[0], 1000)).unwrap();
+    assert_eq!(r.icmp_stats().mtu_reductions, 1);
+    let resent = segments(&r.take_sent(), 1000);
+    assert_eq!(resent.first(), Some(&(iss, 960)));
+    assert!(resent.iter().all(|&(_, len)| len <= 960));
+    let mut seq = iss;
+    for &(s, len) in &resent {
+        assert_eq!(s, seq, "resent segments aren't back to back");
+        seq = seq.wrapping_add(len as u32);
+    }
+
+    // and if those are lost too, the retransmission timeout resends at the new size as well
+    r.advance(Duration::from_secs(1)).unwrap();
+    let again = segments(&r.take_sent(), 1000);
+    assert_eq!(again.first(), Some(&(iss, 960)));
+    r.check_invariants();
+}
+
+#[test]
+fn new_data_goes_out_under_the_bottleneck() {
+    let (mut r, iss) = establish();
+    r.write(QUAD, &[7; 2920]).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(segments(&sent, 1500).len(), 2);
+    assert!(sent.iter().all(|p| parse_segment(p).0.dont_fragment()));
+
+    // a 1400-byte link on the way: what's in flight goes again to fit, and so does everything
+    // written after it
+    r.feed(&frag_needed(&sent[0], 1400)).unwrap();
+    r.take_sent();
+    let ack = Segment::new(PEER_ISS + 1).ack(iss.wrapping_add(2920));
+    r.feed(&ack.build(&[])).unwrap();
+    r.write(QUAD, &[7; 3000]).unwrap();
+    let sent = r.take_sent();
+    let lens: Vec<_> = segments(&sent, 1400).iter().map(|&(_, len)| len).collect();
+    assert_eq!(lens, [1360, 1360, 280]);
+    // still with DF, to find out if the path shrinks any further
+    assert!(sent.iter().all(|p| parse_segment(p).0.dont_fragment()));
+}
+
+/// The MSS option on `packet`, if it has one.
+fn mss(packet: &[u8]) -> Option<u16> {
+    parse_segment(packet)
+        .1
+        .options_iterator()
+        .find_map(|o| match o {
+            Ok(TcpOptionElement::MaximumSegmentSize(mss)) => Some(mss),
+            _ => None,
+        })
+}
+
+#[test]
+fn new_connections_to_the_host_start_small_until_it_expires() {
+    let (mut r, _) = establish();
+    r.write(QUAD, &[7; 1460]).unwrap();
+    let sent = r.take_sent();
+    r.feed(&frag_needed(&sent[0], 1400)).unwrap();
+
+    // another connection from the same host, which offers the same big MSS, but gets one that
+    // fits the path back, and sends no bigger than that
+    let on_port = |port| Quad {
+        src: (IpAddr::V4(PEER), port),
+        ..QUAD
+    };
+    let syn = Segment::syn_at(PEER_ISS).mss(1460).on(on_port(40001));
+    let (_, synack) = handshake(&mut r, syn);
+    assert_eq!(mss(&synack), Some(1360));
+    r.write(on_port(40001), &[7; 2000]).unwrap();
+    let lens: Vec<_> = segments(&r.take_sent(), 1400)
+        .iter()
+        .map(|&(_, len)| len)
+        .collect();
+    assert_eq!(lens, [1360, 640]);
+
+    // ten minutes on, the path's forgotten, and the next connection starts out full-sized
+    r.advance(Duration::from_secs(601)).unwrap();
+    r.take_sent();
+    let syn = Segment::syn_at(PEER_ISS).mss(1460).on(on_port(40002));
+    let (_, synack) = handshake(&mut r, syn);
+    assert_eq!(mss(&synack), Some(1460));
+}
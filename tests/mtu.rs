@@ -0,0 +1,68 @@
+//! The MTU a connection sends under: the NIC's, or a smaller one from the config. Nothing goes
+//! out bigger than it, and the MSS we advertise is what fits in it.
+
+use std::io::Write;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, wait_sent};
+use etherparse::{Ipv4HeaderSlice, TcpOptionElement};
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Interface};
+
+mod common;
+
+/// The MSS option on `packet`, if it has one.
+fn mss(packet: &[u8]) -> Option<u16> {
+    parse_segment(packet)
+        .1
+        .options_iterator()
+        .find_map(|o| match o {
+            Ok(TcpOptionElement::MaximumSegmentSize(mss)) => Some(mss),
+            _ => None,
+        })
+}
+
+/// The IP total length of each of `packets`.
+fn lengths(packets: &[Vec<u8>]) -> Vec<usize> {
+    packets
+        .iter()
+        .map(|p| Ipv4HeaderSlice::from_slice(p).unwrap().total_len() as usize)
+        .collect()
+}
+
+#[test]
+fn config_mtu_caps_every_packet() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().mtu(600));
+    // the peer could take far bigger segments than that
+    let (_, synack) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    assert_eq!(mss(&synack), Some(560));
+
+    r.write(QUAD, &[7; 5000]).unwrap();
+    let sent = r.take_sent();
+    assert!(sent.len() > 1);
+    let lengths = lengths(&sent);
+    assert!(lengths.iter().all(|&n| n <= 600), "{lengths:?}");
+    // and full-sized ones are as big as it lets them be
+    assert_eq!(lengths[0], 600);
+}
+
+#[test]
+fn nic_mtu_caps_every_packet() {
+    let nic = MockNic::with_mtu(600);
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let syn = Segment::syn_at(PEER_ISS).mss(1460);
+    nic.inject(&syn.build(&[]));
+    let synack = wait_sent(&nic, 1).remove(0);
+    assert_eq!(mss(&synack), Some(560));
+    let iss = parse_segment(&synack).1.sequence_number().wrapping_add(1);
+    nic.inject(&syn.next(iss).build(&[]));
+
+    let mut s = l.accept().unwrap();
+    s.write_all(&[7; 5000]).unwrap();
+    // nine segments, all of which the initial window lets out at once
+    let sent = wait_sent(&nic, 9);
+    let lengths = lengths(&sent);
+    assert!(lengths.iter().all(|&n| n <= 600), "{lengths:?}");
+    assert_eq!(lengths.iter().map(|n| n - 40).sum::<usize>(), 5000);
+}
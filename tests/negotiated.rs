@@ -0,0 +1,50 @@
+//! What the peer's SYN negotiates, as `info` reports it once the handshake is done: the MSS
+//! it offered, or the default if it didn't, with an offer of zero taken as no offer and
+//! anything tiny brought up to a floor. Driven through `Replay`.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake};
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+/// The MSS in force after a handshake opened with `syn`, and the lengths of the segments a
+/// 1000-byte write goes out in.
+fn mss_after(syn: Segment) -> (u16, Vec<usize>) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    handshake(&mut r, syn);
+    r.write(QUAD, &[0; 1000]).unwrap();
+    let sent = r
+        .take_sent()
+        .iter()
+        .map(|p| parse_segment(p).2.len())
+        .collect();
+    (r.info(QUAD).unwrap().mss, sent)
+}
+
+#[test]
+fn offered_mss_is_used() {
+    let (mss, sent) = mss_after(Segment::syn_at(PEER_ISS).mss(400));
+    assert_eq!(mss, 400);
+    assert_eq!(sent, [400, 400, 200]);
+}
+
+#[test]
+fn no_offer_means_the_default() {
+    assert_eq!(mss_after(Segment::syn_at(PEER_ISS)).0, 536);
+}
+
+#[test]
+fn zero_is_ignored() {
+    let (mss, sent) = mss_after(Segment::syn_at(PEER_ISS).mss(0));
+    assert_eq!(mss, 536);
+    assert_eq!(sent, [536, 464]);
+}
+
+#[test]
+fn tiny_is_clamped() {
+    let (mss, sent) = mss_after(Segment::syn_at(PEER_ISS).mss(1));
+    assert_eq!(mss, 88);
+    assert!(sent.iter().all(|&n| n == 88), "sent {sent:?}");
+}
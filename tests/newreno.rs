@@ -0,0 +1,105 @@
+//! Two segments lost from one window. Reno's fast retransmit recovers the first, but the ACK
+//! that follows is only partial and ends its recovery, leaving the second loss to the timer.
+//! NewReno stays in recovery and resends the second at once. Driven through `Replay`.
+
+use std::time::Duration;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, NewReno, Reno};
+
+mod common;
+
+const MSS: u32 = 1000;
+
+/// A connection whose peer offered an MSS of `MSS`, with ten segments of data in flight, and
+/// our sequence number for the first of them.
+fn ten_in_flight(config: ConnectionConfig) -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, config);
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(MSS as u16));
+    r.write(QUAD, &[7; 10 * MSS as usize]).unwrap();
+    assert_eq!(
+        sent(&mut r, iss),
+        (0..10).map(|k| k * MSS).collect::<Vec<_>>()
+    );
+    (r, iss)
+}
+
+/// Where each data segment we sent starts, counted from `iss`.
+fn sent(r: &mut Replay, iss: u32) -> Vec<u32> {
+    r.take_sent()
+        .iter()
+        .filter_map(|p| {
+            let (_, tcph, data) = parse_segment(p);
+            (!data.is_empty()).then(|| tcph.sequence_number().wrapping_sub(iss))
+        })
+        .collect()
+}
+
+fn ack(r: &mut Replay, iss: u32, offset: u32) {
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(offset)), &[]))
+        .unwrap();
+}
+
+/// The segments at offsets 1000 and 4000 are lost. The peer ACKs the first, then sends a
+/// duplicate for each of the seven others that arrive; the third has the first hole resent.
+/// Returns what was sent from the fourth duplicate on.
+fn lose_two(r: &mut Replay, iss: u32) -> Vec<u32> {
+    ack(r, iss, MSS);
+    for _ in 0..2 {
+        ack(r, iss, MSS);
+    }
+    assert_eq!(sent(r, iss), []);
+    ack(r, iss, MSS);
+    assert_eq!(sent(r, iss), [MSS], "no fast retransmit");
+    for _ in 0..4 {
+        ack(r, iss, MSS);
+    }
+    sent(r, iss)
+}
+
+#[test]
+fn newreno_resends_the_second_hole_on_the_partial_ack() {
+    let config = ConnectionConfig::default().congestion_control(NewReno::new);
+    let (mut r, iss) = ten_in_flight(config);
+    assert_eq!(lose_two(&mut r, iss), []);
+
+    // the resent segment fills the first hole, up to the second
+    ack(&mut r, iss, 4 * MSS);
+    assert_eq!(sent(&mut r, iss), [4 * MSS]);
+    // and that fills the second, which ends recovery with everything ACKed
+    ack(&mut r, iss, 10 * MSS);
+    assert_eq!(sent(&mut r, iss), []);
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 0);
+    r.check_invariants();
+}
+
+#[test]
+fn duplicates_in_recovery_start_no_second_fast_retransmit() {
+    let config = ConnectionConfig::default().congestion_control(NewReno::new);
+    let (mut r, iss) = ten_in_flight(config);
+    lose_two(&mut r, iss);
+    ack(&mut r, iss, 4 * MSS);
+    sent(&mut r, iss);
+    // more duplicates of the partial ACK don't start another fast retransmit
+    for _ in 0..3 {
+        ack(&mut r, iss, 4 * MSS);
+    }
+    assert!(!sent(&mut r, iss).contains(&(4 * MSS)));
+}
+
+#[test]
+fn reno_leaves_the_second_hole_to_the_timer() {
+    let config = ConnectionConfig::default().congestion_control(Reno::new);
+    let (mut r, iss) = ten_in_flight(config);
+    assert_eq!(lose_two(&mut r, iss), []);
+
+    ack(&mut r, iss, 4 * MSS);
+    assert_eq!(sent(&mut r, iss), []);
+    // nothing goes until the retransmission timer, a second on
+    r.advance(Duration::from_millis(900)).unwrap();
+    assert_eq!(sent(&mut r, iss), []);
+    r.advance(Duration::from_millis(200)).unwrap();
+    assert_eq!(sent(&mut r, iss).first(), Some(&(4 * MSS)));
+}
@@ -0,0 +1,124 @@
+//! What `Interface::on_state_change` reports over a connection's life, run through `MockNic`.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use etherparse::{IpTrafficClass, Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice};
+use trust::testing::MockNic;
+use trust::{Interface, Quad, State, StateChange};
+
+const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const PEER_ISS: u32 = 1000;
+
+const QUAD: Quad = Quad {
+    src: (IpAddr::V4(PEER), 40000),
+    dst: (IpAddr::V4(LOCAL), 80),
+};
+
+/// A segment from the peer's port 40000 to our port 80 starting at `seq`, with no flags set.
+fn header(seq: u32) -> TcpHeader {
+    TcpHeader::new(40000, 80, seq, 1024)
+}
+
+fn packet(mut tcph: TcpHeader) -> Vec<u8> {
+    let mut iph = Ipv4Header::new(0, 64, IpTrafficClass::Tcp, PEER.octets(), LOCAL.octets());
+    iph.set_payload_len(tcph.header_len() as usize).unwrap();
+    tcph.checksum = tcph.calc_checksum_ipv4(&iph, &[]).unwrap();
+    let mut p = Vec::new();
+    iph.write(&mut p).unwrap();
+    tcph.write(&mut p).unwrap();
+    p
+}
+
+fn ack(seq: u32, ack: u32) -> TcpHeader {
+    let mut tcph = header(seq);
+    tcph.ack = true;
+    tcph.acknowledgment_number = ack;
+    tcph
+}
+
+/// Wait for the packet loop to send something, and return the sequence number of the last
+/// segment it sent.
+fn wait_sent(nic: &MockNic) -> u32 {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while nic.sent_len() == 0 {
+        assert!(Instant::now() < deadline, "nothing sent");
+        thread::sleep(Duration::from_millis(1));
+    }
+    let sent = nic.take_sent();
+    let last = sent.last().unwrap();
+    let iph = Ipv4HeaderSlice::from_slice(last).unwrap();
+    let tcph = TcpHeaderSlice::from_slice(&last[iph.slice().len()..]).unwrap();
+    tcph.sequence_number()
+}
+
+/// An interface reporting its state changes to the returned channel.
+fn observed() -> (MockNic, Interface, mpsc::Receiver<StateChange>) {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let (tx, rx) = mpsc::channel();
+    iface.on_state_change(move |change| tx.send(*change).unwrap());
+    (nic, iface, rx)
+}
+
+fn next(rx: &mpsc::Receiver<StateChange>) -> StateChange {
+    rx.recv_timeout(Duration::from_secs(5))
+        .expect("no state change")
+}
+
+#[test]
+fn handshake_reports_the_ack_that_completed_it() {
+    let (nic, mut iface, rx) = observed();
+    let _l = iface.bind(80).unwrap();
+    let mut syn = header(PEER_ISS);
+    syn.syn = true;
+    nic.inject(&packet(syn));
+    let iss = wait_sent(&nic);
+    nic.inject(&packet(ack(PEER_ISS + 1, iss.wrapping_add(1))));
+
+    let change = next(&rx);
+    assert_eq!(change.quad, QUAD);
+    assert_eq!((change.from, change.to), (State::SynRcvd, State::Estab));
+    let segment = change.segment.expect("no triggering segment");
+    assert_eq!(segment.seq, PEER_ISS + 1);
+    assert_eq!(segment.ack, Some(iss.wrapping_add(1)));
+    assert!(!segment.syn && !segment.fin && !segment.rst);
+    assert_eq!(segment.len, 0);
+}
+
+#[test]
+fn active_close_reports_every_transition_in_order() {
+    let (nic, mut iface, rx) = observed();
+    let mut l = iface.bind(80).unwrap();
+    let mut syn = header(PEER_ISS);
+    syn.syn = true;
+    nic.inject(&packet(syn));
+    let iss = wait_sent(&nic);
+    nic.inject(&packet(ack(PEER_ISS + 1, iss.wrapping_add(1))));
+    let mut changes = vec![next(&rx)];
+    drop(l.accept().unwrap());
+
+    // our FIN, which the application closing the stream caused
+    let fin_seq = wait_sent(&nic);
+    nic.inject(&packet(ack(PEER_ISS + 1, fin_seq.wrapping_add(1))));
+    let mut fin = ack(PEER_ISS + 1, fin_seq.wrapping_add(1));
+    fin.fin = true;
+    nic.inject(&packet(fin));
+
+    changes.extend((0..3).map(|_| next(&rx)));
+    let states: Vec<_> = changes.iter().map(|c| (c.from, c.to)).collect();
+    assert_eq!(
+        states,
+        [
+            (State::SynRcvd, State::Estab),
+            (State::Estab, State::FinWait1),
+            (State::FinWait1, State::FinWait2),
+            (State::FinWait2, State::TimeWait),
+        ]
+    );
+    assert!(changes[1].segment.is_none(), "the close came from us");
+    assert!(changes[3].segment.unwrap().fin);
+}
@@ -0,0 +1,78 @@
+//! `poll_write`: with the send queue full it's `Pending`, and the waker it was given is woken
+//! once the peer's ACKs make room. Driven through `Replay`, so no device or executor needed.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+use common::{PEER_ISS, QUAD, establish, segment};
+use trust::ConnectionConfig;
+
+mod common;
+
+/// A waker that counts how often it's been woken.
+#[derive(Default)]
+struct Count(AtomicUsize);
+
+impl Wake for Count {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn waker() -> (Arc<Count>, Waker) {
+    let count = Arc::new(Count::default());
+    (count.clone(), Waker::from(count))
+}
+
+#[test]
+fn full_queue_is_pending_until_acked() {
+    let config = ConnectionConfig::default().send_buffer(4096, 4096);
+    let (mut r, iss) = establish(config);
+    let (count, waker) = waker();
+    let mut cx = Context::from_waker(&waker);
+
+    let data = [7; 8192];
+    assert!(matches!(
+        r.poll_write(QUAD, &mut cx, &data),
+        Poll::Ready(Ok(4096))
+    ));
+    assert!(r.poll_write(QUAD, &mut cx, &data).is_pending());
+    assert_eq!(count.0.load(Ordering::Relaxed), 0);
+
+    // one segment's worth ACKed is room enough
+    r.feed(&segment(PEER_ISS + 1, Some(iss + 536), &[]))
+        .unwrap();
+    assert_eq!(count.0.load(Ordering::Relaxed), 1);
+    assert!(matches!(
+        r.poll_write(QUAD, &mut cx, &data),
+        Poll::Ready(Ok(536))
+    ));
+}
+
+#[test]
+fn woken_only_below_the_low_watermark() {
+    let config = ConnectionConfig::default().send_buffer(4096, 1024);
+    let (mut r, iss) = establish(config);
+    let (count, waker) = waker();
+    let mut cx = Context::from_waker(&waker);
+
+    let data = [7; 8192];
+    assert!(matches!(
+        r.poll_write(QUAD, &mut cx, &data),
+        Poll::Ready(Ok(4096))
+    ));
+    assert!(r.poll_write(QUAD, &mut cx, &data).is_pending());
+
+    // room, but not enough of it to be worth waking for
+    r.feed(&segment(PEER_ISS + 1, Some(iss + 536), &[]))
+        .unwrap();
+    assert_eq!(count.0.load(Ordering::Relaxed), 0);
+    r.feed(&segment(PEER_ISS + 1, Some(iss + 4096), &[]))
+        .unwrap();
+    assert_eq!(count.0.load(Ordering::Relaxed), 1);
+    // and woken the once, not again for nothing
+    r.feed(&segment(PEER_ISS + 1, Some(iss + 4096), &[]))
+        .unwrap();
+    assert_eq!(count.0.load(Ordering::Relaxed), 1);
+}
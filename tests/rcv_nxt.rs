@@ -0,0 +1,96 @@
+//! RCV.NXT moves only over what the connection takes in: a segment that's dropped, or that
+//! it can't use yet, leaves it where it was, and the data that really comes next is still
+//! taken. Driven through `Replay`.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, assert_one_ack, establish, fin, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+fn rcv_nxt(r: &Replay) -> u32 {
+    r.snapshot(QUAD).expect("no connection").rcv_nxt
+}
+
+/// Data at RCV.NXT is still taken, and ACKed.
+fn still_in_step(r: &mut Replay, iss: u32) {
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"hello")).unwrap();
+    assert_one_ack(r, PEER_ISS + 6);
+    assert_eq!(rcv_nxt(r), PEER_ISS + 6);
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"hello");
+    r.check_invariants();
+}
+
+#[test]
+fn segment_without_ack_is_ignored() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&segment(PEER_ISS + 1, None, b"bogus")).unwrap();
+    assert_eq!(rcv_nxt(&r), PEER_ISS + 1);
+    assert!(r.take_sent().is_empty());
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+    still_in_step(&mut r, iss);
+}
+
+#[test]
+fn ack_of_unsent_data_is_answered_and_dropped() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&segment(
+        PEER_ISS + 1,
+        Some(iss.wrapping_add(1000)),
+        b"bogus",
+    ))
+    .unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.ack() && !tcph.rst());
+    assert_eq!(tcph.sequence_number(), iss);
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 1);
+    assert_eq!(rcv_nxt(&r), PEER_ISS + 1);
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+    still_in_step(&mut r, iss);
+}
+
+#[test]
+fn pure_ack_from_ahead_in_the_window() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&segment(PEER_ISS + 100, Some(iss), &[])).unwrap();
+    assert_eq!(rcv_nxt(&r), PEER_ISS + 1);
+    r.take_sent();
+    still_in_step(&mut r, iss);
+}
+
+#[test]
+fn fin_out_of_order_is_left_for_later() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&fin(PEER_ISS + 6, iss, &[])).unwrap();
+    assert_eq!(rcv_nxt(&r), PEER_ISS + 1);
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    r.take_sent();
+    still_in_step(&mut r, iss);
+
+    // sent again once the data before it is in, it's taken
+    r.feed(&fin(PEER_ISS + 6, iss, &[])).unwrap();
+    assert_eq!(rcv_nxt(&r), PEER_ISS + 7);
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+}
+
+#[test]
+fn data_in_syn_rcvd_with_a_wrong_ack() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    r.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+    let synack = r.take_sent().pop().expect("no SYN-ACK");
+    let iss = parse_segment(&synack).1.sequence_number().wrapping_add(1);
+
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(5)), b"early"))
+        .unwrap();
+    assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+    assert_eq!(rcv_nxt(&r), PEER_ISS + 1);
+    r.take_sent();
+
+    // the handshake still completes, and the data comes along in step
+    r.feed(&segment(PEER_ISS + 1, Some(iss), &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    still_in_step(&mut r, iss);
+}
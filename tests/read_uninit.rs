@@ -0,0 +1,32 @@
+//! `read_uninit`: reading into memory that was never initialized, through to EOF. Driven
+//! through `Replay`, so no device needed.
+
+use common::{PEER_ISS, QUAD, establish, fin, segment};
+use trust::ConnectionConfig;
+
+mod common;
+
+#[test]
+fn reads_into_spare_capacity_until_eof() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"hello, "))
+        .unwrap();
+    r.feed(&fin(PEER_ISS + 8, iss, b"world")).unwrap();
+
+    // a few bytes at a time, so it takes more than one read to get there
+    let mut got = Vec::with_capacity(4);
+    loop {
+        if got.capacity() - got.len() < 4 {
+            got.reserve_exact(4);
+        }
+        let n = r
+            .read_uninit(QUAD, &mut got.spare_capacity_mut()[..4])
+            .unwrap();
+        if n == 0 {
+            break;
+        }
+        // SAFETY: `read_uninit` just initialized the next `n` bytes
+        unsafe { got.set_len(got.len() + n) };
+    }
+    assert_eq!(got, b"hello, world");
+}
@@ -0,0 +1,160 @@
+//! Taking in a burst of packets with `Nic::recv_batch`: the default, one `recv` at a time, and
+//! `MockNic`'s, all at once, both stop at what's waiting, and the packet loop reads through
+//! nothing else.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{LOCAL, PEER};
+use etherparse::PacketBuilder;
+use trust::testing::MockNic;
+use trust::{Interface, Nic};
+
+mod common;
+
+fn syn(port: u16) -> Vec<u8> {
+    let mut p = Vec::new();
+    PacketBuilder::ipv4(PEER.octets(), LOCAL.octets(), 64)
+        .tcp(port, 80, 100, u16::MAX)
+        .syn()
+        .write(&mut p, &[])
+        .unwrap();
+    p
+}
+
+/// Counts how packets are read, and can hide them from `poll` until they've all queued up.
+#[derive(Clone)]
+struct Counting {
+    nic: MockNic,
+    recvs: Arc<AtomicUsize>,
+    batches: Arc<AtomicUsize>,
+    held: Arc<AtomicBool>,
+}
+
+impl Counting {
+    fn new(nic: MockNic) -> Self {
+        Counting {
+            nic,
+            recvs: Arc::default(),
+            batches: Arc::default(),
+            held: Arc::default(),
+        }
+    }
+}
+
+impl Nic for Counting {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.nic.send(buf)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.recvs.fetch_add(1, Ordering::Relaxed);
+        self.nic.recv(buf)
+    }
+
+    fn recv_batch(&mut self, bufs: &mut [&mut [u8]], lens: &mut [usize]) -> io::Result<usize> {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.nic.recv_batch(bufs, lens)
+    }
+
+    fn mtu(&self) -> usize {
+        self.nic.mtu()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        if self.held.load(Ordering::Acquire) {
+            thread::sleep(timeout.min(Duration::from_millis(1)));
+            return Ok(false);
+        }
+        self.nic.poll(timeout)
+    }
+}
+
+/// A `Nic` that only does what the trait requires, so `recv_batch` is the default.
+struct Plain(MockNic);
+
+impl Nic for Plain {
+    fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf)
+    }
+
+    fn mtu(&self) -> usize {
+        self.0.mtu()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.0.poll(timeout)
+    }
+}
+
+/// Inject five packets, then read them in batches of at most three.
+fn batches(nic: &mut impl Nic, wire: &MockNic) -> Vec<Vec<Vec<u8>>> {
+    let packets: Vec<_> = (40000..40005).map(syn).collect();
+    for p in &packets {
+        wire.inject(p);
+    }
+    let mut out = Vec::new();
+    let mut read = 0;
+    while read < packets.len() {
+        let mut storage = [[0; 1500]; 3];
+        let mut bufs = storage.each_mut().map(|b| b.as_mut_slice());
+        let mut lens = [0; 3];
+        let n = nic.recv_batch(&mut bufs, &mut lens).unwrap();
+        assert!(n > 0);
+        out.push((0..n).map(|i| bufs[i][..lens[i]].to_vec()).collect());
+        read += n;
+    }
+    let all: Vec<_> = out.iter().flatten().cloned().collect();
+    assert_eq!(all, packets);
+    out
+}
+
+#[test]
+fn default_reads_what_is_waiting() {
+    let wire = MockNic::new();
+    let sizes: Vec<_> = batches(&mut Plain(wire.clone()), &wire)
+        .iter()
+        .map(Vec::len)
+        .collect();
+    assert_eq!(sizes, [3, 2]);
+}
+
+#[test]
+fn mock_takes_the_lot_at_once() {
+    let wire = MockNic::new();
+    let mut nic = Counting::new(wire.clone());
+    let sizes: Vec<_> = batches(&mut nic, &wire).iter().map(Vec::len).collect();
+    assert_eq!(sizes, [3, 2]);
+    assert_eq!(nic.recvs.load(Ordering::Relaxed), 0);
+    assert_eq!(nic.batches.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn packet_loop_reads_in_batches() {
+    let wire = MockNic::new();
+    let nic = Counting::new(wire.clone());
+    let (recvs, batches, held) = (nic.recvs.clone(), nic.batches.clone(), nic.held.clone());
+    // nothing gets to the loop until it's all there, so it has the chance to take it together
+    held.store(true, Ordering::Release);
+    let mut iface = Interface::with_nic(nic);
+    let _l = iface.bind(80).unwrap();
+    for port in 40000..40020 {
+        wire.inject(&syn(port));
+    }
+    held.store(false, Ordering::Release);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while wire.sent_len() < 20 {
+        assert!(Instant::now() < deadline, "not every SYN was answered");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(recvs.load(Ordering::Relaxed), 0);
+    assert!(batches.load(Ordering::Relaxed) < 20, "read one at a time");
+}
@@ -0,0 +1,36 @@
+//! Flow control with the receive window set by hand, through `Replay::set_recv_window`, which
+//! the `testing` feature brings in: the window we advertise shrinks as data comes in, closes
+//! when it's all there, and reopens with a window update once the application reads.
+
+use common::{PEER_ISS, QUAD, establish, segment};
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+/// The window on the one ACK we've sent since last time, which ACKs `ack`.
+fn window(r: &Replay, ack: u32) -> u16 {
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let tcph = parse_segment(&sent[0]).1;
+    assert_eq!(tcph.acknowledgment_number(), ack);
+    tcph.window_size()
+}
+
+#[test]
+fn forced_window_closes_and_reopens() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.set_recv_window(QUAD, 1000).unwrap();
+    r.take_sent();
+
+    r.feed(&segment(PEER_ISS + 1, Some(iss), &[b'x'; 600]))
+        .unwrap();
+    assert_eq!(window(&r, PEER_ISS + 601), 400);
+    r.feed(&segment(PEER_ISS + 601, Some(iss), &[b'x'; 400]))
+        .unwrap();
+    assert_eq!(window(&r, PEER_ISS + 1001), 0);
+
+    // reading some of it opens the window again, and the peer is told
+    assert_eq!(r.read(QUAD, 300).unwrap().len(), 300);
+    assert_eq!(window(&r, PEER_ISS + 1001), 300);
+}
@@ -0,0 +1,56 @@
+//! Regression tests replayed from the captures in `tests/captures`, through `Replay::run`.
+//!
+//! The captures were taken with our ISS pinned to 5000, so the peer's ACKs in them line up with
+//! a listener configured the same way.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use common::{LOCAL, QUAD};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+const ISS: u32 = 5000;
+/// the peer's, in the captures
+const PEER_ISN: u32 = 1000;
+
+fn capture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/captures")
+        .join(name)
+}
+
+/// A SYN, the same SYN again a second later as if our SYN-ACK had been lost, the ACK that
+/// finishes the handshake, and five bytes of data. Our own SYN-ACKs are in the capture too.
+#[test]
+fn duplicate_syn() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().initial_sequence_number(ISS));
+    // only the four packets addressed to us
+    assert_eq!(r.run(capture("duplicate_syn.pcap")).unwrap(), 4);
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+
+    let sent = r.take_sent();
+    let (synacks, rest) = sent.split_at(2);
+    for synack in synacks {
+        let tcph = parse_segment(synack).1;
+        assert!(tcph.syn() && tcph.ack());
+        assert_eq!(tcph.sequence_number(), ISS);
+        assert_eq!(tcph.acknowledgment_number(), PEER_ISN + 1);
+    }
+    // the same SYN-ACK both times, whatever the IP ID
+    assert_eq!(
+        parse_segment(&synacks[0]).1.slice(),
+        parse_segment(&synacks[1]).1.slice()
+    );
+    // and the data ACKed, if not straight away then once the delayed ACK goes
+    r.advance(Duration::from_millis(500)).unwrap();
+    let acked = rest
+        .iter()
+        .chain(&r.take_sent())
+        .any(|p| parse_segment(p).1.acknowledgment_number() == PEER_ISN + 6);
+    assert!(acked, "data never ACKed");
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"hello");
+}
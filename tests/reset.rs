@@ -0,0 +1,64 @@
+//! RSTs from the peer on a connection past SYN-SENT, run through `Replay`.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, establish, rst, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// A listener on port 80 with a connection over `QUAD` half-way through its handshake, and
+/// our ISS.
+fn half_open() -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    r.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+    let iss = parse_segment(&r.take_sent()[0]).1.sequence_number();
+    (r, iss)
+}
+
+#[test]
+fn reset_half_open_connection_goes_away() {
+    let (mut r, iss) = half_open();
+    assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+    r.feed(&rst(PEER_ISS + 1, iss.wrapping_add(1))).unwrap();
+
+    assert_eq!(r.state(QUAD), None);
+    assert!(r.quads().is_empty());
+    assert!(r.take_sent().is_empty(), "answered a RST");
+    assert_eq!(r.accept(), None, "queued a reset connection for accept");
+    // and the peer's late ACK of the SYN-ACK doesn't bring it back
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(1)), &[]))
+        .unwrap();
+    assert_eq!(r.state(QUAD), None);
+    assert_eq!(r.accept(), None);
+}
+
+#[test]
+fn reset_off_rcv_nxt_draws_a_challenge_ack() {
+    let (mut r, iss) = half_open();
+    r.feed(&rst(PEER_ISS + 11, iss.wrapping_add(1))).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.ack() && !tcph.rst());
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 1);
+}
+
+#[test]
+fn reset_outside_the_window_is_dropped() {
+    let (mut r, iss) = half_open();
+    r.feed(&rst(PEER_ISS.wrapping_sub(1000), iss.wrapping_add(1)))
+        .unwrap();
+    assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+    assert!(r.take_sent().is_empty());
+}
+
+#[test]
+fn reset_established_connection_goes_away() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    assert_eq!(r.accept(), Some(QUAD));
+    r.feed(&rst(PEER_ISS + 1, iss)).unwrap();
+    assert_eq!(r.state(QUAD), None);
+    assert!(r.take_sent().is_empty());
+}
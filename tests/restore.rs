@@ -0,0 +1,78 @@
+//! Picking a connection up where another stack left it: a session is run partway through
+//! `Replay`, what it's got to is taken from a snapshot as an `Established`, and the transfer
+//! carries on from there on an `Interface` that never saw the handshake, over a `MockNic`.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+
+use common::{LOCAL, PEER, PEER_ISS, QUAD, establish, segment, wait_sent};
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Established, Interface, State};
+
+mod common;
+
+/// Where a connection that's sent "hello" and had "hi" back has got to.
+fn partway() -> Established {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.write(QUAD, b"hello").unwrap();
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(5)), b"hi"))
+        .unwrap();
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"hi");
+    let snap = r.snapshot(QUAD).unwrap();
+    Established {
+        state: snap.info.state,
+        snd_una: snap.snd_una,
+        snd_wnd: snap.snd_wnd,
+        rcv_nxt: snap.rcv_nxt,
+        mss: 536,
+    }
+}
+
+#[test]
+fn transfer_carries_on_after_a_restore() {
+    let established = partway();
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let local = SocketAddr::new(IpAddr::V4(LOCAL), 80);
+    let remote = SocketAddr::new(IpAddr::V4(PEER), 40000);
+    let mut s = iface
+        .restore(local, remote, &established, ConnectionConfig::default())
+        .unwrap();
+    assert_eq!(s.info().unwrap().state, State::Estab);
+    assert!(nic.take_sent().is_empty(), "sent something on restore");
+
+    // our next byte goes where the first stack's would have
+    s.write_all(b"world").unwrap();
+    let sent = wait_sent(&nic, 1);
+    let (_, tcph, data) = parse_segment(&sent[0]);
+    assert_eq!(tcph.sequence_number(), established.snd_una);
+    assert_eq!(tcph.acknowledgment_number(), established.rcv_nxt);
+    assert_eq!(data, b"world");
+
+    // and the peer's next goes on from where we'd read up to
+    nic.inject(&segment(
+        established.rcv_nxt,
+        Some(established.snd_una.wrapping_add(5)),
+        b"there",
+    ));
+    let mut got = [0; 5];
+    s.read_exact(&mut got).unwrap();
+    assert_eq!(&got, b"there");
+    assert_eq!(s.info().unwrap().bytes_in_flight, 0);
+
+    // there's only room for the one
+    let again = iface.restore(local, remote, &established, ConnectionConfig::default());
+    assert_eq!(again.err().unwrap().kind(), io::ErrorKind::AddrInUse);
+}
+
+#[test]
+fn only_established_or_closing_can_be_restored() {
+    let mut established = partway();
+    established.state = State::SynRcvd;
+    let mut r = Replay::new(LOCAL);
+    let err = r
+        .restore(QUAD, &established, ConnectionConfig::default())
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(r.state(QUAD), None);
+}
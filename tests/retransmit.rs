@@ -0,0 +1,132 @@
+//! Retransmissions come from the send queue by sequence number, the same way the first send
+//! did: a segment resent from the middle of what's in flight, by fast retransmit, after a
+//! partial ACK or on a timeout, carries the same bytes at the same sequence numbers as the
+//! first time, across the wrap too. Driven through `Replay`.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, NewReno, State};
+
+mod common;
+
+const MSS: u32 = 1000;
+
+/// `len` bytes that don't repeat with any period a segment at the wrong offset would hide in.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+        .collect()
+}
+
+/// Ten segments in flight with NewReno from a connection starting at `iss`. Returns our
+/// sequence number for the first, and each segment's payload by sequence number as first
+/// sent.
+fn ten_in_flight(iss: u32) -> (Replay, u32, HashMap<u32, Vec<u8>>) {
+    let mut r = Replay::new(LOCAL);
+    let config = ConnectionConfig::default()
+        .initial_sequence_number(iss)
+        .congestion_control(NewReno::new);
+    r.listen(80, config);
+    let (start, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(MSS as u16));
+    r.write(QUAD, &pattern(10 * MSS as usize)).unwrap();
+    let first = sent(&mut r);
+    assert_eq!(first.len(), 10);
+    (r, start, first.into_iter().collect())
+}
+
+/// Each data segment sent since last time, by sequence number.
+fn sent(r: &mut Replay) -> Vec<(u32, Vec<u8>)> {
+    r.take_sent()
+        .iter()
+        .filter_map(|p| {
+            let (_, tcph, data) = parse_segment(p);
+            (!data.is_empty()).then(|| (tcph.sequence_number(), data.to_vec()))
+        })
+        .collect()
+}
+
+fn ack(r: &mut Replay, ack: u32) {
+    r.feed(&segment(PEER_ISS + 1, Some(ack), &[])).unwrap();
+}
+
+/// Exactly one segment was resent, starting at `seq`, and it was what first went there.
+fn assert_resent(r: &mut Replay, first: &HashMap<u32, Vec<u8>>, seq: u32) {
+    let resent = sent(r);
+    assert_eq!(resent.len(), 1, "resent {} segments", resent.len());
+    assert_eq!(resent[0].0, seq);
+    assert!(
+        resent[0].1 == first[&seq],
+        "resent different bytes at {seq}"
+    );
+}
+
+#[test]
+fn middle_segments_are_resent_as_they_were() {
+    // 2500 short of the wrap, so the holes are on both sides of it
+    for iss in [0, u32::MAX - 2500] {
+        let (mut r, start, first) = ten_in_flight(iss);
+        let at = |k: u32| start.wrapping_add(k * MSS);
+
+        // the segments at 2 and 5 are lost: three duplicates have the first resent
+        for _ in 0..4 {
+            ack(&mut r, at(2));
+        }
+        assert_resent(&mut r, &first, at(2));
+        // the ACK for it is partial, up to the second hole, which goes at once
+        ack(&mut r, at(5));
+        assert_resent(&mut r, &first, at(5));
+        ack(&mut r, at(10));
+        assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 0);
+        r.check_invariants();
+    }
+}
+
+#[test]
+fn timeout_resends_from_snd_una_as_it_was() {
+    let (mut r, start, first) = ten_in_flight(u32::MAX - 2500);
+    ack(&mut r, start.wrapping_add(3 * MSS));
+    r.advance(Duration::from_millis(1100)).unwrap();
+    assert_resent(&mut r, &first, start.wrapping_add(3 * MSS));
+    // and leaves the highest sequence number sent where the first sends took it
+    assert_eq!(
+        r.snapshot(QUAD).unwrap().snd_max,
+        start.wrapping_add(10 * MSS)
+    );
+}
+
+#[test]
+fn fin_follows_the_resent_data() {
+    let (mut r, start, first) = ten_in_flight(0);
+    r.close(QUAD).unwrap();
+    assert!(r.take_sent().is_empty(), "FIN sent on close");
+    // the FIN waits for room behind the ten segments in flight
+    ack(&mut r, start.wrapping_add(9 * MSS));
+    let after = r.take_sent();
+    let tcph = parse_segment(after.last().unwrap()).1;
+    assert!(tcph.fin());
+    assert_eq!(tcph.sequence_number(), start.wrapping_add(10 * MSS));
+
+    // the last segment times out, and goes again as it was, on its own with the window back
+    // to one segment
+    r.advance(Duration::from_millis(1100)).unwrap();
+    let resent = r.take_sent();
+    assert_eq!(resent.len(), 1);
+    let (_, tcph, data) = parse_segment(&resent[0]);
+    let seq = start.wrapping_add(9 * MSS);
+    assert_eq!(tcph.sequence_number(), seq);
+    assert!(data == first[&seq].as_slice());
+    assert!(!tcph.fin());
+
+    // and once it's ACKed, the FIN goes again right behind it
+    ack(&mut r, start.wrapping_add(10 * MSS));
+    let resent = r.take_sent();
+    assert_eq!(resent.len(), 1);
+    let (_, tcph, data) = parse_segment(&resent[0]);
+    assert!(tcph.fin() && data.is_empty());
+    assert_eq!(tcph.sequence_number(), start.wrapping_add(10 * MSS));
+    ack(&mut r, start.wrapping_add(10 * MSS + 1));
+    assert_eq!(r.state(QUAD), Some(State::FinWait2));
+}
@@ -0,0 +1,103 @@
+//! Ports and quads whose last connection is still in TIME-WAIT: a port can be bound again
+//! straight away unless the listener says otherwise, and a connection of ours can take over a
+//! quad as long as its ISS puts it clear of the old one. Driven through `Replay`, so no device
+//! needed.
+
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use common::{LOCAL, PEER, PEER_ISS, Segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, Quad, State, TcpError};
+
+mod common;
+
+/// The peer's port 80 to our `port`, as for a connection we opened.
+fn quad(port: u16) -> Quad {
+    Quad {
+        src: (IpAddr::V4(PEER), 80),
+        dst: (IpAddr::V4(LOCAL), port),
+    }
+}
+
+/// Take the connection we just opened on `quad` through the handshake, close it, and have
+/// the peer close too, leaving it in TIME-WAIT. Returns the ISS our SYN had.
+fn open_and_close(r: &mut Replay, quad: Quad) -> u32 {
+    let syn = r.take_sent().pop().expect("no SYN");
+    let iss = parse_segment(&syn).1.sequence_number();
+    let synack = Segment::syn_at(PEER_ISS).ack(iss + 1).on(quad);
+    r.feed(&synack.build(&[])).unwrap();
+    assert_eq!(r.state(quad), Some(State::Estab));
+    r.close(quad).unwrap();
+    r.feed(&synack.next(iss + 2).fin().build(&[])).unwrap();
+    assert_eq!(r.state(quad), Some(State::TimeWait));
+    r.take_sent();
+    iss
+}
+
+#[test]
+fn bind_despite_time_wait_unless_told_not_to() {
+    let mut r = Replay::new(LOCAL);
+    r.connect(quad(5000), ConnectionConfig::default()).unwrap();
+    open_and_close(&mut r, quad(5000));
+
+    let strict = ConnectionConfig::default().reuse_addr(false);
+    let e = r.bind(5000, strict.clone()).unwrap_err();
+    assert_eq!(TcpError::from_io(&e), Some(TcpError::PortInUse(5000)));
+    r.bind(5000, ConnectionConfig::default()).unwrap();
+
+    // and once the 2MSL is up, so can a listener that doesn't reuse addresses
+    let mut r = Replay::new(LOCAL);
+    r.connect(quad(5000), ConnectionConfig::default()).unwrap();
+    open_and_close(&mut r, quad(5000));
+    r.advance(Duration::from_secs(61)).unwrap();
+    r.bind(5000, strict).unwrap();
+}
+
+#[test]
+fn reconnecting_on_the_same_quad_does_not_wait_out_2msl() {
+    let mut r = Replay::new(LOCAL);
+    let mut last = None;
+    for _ in 0..50 {
+        r.connect(quad(5000), ConnectionConfig::default()).unwrap();
+        let iss = open_and_close(&mut r, quad(5000));
+        // each one starts clear of everything the one before sent
+        if let Some(last) = last {
+            assert!(iss.wrapping_sub(last + 2) < 1 << 31);
+        }
+        last = Some(iss);
+        r.advance(Duration::from_millis(1)).unwrap();
+    }
+    assert_eq!(r.quads(), vec![quad(5000)]);
+}
+
+#[test]
+fn quad_is_kept_if_the_new_iss_is_not_past_the_old_one() {
+    let config = ConnectionConfig::default().initial_sequence_number(1000);
+    let mut r = Replay::new(LOCAL);
+    r.connect(quad(5000), config.clone()).unwrap();
+    open_and_close(&mut r, quad(5000));
+
+    let e = r.connect(quad(5000), config.clone()).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::AddrInUse);
+    assert_eq!(r.state(quad(5000)), Some(State::TimeWait));
+    assert!(r.take_sent().is_empty());
+
+    // past it, though, the quad is ours again
+    let config = config.initial_sequence_number(1002);
+    r.connect(quad(5000), config).unwrap();
+    assert_eq!(r.state(quad(5000)), Some(State::SynSent));
+}
+
+#[test]
+fn rapid_connects_to_the_same_destination() {
+    let mut r = Replay::new(LOCAL);
+    for _ in 0..50 {
+        let q = r
+            .connect_to((PEER.into(), 80), ConnectionConfig::default())
+            .unwrap();
+        assert_eq!(q.src, (IpAddr::V4(PEER), 80));
+        open_and_close(&mut r, q);
+    }
+}
@@ -0,0 +1,106 @@
+//! The send buffer's low watermark: once the send queue has filled up, a writer waiting for
+//! room isn't woken until the peer has ACKed enough to drain it below the watermark. Driven
+//! through `Replay`, so no device needed.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+use common::{PEER_ISS, QUAD, establish, segment};
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+const BUFFER: usize = 64 * 1024;
+const WATERMARK: usize = 16 * 1024;
+/// how much the peer ACKs at a time
+const STEP: u32 = 4096;
+
+/// Counts how many times it's been woken.
+#[derive(Default)]
+struct Wakes(AtomicUsize);
+
+impl Wake for Wakes {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The peer's view of the connection: how far we've sent, and how far it's ACKed.
+struct Peer {
+    r: Replay,
+    sent: u32,
+    acked: u32,
+}
+
+impl Peer {
+    fn establish(config: ConnectionConfig) -> Self {
+        let (r, iss) = establish(config);
+        Peer {
+            r,
+            sent: iss,
+            acked: iss,
+        }
+    }
+
+    /// Write all that fits, until the send queue is full and `waker` is left waiting for
+    /// room. Returns how much was written.
+    fn fill(&mut self, waker: &Waker) -> usize {
+        let mut cx = Context::from_waker(waker);
+        let mut written = 0;
+        while let Poll::Ready(n) = self.r.poll_write(QUAD, &mut cx, &[0; BUFFER]) {
+            written += n.unwrap();
+        }
+        self.take_sent();
+        written
+    }
+
+    fn take_sent(&mut self) {
+        for p in self.r.take_sent() {
+            let (_, tcph, data) = parse_segment(&p);
+            let end = tcph.sequence_number().wrapping_add(data.len() as u32);
+            if end.wrapping_sub(self.sent) as i32 > 0 {
+                self.sent = end;
+            }
+        }
+    }
+
+    /// ACK another `STEP` of what we've sent.
+    fn ack(&mut self) {
+        assert!(
+            self.sent.wrapping_sub(self.acked) >= STEP,
+            "not enough sent to ACK"
+        );
+        self.acked = self.acked.wrapping_add(STEP);
+        self.r
+            .feed(&segment(PEER_ISS + 1, Some(self.acked), &[]))
+            .unwrap();
+        self.take_sent();
+    }
+}
+
+#[test]
+fn writers_are_woken_only_below_the_watermark() {
+    let mut peer = Peer::establish(ConnectionConfig::default().send_buffer(BUFFER, WATERMARK));
+    let wakes = Arc::new(Wakes::default());
+    let waker = Waker::from(wakes.clone());
+    assert_eq!(peer.fill(&waker), BUFFER);
+
+    // which of the peer's ACKs woke the writer, which fills the queue up again each time
+    let mut woken_by = Vec::new();
+    for i in 1..=40 {
+        peer.ack();
+        if wakes.0.swap(0, Ordering::Relaxed) > 0 {
+            woken_by.push(i);
+            // everything ACKed since the queue filled up can be written again, all at once
+            assert_eq!(peer.fill(&waker), 13 * STEP as usize);
+        } else {
+            // there's room, but not enough yet to be let in
+            let mut cx = Context::from_waker(&waker);
+            assert!(peer.r.poll_write(QUAD, &mut cx, &[0]).is_pending());
+        }
+    }
+    // 64 KiB less 13 ACKs of 4 KiB is the first time it's below 16 KiB
+    assert_eq!(woken_by, [13, 26, 39]);
+}
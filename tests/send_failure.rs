@@ -0,0 +1,45 @@
+//! A NIC that turns sends down for a while, under the packet loop: what didn't go out goes
+//! later, as if it had been lost, and the loop carries on.
+
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{PEER_ISS, QUAD, segment};
+use trust::testing::{MockNic, parse_segment};
+use trust::{Interface, State};
+
+mod common;
+
+/// Wait for the packet loop to have sent `n` packets, and take them.
+fn wait_sent(nic: &MockNic, n: usize) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while nic.sent_len() < n {
+        assert!(Instant::now() < deadline, "sent {} of {n}", nic.sent_len());
+        thread::sleep(Duration::from_millis(1));
+    }
+    nic.take_sent()
+}
+
+#[test]
+fn packet_loop_outlasts_failed_sends() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+
+    // the SYN-ACK can't go out, which leaves nothing behind for the peer's next SYN
+    nic.fail_sends(Some(io::ErrorKind::OutOfMemory));
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    thread::sleep(Duration::from_millis(50));
+    assert!(nic.take_sent().is_empty());
+    nic.fail_sends(None);
+
+    nic.inject(&segment(PEER_ISS, None, &[]));
+    let synack = &wait_sent(&nic, 1)[0];
+    let iss = parse_segment(synack).1.sequence_number();
+    nic.inject(&segment(PEER_ISS + 1, Some(iss.wrapping_add(1)), &[]));
+    let s = l.accept().unwrap();
+    assert_eq!(s.info().unwrap().state, State::Estab);
+    let quads: Vec<_> = iface.connections().unwrap().map(|(q, _)| q).collect();
+    assert_eq!(quads, [QUAD]);
+}
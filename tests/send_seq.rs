@@ -0,0 +1,105 @@
+//! Send-side sequence bookkeeping with many segments in flight, under writes and ACKs picked
+//! at random, run through `Replay`.
+//!
+//! The invariants, after every write, ACK and timer tick:
+//!
+//! - SND.UNA =< SND.NXT =< SND.MAX, in wrapped terms, and SND.UNA never goes back.
+//! - Every segment we send lies within what's been written, and carries the bytes written at
+//!   its sequence numbers, so no sequence number is ever used for two different bytes.
+
+use std::time::Duration;
+
+use common::{PEER_ISS, QUAD, establish, segment};
+use proptest::prelude::*;
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+/// The byte written at `offset` into the stream, distinct enough that a segment carrying the
+/// wrong sequence number for its data is caught.
+fn byte_at(offset: u32) -> u8 {
+    (offset % 251) as u8
+}
+
+/// What the peer does next.
+#[derive(Clone, Debug)]
+enum Step {
+    Write(usize),
+    /// ACK `n` sequence numbers past SND.UNA, which may be more than we've sent
+    Ack(u32),
+    /// ACK something from before SND.UNA
+    OldAck(u32),
+    /// let the retransmission timer go off
+    Timeout,
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        3 => (1usize..3000).prop_map(Step::Write),
+        3 => (0u32..4000).prop_map(Step::Ack),
+        1 => (1u32..1000).prop_map(Step::OldAck),
+        1 => Just(Step::Timeout),
+    ]
+}
+
+/// Check what went out against what was written, `iss` being the sequence number of the first
+/// byte of it.
+fn check_sent(r: &Replay, iss: u32, written: u32) {
+    for p in r.take_sent() {
+        let (_, tcph, data) = parse_segment(&p);
+        let offset = tcph.sequence_number().wrapping_sub(iss);
+        assert!(
+            offset as u64 + data.len() as u64 <= written as u64,
+            "sent {} bytes at {offset} with only {written} written",
+            data.len()
+        );
+        for (i, &b) in data.iter().enumerate() {
+            assert_eq!(
+                b,
+                byte_at(offset + i as u32),
+                "wrong byte at {}",
+                offset + i as u32
+            );
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn sequence_numbers_hold_under_random_acks(steps in prop::collection::vec(step(), 1..40)) {
+        let (mut r, iss) = establish(ConnectionConfig::default());
+        let mut written = 0u32;
+        let mut una = iss;
+        for step in steps {
+            match step {
+                Step::Write(n) => {
+                    let data: Vec<u8> = (written..written + n as u32).map(byte_at).collect();
+                    written += r.write(QUAD, &data).unwrap() as u32;
+                }
+                Step::Ack(n) => {
+                    let ack = una.wrapping_add(n);
+                    r.feed(&segment(PEER_ISS + 1, Some(ack), &[])).unwrap();
+                }
+                Step::OldAck(n) => {
+                    let ack = una.wrapping_sub(n);
+                    r.feed(&segment(PEER_ISS + 1, Some(ack), &[])).unwrap();
+                }
+                Step::Timeout => r.advance(Duration::from_secs(60)).unwrap(),
+            }
+            r.check_invariants();
+            check_sent(&r, iss, written);
+
+            let s = r.snapshot(QUAD).unwrap();
+            let (to_una, to_nxt, to_max) = (
+                s.snd_una.wrapping_sub(iss),
+                s.snd_nxt.wrapping_sub(iss),
+                s.snd_max.wrapping_sub(iss),
+            );
+            prop_assert!(to_una <= to_nxt && to_nxt <= to_max, "{s:?}");
+            prop_assert!(to_max <= written, "{s:?} with {written} written");
+            prop_assert!(s.snd_una.wrapping_sub(una) < 1 << 31, "SND.UNA went back");
+            una = s.snd_una;
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Properties of the sequence number comparisons, checked against slow models that don't wrap:
+//! each window is unrolled onto a 64-bit line, or walked one sequence number at a time, and
+//! the answers have to agree anywhere in the sequence space, across the wrap, and for the
+//! empty windows whose ends are equal.
+//!
+//! The invariants, as the state machine relies on them:
+//!
+//! - `in_window(s, x, e)` holds for exactly the `e - s` (mod 2^32) numbers from `s` on, so a
+//!   window whose ends are equal has nothing in it.
+//! - `is_between_wrapped(s, x, e)` is the same window without `s` itself.
+//! - `wrapping_lt(a, b)` holds for `b` up to 2^31 - 1 ahead of `a`, and never both ways.
+//! - `segment_acceptable` is the four cases of RFC 793's table, and nothing more.
+
+use proptest::prelude::*;
+use trust::testing::seq::{in_window, is_between_wrapped, segment_acceptable, wrapping_lt};
+
+const WRAP: u64 = 1 << 32;
+
+/// `[start, end)` as the 64-bit line has it: `end` is moved up past `start`, and so is `x` if
+/// it's behind.
+fn model_in_window(start: u32, x: u32, end: u32) -> bool {
+    let unroll = |n: u32| {
+        let n = n as u64;
+        if n < start as u64 { n + WRAP } else { n }
+    };
+    let (start, x, end) = (start as u64, unroll(x), unroll(end));
+    // `end` unrolled onto `start` itself is the empty window, not the whole space
+    start <= x && x < end
+}
+
+/// Every sequence number in the window of `len` from `start`, one at a time.
+fn walk(start: u32, len: u32) -> impl Iterator<Item = u32> {
+    (0..len).map(move |i| start.wrapping_add(i))
+}
+
+/// Sequence numbers within `reach` of the wrap, on either side.
+fn near_wrap(reach: u32) -> impl Strategy<Value = u32> {
+    prop_oneof![u32::MAX - reach..=u32::MAX, 0..=reach]
+}
+
+/// RFC 793 S3.3's table, case by case, with the window tests done on the 64-bit line.
+fn model_acceptable(rcv_nxt: u32, rcv_wnd: u32, seq: u32, len: u32) -> bool {
+    let wend = rcv_nxt.wrapping_add(rcv_wnd);
+    let in_wnd = |n: u32| model_in_window(rcv_nxt, n, wend);
+    match (len > 0, rcv_wnd > 0) {
+        (false, false) => seq == rcv_nxt,
+        (false, true) => in_wnd(seq),
+        (true, false) => false,
+        (true, true) => in_wnd(seq) || in_wnd(seq.wrapping_add(len - 1)),
+    }
+}
+
+proptest! {
+    #[test]
+    fn in_window_matches_the_model(start: u32, x: u32, end: u32) {
+        prop_assert_eq!(in_window(start, x, end), model_in_window(start, x, end));
+    }
+
+    #[test]
+    fn in_window_matches_the_model_across_the_wrap(
+        start in near_wrap(1 << 16),
+        x in near_wrap(1 << 17),
+        end in near_wrap(1 << 16),
+    ) {
+        prop_assert_eq!(in_window(start, x, end), model_in_window(start, x, end));
+    }
+
+    #[test]
+    fn window_holds_exactly_what_walking_it_reaches(
+        start in prop_oneof![any::<u32>(), near_wrap(100)],
+        len in 0u32..300,
+    ) {
+        let end = start.wrapping_add(len);
+        for x in walk(start, len) {
+            prop_assert!(in_window(start, x, end), "{} not in [{}, {})", x, start, end);
+        }
+        // and nothing just outside it, on either side
+        for x in walk(end, 300).chain(walk(start.wrapping_sub(300), 300)) {
+            prop_assert!(!in_window(start, x, end), "{} in [{}, {})", x, start, end);
+        }
+    }
+
+    #[test]
+    fn window_with_equal_ends_is_empty(start: u32, x: u32) {
+        prop_assert!(!in_window(start, x, start));
+        prop_assert!(!is_between_wrapped(start, x, start));
+    }
+
+    #[test]
+    fn window_of_all_but_one_holds_all_but_its_end(start: u32, x: u32) {
+        let end = start.wrapping_sub(1);
+        prop_assert_eq!(in_window(start, x, end), x != end);
+    }
+
+    #[test]
+    fn between_is_the_window_without_its_start(start: u32, x: u32, end: u32) {
+        prop_assert_eq!(
+            is_between_wrapped(start, x, end),
+            x != start && model_in_window(start, x, end)
+        );
+        prop_assert!(!is_between_wrapped(start, start, end));
+    }
+
+    #[test]
+    fn wrapping_lt_orders_half_the_space_ahead(a: u32, d in 1u32..1 << 31) {
+        let b = a.wrapping_add(d);
+        prop_assert!(wrapping_lt(a, b));
+        prop_assert!(!wrapping_lt(b, a));
+        prop_assert!(!wrapping_lt(a, a));
+    }
+
+    #[test]
+    fn segment_acceptable_is_the_rfc_793_table(
+        rcv_nxt: u32,
+        rcv_wnd in prop_oneof![Just(0u32), 1u32..=65535, 65536u32..=1 << 30],
+        seq: u32,
+        len in prop_oneof![Just(0u32), 1u32..=65535],
+    ) {
+        prop_assert_eq!(
+            segment_acceptable(rcv_nxt, rcv_wnd, seq, len),
+            model_acceptable(rcv_nxt, rcv_wnd, seq, len)
+        );
+    }
+
+    /// The same, with the segment somewhere around the window, which the last property rarely
+    /// lands on by chance.
+    #[test]
+    fn segment_acceptable_is_the_rfc_793_table_near_the_window(
+        rcv_nxt in prop_oneof![any::<u32>(), near_wrap(100)],
+        rcv_wnd in prop_oneof![Just(0u32), 1u32..=2000],
+        offset in -2100i64..=2100,
+        len in prop_oneof![Just(0u32), 1u32..=2000],
+    ) {
+        let seq = (rcv_nxt as i64 + offset).rem_euclid(WRAP as i64) as u32;
+        prop_assert_eq!(
+            segment_acceptable(rcv_nxt, rcv_wnd, seq, len),
+            model_acceptable(rcv_nxt, rcv_wnd, seq, len)
+        );
+    }
+
+    /// Each row of the table on its own: an empty segment to a shut window has to be at
+    /// RCV.NXT exactly, anything longer is refused outright, and otherwise a segment is taken
+    /// iff its first or last sequence number is in the window.
+    #[test]
+    fn segment_acceptable_row_by_row(
+        rcv_nxt: u32,
+        seq: u32,
+        rcv_wnd in 1u32..=1 << 30,
+        len in 1u32..=65535,
+    ) {
+        prop_assert_eq!(segment_acceptable(rcv_nxt, 0, seq, 0), seq == rcv_nxt);
+        prop_assert!(segment_acceptable(rcv_nxt, 0, rcv_nxt, 0));
+        prop_assert!(!segment_acceptable(rcv_nxt, 0, seq, len));
+        prop_assert!(!segment_acceptable(rcv_nxt, 0, rcv_nxt, len));
+        let wend = rcv_nxt.wrapping_add(rcv_wnd);
+        prop_assert_eq!(
+            segment_acceptable(rcv_nxt, rcv_wnd, seq, 0),
+            model_in_window(rcv_nxt, seq, wend)
+        );
+        let last = seq.wrapping_add(len - 1);
+        prop_assert_eq!(
+            segment_acceptable(rcv_nxt, rcv_wnd, seq, len),
+            model_in_window(rcv_nxt, seq, wend) || model_in_window(rcv_nxt, last, wend)
+        );
+    }
+}
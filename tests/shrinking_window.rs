@@ -0,0 +1,100 @@
+//! A peer that shrinks its window mid-transfer, taking back room it had offered for data
+//! that's already on its way: nothing new goes out past the new right edge, what's resent
+//! stays inside it, and a window shut altogether is probed for as long as the peer answers,
+//! without the connection timing out. Driven through `Replay`, so no device needed.
+
+use std::time::Duration;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// the MSS with no option to say otherwise
+const MSS: u32 = 536;
+
+/// An ACK of `ack` from the peer, offering `wnd`.
+fn ack(r: &mut Replay, ack: u32, wnd: u16) {
+    let segment = Segment::new(PEER_ISS + 1).ack(ack).window(wnd);
+    r.feed(&segment.build(&[])).unwrap();
+}
+
+/// An established connection with the peer offering 8000 bytes, and our sequence number for
+/// the first byte of data.
+fn established() -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default());
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).window(8000));
+    (r, iss)
+}
+
+/// The sequence numbers each segment we sent started and ended at.
+fn sent(r: &mut Replay) -> Vec<(u32, u32)> {
+    r.take_sent()
+        .iter()
+        .map(|p| {
+            let (_, tcph, data) = parse_segment(p);
+            let seq = tcph.sequence_number();
+            (seq, seq + data.len() as u32 + tcph.fin() as u32)
+        })
+        .collect()
+}
+
+#[test]
+fn nothing_goes_past_the_new_right_edge() {
+    let (mut r, start) = established();
+    r.write(QUAD, &[1; 4 * MSS as usize]).unwrap();
+    assert_eq!(sent(&mut r).last().unwrap().1, start + 4 * MSS);
+
+    // the first segment is ACKed, but the window now ends inside the second one
+    ack(&mut r, start + MSS, 200);
+    r.write(QUAD, &[2; 1000]).unwrap();
+    assert!(sent(&mut r).is_empty(), "sent past the shrunken window");
+
+    // the timeout resends only what's inside it
+    r.advance(Duration::from_secs(1)).unwrap();
+    assert_eq!(sent(&mut r), vec![(start + MSS, start + MSS + 200)]);
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+    r.check_invariants();
+}
+
+#[test]
+fn shut_window_is_probed_without_timing_out() {
+    let (mut r, start) = established();
+    r.write(QUAD, &[1; 4 * MSS as usize]).unwrap();
+    sent(&mut r);
+    ack(&mut r, start + MSS, 0);
+
+    // far more timeouts than it would take to give up on a peer that wasn't answering
+    for _ in 0..30 {
+        r.advance(Duration::from_secs(60)).unwrap();
+        // a byte at a time, and nothing that's been ACKed
+        assert_eq!(sent(&mut r), vec![(start + MSS, start + MSS + 1)]);
+        ack(&mut r, start + MSS, 0);
+        assert_eq!(r.state(QUAD), Some(State::Estab));
+    }
+
+    // once the window opens, the rest goes out, none of it from before SND.UNA
+    ack(&mut r, start + MSS, 8000);
+    let resent = sent(&mut r);
+    assert!(!resent.is_empty());
+    assert!(resent.iter().all(|&(seq, _)| seq >= start + MSS));
+    assert_eq!(
+        resent.iter().map(|&(_, end)| end).max(),
+        Some(start + 4 * MSS)
+    );
+    r.check_invariants();
+}
+
+#[test]
+fn unanswered_probes_still_time_out() {
+    let (mut r, start) = established();
+    r.write(QUAD, &[1; 100]).unwrap();
+    sent(&mut r);
+    ack(&mut r, start, 0);
+    for _ in 0..30 {
+        r.advance(Duration::from_secs(60)).unwrap();
+    }
+    assert_eq!(r.state(QUAD), None);
+}
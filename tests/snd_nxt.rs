@@ -0,0 +1,112 @@
+//! SND.NXT and the bytes in flight behind it, as a SYN, data and a FIN each take their sequence
+//! numbers, on both sides of the 2^32 wrap. Driven through `Replay` with the ISS picked.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, fin, handshake, segment};
+use trust::testing::{Replay, Snapshot};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// An ISS 200 short of the wrap, so 500 bytes of data cross it.
+const NEAR_WRAP: u32 = u32::MAX - 199;
+
+/// A listener on port 80 whose connections start at `iss`.
+fn listening(iss: u32) -> Replay {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().initial_sequence_number(iss));
+    r
+}
+
+/// A connection established with `iss` as ours, whose peer offered an MSS of 1460.
+fn establish(iss: u32) -> Replay {
+    let mut r = listening(iss);
+    let (next, _) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    assert_eq!(next, iss.wrapping_add(1));
+    r
+}
+
+fn snapshot(r: &Replay) -> Snapshot {
+    r.snapshot(QUAD).expect("no connection")
+}
+
+#[test]
+fn syn_takes_one() {
+    for iss in [0, NEAR_WRAP, u32::MAX] {
+        let mut r = listening(iss);
+        r.feed(&Segment::syn_at(PEER_ISS).build(&[])).unwrap();
+        let s = snapshot(&r);
+        assert_eq!(s.snd_una, iss);
+        assert_eq!(s.snd_nxt, iss.wrapping_add(1));
+        assert_eq!(s.info.bytes_in_flight, 1);
+    }
+}
+
+#[test]
+fn data_takes_its_length() {
+    for iss in [0, NEAR_WRAP] {
+        let mut r = establish(iss);
+        assert_eq!(r.write(QUAD, &[7; 500]).unwrap(), 500);
+        let s = snapshot(&r);
+        assert_eq!(s.snd_una, iss.wrapping_add(1));
+        assert_eq!(s.snd_nxt, iss.wrapping_add(501));
+        assert_eq!(s.info.bytes_in_flight, 500);
+
+        r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(501)), &[]))
+            .unwrap();
+        let s = snapshot(&r);
+        assert_eq!(s.snd_una, iss.wrapping_add(501));
+        assert_eq!(s.info.bytes_in_flight, 0);
+    }
+}
+
+#[test]
+fn fin_takes_one() {
+    for iss in [0, NEAR_WRAP, u32::MAX - 1] {
+        let mut r = establish(iss);
+        r.close(QUAD).unwrap();
+        let s = snapshot(&r);
+        assert_eq!(s.snd_nxt, iss.wrapping_add(2));
+        assert_eq!(s.info.bytes_in_flight, 1);
+
+        r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(2)), &[]))
+            .unwrap();
+        assert_eq!(r.state(QUAD), Some(State::FinWait2));
+        assert_eq!(snapshot(&r).info.bytes_in_flight, 0);
+    }
+}
+
+#[test]
+fn data_then_fin() {
+    for iss in [0, NEAR_WRAP] {
+        let mut r = establish(iss);
+        r.write(QUAD, &[7; 500]).unwrap();
+        r.close(QUAD).unwrap();
+        let s = snapshot(&r);
+        assert_eq!(s.snd_nxt, iss.wrapping_add(502));
+        assert_eq!(s.info.bytes_in_flight, 501);
+
+        // the peer's FIN with its ACK of everything, and that's both ways closed
+        r.feed(&fin(PEER_ISS + 1, iss.wrapping_add(502), &[]))
+            .unwrap();
+        assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    }
+}
+
+#[test]
+fn across_the_wrap() {
+    let mut r = establish(NEAR_WRAP);
+    r.write(QUAD, &[7; 500]).unwrap();
+    // 199 sequence numbers before the wrap, and 301 after it
+    assert_eq!(snapshot(&r).snd_nxt, 301);
+
+    // a partial ACK from before the wrap, then one from after it
+    r.feed(&segment(PEER_ISS + 1, Some(u32::MAX), &[])).unwrap();
+    let s = snapshot(&r);
+    assert_eq!(s.snd_una, u32::MAX);
+    assert_eq!(s.info.bytes_in_flight, 302);
+    r.feed(&segment(PEER_ISS + 1, Some(100), &[])).unwrap();
+    let s = snapshot(&r);
+    assert_eq!(s.snd_una, 100);
+    assert_eq!(s.info.bytes_in_flight, 201);
+    r.check_invariants();
+}
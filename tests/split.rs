@@ -0,0 +1,74 @@
+//! A `TcpStream` split into its read and write halves, over a `MockNic`: each half shuts down
+//! its own side when it's dropped, and the other goes on.
+
+use std::io::{Read, Write};
+use std::thread;
+
+use common::{PEER_ISS, accept, fin, segment, sent_until_fin, wait_sent};
+use trust::Interface;
+use trust::testing::{MockNic, parse_segment};
+
+mod common;
+
+#[test]
+fn write_half_closes_while_read_half_reads() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (s, _) = accept(&nic, &mut l);
+    let (mut rx, mut tx) = s.split();
+    let reader = thread::spawn(move || {
+        let mut got = Vec::new();
+        rx.read_to_end(&mut got).map(|_| got)
+    });
+
+    tx.write_all(b"from us").unwrap();
+    drop(tx);
+    let (got, our_fin) = sent_until_fin(&nic);
+    assert_eq!(got, b"from us");
+
+    nic.inject(&fin(
+        PEER_ISS + 1,
+        our_fin.wrapping_add(1),
+        b"from the peer",
+    ));
+    assert_eq!(reader.join().unwrap().unwrap(), b"from the peer");
+}
+
+#[test]
+fn dropped_read_half_leaves_the_write_half_going() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (s, iss) = accept(&nic, &mut l);
+    let (rx, mut tx) = s.split();
+    drop(rx);
+
+    // with nobody left to read it, what arrives is still ACKed
+    nic.inject(&segment(PEER_ISS + 1, Some(iss), b"ignored"));
+    let ack = parse_segment(&wait_sent(&nic, 1)[0])
+        .1
+        .acknowledgment_number();
+    assert_eq!(ack, PEER_ISS + 8);
+
+    tx.write_all(b"bye").unwrap();
+    drop(tx);
+    assert_eq!(sent_until_fin(&nic).0, b"bye");
+}
+
+#[test]
+fn unsplit_is_a_stream_like_any_other() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (s, iss) = accept(&nic, &mut l);
+    let (rx, tx) = s.split();
+    let mut s = rx.unsplit(tx);
+
+    nic.inject(&segment(PEER_ISS + 1, Some(iss), b"ping"));
+    let mut buf = [0; 4];
+    s.read_exact(&mut buf).unwrap();
+    s.write_all(&buf).unwrap();
+    drop(s);
+    assert_eq!(sent_until_fin(&nic).0, b"ping");
+}
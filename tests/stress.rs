@@ -0,0 +1,78 @@
+//! Many streams at once between two of our stacks, each with its own reader and writer thread,
+//! all going through the one packet loop on each side.
+
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use trust::testing::{ChaosConfig, ChaosLink};
+use trust::{ConnectionConfig, Interface, MonotonicClock};
+
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+#[test]
+fn fifty_concurrent_echo_streams() {
+    const STREAMS: usize = 50;
+    const LEN: usize = 32 * 1024;
+    let (client_end, server_end) = ChaosLink::pair(
+        ChaosConfig::default(),
+        ChaosConfig::default(),
+        116,
+        MonotonicClock,
+    );
+    let mut client = Interface::with_nic(client_end);
+    client.add_address(CLIENT.into());
+    let mut server = Interface::with_nic(server_end);
+    server.add_address(SERVER.into());
+
+    let mut l = server
+        .bind_with_config(80, STREAMS, ConnectionConfig::default())
+        .unwrap();
+    thread::spawn(move || {
+        while let Ok(mut s) = l.accept() {
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                loop {
+                    match s.read(&mut buf) {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => s.write_all(&buf[..n]).unwrap(),
+                    }
+                }
+            });
+        }
+    });
+
+    // each stream's writer and reader on threads of their own, so neither waits on the other
+    let (done, results) = mpsc::channel();
+    for i in 0..STREAMS {
+        let mut s = client
+            .connect(CLIENT.into(), SocketAddr::from((SERVER, 80)))
+            .unwrap();
+        let data: Vec<u8> = (0..LEN).map(|j| (i * 7 + j % 251) as u8).collect();
+        let mut w = s.clone();
+        let sent = data.clone();
+        thread::spawn(move || {
+            for chunk in sent.chunks(1000) {
+                w.write_all(chunk).unwrap();
+            }
+            w.shutdown(Shutdown::Write).unwrap();
+        });
+        let done = done.clone();
+        thread::spawn(move || {
+            let mut got = Vec::new();
+            let res = s.read_to_end(&mut got).map(|_| got == data);
+            done.send((i, res)).unwrap();
+        });
+    }
+
+    // a deadlock shows up as a stream that never finishes, rather than a test that never does
+    let deadline = Instant::now() + Duration::from_secs(60);
+    for _ in 0..STREAMS {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let (i, res) = results.recv_timeout(left).expect("streams stuck");
+        assert!(res.unwrap(), "stream {i} echoed back something else");
+    }
+}
@@ -0,0 +1,139 @@
+//! TIME-WAIT, once the connection has been cut down to the little it needs: a retransmitted
+//! FIN is ACKed again, a new SYN can have the quad, and after 2MSL it's gone. Driven through
+//! `Replay`, so no device needed.
+
+use std::time::Duration;
+
+use common::{PEER_ISS, QUAD, establish, fin, rst, segment};
+use trust::testing::{Replay, TIME_WAIT_ENTRY_SIZE, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// A connection we closed first, taken into TIME-WAIT by the peer's FIN, and our sequence
+/// number past our FIN.
+fn time_wait() -> (Replay, u32) {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.close(QUAD).unwrap();
+    r.feed(&fin(PEER_ISS + 1, iss + 1, &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    r.take_sent();
+    (r, iss + 1)
+}
+
+#[test]
+fn entry_is_small() {
+    // 72 bytes as it is, and 96 with an MD5 key
+    const { assert!(TIME_WAIT_ENTRY_SIZE <= 100) };
+}
+
+#[test]
+fn retransmitted_fin_is_acked_again() {
+    let (mut r, nxt) = time_wait();
+    r.advance(Duration::from_secs(40)).unwrap();
+    r.feed(&fin(PEER_ISS + 1, nxt, &[])).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1);
+    let (_, tcph, _) = parse_segment(&sent[0]);
+    assert!(tcph.ack() && !tcph.fin() && !tcph.rst());
+    assert_eq!(tcph.sequence_number(), nxt);
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 2);
+
+    // and the 2MSL wait starts over from there
+    r.advance(Duration::from_secs(40)).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    r.advance(Duration::from_secs(21)).unwrap();
+    assert_eq!(r.state(QUAD), None);
+}
+
+#[test]
+fn every_retransmitted_fin_restarts_the_wait() {
+    // both FINs crossed on the wire, so it's through CLOSING that we get here
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.close(QUAD).unwrap();
+    r.feed(&fin(PEER_ISS + 1, iss, &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Closing));
+    r.feed(&segment(PEER_ISS + 2, Some(iss + 1), &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    r.take_sent();
+
+    // the peer keeps losing our ACK, and each FIN it sends again holds the quad for another
+    // 2MSL from then
+    for _ in 0..3 {
+        r.advance(Duration::from_secs(50)).unwrap();
+        assert_eq!(r.state(QUAD), Some(State::TimeWait));
+        r.feed(&fin(PEER_ISS + 1, iss + 1, &[])).unwrap();
+        let sent = r.take_sent();
+        assert_eq!(sent.len(), 1);
+        let (_, tcph, _) = parse_segment(&sent[0]);
+        assert!(tcph.ack() && !tcph.fin());
+        assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 2);
+    }
+    r.advance(Duration::from_secs(59)).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    r.advance(Duration::from_secs(1)).unwrap();
+    assert_eq!(r.state(QUAD), None);
+}
+
+#[test]
+fn gone_after_2msl() {
+    let (mut r, _) = time_wait();
+    assert_eq!(r.quads(), [QUAD]);
+    assert_eq!(r.info(QUAD).unwrap().state, State::TimeWait);
+    r.advance(Duration::from_secs(59)).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    r.advance(Duration::from_secs(1)).unwrap();
+    assert_eq!(r.state(QUAD), None);
+    assert!(r.quads().is_empty());
+    assert!(r.take_sent().is_empty());
+}
+
+#[test]
+fn new_syn_takes_over_the_quad() {
+    let (mut r, _) = time_wait();
+    // an old duplicate of the first SYN is no new connection
+    r.feed(&segment(PEER_ISS, None, &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    assert!(r.take_sent().is_empty());
+
+    r.feed(&segment(PEER_ISS + 100_000, None, &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::SynRcvd));
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1);
+    let (_, tcph, _) = parse_segment(&sent[0]);
+    assert!(tcph.syn() && tcph.ack());
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 100_001);
+}
+
+#[test]
+fn reset_ends_it() {
+    let (mut r, nxt) = time_wait();
+    // one that isn't at RCV.NXT only gets a challenge ACK
+    r.feed(&rst(PEER_ISS + 3, nxt)).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+    assert_eq!(r.take_sent().len(), 1);
+
+    r.feed(&rst(PEER_ISS + 2, nxt)).unwrap();
+    assert_eq!(r.state(QUAD), None);
+    assert!(r.take_sent().is_empty());
+}
+
+#[test]
+fn fin_in_fin_wait2_gets_the_final_ack() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.close(QUAD).unwrap();
+    // our FIN ACKed on its own, and the peer's FIN after it
+    r.feed(&segment(PEER_ISS + 1, Some(iss + 1), &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::FinWait2));
+    r.take_sent();
+
+    r.feed(&fin(PEER_ISS + 1, iss + 1, &[])).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let (_, tcph, data) = parse_segment(&sent[0]);
+    assert!(tcph.ack() && !tcph.fin() && !tcph.rst() && data.is_empty());
+    // from just past our FIN, to just past theirs
+    assert_eq!(tcph.sequence_number(), iss + 1);
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 2);
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+}
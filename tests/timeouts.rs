@@ -0,0 +1,127 @@
+//! Read and write timeouts on a `TcpStream`, over a `MockNic` with nobody on the other end to
+//! send anything or ACK what we do.
+
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{PEER_ISS, Segment, accept, segment};
+use trust::testing::{MockNic, parse_segment};
+use trust::{ConnectionConfig, Interface};
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_millis(50);
+
+#[test]
+fn read_times_out_and_the_stream_goes_on() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (mut s, iss) = accept(&nic, &mut l);
+
+    s.set_read_timeout(Some(TIMEOUT)).unwrap();
+    assert_eq!(s.read_timeout(), Some(TIMEOUT));
+    let mut buf = [0; 16];
+    let start = Instant::now();
+    let err = s.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    let waited = start.elapsed();
+    assert!(waited >= TIMEOUT && waited < 20 * TIMEOUT, "{waited:?}");
+
+    // and the stream is none the worse for it
+    nic.inject(&segment(PEER_ISS + 1, Some(iss), b"late"));
+    assert_eq!(s.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], b"late");
+}
+
+#[test]
+fn read_wakes_for_data_that_comes_in_time() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (mut s, iss) = accept(&nic, &mut l);
+
+    // a long timeout, and data well inside it: the read has it as soon as it's there
+    s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let start = Instant::now();
+    let reader = thread::spawn(move || {
+        let mut buf = [0; 16];
+        s.read(&mut buf).map(|n| buf[..n].to_vec())
+    });
+    thread::sleep(TIMEOUT);
+    nic.inject(&segment(PEER_ISS + 1, Some(iss), b"in time"));
+    assert_eq!(reader.join().unwrap().unwrap(), b"in time");
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "{:?}",
+        start.elapsed()
+    );
+}
+
+#[test]
+fn write_times_out_once_the_send_buffer_is_full() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let config = ConnectionConfig::default().send_buffer(4096, 4096);
+    let mut l = iface.bind_with_config(80, 1, config).unwrap();
+    let (mut s, _) = accept(&nic, &mut l);
+
+    s.set_write_timeout(Some(TIMEOUT)).unwrap();
+    assert_eq!(s.write_timeout(), Some(TIMEOUT));
+    // nothing is ever ACKed, so the buffer only fills
+    assert_eq!(s.write(&[0; 8192]).unwrap(), 4096);
+    let start = Instant::now();
+    let err = s.write(&[0; 16]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    let waited = start.elapsed();
+    assert!(waited >= TIMEOUT && waited < 20 * TIMEOUT, "{waited:?}");
+}
+
+#[test]
+fn write_times_out_against_a_window_that_never_opens() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let config = ConnectionConfig::default().send_buffer(4096, 4096);
+    let mut l = iface.bind_with_config(80, 1, config).unwrap();
+    let (mut s, iss) = accept(&nic, &mut l);
+    // the peer closes its window before we've sent anything, and keeps it closed
+    nic.inject(&Segment::new(PEER_ISS + 1).ack(iss).window(0).build(&[]));
+    thread::sleep(TIMEOUT);
+
+    s.set_write_timeout(Some(TIMEOUT)).unwrap();
+    let start = Instant::now();
+    let mut written = 0;
+    let err = loop {
+        match s.write(&[0; 1024]) {
+            Ok(n) => written += n,
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(written, 4096);
+    assert!(start.elapsed() >= TIMEOUT, "{:?}", start.elapsed());
+    // none of it went anywhere, bar the odd window probe
+    let sent: usize = nic
+        .take_sent()
+        .iter()
+        .map(|p| parse_segment(p).2.len())
+        .sum();
+    assert!(sent <= 1, "sent {sent} bytes into a zero window");
+}
+
+#[test]
+fn zero_timeouts_are_rejected() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let mut l = iface.bind(80).unwrap();
+    let (mut s, _) = accept(&nic, &mut l);
+
+    let err = s.set_read_timeout(Some(Duration::ZERO)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = s.set_write_timeout(Some(Duration::ZERO)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    // leaving them as they were, which is no timeout at all
+    assert_eq!(s.read_timeout(), None);
+    assert_eq!(s.write_timeout(), None);
+}
@@ -0,0 +1,120 @@
+//! The one-line-per-segment trace: `format_segment` on segments built to order, and
+//! `Interface::trace` writing a handshake to a sink over a `MockNic`.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{PEER, PEER_ISS, Segment, accept};
+use etherparse::{IpTrafficClass, Ipv4Header};
+use trust::testing::MockNic;
+use trust::trace::{Direction, format_segment};
+use trust::{Interface, State};
+
+mod common;
+
+#[test]
+fn syn_with_no_connection() {
+    let syn = Segment::syn_at(PEER_ISS).build(&[]);
+    assert_eq!(
+        format_segment(Direction::In, &syn, None, 0).unwrap(),
+        "in  10.0.0.1:40000 > 10.0.0.2:80 [S] seq 100:0 win 65535 (none)"
+    );
+}
+
+#[test]
+fn data_with_a_hexdump() {
+    let p = Segment::new(PEER_ISS + 1)
+        .ack(5001)
+        .psh()
+        .fin()
+        .window(1024)
+        .build(b"hello, world!\r\n\x00\x01 and the rest");
+    // only the first 20 bytes, 16 to a row, with what's printable alongside
+    assert_eq!(
+        format_segment(Direction::In, &p, Some(State::Estab), 20).unwrap(),
+        "in  10.0.0.1:40000 > 10.0.0.2:80 [FP.] seq 101:30 ack 5001 win 1024 (Estab)\n    \
+         0000  68 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21 0d 0a 00  hello, world!...\n    \
+         0010  01 20 61 6e                                      . an"
+    );
+    // and none at all, with no room for one
+    let line = format_segment(Direction::Out, &p, None, 0).unwrap();
+    assert!(line.starts_with("out ") && !line.contains('\n'), "{line}");
+}
+
+#[test]
+fn not_tcp() {
+    let mut iph = Ipv4Header::new(8, 64, IpTrafficClass::Udp, PEER.octets(), [10, 0, 0, 2]);
+    iph.set_payload_len(8).unwrap();
+    let mut p = Vec::new();
+    iph.write(&mut p).unwrap();
+    p.extend_from_slice(&[0; 8]);
+    assert!(format_segment(Direction::In, &p, None, 0).is_none());
+    assert!(format_segment(Direction::In, &[0x45, 0], None, 0).is_none());
+}
+
+/// A sink the test can read back from while the packet loop writes to it.
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sink {
+    /// Wait for `n` lines to have been written, and return them.
+    fn lines(&self, n: usize) -> Vec<String> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            let lines: Vec<_> = text.lines().map(str::to_owned).collect();
+            if lines.len() >= n {
+                return lines;
+            }
+            assert!(Instant::now() < deadline, "only {lines:?}");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[test]
+fn handshake_traced_to_a_sink() {
+    let nic = MockNic::new();
+    let mut iface = Interface::with_nic(nic.clone());
+    let sink = Sink::default();
+    iface.trace(sink.clone(), 0);
+    let mut l = iface.bind(80).unwrap();
+    let _s = accept(&nic, &mut l);
+
+    // each with the state of the connection that handled it: none yet for the SYN, the new
+    // connection's once it's answered it, and the state the ACK found it in
+    let lines = sink.lines(3);
+    let ends: Vec<_> = lines
+        .iter()
+        .map(|l| (&l[..3], l.rsplit(' ').next().unwrap()))
+        .collect();
+    assert_eq!(
+        ends,
+        [
+            ("in ", "(none)"),
+            ("out", "(SynRcvd)"),
+            ("in ", "(SynRcvd)")
+        ],
+        "{lines:#?}"
+    );
+    assert!(lines[1].contains("[S.]"), "{}", lines[1]);
+
+    // and nothing more once it's stopped
+    iface.stop_trace();
+    nic.inject(&Segment::new(PEER_ISS + 1).ack(1).build(&[]));
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(sink.lines(3).len(), 3);
+}
@@ -0,0 +1,60 @@
+//! Segments that fall outside the receive window: each is answered with an ACK of where we are,
+//! `<SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>`, so a peer that lost our last ACK finds out. Driven
+//! through `Replay`.
+
+use common::{PEER_ISS, QUAD, assert_one_ack, establish, fin, segment};
+use trust::testing::parse_segment;
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+#[test]
+fn old_duplicate_data_is_acked() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"hello")).unwrap();
+    r.flush_ack(QUAD).unwrap();
+    r.take_sent();
+
+    // the same five bytes again, as if our ACK of them never arrived
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"hello")).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let (_, tcph, data) = parse_segment(&sent[0]);
+    assert!(tcph.ack() && data.is_empty());
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 6);
+    assert_eq!(tcph.sequence_number(), iss);
+    // and they're only read the once
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"hello");
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+}
+
+#[test]
+fn retransmitted_fin_in_close_wait_is_acked() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&fin(PEER_ISS + 1, iss, &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+    r.take_sent();
+
+    r.feed(&fin(PEER_ISS + 1, iss, &[])).unwrap();
+    assert_one_ack(&r, PEER_ISS + 2);
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+}
+
+#[test]
+fn retransmitted_fin_in_last_ack_is_acked() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&fin(PEER_ISS + 1, iss, &[])).unwrap();
+    r.close(QUAD).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::LastAck));
+    r.take_sent();
+
+    // the FIN again, ACKing nothing new: our FIN is still out, so SND.NXT is past it
+    r.feed(&fin(PEER_ISS + 1, iss, &[])).unwrap();
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.ack() && !tcph.fin() && !tcph.rst());
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 2);
+    assert_eq!(tcph.sequence_number(), iss.wrapping_add(1));
+    assert_eq!(r.state(QUAD), Some(State::LastAck));
+}
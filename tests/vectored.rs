@@ -0,0 +1,110 @@
+//! `read_vectored` and `write_vectored`: a stream in pieces is the same stream as one taken
+//! whole. Driven through `Replay`, so no device needed.
+
+use std::io::{IoSlice, IoSliceMut};
+
+use common::{PEER_ISS, QUAD, establish, segment};
+use trust::ConnectionConfig;
+use trust::testing::parse_segment;
+
+mod common;
+
+#[test]
+fn read_vectored_splits_header_and_body() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"headers!and the body"))
+        .unwrap();
+
+    let (mut header, mut body) = ([0; 8], [0; 64]);
+    let mut bufs = [IoSliceMut::new(&mut header), IoSliceMut::new(&mut body)];
+    assert_eq!(r.read_vectored(QUAD, &mut bufs).unwrap(), 20);
+    assert_eq!(&header, b"headers!");
+    assert_eq!(&body[..12], b"and the body");
+    assert!(body[12..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn read_vectored_takes_what_arrived_in_several_segments() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"head")).unwrap();
+    r.feed(&segment(PEER_ISS + 5, Some(iss), b"ers!body"))
+        .unwrap();
+
+    // less room than there is data, filled across the join between the segments
+    let (mut a, mut b) = ([0; 3], [0; 6]);
+    let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+    assert_eq!(r.read_vectored(QUAD, &mut bufs).unwrap(), 9);
+    assert_eq!(&a, b"hea");
+    assert_eq!(&b, b"ders!b");
+    // and the rest is there for the next read
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"ody");
+}
+
+#[test]
+fn write_vectored_goes_out_as_one_segment() {
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    let body = [7; 300];
+    let bufs = [IoSlice::new(b"headers!"), IoSlice::new(&body)];
+    assert_eq!(r.write_vectored(QUAD, &bufs).unwrap(), 308);
+
+    // the two pieces, coalesced, rather than a segment each
+    let sent = r.take_sent();
+    assert_eq!(sent.len(), 1, "sent {} segments", sent.len());
+    let (_, tcph, data) = parse_segment(&sent[0]);
+    assert_eq!(tcph.sequence_number(), iss);
+    assert_eq!(&data[..8], b"headers!");
+    assert_eq!(&data[8..], &body);
+}
+
+#[test]
+fn write_vectored_is_segmented_against_the_mss() {
+    // the peer offered no MSS, so it's 536
+    let (mut r, iss) = establish(ConnectionConfig::default());
+    let (head, body) = ([1; 500], [2; 500]);
+    let bufs = [IoSlice::new(&head), IoSlice::new(&body)];
+    assert_eq!(r.write_vectored(QUAD, &bufs).unwrap(), 1000);
+
+    // full segments, the first running across the join between the two
+    let (mut stream, mut lens) = (Vec::new(), Vec::new());
+    for p in r.take_sent() {
+        let (_, tcph, data) = parse_segment(&p);
+        assert_eq!(
+            tcph.sequence_number(),
+            iss.wrapping_add(stream.len() as u32)
+        );
+        stream.extend_from_slice(data);
+        lens.push(data.len());
+    }
+    assert_eq!(lens, [536, 464]);
+    assert_eq!(stream, [head, body].concat());
+}
+
+#[test]
+fn write_vectored_stops_partway_through_a_slice() {
+    // room for a slice and a half
+    let config = ConnectionConfig::default().send_buffer(1500, 1500);
+    let (mut r, iss) = establish(config);
+    let slices = [[1; 1000], [2; 1000], [3; 1000]];
+    let bufs = slices.each_ref().map(|s| IoSlice::new(s));
+    assert_eq!(r.write_vectored(QUAD, &bufs).unwrap(), 1500);
+
+    // the first slice and the front half of the second, and nothing past that
+    let sent: Vec<u8> = r
+        .take_sent()
+        .iter()
+        .flat_map(|p| parse_segment(p).2.to_vec())
+        .collect();
+    assert_eq!(sent, [&slices[0][..], &slices[1][..500]].concat());
+
+    // the rest goes once the caller hands it over again, from where it left off
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(1500)), &[]))
+        .unwrap();
+    let rest = [IoSlice::new(&slices[1][500..]), IoSlice::new(&slices[2])];
+    assert_eq!(r.write_vectored(QUAD, &rest).unwrap(), 1500);
+    let sent: Vec<u8> = r
+        .take_sent()
+        .iter()
+        .flat_map(|p| parse_segment(p).2.to_vec())
+        .collect();
+    assert_eq!(sent, [&slices[1][500..], &slices[2][..]].concat());
+}
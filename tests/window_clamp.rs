@@ -0,0 +1,84 @@
+//! The window we advertise is never more than the room left in the receive buffer, so the
+//! right edge we offer is always one we can hold: it closes as data comes in, a segment past
+//! it isn't taken, and it opens again as the application reads. Driven through `Replay`.
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, handshake, segment};
+use trust::ConnectionConfig;
+use trust::testing::{Replay, parse_segment};
+
+mod common;
+
+/// An established connection offering `window`, whose peer offered an MSS of 1460. Returns
+/// our next sequence number too.
+fn establish(window: u16) -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().recv_window(window));
+    let (iss, synack) = handshake(&mut r, Segment::syn_at(PEER_ISS).mss(1460));
+    assert_eq!(parse_segment(&synack).1.window_size(), window);
+    (r, iss)
+}
+
+/// Send `len` bytes from the peer at `seq`, and return what our ACK of them acknowledges and
+/// the window it offers.
+fn send(r: &mut Replay, iss: u32, seq: u32, len: usize) -> (u32, u16) {
+    r.feed(&segment(seq, Some(iss), &vec![7; len])).unwrap();
+    r.flush_ack(QUAD).unwrap();
+    let sent = r.take_sent();
+    let tcph = parse_segment(sent.last().expect("nothing ACKed")).1;
+    (tcph.acknowledgment_number(), tcph.window_size())
+}
+
+#[test]
+fn window_is_the_room_left() {
+    let (mut r, iss) = establish(1000);
+    let mut seq = PEER_ISS + 1;
+    for (len, left) in [(300, 700), (250, 450), (450, 0)] {
+        let (ack, wnd) = send(&mut r, iss, seq, len);
+        seq += len as u32;
+        assert_eq!((ack, wnd), (seq, left));
+        let s = r.snapshot(QUAD).unwrap();
+        assert_eq!(s.rcv_wnd, left);
+        assert_eq!(s.info.recv_buffer_len + left as usize, 1000);
+        r.check_invariants();
+    }
+}
+
+#[test]
+fn nothing_is_taken_past_the_right_edge() {
+    let (mut r, iss) = establish(1000);
+    assert_eq!(send(&mut r, iss, PEER_ISS + 1, 900), (PEER_ISS + 901, 100));
+
+    // 200 bytes where there's room for 100: the 100 is kept, and the rest cut off
+    assert_eq!(send(&mut r, iss, PEER_ISS + 901, 200), (PEER_ISS + 1001, 0));
+    assert_eq!(r.info(QUAD).unwrap().recv_buffer_len, 1000);
+
+    // and with the window shut, nothing at all
+    assert_eq!(
+        send(&mut r, iss, PEER_ISS + 1001, 100),
+        (PEER_ISS + 1001, 0)
+    );
+    assert_eq!(r.info(QUAD).unwrap().recv_buffer_len, 1000);
+    r.check_invariants();
+}
+
+#[test]
+fn reading_opens_it_again() {
+    let (mut r, iss) = establish(1000);
+    assert_eq!(send(&mut r, iss, PEER_ISS + 1, 1000), (PEER_ISS + 1001, 0));
+
+    assert_eq!(r.read(QUAD, 600).unwrap().len(), 600);
+    r.flush_ack(QUAD).unwrap();
+    let sent = r.take_sent();
+    let tcph = parse_segment(sent.last().expect("no window update")).1;
+    assert_eq!(tcph.acknowledgment_number(), PEER_ISS + 1001);
+    assert_eq!(tcph.window_size(), 600);
+    assert_eq!(r.snapshot(QUAD).unwrap().rcv_wnd, 600);
+}
+
+#[test]
+fn largest_window_clamps_the_same() {
+    let (mut r, iss) = establish(u16::MAX);
+    let (_, wnd) = send(&mut r, iss, PEER_ISS + 1, 1460);
+    assert_eq!(wnd, u16::MAX - 1460);
+    r.check_invariants();
+}
@@ -0,0 +1,110 @@
+//! The receive window where it runs across the end of the sequence space: RCV.NXT just short
+//! of `u32::MAX`, and RCV.NXT+RCV.WND past zero. Driven through `Replay`, so no device needed.
+
+use common::{LOCAL, QUAD, Segment, handshake, rst, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+/// so RCV.NXT starts 1000 short of wrapping around
+const PEER_ISS: u32 = u32::MAX - 1000;
+const WINDOW: u16 = 4000;
+
+/// An established connection with a receive window of `window`, and our next sequence
+/// number.
+fn establish(window: u16) -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().recv_window(window));
+    let (iss, _) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+    (r, iss)
+}
+
+/// The acknowledgment number on the last segment we sent, and how many we sent.
+fn last_ack(r: &Replay) -> Option<(u32, usize)> {
+    let sent = r.take_sent();
+    let last = sent.last()?;
+    Some((parse_segment(last).1.acknowledgment_number(), sent.len()))
+}
+
+#[test]
+fn data_across_the_wrap_is_accepted() {
+    let (mut r, iss) = establish(WINDOW);
+    let nxt = PEER_ISS.wrapping_add(1);
+    // straddling zero, in one segment and then across several
+    let data: Vec<u8> = (0..4000u32).map(|i| i as u8).collect();
+    r.feed(&segment(nxt, Some(iss), &data[..1460])).unwrap();
+    assert_eq!(last_ack(&r), Some((nxt.wrapping_add(1460), 1)));
+    assert_eq!(r.read(QUAD, 4000).unwrap(), &data[..1460]);
+    for chunk in (1460..4000).step_by(500) {
+        let seq = nxt.wrapping_add(chunk as u32);
+        let end = (chunk + 500).min(4000);
+        r.feed(&segment(seq, Some(iss), &data[chunk..end])).unwrap();
+    }
+    assert_eq!(last_ack(&r).unwrap().0, nxt.wrapping_add(4000));
+    assert_eq!(r.read(QUAD, 4000).unwrap(), &data[1460..]);
+    r.check_invariants();
+}
+
+#[test]
+fn old_data_overlapping_the_wrap_is_trimmed() {
+    let (mut r, iss) = establish(WINDOW);
+    let nxt = PEER_ISS.wrapping_add(1);
+    // RCV.NXT to just past zero
+    r.feed(&segment(nxt, Some(iss), &[1; 1005])).unwrap();
+    r.read(QUAD, 2000).unwrap();
+    assert_eq!(nxt.wrapping_add(1005), 5);
+    // a retransmission from before zero that runs on past RCV.NXT: only the new part is kept
+    let seq = nxt.wrapping_add(995);
+    r.feed(&segment(seq, Some(iss), &[2; 20])).unwrap();
+    assert_eq!(last_ack(&r).unwrap().0, nxt.wrapping_add(1015));
+    assert_eq!(r.read(QUAD, 100).unwrap(), [2; 10]);
+}
+
+#[test]
+fn data_past_the_window_end_is_cut_off() {
+    let (mut r, iss) = establish(WINDOW);
+    let nxt = PEER_ISS.wrapping_add(1);
+    // the window ends past zero; a segment running over it is only taken up to there
+    let wend = nxt.wrapping_add(WINDOW as u32);
+    assert!(wend < nxt);
+    r.feed(&segment(nxt, Some(iss), &[3; 4100])).unwrap();
+    assert_eq!(last_ack(&r).unwrap().0, wend);
+    assert_eq!(r.read(QUAD, 5000).unwrap().len(), WINDOW as usize);
+}
+
+/// A RST shows where the acceptability test puts the window's edges: one anywhere in the
+/// window but at RCV.NXT draws a challenge ACK, and one outside it nothing at all. As well as
+/// a window that ends just past zero, there's the largest one we offer, without scaling.
+#[test]
+fn window_edges_across_the_wrap() {
+    window_edges(WINDOW);
+    window_edges(u16::MAX);
+}
+
+fn window_edges(window: u16) {
+    let (mut r, iss) = establish(window);
+    let nxt = PEER_ISS.wrapping_add(1);
+    let wend = nxt.wrapping_add(window as u32);
+    for seq in [nxt.wrapping_add(1), u32::MAX, 0, wend.wrapping_sub(1)] {
+        r.feed(&rst(seq, iss)).unwrap();
+        assert_eq!(
+            last_ack(&r),
+            Some((nxt, 1)),
+            "RST at {seq} is in the window"
+        );
+        assert_eq!(r.state(QUAD), Some(State::Estab));
+    }
+    for seq in [
+        nxt.wrapping_sub(1),
+        wend,
+        wend.wrapping_add(1),
+        nxt.wrapping_add(1 << 31),
+    ] {
+        r.feed(&rst(seq, iss)).unwrap();
+        assert_eq!(last_ack(&r), None, "RST at {seq} is outside the window");
+        assert_eq!(r.state(QUAD), Some(State::Estab));
+    }
+    r.feed(&rst(nxt, iss)).unwrap();
+    assert_eq!(r.state(QUAD), None);
+}
@@ -0,0 +1,72 @@
+//! Both initial sequence numbers right at the end of the sequence space: the peer's at
+//! `u32::MAX` and ours at `u32::MAX - 1`, so the handshake, the data and the FINs all cross
+//! zero. Run in debug builds, where arithmetic that doesn't wrap would panic. Driven through
+//! `Replay`.
+
+use common::{LOCAL, QUAD, Segment, assert_one_ack, fin, handshake, segment};
+use trust::testing::{Replay, parse_segment};
+use trust::{ConnectionConfig, State};
+
+mod common;
+
+const PEER_ISS: u32 = u32::MAX;
+const ISS: u32 = u32::MAX - 1;
+
+/// A connection through the handshake, with five bytes each way. Returns our sequence number
+/// for the FIN.
+fn establish() -> (Replay, u32) {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, ConnectionConfig::default().initial_sequence_number(ISS));
+    let (iss, synack) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+    let tcph = parse_segment(&synack).1;
+    assert_eq!(tcph.sequence_number(), ISS);
+    assert_eq!(tcph.acknowledgment_number(), 0);
+    assert_eq!(iss, u32::MAX);
+
+    r.feed(&segment(0, Some(iss), b"hello")).unwrap();
+    assert_one_ack(&r, 5);
+    assert_eq!(r.read(QUAD, 100).unwrap(), b"hello");
+
+    r.write(QUAD, b"world").unwrap();
+    let sent = r.take_sent();
+    let (_, tcph, data) = parse_segment(&sent[0]);
+    assert_eq!((tcph.sequence_number(), data), (u32::MAX, &b"world"[..]));
+    r.feed(&segment(5, Some(4), &[])).unwrap();
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 0);
+    r.check_invariants();
+    (r, 4)
+}
+
+#[test]
+fn we_close_first() {
+    let (mut r, our_fin) = establish();
+    r.close(QUAD).unwrap();
+    let sent = r.take_sent();
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.fin());
+    assert_eq!(tcph.sequence_number(), our_fin);
+    assert_eq!(r.state(QUAD), Some(State::FinWait1));
+
+    r.feed(&segment(5, Some(our_fin + 1), &[])).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::FinWait2));
+    r.feed(&fin(5, our_fin + 1, &[])).unwrap();
+    assert_one_ack(&r, 6);
+    assert_eq!(r.state(QUAD), Some(State::TimeWait));
+}
+
+#[test]
+fn peer_closes_first() {
+    let (mut r, our_fin) = establish();
+    r.feed(&fin(5, our_fin, &[])).unwrap();
+    assert_one_ack(&r, 6);
+    assert_eq!(r.state(QUAD), Some(State::CloseWait));
+
+    r.close(QUAD).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::LastAck));
+    let sent = r.take_sent();
+    let tcph = parse_segment(&sent[0]).1;
+    assert!(tcph.fin());
+    assert_eq!(tcph.sequence_number(), our_fin);
+    r.feed(&segment(6, Some(our_fin + 1), &[])).unwrap();
+    assert_eq!(r.state(QUAD), None);
+}
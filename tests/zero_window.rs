@@ -0,0 +1,76 @@
+//! A receive window of zero: binding with one is refused, and a connection that has one anyway
+//! (`Replay::listen` doesn't check) takes only what RFC 9293 lets through a closed window. A
+//! segment with no data at RCV.NXT, ACKs and RSTs included, is acceptable, and one with data is
+//! not.
+
+use std::io;
+
+use common::{LOCAL, PEER_ISS, QUAD, Segment, assert_one_ack, establish, handshake, rst, segment};
+use trust::testing::{MockNic, Replay, parse_segment};
+use trust::{ConnectionConfig, Interface, State};
+
+mod common;
+
+/// A config offering no window at all.
+fn closed() -> ConnectionConfig {
+    ConnectionConfig::default().recv_window(0)
+}
+
+#[test]
+fn bind_refuses_a_zero_window() {
+    let mut iface = Interface::with_nic(MockNic::new());
+    match iface.bind_with_config(80, 1, closed()) {
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+        Ok(_) => panic!("bound with a zero window"),
+    }
+    // the smallest window there is will do
+    let config = ConnectionConfig::default().recv_window(1);
+    iface.bind_with_config(80, 1, config).unwrap();
+}
+
+#[test]
+fn synack_offers_a_zero_window() {
+    let mut r = Replay::new(LOCAL);
+    r.listen(80, closed());
+    let (_, synack) = handshake(&mut r, Segment::syn_at(PEER_ISS));
+    assert_eq!(parse_segment(&synack).1.window_size(), 0);
+}
+
+#[test]
+fn data_is_refused_and_acked() {
+    let (mut r, iss) = establish(closed());
+    r.feed(&segment(PEER_ISS + 1, Some(iss), b"x")).unwrap();
+    assert_one_ack(&r, PEER_ISS + 1);
+    assert!(r.read(QUAD, 100).unwrap().is_empty());
+    assert_eq!(r.info(QUAD).unwrap().recv_buffer_len, 0);
+    r.check_invariants();
+}
+
+#[test]
+fn ack_at_rcv_nxt_is_accepted() {
+    let (mut r, iss) = establish(closed());
+    assert_eq!(r.write(QUAD, b"hello").unwrap(), 5);
+    r.take_sent();
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 5);
+
+    r.feed(&segment(PEER_ISS + 1, Some(iss.wrapping_add(5)), &[]))
+        .unwrap();
+    assert_eq!(r.info(QUAD).unwrap().bytes_in_flight, 0);
+    assert!(r.take_sent().is_empty());
+    r.check_invariants();
+}
+
+#[test]
+fn rst_at_rcv_nxt_is_accepted() {
+    let (mut r, iss) = establish(closed());
+    r.feed(&rst(PEER_ISS + 1, iss)).unwrap();
+    assert_ne!(r.state(QUAD), Some(State::Estab));
+    assert_eq!(r.error(QUAD), Some(io::ErrorKind::ConnectionReset));
+}
+
+#[test]
+fn rst_past_rcv_nxt_is_ignored() {
+    let (mut r, iss) = establish(closed());
+    r.feed(&rst(PEER_ISS + 2, iss)).unwrap();
+    assert_eq!(r.state(QUAD), Some(State::Estab));
+}